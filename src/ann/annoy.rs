@@ -1,45 +1,372 @@
-use super::AnnIndex;
-use crate::BinarySerialize;
-use crate::Metric;
-struct Annoy<V> {
-    v: V,
+use super::super::util::error::GyResult;
+use super::{read_align, write_align, AnnIndex, Metric, MetricType, Neighbor};
+use crate::disk::{GyRead, GyWrite};
+use crate::schema::BinarySerialize;
+use crate::TensorEntry;
+use crate::VectorSerialize;
+use rand::Rng;
+use std::collections::BinaryHeap;
+use std::collections::HashSet;
+use std::io::{Read, Write};
+
+pub(crate) const DEFAULT_TREES: usize = 10;
+const DEFAULT_LEAF_SIZE: usize = 64;
+const SPLIT_ATTEMPTS: usize = 3;
+
+const LEAF_NODE: u8 = 0;
+const SPLIT_NODE: u8 = 1;
+
+enum TreeNode {
+    // 叶子节点 保存向量id
+    Leaf(Vec<usize>),
+    // 分裂节点 以 left right 两个向量的中垂面切分空间, 离 left 更近的进入 children[0]
+    Split {
+        left: usize,
+        right: usize,
+        children: [usize; 2],
+    },
+}
+
+impl BinarySerialize for TreeNode {
+    fn binary_serialize<W: Write>(&self, writer: &mut W) -> GyResult<()> {
+        match self {
+            TreeNode::Leaf(ids) => {
+                LEAF_NODE.binary_serialize(writer)?;
+                ids.binary_serialize(writer)?;
+            }
+            TreeNode::Split {
+                left,
+                right,
+                children,
+            } => {
+                SPLIT_NODE.binary_serialize(writer)?;
+                left.binary_serialize(writer)?;
+                right.binary_serialize(writer)?;
+                children[0].binary_serialize(writer)?;
+                children[1].binary_serialize(writer)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn binary_deserialize<R: Read>(reader: &mut R) -> GyResult<Self> {
+        match u8::binary_deserialize(reader)? {
+            LEAF_NODE => Ok(TreeNode::Leaf(Vec::<usize>::binary_deserialize(reader)?)),
+            SPLIT_NODE => {
+                let left = usize::binary_deserialize(reader)?;
+                let right = usize::binary_deserialize(reader)?;
+                let c0 = usize::binary_deserialize(reader)?;
+                let c1 = usize::binary_deserialize(reader)?;
+                Ok(TreeNode::Split {
+                    left: left,
+                    right: right,
+                    children: [c0, c1],
+                })
+            }
+            _ => Err("invalid annoy tree node".into()),
+        }
+    }
+}
+
+// 随机投影森林
+// 每棵树递归地用两个随机样本的中垂面切分空间, 查询时按 margin 优先遍历所有树的叶子
+pub struct Annoy<V: VectorSerialize + Clone> {
+    n_trees: usize,
+    leaf_size: usize,
+    metric: MetricType,
+    roots: Vec<usize>,
+    nodes: Vec<TreeNode>,
+    vectors: Vec<V>,
 }
 
-// impl<V> BinarySerialize for Annoy<V> {
-//     fn binary_deserialize<R: std::io::Read>(
-//         reader: &mut R,
-//     ) -> crate::util::error::GyResult<Self> {
-//         todo!()
-//     }
-
-//     fn binary_serialize<W: std::io::Write>(
-//         &self,
-//         writer: &mut W,
-//     ) -> crate::util::error::GyResult<()> {
-//         todo!()
-//     }
-// }
-
-// impl<V: BinarySerialize> AnnIndex<V> for Annoy<V>
-// where
-//     V: Metric<V>,
-// {
-//     fn insert(&mut self, q: V) -> crate::util::error::GyResult<usize> {
-//         todo!()
-//     }
-
-//     fn query(
-//         &self,
-//         q: &V,
-//         k: usize,
-//     ) -> crate::util::error::GyResult<Vec<super::Neighbor>> {
-//         todo!()
-//     }
-
-//     fn serialize<W: std::io::Write>(
-//         &self,
-//         writer: &mut W,
-//     ) -> crate::util::error::GyResult<()> {
-//         todo!()
-//     }
-// }
+impl<V: VectorSerialize + Clone> VectorSerialize for Annoy<V> {
+    fn vector_deserialize<R: Read + GyRead>(reader: &mut R, entry: &TensorEntry) -> GyResult<Self> {
+        let n_trees = usize::binary_deserialize(reader)?;
+        let leaf_size = usize::binary_deserialize(reader)?;
+        let metric = MetricType::binary_deserialize(reader)?;
+        let roots = Vec::<usize>::binary_deserialize(reader)?;
+        let nodes = Vec::<TreeNode>::binary_deserialize(reader)?;
+        let vector_len = usize::binary_deserialize(reader)?;
+        read_align(reader)?;
+        let mut vectors: Vec<V> = Vec::with_capacity(vector_len);
+        for _ in 0..vector_len {
+            vectors.push(V::vector_deserialize(reader, entry)?);
+        }
+        Ok(Annoy {
+            n_trees: n_trees,
+            leaf_size: leaf_size,
+            metric: metric,
+            roots: roots,
+            nodes: nodes,
+            vectors: vectors,
+        })
+    }
+
+    fn vector_serialize<W: Write + GyWrite>(&self, writer: &mut W) -> GyResult<()> {
+        self.n_trees.binary_serialize(writer)?;
+        self.leaf_size.binary_serialize(writer)?;
+        self.metric.binary_serialize(writer)?;
+        self.roots.binary_serialize(writer)?;
+        self.nodes.binary_serialize(writer)?;
+        self.vectors.len().binary_serialize(writer)?;
+        write_align(writer)?;
+        for v in self.vectors.iter() {
+            v.vector_serialize(writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl<V: VectorSerialize + Clone> AnnIndex<V> for Annoy<V>
+where
+    V: Metric<V>,
+{
+    fn insert(&mut self, q: V) -> GyResult<usize> {
+        let id = self.vectors.len();
+        self.vectors.push(q);
+        if self.roots.is_empty() {
+            for _ in 0..self.n_trees {
+                self.roots.push(self.nodes.len());
+                self.nodes
+                    .push(TreeNode::Leaf(Vec::with_capacity(self.leaf_size)));
+            }
+        }
+        for t in 0..self.roots.len() {
+            let leaf = self.descend(self.roots[t], self.get_vector(id));
+            let len = match &mut self.nodes[leaf] {
+                TreeNode::Leaf(ids) => {
+                    ids.push(id);
+                    ids.len()
+                }
+                TreeNode::Split { .. } => unreachable!(),
+            };
+            if len > self.leaf_size {
+                self.split(leaf);
+            }
+        }
+        Ok(id)
+    }
+
+    fn query(&self, q: &V, k: usize) -> GyResult<Vec<Neighbor>> {
+        if self.vectors.is_empty() || k == 0 {
+            return Ok(Vec::new());
+        }
+        let search_k = (self.roots.len() * k).max(self.leaf_size);
+        // 按 margin 从大到小遍历节点, margin 越大说明 q 越确定落在这一侧
+        let mut queue: BinaryHeap<Neighbor> = BinaryHeap::with_capacity(self.roots.len() * 2);
+        for root in self.roots.iter() {
+            queue.push(Neighbor {
+                id: *root,
+                d: f32::MAX,
+            });
+        }
+        let mut candidates: HashSet<usize> = HashSet::with_capacity(search_k);
+        while let Some(n) = queue.pop() {
+            if candidates.len() >= search_k {
+                break;
+            }
+            match &self.nodes[n.id] {
+                TreeNode::Leaf(ids) => candidates.extend(ids.iter().cloned()),
+                TreeNode::Split {
+                    left,
+                    right,
+                    children,
+                } => {
+                    let margin = self.margin(q, *left, *right);
+                    queue.push(Neighbor {
+                        id: children[0],
+                        d: n.d.min(margin),
+                    });
+                    queue.push(Neighbor {
+                        id: children[1],
+                        d: n.d.min(-margin),
+                    });
+                }
+            }
+        }
+        let mut results: BinaryHeap<Neighbor> = BinaryHeap::with_capacity(k + 1);
+        for id in candidates {
+            results.push(Neighbor {
+                id: id,
+                d: q.distance(self.get_vector(id), self.metric),
+            });
+            if results.len() > k {
+                results.pop();
+            }
+        }
+        Ok(results.into_sorted_vec())
+    }
+}
+
+impl<V: VectorSerialize + Clone> Annoy<V>
+where
+    V: Metric<V>,
+{
+    pub fn new(n_trees: usize) -> Annoy<V> {
+        Self {
+            n_trees: n_trees.max(1),
+            leaf_size: DEFAULT_LEAF_SIZE,
+            metric: MetricType::L2,
+            roots: Vec::new(),
+            nodes: Vec::new(),
+            vectors: Vec::with_capacity(10000),
+        }
+    }
+
+    pub fn with_metric(mut self, metric: MetricType) -> Annoy<V> {
+        self.metric = metric;
+        self
+    }
+
+    pub fn metric(&self) -> MetricType {
+        self.metric
+    }
+
+    // 合并后 b 的 id 整体偏移 a.len(), 与倒排表合并时的 doc_size 偏移保持一致
+    pub fn merge(&self, other: &Self) -> GyResult<Annoy<V>> {
+        let mut new_annoy = Annoy::<V>::new(self.n_trees).with_metric(self.metric);
+        new_annoy.leaf_size = self.leaf_size;
+        for v in self.vectors.iter() {
+            new_annoy.insert(v.clone())?;
+        }
+        for v in other.vectors.iter() {
+            new_annoy.insert(v.clone())?;
+        }
+        Ok(new_annoy)
+    }
+
+    fn get_vector(&self, x: usize) -> &V {
+        self.vectors.get(x).expect("get vector fail")
+    }
+
+    pub fn get_vectors(&self) -> &Vec<V> {
+        &self.vectors
+    }
+
+    // 大于 0 表示 q 离 left 更近
+    fn margin(&self, q: &V, left: usize, right: usize) -> f32 {
+        q.distance(self.get_vector(right), self.metric)
+            - q.distance(self.get_vector(left), self.metric)
+    }
+
+    fn descend(&self, mut n: usize, v: &V) -> usize {
+        loop {
+            match &self.nodes[n] {
+                TreeNode::Leaf(_) => return n,
+                TreeNode::Split {
+                    left,
+                    right,
+                    children,
+                } => {
+                    n = if self.margin(v, *left, *right) >= 0.0 {
+                        children[0]
+                    } else {
+                        children[1]
+                    };
+                }
+            }
+        }
+    }
+
+    // 叶子超过 leaf_size 后随机挑选两个样本切分, 如果样本全部重合则保留为大叶子
+    fn split(&mut self, leaf: usize) {
+        let ids = match &self.nodes[leaf] {
+            TreeNode::Leaf(ids) => ids.clone(),
+            TreeNode::Split { .. } => return,
+        };
+        let mut rng = rand::thread_rng();
+        for _ in 0..SPLIT_ATTEMPTS {
+            let left = ids[rng.gen_range(0..ids.len())];
+            let right = ids[rng.gen_range(0..ids.len())];
+            if left == right {
+                continue;
+            }
+            let (mut l, mut r): (Vec<usize>, Vec<usize>) = (Vec::new(), Vec::new());
+            for id in ids.iter() {
+                if self.margin(self.get_vector(*id), left, right) >= 0.0 {
+                    l.push(*id);
+                } else {
+                    r.push(*id);
+                }
+            }
+            if l.is_empty() || r.is_empty() {
+                continue;
+            }
+            let c0 = self.nodes.len();
+            self.nodes.push(TreeNode::Leaf(l));
+            self.nodes.push(TreeNode::Leaf(r));
+            self.nodes[leaf] = TreeNode::Split {
+                left: left,
+                right: right,
+                children: [c0, c0 + 1],
+            };
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ann::random_vectors;
+    use crate::disk::MmapReader;
+    use crate::schema::VectorType;
+    use crate::util::fs::GyFile;
+    use galois::Tensor;
+    use memmap2::Mmap;
+    use std::fs::File;
+
+    #[test]
+    fn test_annoy_search() {
+        let mut annoy = Annoy::<Vec<f32>>::new(DEFAULT_TREES);
+        let features = random_vectors(1000, 16);
+        for f in features.iter() {
+            annoy.insert(f.clone()).unwrap();
+        }
+        for i in [0usize, 10, 500, 999] {
+            let neighbors = annoy.query(&features[i], 4).unwrap();
+            assert_eq!(neighbors[0].doc_id(), i as u64);
+        }
+    }
+
+    #[test]
+    fn test_annoy_merge() {
+        let mut a = Annoy::<Vec<f32>>::new(4);
+        let mut b = Annoy::<Vec<f32>>::new(4);
+        let features = random_vectors(200, 8);
+        for f in features[..100].iter() {
+            a.insert(f.clone()).unwrap();
+        }
+        for f in features[100..].iter() {
+            b.insert(f.clone()).unwrap();
+        }
+        let annoy = a.merge(&b).unwrap();
+        assert_eq!(annoy.get_vectors().len(), 200);
+        let neighbors = annoy.query(&features[150], 1).unwrap();
+        assert_eq!(neighbors[0].doc_id(), 150);
+    }
+
+    #[test]
+    fn test_annoy_serialize() {
+        let mut annoy = Annoy::<Tensor>::new(DEFAULT_TREES);
+        let features = random_vectors(300, 32);
+        for f in features.iter() {
+            annoy.insert(Tensor::arr(f.clone())).unwrap();
+        }
+        let mut file = File::create("./data.annoy").unwrap();
+        annoy.vector_serialize(&mut file).unwrap();
+        file.flush().unwrap();
+
+        let file = GyFile::open("./data.annoy").unwrap();
+        let file_size = file.fsize().unwrap();
+        let mmap: Mmap = unsafe { memmap2::MmapOptions::new().map(file.file()).unwrap() };
+        let mut mmap_reader = MmapReader::new(&mmap, 0, file_size);
+        let entry = TensorEntry::new(1, [32], VectorType::F32);
+        let annoy = Annoy::<Tensor>::vector_deserialize(&mut mmap_reader, &entry).unwrap();
+        assert_eq!(annoy.get_vectors().len(), 300);
+        let neighbors = annoy.query(&Tensor::arr(features[42].clone()), 1).unwrap();
+        assert_eq!(neighbors[0].doc_id(), 42);
+        drop(annoy);
+        std::fs::remove_file("./data.annoy").unwrap();
+    }
+}
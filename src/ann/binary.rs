@@ -0,0 +1,239 @@
+use super::super::util::error::{GyError, GyResult};
+use super::{Metric, MetricType, VectorElems};
+use crate::disk::{GyRead, GyWrite};
+use crate::schema::{ValueSized, VectorOps, VectorType};
+use crate::TensorEntry;
+use crate::VectorSerialize;
+use galois::TensorType;
+use std::io::{Read, Write};
+
+// 按位打包的二值向量, 每个字节保存 8 维, 低位在前
+// 不依赖 Tensor, 用于哈希码等只需要汉明距离的场景
+#[derive(Clone, Debug, PartialEq)]
+pub struct BinaryVector {
+    bits: usize,
+    data: Vec<u8>,
+}
+
+impl BinaryVector {
+    pub fn new(bits: usize) -> BinaryVector {
+        BinaryVector {
+            bits: bits,
+            data: vec![0u8; (bits + 7) / 8],
+        }
+    }
+
+    pub fn from_bytes(data: &[u8], bits: usize) -> GyResult<BinaryVector> {
+        if data.len() * 8 < bits {
+            return Err(GyError::ErrInvalidBinaryVector(bits, data.len()));
+        }
+        Ok(BinaryVector {
+            bits: bits,
+            data: data[..(bits + 7) / 8].to_vec(),
+        })
+    }
+
+    pub fn from_bools(v: &[bool]) -> BinaryVector {
+        let mut b = BinaryVector::new(v.len());
+        v.iter().enumerate().for_each(|(i, x)| b.set(i, *x));
+        b
+    }
+
+    pub fn bits(&self) -> usize {
+        self.bits
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn get(&self, i: usize) -> bool {
+        self.data[i / 8] & (1u8 << (i % 8)) != 0
+    }
+
+    pub fn set(&mut self, i: usize, v: bool) {
+        assert!(i < self.bits);
+        if v {
+            self.data[i / 8] |= 1u8 << (i % 8);
+        } else {
+            self.data[i / 8] &= !(1u8 << (i % 8));
+        }
+    }
+
+    pub fn count_ones(&self) -> u32 {
+        self.data.iter().map(|x| x.count_ones()).sum()
+    }
+
+    // 按 8 字节一组做 popcount
+    fn popcount_with<F: Fn(u64, u64) -> u64>(&self, other: &BinaryVector, f: F) -> u32 {
+        let mut a = self.data.chunks_exact(8);
+        let mut b = other.data.chunks_exact(8);
+        let mut n = 0u32;
+        for (x, y) in (&mut a).zip(&mut b) {
+            let x = u64::from_le_bytes(x.try_into().unwrap());
+            let y = u64::from_le_bytes(y.try_into().unwrap());
+            n += f(x, y).count_ones();
+        }
+        for (x, y) in a.remainder().iter().zip(b.remainder().iter()) {
+            n += f(*x as u64, *y as u64).count_ones();
+        }
+        n
+    }
+
+    pub fn hamming(&self, other: &BinaryVector) -> u32 {
+        self.popcount_with(other, |x, y| x ^ y)
+    }
+
+    fn and_count(&self, other: &BinaryVector) -> u32 {
+        self.popcount_with(other, |x, y| x & y)
+    }
+}
+
+impl Metric for BinaryVector {
+    fn distance(&self, b: &BinaryVector, metric: MetricType) -> f32 {
+        match metric {
+            MetricType::Hamming | MetricType::L1 => self.hamming(b) as f32,
+            MetricType::L2 => (self.hamming(b) as f32).sqrt(),
+            MetricType::InnerProduct => -(self.and_count(b) as f32),
+            MetricType::Cosine => {
+                let norm = ((self.count_ones() * b.count_ones()) as f32).sqrt();
+                if norm == 0.0 {
+                    1.0
+                } else {
+                    1.0 - self.and_count(b) as f32 / norm
+                }
+            }
+        }
+    }
+}
+
+// 每一位展开成 0.0 或 1.0, 用于聚类等需要浮点分量的索引
+impl VectorElems for BinaryVector {
+    fn to_elems(&self) -> Vec<f32> {
+        (0..self.bits)
+            .map(|i| if self.get(i) { 1.0 } else { 0.0 })
+            .collect()
+    }
+
+    fn from_elems(v: Vec<f32>) -> Self {
+        let mut b = BinaryVector::new(v.len());
+        v.iter().enumerate().for_each(|(i, x)| b.set(i, *x > 0.5));
+        b
+    }
+}
+
+impl VectorSerialize for BinaryVector {
+    fn vector_serialize<W: Write + GyWrite>(&self, writer: &mut W) -> GyResult<()> {
+        writer.write_all(&self.data)?;
+        Ok(())
+    }
+
+    fn vector_deserialize<R: Read + GyRead>(reader: &mut R, entry: &TensorEntry) -> GyResult<Self> {
+        let data = reader.read_bytes(entry.nbytes())?;
+        BinaryVector::from_bytes(data, entry.elem_count())
+    }
+}
+
+impl ValueSized for BinaryVector {
+    fn bytes_size(&self) -> usize {
+        self.data.len()
+    }
+}
+
+// 按元素的内存表示逐位解释, 例如 [u64; 4] 为 256 位
+impl VectorOps for BinaryVector {
+    fn from_vec<T: TensorType>(v: Vec<T>) -> Self {
+        let n = v.len() * std::mem::size_of::<T>();
+        let data = unsafe { std::slice::from_raw_parts(v.as_ptr() as *const u8, n) };
+        BinaryVector {
+            bits: n * 8,
+            data: data.to_vec(),
+        }
+    }
+
+    fn from_arr<T: TensorType, const N: usize>(v: [T; N]) -> Self {
+        let n = N * std::mem::size_of::<T>();
+        let data = unsafe { std::slice::from_raw_parts(v.as_ptr() as *const u8, n) };
+        BinaryVector {
+            bits: n * 8,
+            data: data.to_vec(),
+        }
+    }
+
+    fn supports(t: &VectorType) -> bool {
+        *t == VectorType::Binary
+    }
+
+    fn vector_type(&self) -> Option<VectorType> {
+        Some(VectorType::Binary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ann::{AnnIndex, Flat, HNSW};
+    use crate::schema::VectorType;
+    use rand::Rng;
+
+    fn random_codes(count: usize, bits: usize) -> Vec<BinaryVector> {
+        let mut rng = rand::thread_rng();
+        (0..count)
+            .map(|_| {
+                let bytes: Vec<u8> = (0..(bits + 7) / 8).map(|_| rng.gen::<u8>()).collect();
+                BinaryVector::from_bytes(&bytes, bits).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_binary_hamming() {
+        let a = BinaryVector::from_bools(&[true, false, true, true, false]);
+        let b = BinaryVector::from_bools(&[true, true, false, true, false]);
+        assert_eq!(a.hamming(&b), 2);
+        assert_eq!(a.distance(&b, MetricType::Hamming), 2.0);
+        assert_eq!(a.distance(&b, MetricType::InnerProduct), -2.0);
+        let a = random_codes(1, 1000).pop().unwrap();
+        let b = random_codes(1, 1000).pop().unwrap();
+        let naive = (0..1000).filter(|i| a.get(*i) != b.get(*i)).count();
+        assert_eq!(a.hamming(&b) as usize, naive);
+        assert_eq!(BinaryVector::from_elems(a.to_elems()), a);
+        // 字节数不足时返回错误
+        assert!(matches!(
+            BinaryVector::from_bytes(&[0u8; 3], 25),
+            Err(GyError::ErrInvalidBinaryVector(25, 3))
+        ));
+        assert_eq!(
+            BinaryVector::from_bytes(&[0xffu8; 4], 25).unwrap().bits(),
+            25
+        );
+    }
+
+    #[test]
+    fn test_binary_index() {
+        let codes = random_codes(500, 256);
+        let mut flat = Flat::<BinaryVector>::new().with_metric(MetricType::Hamming);
+        let mut hnsw = HNSW::<BinaryVector>::new(16).with_metric(MetricType::Hamming);
+        for c in codes.iter() {
+            flat.insert(c.clone()).unwrap();
+            hnsw.insert(c.clone()).unwrap();
+        }
+        for (i, c) in codes.iter().enumerate().step_by(50) {
+            let n = flat.query(c, 1).unwrap();
+            assert_eq!(n[0].doc_id(), i as u64);
+            assert_eq!(n[0].distance(), 0.0);
+            assert_eq!(hnsw.query(c, 1).unwrap()[0].doc_id(), i as u64);
+        }
+        // 段中保存与加载
+        let mut bytes: Vec<u8> = Vec::new();
+        hnsw.vector_serialize(&mut std::io::Cursor::new(&mut bytes))
+            .unwrap();
+        let entry = TensorEntry::new(1, [256], VectorType::Binary);
+        assert_eq!(entry.nbytes(), 32);
+        let loaded =
+            HNSW::<BinaryVector>::vector_deserialize(&mut std::io::Cursor::new(&bytes), &entry)
+                .unwrap();
+        assert_eq!(loaded.get_vectors()[7], codes[7]);
+        assert_eq!(loaded.query(&codes[7], 1).unwrap()[0].doc_id(), 7);
+    }
+}
@@ -0,0 +1,262 @@
+use super::super::util::error::GyResult;
+use super::sq::{Quantizer, SqRange, VectorStore};
+use super::{AnnIndex, Metric, MetricType, Neighbor, VectorElems};
+use crate::disk::{GyRead, GyWrite};
+use crate::util::bitmap::BitMap;
+use crate::TensorEntry;
+use crate::VectorSerialize;
+use std::collections::BinaryHeap;
+use std::io::{Read, Write};
+
+// 暴力搜索 线性扫描所有向量, 结果精确
+// 适用于小集合, 也作为衡量 HNSW 召回率的基准
+pub struct Flat<V: VectorSerialize + Clone> {
+    vectors: VectorStore<V>,
+}
+
+impl<V: VectorSerialize + Clone> VectorSerialize for Flat<V> {
+    fn vector_deserialize<R: Read + GyRead>(reader: &mut R, entry: &TensorEntry) -> GyResult<Self> {
+        let vectors = VectorStore::<V>::vector_deserialize(reader, entry)?;
+        Ok(Flat { vectors: vectors })
+    }
+
+    fn vector_serialize<W: Write + GyWrite>(&self, writer: &mut W) -> GyResult<()> {
+        self.vectors.vector_serialize(writer)
+    }
+}
+
+impl<V: VectorSerialize + Clone> AnnIndex<V> for Flat<V>
+where
+    V: Metric<V> + VectorElems,
+{
+    fn insert(&mut self, q: V) -> GyResult<usize> {
+        self.vectors.push(q);
+        Ok(self.vectors.len() - 1)
+    }
+
+    fn query(&self, q: &V, k: usize) -> GyResult<Vec<Neighbor>> {
+        self.search(q, k, None)
+    }
+}
+
+impl<V: VectorSerialize + Clone> Flat<V>
+where
+    V: Metric<V> + VectorElems,
+{
+    // allow 不为空时跳过不允许的点
+    pub fn search(&self, q: &V, k: usize, allow: Option<&BitMap>) -> GyResult<Vec<Neighbor>> {
+        if k == 0 {
+            return Ok(Vec::new());
+        }
+        // 大顶堆 堆顶为当前 k 个结果中最远的点
+        let mut results: BinaryHeap<Neighbor> = BinaryHeap::with_capacity(k + 1);
+        let q = self.vectors.prepare(q);
+        for id in 0..self.vectors.len() {
+            if allow.map_or(false, |a| !a.contains(id)) {
+                continue;
+            }
+            let d = self.vectors.distance(&q, id);
+            if results.len() < k {
+                results.push(Neighbor { id: id, d: d });
+            } else if d < results.peek().unwrap().d {
+                results.pop();
+                results.push(Neighbor { id: id, d: d });
+            }
+        }
+        Ok(results.into_sorted_vec())
+    }
+
+    pub fn search_radius(
+        &self,
+        q: &V,
+        radius: f32,
+        limit: Option<usize>,
+    ) -> GyResult<Vec<Neighbor>> {
+        let q = self.vectors.prepare(q);
+        let mut results: Vec<Neighbor> = (0..self.vectors.len())
+            .map(|id| Neighbor {
+                id: id,
+                d: self.vectors.distance(&q, id),
+            })
+            .filter(|n| n.d <= radius)
+            .collect();
+        results.sort_by(|a, b| a.d.partial_cmp(&b.d).unwrap());
+        if let Some(l) = limit {
+            results.truncate(l);
+        }
+        Ok(results)
+    }
+
+    pub fn new() -> Flat<V> {
+        Self::with_quantizer(Quantizer::None)
+    }
+
+    pub fn with_quantizer(quantizer: Quantizer) -> Flat<V> {
+        Self {
+            vectors: VectorStore::new(quantizer),
+        }
+    }
+
+    // SQ8 的取值范围, 见 SqRange
+    pub fn with_sq_range(mut self, range: SqRange) -> Flat<V> {
+        self.vectors.set_range(range);
+        self
+    }
+
+    pub fn with_metric(mut self, metric: MetricType) -> Flat<V> {
+        self.vectors.set_metric(metric);
+        self
+    }
+
+    pub fn metric(&self) -> MetricType {
+        self.vectors.metric()
+    }
+
+    pub fn merge(&self, other: &Self) -> GyResult<Flat<V>> {
+        Ok(Flat {
+            vectors: self.vectors.merge(&other.vectors),
+        })
+    }
+
+    // 开启量化后原始向量不再保留
+    pub fn get_vectors(&self) -> &[V] {
+        self.vectors.raw()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ann::random_vectors;
+    use crate::ann::HNSW;
+
+    #[test]
+    fn test_flat_search() {
+        let mut flat = Flat::<Vec<f32>>::new();
+        let features = [
+            [0.0f32, 0.0, 0.0, 1.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 1.0],
+        ];
+        for f in features.iter() {
+            flat.insert(f.to_vec()).unwrap();
+        }
+        let neighbors = flat.query(&vec![0.0f32, 0.0, 1.0, 0.9], 2).unwrap();
+        assert_eq!(neighbors.len(), 2);
+        assert_eq!(neighbors[0].doc_id(), 4);
+        assert_eq!(neighbors[1].doc_id(), 1);
+    }
+
+    #[test]
+    fn test_flat_merge() {
+        let mut a = Flat::<Vec<f32>>::new();
+        let mut b = Flat::<Vec<f32>>::new();
+        a.insert(vec![0.0f32, 1.0]).unwrap();
+        b.insert(vec![1.0f32, 0.0]).unwrap();
+        let flat = a.merge(&b).unwrap();
+        let neighbors = flat.query(&vec![1.0f32, 0.1], 1).unwrap();
+        assert_eq!(neighbors[0].doc_id(), 1);
+    }
+
+    #[test]
+    fn test_flat_metric() {
+        let features = [[1.0f32, 0.0], [10.0, 10.0], [0.0, 3.0]];
+        let q = vec![2.0f32, 2.1];
+        let mut l2 = Flat::<Vec<f32>>::new();
+        let mut ip = Flat::<Vec<f32>>::new().with_metric(MetricType::InnerProduct);
+        let mut cosine = Flat::<Vec<f32>>::new().with_metric(MetricType::Cosine);
+        for f in features.iter() {
+            l2.insert(f.to_vec()).unwrap();
+            ip.insert(f.to_vec()).unwrap();
+            cosine.insert(f.to_vec()).unwrap();
+        }
+        assert_eq!(l2.query(&q, 1).unwrap()[0].doc_id(), 2);
+        let neighbors = ip.query(&q, 3).unwrap();
+        assert_eq!(neighbors[0].doc_id(), 1);
+        assert_eq!(neighbors[0].score(MetricType::InnerProduct), 41.0);
+        let neighbors = cosine.query(&q, 3).unwrap();
+        assert_eq!(neighbors[0].doc_id(), 1);
+        assert_eq!(neighbors[2].doc_id(), 0);
+    }
+
+    #[test]
+    fn test_hnsw_recall() {
+        let k = 10;
+        let mut flat = Flat::<Vec<f32>>::new();
+        let mut hnsw = HNSW::<Vec<f32>>::new(32).with_ef(200, 100);
+        for v in random_vectors(2000, 32) {
+            flat.insert(v.clone()).unwrap();
+            hnsw.insert(v).unwrap();
+        }
+        let mut hit = 0;
+        let queries = random_vectors(20, 32);
+        for q in queries.iter() {
+            let truth: Vec<u64> = flat
+                .query(q, k)
+                .unwrap()
+                .iter()
+                .map(|n| n.doc_id())
+                .collect();
+            hit += hnsw
+                .query(q, k)
+                .unwrap()
+                .iter()
+                .filter(|n| truth.contains(&n.doc_id()))
+                .count();
+        }
+        // 以 Flat 的精确结果为准, 召回率不低于 0.9
+        let recall = hit as f32 / (k * queries.len()) as f32;
+        assert!(recall >= 0.9, "recall@{}: {}", k, recall);
+    }
+
+    #[test]
+    fn test_radius_search() {
+        let mut flat = Flat::<Vec<f32>>::new();
+        let mut hnsw = HNSW::<Vec<f32>>::new(16).with_ef(100, 8);
+        for v in random_vectors(1000, 4) {
+            flat.insert(v.clone()).unwrap();
+            hnsw.insert(v).unwrap();
+        }
+        let q = vec![0.5f32; 4];
+        let truth = flat.search_radius(&q, 0.3, None).unwrap();
+        assert!(truth.iter().all(|n| n.distance() <= 0.3));
+        // 半径内的点远多于 ef_search, 需要多次扩大候选集
+        assert!(truth.len() > 8);
+        let neighbors = hnsw.search_radius(&q, 0.3, None).unwrap();
+        assert!(neighbors.iter().all(|n| n.distance() <= 0.3));
+        assert!(neighbors.len() as f32 >= truth.len() as f32 * 0.9);
+        let neighbors = hnsw.search_radius(&q, 0.3, Some(5)).unwrap();
+        assert_eq!(neighbors.len(), 5);
+    }
+
+    #[test]
+    fn test_filtered_search() {
+        let k = 5;
+        let mut flat = Flat::<Vec<f32>>::new();
+        let mut hnsw = HNSW::<Vec<f32>>::new(16);
+        for v in random_vectors(1000, 8) {
+            flat.insert(v.clone()).unwrap();
+            hnsw.insert(v).unwrap();
+        }
+        // 只允许编号为 3 的倍数的点
+        let mut allow = BitMap::new(1000);
+        (0..1000).step_by(3).for_each(|i| allow.insert(i));
+        let mut hit = 0;
+        let queries = random_vectors(20, 8);
+        for q in queries.iter() {
+            let truth = flat.search(q, k, Some(&allow)).unwrap();
+            assert_eq!(truth.len(), k);
+            assert!(truth.iter().all(|n| n.doc_id() % 3 == 0));
+            let neighbors = hnsw.search_filtered(q, k, 64, Some(&allow)).unwrap();
+            assert_eq!(neighbors.len(), k);
+            assert!(neighbors.iter().all(|n| n.doc_id() % 3 == 0));
+            hit += neighbors
+                .iter()
+                .filter(|n| truth.iter().any(|t| t.doc_id() == n.doc_id()))
+                .count();
+        }
+        assert!(hit as f32 / (k * queries.len()) as f32 > 0.8);
+    }
+}
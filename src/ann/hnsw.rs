@@ -1,36 +1,201 @@
 use super::super::util::error::GyResult;
-use super::{AnnIndex, Metric, Neighbor};
-use crate::disk::{GyRead, GyWrite};
+use super::sq::{Quantizer, QueryVec, SqRange, VectorStore};
+use super::{read_align, write_align, AnnIndex, Metric, MetricType, Neighbor, VectorElems};
+use crate::disk::{GyRead, GyWrite, SharedBytes};
 use crate::schema::BinarySerialize;
+use crate::util::bitmap::BitMap;
 use crate::TensorEntry;
 use crate::VectorSerialize;
 use rand::prelude::ThreadRng;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 use std::collections::BinaryHeap;
 use std::collections::HashSet;
 
-impl Metric<Vec<f32>> for Vec<f32> {
-    fn distance(&self, b: &Vec<f32>) -> f32 {
-        self.iter()
-            .zip(b.iter())
-            .map(|(&a1, &b1)| (a1 - b1).powi(2))
-            .sum::<f32>()
-            .sqrt()
+pub(crate) const DEFAULT_M: usize = 32;
+pub(crate) const DEFAULT_EF_CONSTRUCTION: usize = 400;
+pub(crate) const DEFAULT_EF_SEARCH: usize = 64;
+
+// HNSW 建图与查询参数
+// m 越大图越稠密, ef_construction 越大建图越慢质量越高, ef_search 为查询时的候选集大小
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct HnswConfig {
+    pub m: usize,
+    pub ef_construction: usize,
+    pub ef_search: usize,
+}
+
+impl Default for HnswConfig {
+    fn default() -> Self {
+        HnswConfig {
+            m: DEFAULT_M,
+            ef_construction: DEFAULT_EF_CONSTRUCTION,
+            ef_search: DEFAULT_EF_SEARCH,
+        }
     }
 }
 
-const GGUF_DEFAULT_ALIGNMENT: usize = 32;
+impl Metric<Vec<f32>> for Vec<f32> {
+    fn distance(&self, b: &Vec<f32>, metric: MetricType) -> f32 {
+        metric.distance(self, b)
+    }
+}
 
 #[derive(Default)]
 struct Node {
     level: usize,
-    neighbors: Vec<Vec<usize>>, //layer --> vec
+    neighbors: Vec<Vec<usize>>, //layer --> vec, 共 level + 1 层
+}
+
+// 段文件中的邻接表, 持有段文件的 mmap, 打开段时不需要重新建图
+// 布局 (小端):
+// offsets: [u64; node_len + 1] 每个节点记录相对于邻接表起始位置的偏移
+// node:    level: u32, layers: u32, 每层 count: u32 + [u32; count]
+struct MappedGraph {
+    node_len: usize,
+    data: SharedBytes,
+}
+
+impl MappedGraph {
+    fn u32_at(&self, pos: usize) -> usize {
+        u32::from_le_bytes(self.data[pos..pos + 4].try_into().unwrap()) as usize
+    }
+
+    fn node_start(&self, n: usize) -> usize {
+        let pos = n * 8;
+        u64::from_le_bytes(self.data[pos..pos + 8].try_into().unwrap()) as usize
+    }
+
+    fn level(&self, n: usize) -> usize {
+        self.u32_at(self.node_start(n))
+    }
+
+    fn layers(&self, n: usize) -> usize {
+        self.u32_at(self.node_start(n) + 4)
+    }
+
+    fn neighbors(&self, n: usize, level: usize) -> Option<&[u8]> {
+        let start = self.node_start(n);
+        if level >= self.u32_at(start + 4) {
+            return None;
+        }
+        let mut pos = start + 8;
+        for _ in 0..level {
+            pos += 4 + self.u32_at(pos) * 4;
+        }
+        let count = self.u32_at(pos);
+        Some(&self.data[pos + 4..pos + 4 + count * 4])
+    }
+
+    fn to_nodes(&self) -> Vec<Node> {
+        (0..self.node_len)
+            .map(|n| Node {
+                level: self.level(n),
+                neighbors: (0..self.layers(n))
+                    .map(|l| {
+                        NeighborIter::Mapped(self.neighbors(n, l).unwrap().chunks_exact(4))
+                            .collect()
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+enum NeighborIter<'a> {
+    Owned(std::slice::Iter<'a, usize>),
+    Mapped(std::slice::ChunksExact<'a, u8>),
 }
 
-impl Node {
-    fn get_neighbors(&self, level: usize) -> Option<impl Iterator<Item = usize> + '_> {
-        let x = self.neighbors.get(level)?;
-        Some(x.iter().cloned())
+impl<'a> Iterator for NeighborIter<'a> {
+    type Item = usize;
+    fn next(&mut self) -> Option<usize> {
+        match self {
+            NeighborIter::Owned(it) => it.next().cloned(),
+            NeighborIter::Mapped(it) => it
+                .next()
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize),
+        }
+    }
+}
+
+// 内存中新建的图为 Owned, 从段文件加载的图为 Mapped
+// Mapped 图第一次被修改时拷贝成 Owned
+enum Graph {
+    Owned(Vec<Node>),
+    Mapped(MappedGraph),
+}
+
+impl Graph {
+    fn len(&self) -> usize {
+        match self {
+            Graph::Owned(nodes) => nodes.len(),
+            Graph::Mapped(g) => g.node_len,
+        }
+    }
+
+    fn level(&self, n: usize) -> usize {
+        match self {
+            Graph::Owned(nodes) => nodes[n].level,
+            Graph::Mapped(g) => g.level(n),
+        }
+    }
+
+    fn layers(&self, n: usize) -> usize {
+        match self {
+            Graph::Owned(nodes) => nodes[n].neighbors.len(),
+            Graph::Mapped(g) => g.layers(n),
+        }
+    }
+
+    fn neighbors(&self, n: usize, level: usize) -> Option<NeighborIter<'_>> {
+        match self {
+            Graph::Owned(nodes) => Some(NeighborIter::Owned(
+                nodes.get(n)?.neighbors.get(level)?.iter(),
+            )),
+            Graph::Mapped(g) => Some(NeighborIter::Mapped(g.neighbors(n, level)?.chunks_exact(4))),
+        }
+    }
+
+    fn nodes_mut(&mut self) -> &mut Vec<Node> {
+        if let Graph::Mapped(g) = self {
+            *self = Graph::Owned(g.to_nodes());
+        }
+        match self {
+            Graph::Owned(nodes) => nodes,
+            Graph::Mapped(_) => unreachable!(),
+        }
+    }
+
+    fn node_size(&self, n: usize) -> usize {
+        8 + (0..self.layers(n))
+            .map(|l| 4 + self.neighbors(n, l).unwrap().count() * 4)
+            .sum::<usize>()
+    }
+
+    fn write_to<W: std::io::Write>(&self, writer: &mut W) -> GyResult<()> {
+        let mut offset = (self.len() + 1) * 8;
+        for n in 0..self.len() {
+            writer.write_all(&(offset as u64).to_le_bytes())?;
+            offset += self.node_size(n);
+        }
+        writer.write_all(&(offset as u64).to_le_bytes())?;
+        for n in 0..self.len() {
+            writer.write_all(&(self.level(n) as u32).to_le_bytes())?;
+            writer.write_all(&(self.layers(n) as u32).to_le_bytes())?;
+            for l in 0..self.layers(n) {
+                let ids: Vec<usize> = self.neighbors(n, l).unwrap().collect();
+                writer.write_all(&(ids.len() as u32).to_le_bytes())?;
+                for id in ids {
+                    writer.write_all(&(id as u32).to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn bytes_len(&self) -> usize {
+        (self.len() + 1) * 8 + (0..self.len()).map(|n| self.node_size(n)).sum::<usize>()
     }
 }
 
@@ -39,13 +204,14 @@ pub struct HNSW<V: VectorSerialize + Clone> {
     enter_point: usize,
     max_layer: usize,
     ef_construction: usize,
+    ef_search: usize,
     M: usize,
     M0: usize,
     n_items: usize,
     rng: ThreadRng,
     level_mut: f64,
-    nodes: Vec<Node>,
-    vectors: Vec<V>,
+    nodes: Graph,
+    vectors: VectorStore<V>,
     current_id: usize,
 }
 
@@ -57,42 +223,31 @@ impl<V: VectorSerialize + Clone> VectorSerialize for HNSW<V> {
         let M = usize::binary_deserialize(reader)?;
         let M0 = usize::binary_deserialize(reader)?;
         let ef_construction = usize::binary_deserialize(reader)?;
+        let ef_search = usize::binary_deserialize(reader)?;
         let level_mut = f64::binary_deserialize(reader)?;
         let max_layer = usize::binary_deserialize(reader)?;
         let enter_point = usize::binary_deserialize(reader)?;
         let node_len = usize::binary_deserialize(reader)?;
-        let mut nodes: Vec<Node> = Vec::with_capacity(node_len);
-        for _ in 0..node_len {
-            // let p = V::vector_deserialize(reader, entry)?;
-            let level = usize::binary_deserialize(reader)?;
-            let neighbors: Vec<Vec<usize>> = Vec::<Vec<usize>>::binary_deserialize(reader)?;
-            nodes.push(Node {
-                level: level,
-                neighbors: neighbors,
-                //  p: p,
-            });
-        }
-        let position = reader.offset();
-        let next_position = position - (position % GGUF_DEFAULT_ALIGNMENT) + GGUF_DEFAULT_ALIGNMENT;
-        println!("position:{},next_position:{}", position, next_position);
-        reader.read_bytes(next_position - position)?;
-        let mut vectors: Vec<V> = Vec::with_capacity(node_len);
-        for _ in 0..node_len {
-            let p = V::vector_deserialize(reader, entry)?;
-            vectors.push(p)
-        }
+        let graph_len = usize::binary_deserialize(reader)?;
+        read_align(reader)?;
+        let data = reader.read_shared(graph_len)?;
+        let vectors = VectorStore::<V>::vector_deserialize(reader, entry)?;
         Ok(HNSW {
             enter_point: enter_point,
             max_layer: max_layer,
             ef_construction: ef_construction,
+            ef_search: ef_search,
             M: M,
             M0: M0,
-            n_items: 0,
+            n_items: node_len,
             rng: rand::thread_rng(),
             level_mut: level_mut,
-            nodes: nodes,
+            nodes: Graph::Mapped(MappedGraph {
+                node_len: node_len,
+                data: data,
+            }),
             vectors: vectors,
-            current_id: 0,
+            current_id: node_len,
         })
     }
 
@@ -100,113 +255,161 @@ impl<V: VectorSerialize + Clone> VectorSerialize for HNSW<V> {
         self.M.binary_serialize(writer)?;
         self.M0.binary_serialize(writer)?;
         self.ef_construction.binary_serialize(writer)?;
+        self.ef_search.binary_serialize(writer)?;
         self.level_mut.binary_serialize(writer)?;
         self.max_layer.binary_serialize(writer)?;
         self.enter_point.binary_serialize(writer)?;
         self.nodes.len().binary_serialize(writer)?;
-        for n in self.nodes.iter() {
-            // n.p.vector_serialize(writer)?;
-            n.level.binary_serialize(writer)?;
-            n.neighbors.binary_serialize(writer)?;
-        }
-        let position = writer.get_pos()?;
-        let next_position = position - (position % GGUF_DEFAULT_ALIGNMENT) + GGUF_DEFAULT_ALIGNMENT;
-        println!("position:{},next_position:{}", position, next_position);
-        writer.write(&vec![0u8; next_position - position])?;
-        //self.vectors.len().binary_serialize(writer)?;
-        for v in self.vectors.iter() {
-            v.vector_serialize(writer)?;
-        }
-        Ok(())
+        self.nodes.bytes_len().binary_serialize(writer)?;
+        write_align(writer)?;
+        self.nodes.write_to(writer)?;
+        self.vectors.vector_serialize(writer)
     }
 }
 
 impl<V: VectorSerialize + Clone> AnnIndex<V> for HNSW<V>
 where
-    V: Metric<V>,
+    V: Metric<V> + VectorElems,
 {
     //插入
     fn insert(&mut self, q: V) -> GyResult<usize> {
         let cur_level: usize = self.get_random_level();
-        if self.current_id == 0 {
-            let new_node = Node {
-                level: cur_level,
-                neighbors: vec![Vec::new(); cur_level],
-                // p: q,
-            };
-            self.nodes.push(new_node);
-            self.vectors.push(q);
-            self.enter_point = self.current_id;
-            self.current_id += 1;
-            return Ok(self.enter_point);
-        } else {
-            let ep_id = self.enter_point;
-            let current_max_layer = self.get_node(ep_id).level;
-            let new_id = self.current_id;
-            self.current_id += 1;
-
-            //起始点
-            let mut ep = Neighbor {
-                id: self.enter_point,
-                d: self.get_vector(ep_id).distance(&q),
-            };
+        let new_id = self.current_id;
+        self.current_id += 1;
+        self.n_items += 1;
+        let new_node = Node {
+            level: cur_level,
+            neighbors: vec![Vec::new(); cur_level + 1],
+        };
+        self.nodes.nodes_mut().push(new_node);
+        // 先写入向量, 量化后用编码之间的距离建图
+        self.vectors.push(q);
+        if new_id == 0 {
+            self.enter_point = new_id;
+            self.max_layer = cur_level;
+            return Ok(new_id);
+        }
+        self.link(new_id, cur_level);
+        Ok(new_id)
+    }
+
+    fn query(&self, q: &V, K: usize) -> GyResult<Vec<Neighbor>> {
+        self.search(q, K, self.ef_search)
+    }
+}
+
+impl<V: VectorSerialize + Clone> HNSW<V>
+where
+    V: Metric<V> + VectorElems,
+{
+    // 把已经写入节点和向量的 new_id 连接到图中, 要求图中已有入口点
+    fn link(&mut self, new_id: usize, cur_level: usize) {
+        let ep_id = self.enter_point;
+        let current_max_layer = self.max_layer;
+        //起始点
+        let mut ep = Neighbor {
+            id: ep_id,
+            d: self.vectors.distance_between(ep_id, new_id),
+        };
+        //那么从当前图的从最高层逐层往下寻找直至节点的层数+1停止，寻找到离data_point最近的节点，作为下面一层寻找的起始点
+        for level in (cur_level + 1..=current_max_layer).rev() {
             let mut changed = true;
-            //那么从当前图的从最高层逐层往下寻找直至节点的层数+1停止，寻找到离data_point最近的节点，作为下面一层寻找的起始点
-            for level in (cur_level..current_max_layer).rev() {
-                changed = true;
-                while changed {
-                    changed = false;
-                    for i in self.get_neighbors_nodes(ep.id, level).unwrap() {
-                        let d = self.get_vector(ep_id).distance(&q); //distance(self.get_node(ep_id).p.borrow(), &q);
-                        if d < ep.d {
-                            ep.id = i;
-                            ep.d = d;
-                            changed = true;
-                        }
+            while changed {
+                changed = false;
+                for i in self.get_neighbors_nodes(ep.id, level).unwrap() {
+                    let d = self.vectors.distance_between(i, new_id);
+                    if d < ep.d {
+                        ep.id = i;
+                        ep.d = d;
+                        changed = true;
                     }
                 }
             }
+        }
 
-            let new_node = Node {
-                level: cur_level,
-                neighbors: vec![Vec::new(); cur_level],
-                //p: q,
+        //从curlevel依次开始往下，每一层寻找离data_point最接近的ef_construction_（构建HNSW是可指定）个节点构成候选集
+        for level in (0..=core::cmp::min(cur_level, current_max_layer)).rev() {
+            //在每层选择data_point最接近的ef_construction_（构建HNSW是可指定）个节点构成候选集
+            let candidates = {
+                let q = self.vectors.prepare_id(new_id);
+                self.search_at_layer(&q, ep, level, self.ef_construction, None)
             };
-            self.nodes.push(new_node);
-            self.vectors.push(q);
-            //从curlevel依次开始往下，每一层寻找离data_point最接近的ef_construction_（构建HNSW是可指定）个节点构成候选集
-            for level in (0..core::cmp::min(cur_level, current_max_layer)).rev() {
-                //在每层选择data_point最接近的ef_construction_（构建HNSW是可指定）个节点构成候选集
-                let candidates = self.search_at_layer(self.get_vector(new_id), ep, level);
-                //连接邻居?
-                self.connect_neighbor(new_id, candidates, level);
+            // 下一层从本层最近的点开始
+            if let Some(nearest) = candidates
+                .iter()
+                .min_by(|a, b| a.d.partial_cmp(&b.d).unwrap())
+            {
+                ep = *nearest;
             }
-            self.n_items += 1;
+            //连接邻居
+            self.connect_neighbor(new_id, candidates, level);
+        }
 
-            if cur_level > self.max_layer {
-                self.max_layer = cur_level;
-                self.enter_point = new_id;
-            }
-            return Ok(new_id);
+        if cur_level > self.max_layer {
+            self.max_layer = cur_level;
+            self.enter_point = new_id;
         }
+    }
 
-        //  new_id
+    // ef 为第 0 层的候选集大小, 小于 K 时按 K 处理
+    pub fn search(&self, q: &V, K: usize, ef: usize) -> GyResult<Vec<Neighbor>> {
+        self.search_filtered(q, K, ef, None)
     }
 
-    fn query(&self, q: &V, K: usize) -> GyResult<Vec<Neighbor>> {
+    // 只返回 allow 中的点, 上层的贪心下降不受过滤影响
+    pub fn search_filtered(
+        &self,
+        q: &V,
+        K: usize,
+        ef: usize,
+        allow: Option<&BitMap>,
+    ) -> GyResult<Vec<Neighbor>> {
+        if self.vectors.len() == 0 {
+            return Ok(Vec::new());
+        }
+        let q = self.vectors.prepare(q);
+        let ep = self.descend(&q);
+        let mut x = self.search_at_layer(&q, ep, 0, ef.max(K), allow);
+        while x.len() > K {
+            x.pop();
+        }
+        Ok(x.into_sorted_vec())
+    }
+
+    // 半径查询, 候选集中最远的点仍在半径内时把 ef 翻倍继续扩大候选集
+    pub fn search_radius(
+        &self,
+        q: &V,
+        radius: f32,
+        limit: Option<usize>,
+    ) -> GyResult<Vec<Neighbor>> {
+        if self.vectors.len() == 0 {
+            return Ok(Vec::new());
+        }
+        let q = self.vectors.prepare(q);
+        let ep = self.descend(&q);
+        super::expand_radius(
+            |ef| Ok(self.search_at_layer(&q, ep, 0, ef, None).into_sorted_vec()),
+            self.ef_search,
+            radius,
+            limit,
+        )
+    }
+
+    // 从最高层贪心下降到第 1 层, 返回第 0 层的入口点
+    fn descend(&self, q: &QueryVec<V>) -> Neighbor {
         let current_max_layer = self.max_layer;
         let mut ep = Neighbor {
             id: self.enter_point,
-            d: self.get_vector(self.enter_point).distance(&q), //distance(self.get_node(self.enter_point).p.borrow(), &q),
+            d: self.vectors.distance(q, self.enter_point),
         };
-        let mut changed = true;
-        for level in (0..current_max_layer).rev() {
-            changed = true;
+        for level in (1..=current_max_layer).rev() {
+            let mut changed = true;
             while changed {
                 changed = false;
                 if let Some(x) = self.get_neighbors_nodes(ep.id, level) {
                     for i in x {
-                        let d = self.get_vector(self.enter_point).distance(&q); // distance(self.get_node(self.enter_point).p.borrow(), &q);
+                        let d = self.vectors.distance(q, i);
                         if d < ep.d {
                             ep.id = i;
                             ep.d = d;
@@ -216,48 +419,111 @@ where
                 }
             }
         }
-        let mut x = self.search_at_layer(&q, ep, 0);
-        while x.len() > K {
-            x.pop();
-        }
-        Ok(x.into_sorted_vec())
+        ep
     }
-}
 
-impl<V: VectorSerialize + Clone> HNSW<V>
-where
-    V: Metric<V>,
-{
+    // 保留节点较多一侧的图, 只把较少一侧的节点插入进来
+    // 合并后 self 的编号不变, other 的编号加上 self 的节点数, 与倒排表中 doc id 加上 a.doc_size() 一致
     pub fn merge(&self, other: &Self) -> GyResult<HNSW<V>> {
-        let mut new_hnsw = HNSW::<V>::new(self.M);
-        for n in self.vectors.iter() {
-            new_hnsw.insert(n.clone())?;
+        let mut new_hnsw = HNSW::<V>::with_quantizer(self.M, self.vectors.quantizer())
+            .with_ef(self.ef_construction, self.ef_search)
+            .with_metric(self.metric());
+        let offset = self.nodes.len();
+        let total = offset + other.nodes.len();
+        if total == 0 {
+            return Ok(new_hnsw);
         }
-        for n in other.vectors.iter() {
-            new_hnsw.insert(n.clone())?;
+        new_hnsw.vectors = self.vectors.merge(&other.vectors);
+        let (big, big_offset, small, small_offset) = if self.nodes.len() >= other.nodes.len() {
+            (self, 0, other, offset)
+        } else {
+            (other, offset, self, 0)
+        };
+        // 较大一侧的邻接表整体平移编号, 较小一侧先占位, 之后逐个连接
+        let mut nodes: Vec<Node> = Vec::with_capacity(total);
+        for (g, g_offset) in [(&self.nodes, 0), (&other.nodes, offset)] {
+            for n in 0..g.len() {
+                let level = g.level(n);
+                let neighbors = if g_offset == big_offset {
+                    (0..g.layers(n))
+                        .map(|l| g.neighbors(n, l).unwrap().map(|x| x + g_offset).collect())
+                        .collect()
+                } else {
+                    vec![Vec::new(); level + 1]
+                };
+                nodes.push(Node {
+                    level: level,
+                    neighbors: neighbors,
+                });
+            }
+        }
+        new_hnsw.nodes = Graph::Owned(nodes);
+        new_hnsw.enter_point = big.enter_point + big_offset;
+        new_hnsw.max_layer = big.max_layer;
+        new_hnsw.current_id = total;
+        new_hnsw.n_items = total;
+        for n in 0..small.nodes.len() {
+            new_hnsw.link(n + small_offset, small.nodes.level(n));
         }
         Ok(new_hnsw)
     }
 
     pub fn new(M: usize) -> HNSW<V> {
+        Self::with_quantizer(M, Quantizer::None)
+    }
+
+    pub fn with_config(config: &HnswConfig, quantizer: Quantizer) -> HNSW<V> {
+        Self::with_quantizer(config.m, quantizer).with_ef(config.ef_construction, config.ef_search)
+    }
+
+    pub fn with_ef(mut self, ef_construction: usize, ef_search: usize) -> HNSW<V> {
+        self.ef_construction = ef_construction.max(1);
+        self.ef_search = ef_search.max(1);
+        self
+    }
+
+    pub fn ef_search(&self) -> usize {
+        self.ef_search
+    }
+
+    pub fn with_metric(mut self, metric: MetricType) -> HNSW<V> {
+        self.vectors.set_metric(metric);
+        self
+    }
+
+    pub fn metric(&self) -> MetricType {
+        self.vectors.metric()
+    }
+
+    // SQ8 的取值范围, 见 SqRange
+    pub fn with_sq_range(mut self, range: SqRange) -> HNSW<V> {
+        self.vectors.set_range(range);
+        self
+    }
+
+    pub fn with_quantizer(M: usize, quantizer: Quantizer) -> HNSW<V> {
         Self {
             enter_point: 0,
             max_layer: 0,
-            ef_construction: 400,
+            ef_construction: DEFAULT_EF_CONSTRUCTION,
+            ef_search: DEFAULT_EF_SEARCH,
             rng: rand::thread_rng(),
             level_mut: 1f64 / ((M as f64).ln()),
-            nodes: Vec::with_capacity(10000),
-            vectors: Vec::with_capacity(10000),
+            nodes: Graph::Owned(Vec::with_capacity(10000)),
+            vectors: VectorStore::new(quantizer),
             M: M,
             M0: M * 2,
             current_id: 0,
-            n_items: 1,
+            n_items: 0,
         }
     }
 
     fn print(&self) {
-        for (i, x) in self.nodes.iter().enumerate() {
-            println!("level:{:?},{:?}", x.level, x.neighbors);
+        for i in 0..self.nodes.len() {
+            let neighbors: Vec<Vec<usize>> = (0..self.nodes.layers(i))
+                .map(|l| self.nodes.neighbors(i, l).unwrap().collect())
+                .collect();
+            println!("level:{:?},{:?}", self.nodes.level(i), neighbors);
         }
     }
 
@@ -266,20 +532,16 @@ where
         ((-(x * self.level_mut).ln()).floor()) as usize
     }
 
-    fn get_vector(&self, x: usize) -> &V {
-        self.vectors.get(x).expect("get vector fail")
-    }
-
-    pub fn get_vectors(&self) -> &Vec<V> {
-        &self.vectors
-    }
-
-    fn get_node(&self, x: usize) -> &Node {
-        self.nodes.get(x).expect("get node fail")
+    // 开启量化后原始向量不再保留
+    pub fn get_vectors(&self) -> &[V] {
+        self.vectors.raw()
     }
 
     fn get_node_mut(&mut self, x: usize) -> &mut Node {
-        self.nodes.get_mut(x).expect("get mut node fail")
+        self.nodes
+            .nodes_mut()
+            .get_mut(x)
+            .expect("get mut node fail")
     }
 
     //连接邻居
@@ -309,13 +571,12 @@ where
             if l > maxl {
                 let mut result_set: BinaryHeap<Neighbor> = BinaryHeap::with_capacity(maxl);
 
-                let p = self.get_vector(n.id);
                 self.get_neighbors_nodes(n.id, level)
                     .unwrap()
                     .for_each(|x| {
                         result_set.push(Neighbor {
                             id: x,
-                            d: -p.distance(self.get_vector(x)), //distance(p, self.get_node(x).p.borrow()),
+                            d: -self.vectors.distance_between(n.id, x),
                         });
                     });
 
@@ -335,14 +596,22 @@ where
         n: usize,
         level: usize,
     ) -> Option<impl Iterator<Item = usize> + '_> {
-        self.get_node(n).get_neighbors(level)
+        self.nodes.neighbors(n, level)
     }
 
     // 返回 result 从远到近
-    fn search_at_layer(&self, q: &V, ep: Neighbor, level: usize) -> BinaryHeap<Neighbor> {
+    // allow 不为空时只把允许的点放入 result, 不允许的点仍然作为路径继续遍历
+    fn search_at_layer(
+        &self,
+        q: &QueryVec<V>,
+        ep: Neighbor,
+        level: usize,
+        ef: usize,
+        allow: Option<&BitMap>,
+    ) -> BinaryHeap<Neighbor> {
+        let allowed = |id: usize| allow.map_or(true, |a| a.contains(id));
         let mut visited_set: HashSet<usize> = HashSet::new();
-        let mut candidates: BinaryHeap<Neighbor> =
-            BinaryHeap::with_capacity(self.ef_construction * 3);
+        let mut candidates: BinaryHeap<Neighbor> = BinaryHeap::with_capacity(ef * 3);
         let mut results: BinaryHeap<Neighbor> = BinaryHeap::new();
 
         candidates.push(Neighbor {
@@ -350,17 +619,21 @@ where
             d: -ep.d,
         });
         visited_set.insert(ep.id);
-        results.push(ep);
+        if allowed(ep.id) {
+            results.push(ep);
+        }
 
         // 从candidates中选择距离查询点最近的点c
         while let Some(c) = candidates.pop() {
-            let d = results.peek().unwrap();
             // 从candidates中选择距离查询点最近的点c，和results中距离查询点最远的点d进行比较，
             // 如果c和查询点q的距离大于d和查询点q的距离，则结束查询
-            if -c.d > d.d {
-                break;
+            // 过滤时 results 未满说明允许的点还不够, 继续遍历
+            if let Some(d) = results.peek() {
+                if -c.d > d.d && (allow.is_none() || results.len() >= ef) {
+                    break;
+                }
             }
-            if self.get_node(c.id).neighbors.len() < level + 1 {
+            if self.nodes.layers(c.id) < level + 1 {
                 continue;
             }
             // 查询c的所有邻居e，如果e已经在visitedset中存在则跳过，不存在则加入visitedset
@@ -376,18 +649,17 @@ where
                     }
                     //不存在则加入visitedset
                     visited_set.insert(n);
-                    let dist = q.distance(self.get_vector(n)); //   distance(q, self.nodes.get(n).unwrap().p.borrow());
-                    let top_d = results.peek().unwrap();
+                    let dist = self.vectors.distance(q, n);
                     //如果results未满，则把所有的e都加入candidates、results
-
-                    if results.len() < self.ef_construction {
-                        results.push(Neighbor { id: n, d: dist });
-                        candidates.push(Neighbor { id: n, d: -dist });
-                    } else if dist < top_d.d {
-                        // 如果results已满，则弹出和q距离最远的点
-                        results.pop();
-                        results.push(Neighbor { id: n, d: dist });
+                    if results.len() < ef || dist < results.peek().unwrap().d {
                         candidates.push(Neighbor { id: n, d: -dist });
+                        if allowed(n) {
+                            results.push(Neighbor { id: n, d: dist });
+                            // 如果results已满，则弹出和q距离最远的点
+                            if results.len() > ef {
+                                results.pop();
+                            }
+                        }
                     }
                 });
         }
@@ -410,7 +682,7 @@ where
             //如果e和q的距离比e和R中的其中一个元素的距离更小，就把e加入到result中
             if result
                 .iter()
-                .map(|r| self.get_vector(r.id).distance(self.get_vector(e.id))) //distance(self.nodes[r.id].p.borrow(), &self.nodes[e.id].p)
+                .map(|r| self.vectors.distance_between(r.id, e.id))
                 .any(|x| dist < x)
             {
                 result.push(e);
@@ -460,7 +732,7 @@ where
             //如果e和q的距离比e和R中的其中一个元素的距离更小，就把e加入到result中
             if result
                 .iter()
-                .map(|r| self.get_vector(r.id).distance(self.get_vector(e.id))) //distance(self.nodes[r.id].p.borrow(), &self.nodes[e.id].p)
+                .map(|r| self.vectors.distance_between(r.id, e.id))
                 .any(|x| dist < x)
             {
                 result.push(e);
@@ -493,6 +765,7 @@ mod tests {
     use galois::Tensor;
     use rand::{thread_rng, Rng};
     use std::fs::File;
+    use std::sync::Arc;
     use std::{collections::HashMap, io::Write};
     #[test]
     fn test_rng() {
@@ -667,7 +940,7 @@ mod tests {
         let entry = TensorEntry::new(1, [133], VectorType::F32);
         let hnsw = HNSW::<Tensor>::vector_deserialize(&mut mmap_reader, &entry).unwrap();
         hnsw.print();
-        for (i, v) in hnsw.vectors.iter().enumerate() {
+        for (i, v) in hnsw.get_vectors().iter().enumerate() {
             let s = unsafe { v.as_slice::<f32>() };
             assert!(arrays[i] == s)
         }
@@ -679,6 +952,151 @@ mod tests {
         // );
         // println!("xxx{:?}", neighbors);
     }
+    #[test]
+    fn test_hnsw_sq8() {
+        let mut hnsw = HNSW::<Vec<f32>>::with_quantizer(32, Quantizer::SQ8);
+        let mut rng = rand::thread_rng();
+        let features: Vec<Vec<f32>> = (0..2000)
+            .map(|_| (0..32).map(|_| rng.gen::<f32>()).collect())
+            .collect();
+        for f in features.iter() {
+            hnsw.insert(f.clone()).unwrap();
+        }
+        assert!(hnsw.vectors.is_quantized());
+        assert!(hnsw.get_vectors().is_empty());
+        let neighbors = hnsw.query(&features[1500], 10).unwrap();
+        assert!(neighbors.iter().any(|n| n.doc_id() == 1500));
+    }
+
+    #[test]
+    fn test_hnsw_config() {
+        let config = HnswConfig {
+            m: 8,
+            ef_construction: 100,
+            ef_search: 20,
+        };
+        let mut hnsw = HNSW::<Vec<f32>>::with_config(&config, Quantizer::None);
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            hnsw.insert((0..8).map(|_| rng.gen::<f32>()).collect())
+                .unwrap();
+        }
+        let mut bytes: Vec<u8> = Vec::new();
+        hnsw.vector_serialize(&mut std::io::Cursor::new(&mut bytes))
+            .unwrap();
+        let entry = TensorEntry::new(1, [8], VectorType::F32);
+        let hnsw = HNSW::<Vec<f32>>::vector_deserialize(&mut std::io::Cursor::new(&bytes), &entry)
+            .unwrap();
+        assert_eq!(hnsw.M, 8);
+        assert_eq!(hnsw.ef_construction, 100);
+        assert_eq!(hnsw.ef_search(), 20);
+    }
+
+    #[test]
+    fn test_hnsw_mapped_graph() {
+        let mut hnsw = HNSW::<Vec<f32>>::new(8);
+        let mut rng = rand::thread_rng();
+        let features: Vec<Vec<f32>> = (0..300)
+            .map(|_| (0..8).map(|_| rng.gen::<f32>()).collect())
+            .collect();
+        for f in features.iter() {
+            hnsw.insert(f.clone()).unwrap();
+        }
+        let mut file = File::create("./data.mapped.hnsw").unwrap();
+        hnsw.vector_serialize(&mut file).unwrap();
+        file.flush().unwrap();
+
+        let file = GyFile::open("./data.mapped.hnsw").unwrap();
+        let file_size = file.fsize().unwrap();
+        let mmap = Arc::new(unsafe { memmap2::MmapOptions::new().map(file.file()).unwrap() });
+        let mut mmap_reader = MmapReader::shared(&mmap, 0, file_size);
+        let entry = TensorEntry::new(1, [8], VectorType::F32);
+        let mapped = HNSW::<Vec<f32>>::vector_deserialize(&mut mmap_reader, &entry).unwrap();
+        // 邻接表持有 mmap, 外部的引用释放后仍然可以查询
+        drop(mmap);
+        drop(file);
+        assert!(matches!(mapped.nodes, Graph::Mapped(_)));
+        for (i, f) in features.iter().enumerate().take(50) {
+            let neighbors = mapped.query(f, 1).unwrap();
+            assert_eq!(neighbors[0].doc_id(), i as u64);
+        }
+        drop(mapped);
+        std::fs::remove_file("./data.mapped.hnsw").unwrap();
+    }
+
+    #[test]
+    fn test_hnsw_merge() {
+        let mut rng = rand::thread_rng();
+        let points: Vec<Vec<f32>> = (0..600)
+            .map(|_| (0..8).map(|_| rng.gen::<f32>()).collect())
+            .collect();
+        let mut a = HNSW::<Vec<f32>>::new(8).with_ef(100, 50);
+        let mut b = HNSW::<Vec<f32>>::new(8).with_ef(100, 50);
+        for p in points[..100].iter() {
+            a.insert(p.clone()).unwrap();
+        }
+        for p in points[100..].iter() {
+            b.insert(p.clone()).unwrap();
+        }
+        let merged = a.merge(&b).unwrap();
+        assert_eq!(merged.nodes.len(), 600);
+        // b 的图被保留, 编号整体加上 a 的节点数
+        if a.max_layer <= b.max_layer {
+            assert_eq!(merged.enter_point, b.enter_point + 100);
+            assert_eq!(merged.max_layer, b.max_layer);
+        }
+        for (i, p) in points.iter().enumerate() {
+            assert_eq!(merged.get_vectors()[i], *p);
+        }
+        let mut hit = 0;
+        for (i, p) in points.iter().enumerate().step_by(10) {
+            if merged.query(p, 1).unwrap()[0].id == i {
+                hit += 1;
+            }
+        }
+        assert!(hit >= 55);
+    }
+
+    #[test]
+    fn test_hnsw_persist_graph() {
+        let mut hnsw = HNSW::<Vec<f32>>::new(8).with_ef(100, 50);
+        let mut rng = rand::thread_rng();
+        for _ in 0..500 {
+            hnsw.insert((0..8).map(|_| rng.gen::<f32>()).collect())
+                .unwrap();
+        }
+        let mut bytes: Vec<u8> = Vec::new();
+        hnsw.vector_serialize(&mut std::io::Cursor::new(&mut bytes))
+            .unwrap();
+        let entry = TensorEntry::new(1, [8], VectorType::F32);
+        let mut loaded =
+            HNSW::<Vec<f32>>::vector_deserialize(&mut std::io::Cursor::new(&bytes), &entry)
+                .unwrap();
+        assert!(matches!(loaded.nodes, Graph::Mapped(_)));
+        assert_eq!(loaded.nodes.len(), 500);
+        assert_eq!(loaded.max_layer, hnsw.max_layer);
+        assert_eq!(loaded.enter_point, hnsw.enter_point);
+        for n in 0..500 {
+            assert_eq!(loaded.nodes.level(n), hnsw.nodes.level(n));
+            assert_eq!(loaded.nodes.layers(n), hnsw.nodes.level(n) + 1);
+            for l in 0..loaded.nodes.layers(n) {
+                let a: Vec<usize> = loaded.nodes.neighbors(n, l).unwrap().collect();
+                let b: Vec<usize> = hnsw.nodes.neighbors(n, l).unwrap().collect();
+                assert_eq!(a, b);
+            }
+        }
+        let q: Vec<f32> = (0..8).map(|_| rng.gen::<f32>()).collect();
+        let a: Vec<usize> = hnsw.query(&q, 10).unwrap().iter().map(|n| n.id).collect();
+        let b: Vec<usize> = loaded.query(&q, 10).unwrap().iter().map(|n| n.id).collect();
+        assert_eq!(a, b);
+
+        // 加载后继续插入, 新节点编号接在已有节点之后
+        let id = loaded.insert(q.clone()).unwrap();
+        assert_eq!(id, 500);
+        assert!(matches!(loaded.nodes, Graph::Owned(_)));
+        assert_eq!(loaded.query(&q, 1).unwrap()[0].id, 500);
+    }
+
     #[test]
     fn test_slice() {
         let v = vec![0u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 128, 63];
@@ -0,0 +1,582 @@
+use super::super::util::error::GyResult;
+use super::{read_align, write_align, AnnIndex, Metric, MetricType, Neighbor, VectorElems};
+use crate::disk::{GyRead, GyWrite, SharedBytes, SliceReader};
+use crate::schema::{BinarySerialize, VUInt, VarIntSerialize};
+use crate::TensorEntry;
+use crate::VectorSerialize;
+use rand::seq::index::sample;
+use serde::{Deserialize, Serialize};
+use std::collections::BinaryHeap;
+use std::io::{Read, Write};
+
+pub(crate) const DEFAULT_NLIST: usize = 256;
+pub(crate) const DEFAULT_NPROBE: usize = 8;
+// 与 faiss 一致, 每个聚类中心至少需要 39 个样本才开始训练
+pub(crate) const MIN_POINTS_PER_CENTROID: usize = 39;
+pub(crate) const MAX_POINTS_PER_CENTROID: usize = 256;
+pub(crate) const KMEANS_ITERS: usize = 10;
+
+// IVF 聚类参数, IvfFlat 和 IvfPQ 共用
+// nlist 为聚类中心个数, nprobe 为查询时扫描的倒排表个数
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct IvfConfig {
+    pub nlist: usize,
+    pub nprobe: usize,
+}
+
+impl Default for IvfConfig {
+    fn default() -> Self {
+        IvfConfig {
+            nlist: DEFAULT_NLIST,
+            nprobe: DEFAULT_NPROBE,
+        }
+    }
+}
+
+// 倒排表 同一个聚类中心下的向量连续存放
+struct InvertedList<V> {
+    ids: Vec<usize>,
+    vectors: Vec<V>,
+}
+
+impl<V> InvertedList<V> {
+    fn new() -> InvertedList<V> {
+        InvertedList {
+            ids: Vec::new(),
+            vectors: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, id: usize, v: V) {
+        self.ids.push(id);
+        self.vectors.push(v);
+    }
+}
+
+// 从段文件打开的倒排表, 查询时只反序列化被选中的 nprobe 个
+struct MappedLists {
+    data: SharedBytes,
+    // 每个倒排表在 data 中的起始位置, 最后一个是 data 的长度
+    offsets: Vec<usize>,
+    entry: TensorEntry,
+}
+
+impl MappedLists {
+    fn list_reader(&self, p: usize) -> SliceReader {
+        let (start, end) = (self.offsets[p], self.offsets[p + 1]);
+        SliceReader::new(&self.data[start..end], self.data.offset() + start)
+    }
+}
+
+// 跳过一个倒排表, 同一个表中的向量大小相同
+fn skip_list<V: VectorSerialize, R: Read + GyRead>(
+    reader: &mut R,
+    entry: &TensorEntry,
+) -> GyResult<()> {
+    let n = VUInt::binary_deserialize(reader)?.0.val() as usize;
+    reader.read_bytes(n * std::mem::size_of::<u32>())?;
+    read_align(reader)?;
+    if n > 0 {
+        let start = reader.offset();
+        V::vector_deserialize(reader, entry)?;
+        let size = reader.offset() - start;
+        reader.read_bytes((n - 1) * size)?;
+    }
+    Ok(())
+}
+
+// 倒排文件索引
+// 训练前所有向量放在同一个倒排表中暴力搜索, 数量足够后用 k-means 训练 nlist 个聚类中心,
+// 查询时只扫描离 q 最近的 nprobe 个倒排表
+pub struct IvfFlat<V: VectorSerialize + Clone> {
+    nlist: usize,
+    nprobe: usize,
+    metric: MetricType,
+    n_items: usize,
+    // 聚类中心按 f32 保存, 与向量的元素类型无关
+    centroids: Vec<Vec<f32>>,
+    lists: Vec<InvertedList<V>>,
+    // lists 为空时从这里读取倒排表
+    mapped: Option<MappedLists>,
+}
+
+impl<V: VectorSerialize + Clone> VectorSerialize for IvfFlat<V> {
+    fn vector_deserialize<R: Read + GyRead>(reader: &mut R, entry: &TensorEntry) -> GyResult<Self> {
+        let nlist = usize::binary_deserialize(reader)?;
+        let nprobe = usize::binary_deserialize(reader)?;
+        let metric = MetricType::binary_deserialize(reader)?;
+        let n_items = usize::binary_deserialize(reader)?;
+        let centroids = Vec::<Vec<f32>>::binary_deserialize(reader)?;
+        let list_len = usize::binary_deserialize(reader)?;
+        read_align(reader)?;
+        // 只记录每个倒排表的位置, 数据留在 mmap 中
+        let start = reader.offset();
+        let mut offsets: Vec<usize> = Vec::with_capacity(list_len + 1);
+        {
+            let mut r = SliceReader::new(reader.cursor(), start);
+            for _ in 0..list_len {
+                offsets.push(r.offset() - start);
+                skip_list::<V, _>(&mut r, entry)?;
+            }
+            offsets.push(r.offset() - start);
+        }
+        let data = reader.read_shared(offsets[list_len])?;
+        Ok(IvfFlat {
+            nlist: nlist,
+            nprobe: nprobe,
+            metric: metric,
+            n_items: n_items,
+            centroids: centroids,
+            lists: Vec::new(),
+            mapped: Some(MappedLists {
+                data: data,
+                offsets: offsets,
+                entry: entry.clone(),
+            }),
+        })
+    }
+
+    fn vector_serialize<W: Write + GyWrite>(&self, writer: &mut W) -> GyResult<()> {
+        self.nlist.binary_serialize(writer)?;
+        self.nprobe.binary_serialize(writer)?;
+        self.metric.binary_serialize(writer)?;
+        self.n_items.binary_serialize(writer)?;
+        self.centroids.binary_serialize(writer)?;
+        self.list_len().binary_serialize(writer)?;
+        write_align(writer)?;
+        for p in 0..self.list_len() {
+            let mut ids: Vec<usize> = Vec::new();
+            let mut vectors: Vec<V> = Vec::new();
+            self.for_each_in_list(p, |id, v| {
+                ids.push(id);
+                vectors.push(v.clone());
+            })?;
+            ids.binary_serialize(writer)?;
+            write_align(writer)?;
+            for v in vectors.iter() {
+                v.vector_serialize(writer)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<V: VectorSerialize + Clone> AnnIndex<V> for IvfFlat<V>
+where
+    V: Metric<V> + VectorElems,
+{
+    fn insert(&mut self, q: V) -> GyResult<usize> {
+        self.materialize()?;
+        let id = self.n_items;
+        self.n_items += 1;
+        if !self.is_trained() {
+            self.lists[0].push(id, q);
+            if self.n_items >= self.nlist * MIN_POINTS_PER_CENTROID {
+                self.train();
+            }
+        } else {
+            let c = self.nearest_centroid(&q);
+            self.lists[c].push(id, q);
+        }
+        Ok(id)
+    }
+
+    fn query(&self, q: &V, k: usize) -> GyResult<Vec<Neighbor>> {
+        self.search(q, k, self.nprobe)
+    }
+}
+
+impl<V: VectorSerialize + Clone> IvfFlat<V>
+where
+    V: Metric<V> + VectorElems,
+{
+    pub fn new(nlist: usize, nprobe: usize) -> IvfFlat<V> {
+        let mut lists = Vec::with_capacity(1);
+        lists.push(InvertedList::new());
+        Self {
+            nlist: nlist.max(1),
+            nprobe: nprobe.max(1),
+            metric: MetricType::L2,
+            n_items: 0,
+            centroids: Vec::new(),
+            lists: lists,
+            mapped: None,
+        }
+    }
+
+    pub fn with_metric(mut self, metric: MetricType) -> IvfFlat<V> {
+        self.metric = metric;
+        self
+    }
+
+    pub fn metric(&self) -> MetricType {
+        self.metric
+    }
+
+    pub fn is_trained(&self) -> bool {
+        !self.centroids.is_empty()
+    }
+
+    pub fn set_nprobe(&mut self, nprobe: usize) {
+        self.nprobe = nprobe.max(1);
+    }
+
+    pub fn nprobe(&self) -> usize {
+        self.nprobe
+    }
+
+    // 只扫描离 q 最近的 nprobe 个倒排表
+    pub fn search(&self, q: &V, k: usize, nprobe: usize) -> GyResult<Vec<Neighbor>> {
+        if k == 0 {
+            return Ok(Vec::new());
+        }
+        let probes: Vec<usize> = if self.is_trained() {
+            let x = q.to_elems();
+            let mut heap: BinaryHeap<Neighbor> = BinaryHeap::with_capacity(nprobe + 1);
+            for (i, c) in self.centroids.iter().enumerate() {
+                heap.push(Neighbor {
+                    id: i,
+                    d: self.metric.distance(&x, c),
+                });
+                if heap.len() > nprobe.max(1) {
+                    heap.pop();
+                }
+            }
+            heap.into_iter().map(|n| n.id).collect()
+        } else {
+            vec![0]
+        };
+        let mut results: BinaryHeap<Neighbor> = BinaryHeap::with_capacity(k + 1);
+        for p in probes {
+            self.for_each_in_list(p, |id, v| {
+                let d = q.distance(v, self.metric);
+                if results.len() < k {
+                    results.push(Neighbor { id: id, d: d });
+                } else if d < results.peek().unwrap().d {
+                    results.pop();
+                    results.push(Neighbor { id: id, d: d });
+                }
+            })?;
+        }
+        Ok(results.into_sorted_vec())
+    }
+
+    // 保留 self 的聚类中心, other 的 id 整体偏移 self.n_items
+    pub fn merge(&self, other: &Self) -> GyResult<IvfFlat<V>> {
+        let mut new_ivf = IvfFlat::<V>::new(self.nlist, self.nprobe).with_metric(self.metric);
+        if self.is_trained() {
+            new_ivf.centroids = self.centroids.clone();
+            new_ivf.lists = (0..self.centroids.len())
+                .map(|_| InvertedList::new())
+                .collect();
+        }
+        for v in self.ordered_vectors()? {
+            new_ivf.insert(v)?;
+        }
+        for v in other.ordered_vectors()? {
+            new_ivf.insert(v)?;
+        }
+        Ok(new_ivf)
+    }
+
+    // 按 id 顺序返回所有向量
+    fn ordered_vectors(&self) -> GyResult<Vec<V>> {
+        let mut all: Vec<(usize, V)> = Vec::with_capacity(self.n_items);
+        for p in 0..self.list_len() {
+            self.for_each_in_list(p, |id, v| all.push((id, v.clone())))?;
+        }
+        all.sort_by_key(|(id, _)| *id);
+        Ok(all.into_iter().map(|(_, v)| v).collect())
+    }
+
+    fn list_len(&self) -> usize {
+        match &self.mapped {
+            Some(m) if self.lists.is_empty() => m.offsets.len() - 1,
+            _ => self.lists.len(),
+        }
+    }
+
+    // 遍历第 p 个倒排表, 从段文件打开时只读取这一个表
+    fn for_each_in_list<F: FnMut(usize, &V)>(&self, p: usize, mut f: F) -> GyResult<()> {
+        match &self.mapped {
+            Some(m) if self.lists.is_empty() => {
+                let mut r = m.list_reader(p);
+                let ids = Vec::<usize>::binary_deserialize(&mut r)?;
+                read_align(&mut r)?;
+                for id in ids {
+                    let v = V::vector_deserialize(&mut r, &m.entry)?;
+                    f(id, &v);
+                }
+            }
+            _ => {
+                let list = &self.lists[p];
+                for (id, v) in list.ids.iter().zip(list.vectors.iter()) {
+                    f(*id, v);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 插入前把倒排表读到内存, mapped 仍然保留, 读出的向量可能引用其中的数据
+    fn materialize(&mut self) -> GyResult<()> {
+        if self.mapped.is_none() || !self.lists.is_empty() {
+            return Ok(());
+        }
+        let mut lists: Vec<InvertedList<V>> = Vec::with_capacity(self.list_len());
+        for p in 0..self.list_len() {
+            let mut list = InvertedList::new();
+            self.for_each_in_list(p, |id, v| list.push(id, v.clone()))?;
+            lists.push(list);
+        }
+        self.lists = lists;
+        Ok(())
+    }
+
+    fn nearest_centroid(&self, v: &V) -> usize {
+        nearest(&self.centroids, &v.to_elems(), self.metric)
+    }
+
+    // 用 k-means 训练聚类中心, 并把已有向量重新分配到倒排表
+    fn train(&mut self) {
+        let mut all: Vec<(usize, V)> = Vec::with_capacity(self.n_items);
+        for list in self.lists.drain(..) {
+            all.extend(list.ids.into_iter().zip(list.vectors.into_iter()));
+        }
+        all.sort_by_key(|(id, _)| *id);
+        let max_sample = self.nlist * MAX_POINTS_PER_CENTROID;
+        let mut rng = rand::thread_rng();
+        let samples: Vec<Vec<f32>> = if all.len() > max_sample {
+            sample(&mut rng, all.len(), max_sample)
+                .into_iter()
+                .map(|i| all[i].1.to_elems())
+                .collect()
+        } else {
+            all.iter().map(|(_, v)| v.to_elems()).collect()
+        };
+        let samples: Vec<&Vec<f32>> = samples.iter().collect();
+        self.centroids = kmeans(&samples, self.nlist, KMEANS_ITERS, self.metric);
+        self.lists = (0..self.centroids.len())
+            .map(|_| InvertedList::new())
+            .collect();
+        for (id, v) in all {
+            let c = self.nearest_centroid(&v);
+            self.lists[c].push(id, v);
+        }
+    }
+}
+
+pub(crate) fn nearest<V: Metric<V>>(centroids: &[V], v: &V, metric: MetricType) -> usize {
+    let mut best = (0, f32::MAX);
+    for (i, c) in centroids.iter().enumerate() {
+        let d = v.distance(c, metric);
+        if d < best.1 {
+            best = (i, d);
+        }
+    }
+    best.0
+}
+
+// 训练 k 个聚类中心, 空的聚类用随机样本重新初始化
+pub(crate) fn kmeans<V: Metric<V> + VectorElems>(
+    samples: &[&V],
+    k: usize,
+    iters: usize,
+    metric: MetricType,
+) -> Vec<V> {
+    if samples.is_empty() {
+        return Vec::new();
+    }
+    let k = k.min(samples.len()).max(1);
+    let mut rng = rand::thread_rng();
+    let elems: Vec<Vec<f32>> = samples.iter().map(|v| v.to_elems()).collect();
+    let dim = elems.first().map(|e| e.len()).unwrap_or(0);
+    let mut centroids: Vec<V> = sample(&mut rng, samples.len(), k)
+        .into_iter()
+        .map(|i| V::from_elems(elems[i].clone()))
+        .collect();
+    for _ in 0..iters {
+        let mut sums = vec![vec![0.0f32; dim]; k];
+        let mut counts = vec![0usize; k];
+        for (v, e) in samples.iter().zip(elems.iter()) {
+            let c = nearest(&centroids, *v, metric);
+            counts[c] += 1;
+            for (s, x) in sums[c].iter_mut().zip(e.iter()) {
+                *s += *x;
+            }
+        }
+        centroids = sums
+            .into_iter()
+            .zip(counts.iter())
+            .map(|(mut s, &n)| {
+                if n == 0 {
+                    return V::from_elems(elems[sample(&mut rng, elems.len(), 1).index(0)].clone());
+                }
+                s.iter_mut().for_each(|x| *x /= n as f32);
+                V::from_elems(s)
+            })
+            .collect();
+    }
+    centroids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ann::random_vectors;
+    use crate::ann::Flat;
+    use crate::disk::MmapReader;
+    use crate::schema::VectorType;
+    use crate::util::fs::GyFile;
+    use galois::Tensor;
+    use half::f16;
+    use std::fs::File;
+    use std::io::Write;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_ivf_train() {
+        let mut ivf = IvfFlat::<Vec<f32>>::new(4, 2);
+        let features = random_vectors(4 * MIN_POINTS_PER_CENTROID, 8);
+        for f in features.iter() {
+            ivf.insert(f.clone()).unwrap();
+        }
+        assert!(ivf.is_trained());
+        assert_eq!(ivf.lists.len(), 4);
+        assert_eq!(
+            ivf.lists.iter().map(|l| l.ids.len()).sum::<usize>(),
+            features.len()
+        );
+        let neighbors = ivf.query(&features[7], 1).unwrap();
+        assert_eq!(neighbors[0].doc_id(), 7);
+    }
+
+    #[test]
+    fn test_ivf_search_all_lists() {
+        let mut ivf = IvfFlat::<Vec<f32>>::new(8, 8);
+        let mut flat = Flat::<Vec<f32>>::new();
+        for f in random_vectors(8 * MIN_POINTS_PER_CENTROID + 100, 16) {
+            ivf.insert(f.clone()).unwrap();
+            flat.insert(f).unwrap();
+        }
+        // nprobe 等于 nlist 时结果与暴力搜索一致
+        let q = random_vectors(1, 16).remove(0);
+        let a: Vec<u64> = ivf
+            .query(&q, 5)
+            .unwrap()
+            .iter()
+            .map(|n| n.doc_id())
+            .collect();
+        let b: Vec<u64> = flat
+            .query(&q, 5)
+            .unwrap()
+            .iter()
+            .map(|n| n.doc_id())
+            .collect();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_ivf_mapped_lists() {
+        let mut ivf = IvfFlat::<Vec<f32>>::new(4, 2);
+        let features = random_vectors(4 * MIN_POINTS_PER_CENTROID + 10, 8);
+        for f in features.iter() {
+            ivf.insert(f.clone()).unwrap();
+        }
+        let mut file = File::create("./data.ivf").unwrap();
+        ivf.vector_serialize(&mut file).unwrap();
+        file.flush().unwrap();
+
+        let file = GyFile::open("./data.ivf").unwrap();
+        let file_size = file.fsize().unwrap();
+        let mmap = Arc::new(unsafe { memmap2::MmapOptions::new().map(file.file()).unwrap() });
+        let mut reader = MmapReader::shared(&mmap, 0, file_size);
+        let entry = TensorEntry::new(1, [8], VectorType::F32);
+        let mapped = IvfFlat::<Vec<f32>>::vector_deserialize(&mut reader, &entry).unwrap();
+        // 倒排表仍在 mmap 中
+        assert!(mapped.lists.is_empty());
+        assert_eq!(mapped.list_len(), 4);
+        for q in features.iter().take(20) {
+            assert_eq!(
+                mapped
+                    .query(q, 3)
+                    .unwrap()
+                    .iter()
+                    .map(|n| n.doc_id())
+                    .collect::<Vec<_>>(),
+                ivf.query(q, 3)
+                    .unwrap()
+                    .iter()
+                    .map(|n| n.doc_id())
+                    .collect::<Vec<_>>()
+            );
+        }
+        let merged = mapped.merge(&ivf).unwrap();
+        assert_eq!(merged.n_items, features.len() * 2);
+        drop(mapped);
+        drop(mmap);
+        std::fs::remove_file("./data.ivf").unwrap();
+    }
+
+    #[test]
+    fn test_ivf_f16_persist() {
+        let mut ivf = IvfFlat::<Tensor>::new(4, 2);
+        let features: Vec<Tensor> = random_vectors(4 * MIN_POINTS_PER_CENTROID + 10, 8)
+            .into_iter()
+            .map(|f| Tensor::arr(f.into_iter().map(f16::from_f32).collect::<Vec<_>>()))
+            .collect();
+        for f in features.iter() {
+            ivf.insert(f.clone()).unwrap();
+        }
+        let mut file = File::create("./data.f16.ivf").unwrap();
+        ivf.vector_serialize(&mut file).unwrap();
+        file.flush().unwrap();
+
+        let file = GyFile::open("./data.f16.ivf").unwrap();
+        let file_size = file.fsize().unwrap();
+        let mmap = Arc::new(unsafe { memmap2::MmapOptions::new().map(file.file()).unwrap() });
+        let mut reader = MmapReader::shared(&mmap, 0, file_size);
+        // 聚类中心按 f32 保存, 不受域的元素类型影响
+        let entry = TensorEntry::new(1, [8], VectorType::F16);
+        let mapped = IvfFlat::<Tensor>::vector_deserialize(&mut reader, &entry).unwrap();
+        assert_eq!(mapped.centroids, ivf.centroids);
+        assert_eq!(mapped.list_len(), 4);
+        for q in features.iter().take(20) {
+            assert_eq!(
+                mapped
+                    .query(q, 3)
+                    .unwrap()
+                    .iter()
+                    .map(|n| n.doc_id())
+                    .collect::<Vec<_>>(),
+                ivf.query(q, 3)
+                    .unwrap()
+                    .iter()
+                    .map(|n| n.doc_id())
+                    .collect::<Vec<_>>()
+            );
+        }
+        let merged = mapped.merge(&ivf).unwrap();
+        assert_eq!(merged.n_items, features.len() * 2);
+        drop(mapped);
+        drop(mmap);
+        std::fs::remove_file("./data.f16.ivf").unwrap();
+    }
+
+    #[test]
+    fn test_ivf_merge() {
+        let mut a = IvfFlat::<Vec<f32>>::new(2, 2);
+        let mut b = IvfFlat::<Vec<f32>>::new(2, 2);
+        let features = random_vectors(200, 4);
+        for f in features[..100].iter() {
+            a.insert(f.clone()).unwrap();
+        }
+        for f in features[100..].iter() {
+            b.insert(f.clone()).unwrap();
+        }
+        let ivf = a.merge(&b).unwrap();
+        assert_eq!(ivf.n_items, 200);
+        let neighbors = ivf.search(&features[120], 1, 2).unwrap();
+        assert_eq!(neighbors[0].doc_id(), 120);
+    }
+}
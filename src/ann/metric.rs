@@ -0,0 +1,167 @@
+use super::super::util::error::GyResult;
+use crate::schema::BinarySerialize;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+// 向量字段的距离度量
+// 所有度量都转换成越小越相似的距离: 内积取负, 余弦距离为 1 - cos
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
+pub enum MetricType {
+    #[default]
+    L2,
+    Cosine,
+    InnerProduct,
+    L1,
+    Hamming,
+}
+
+impl BinarySerialize for MetricType {
+    fn binary_serialize<W: Write>(&self, writer: &mut W) -> GyResult<()> {
+        let u: u8 = match self {
+            MetricType::L2 => 0,
+            MetricType::Cosine => 1,
+            MetricType::InnerProduct => 2,
+            MetricType::L1 => 3,
+            MetricType::Hamming => 4,
+        };
+        u.binary_serialize(writer)
+    }
+
+    fn binary_deserialize<R: Read>(reader: &mut R) -> GyResult<Self> {
+        match u8::binary_deserialize(reader)? {
+            0 => Ok(MetricType::L2),
+            1 => Ok(MetricType::Cosine),
+            2 => Ok(MetricType::InnerProduct),
+            3 => Ok(MetricType::L1),
+            4 => Ok(MetricType::Hamming),
+            _ => Err("invalid metric type".into()),
+        }
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b.iter()).map(|(x, y)| x * y).sum()
+}
+
+impl MetricType {
+    pub fn distance(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            MetricType::Cosine => {
+                let norm = (dot(a, a) * dot(b, b)).sqrt();
+                if norm == 0.0 {
+                    1.0
+                } else {
+                    1.0 - dot(a, b) / norm
+                }
+            }
+            _ => self.finish(self.partial(a, b)),
+        }
+    }
+
+    // 逐个元素计算距离, 紧凑类型不需要先展开成 f32 数组
+    pub(crate) fn distance_pairs<I: Iterator<Item = (f32, f32)>>(&self, pairs: I) -> f32 {
+        match self {
+            MetricType::Cosine => {
+                let (mut ab, mut aa, mut bb) = (0.0f32, 0.0f32, 0.0f32);
+                for (x, y) in pairs {
+                    ab += x * y;
+                    aa += x * x;
+                    bb += y * y;
+                }
+                let norm = (aa * bb).sqrt();
+                if norm == 0.0 {
+                    1.0
+                } else {
+                    1.0 - ab / norm
+                }
+            }
+            MetricType::L2 => self.finish(pairs.map(|(x, y)| (x - y) * (x - y)).sum()),
+            MetricType::InnerProduct => -pairs.map(|(x, y)| x * y).sum::<f32>(),
+            MetricType::L1 => pairs.map(|(x, y)| (x - y).abs()).sum(),
+            MetricType::Hamming => pairs.filter(|(x, y)| x != y).count() as f32,
+        }
+    }
+
+    // 可以按维度切分后累加的部分, 用于 PQ 距离表
+    // 余弦距离要求向量已经归一化
+    pub(crate) fn partial(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            MetricType::L2 => a.iter().zip(b.iter()).map(|(x, y)| (x - y) * (x - y)).sum(),
+            MetricType::Cosine | MetricType::InnerProduct => -dot(a, b),
+            MetricType::L1 => a.iter().zip(b.iter()).map(|(x, y)| (x - y).abs()).sum(),
+            MetricType::Hamming => a.iter().zip(b.iter()).filter(|(x, y)| x != y).count() as f32,
+        }
+    }
+
+    pub(crate) fn finish(&self, sum: f32) -> f32 {
+        match self {
+            MetricType::L2 => sum.sqrt(),
+            MetricType::Cosine => 1.0 + sum,
+            _ => sum,
+        }
+    }
+
+    // 内积和余弦距离与中心点的残差无关, 需要单独加上查询向量与中心点的内积
+    pub(crate) fn is_inner_product(&self) -> bool {
+        matches!(self, MetricType::Cosine | MetricType::InnerProduct)
+    }
+
+    // 距离转换为分数: 内积返回原始内积, 余弦返回余弦相似度, 其它度量返回距离本身
+    pub fn score(&self, d: f32) -> f32 {
+        match self {
+            MetricType::InnerProduct => -d,
+            MetricType::Cosine => 1.0 - d,
+            _ => d,
+        }
+    }
+}
+
+pub(crate) fn normalize(v: &mut [f32]) {
+    let norm = dot(v, v).sqrt();
+    if norm > 0.0 {
+        v.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metric_distance() {
+        let a = [1.0f32, 0.0, 1.0];
+        let b = [0.0f32, 1.0, 1.0];
+        assert_eq!(MetricType::L2.distance(&a, &b), 2.0f32.sqrt());
+        assert_eq!(MetricType::L1.distance(&a, &b), 2.0);
+        assert_eq!(MetricType::Hamming.distance(&a, &b), 2.0);
+        assert_eq!(MetricType::InnerProduct.distance(&a, &b), -1.0);
+        assert!((MetricType::Cosine.distance(&a, &b) - 0.5).abs() < 1e-6);
+        assert!((MetricType::Cosine.score(MetricType::Cosine.distance(&a, &b)) - 0.5).abs() < 1e-6);
+        assert_eq!(MetricType::InnerProduct.score(-1.0), 1.0);
+        for m in [
+            MetricType::L2,
+            MetricType::L1,
+            MetricType::Hamming,
+            MetricType::InnerProduct,
+            MetricType::Cosine,
+        ] {
+            let d = m.distance_pairs(a.iter().cloned().zip(b.iter().cloned()));
+            assert!((d - m.distance(&a, &b)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_metric_partial() {
+        let mut a = vec![3.0f32, 4.0, 0.0, 1.0];
+        let mut b = vec![1.0f32, 2.0, 2.0, 0.0];
+        for m in [MetricType::L2, MetricType::L1, MetricType::InnerProduct] {
+            let sum = m.partial(&a[..2], &b[..2]) + m.partial(&a[2..], &b[2..]);
+            assert!((m.finish(sum) - m.distance(&a, &b)).abs() < 1e-6);
+        }
+        normalize(&mut a);
+        normalize(&mut b);
+        let m = MetricType::Cosine;
+        let sum = m.partial(&a[..2], &b[..2]) + m.partial(&a[2..], &b[2..]);
+        assert!((m.finish(sum) - m.distance(&a, &b)).abs() < 1e-6);
+    }
+}
@@ -1,9 +1,31 @@
 pub mod annoy;
+pub mod binary;
+pub mod flat;
 pub mod hnsw;
+pub mod ivf;
+pub mod metric;
+pub mod multi;
+pub mod pq;
+pub mod sparse;
+pub mod sq;
+pub use self::annoy::Annoy;
+pub use self::binary::BinaryVector;
+pub use self::flat::Flat;
+pub use self::hnsw::HnswConfig;
 pub use self::hnsw::HNSW;
+pub use self::ivf::IvfConfig;
+pub use self::ivf::IvfFlat;
+pub use self::metric::MetricType;
+pub use self::pq::IvfPQ;
+pub use self::pq::PqConfig;
+pub use self::sparse::SparseVector;
+pub use self::sq::Quantizer;
+pub use self::sq::SqRange;
 use super::schema::BinarySerialize;
 use super::schema::DocID;
-use super::util::error::GyResult;
+use super::schema::VectorEntry;
+use super::util::bitmap::BitMap;
+use super::util::error::{GyError, GyResult};
 use crate::disk::GyRead;
 use crate::disk::GyWrite;
 use crate::TensorEntry;
@@ -15,11 +37,17 @@ use serde::Serialize;
 use std::io::{Read, Write};
 type Endian = LittleEndian;
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
+const GGUF_DEFAULT_ALIGNMENT: usize = 32;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
 #[repr(usize)]
 pub enum AnnType {
     #[default]
     HNSW = 1,
+    ANNOY = 2,
+    FLAT = 3,
+    IvfFlat = 4,
+    IvfPQ = 5,
 }
 
 impl AnnType {
@@ -27,25 +55,38 @@ impl AnnType {
         *self as usize
     }
 
-    fn from_usize(u: usize) -> Self {
+    fn from_usize(u: usize) -> GyResult<Self> {
         match u {
-            1 => AnnType::HNSW,
-            _ => todo!(),
+            1 => Ok(AnnType::HNSW),
+            2 => Ok(AnnType::ANNOY),
+            3 => Ok(AnnType::FLAT),
+            4 => Ok(AnnType::IvfFlat),
+            5 => Ok(AnnType::IvfPQ),
+            _ => Err(GyError::ErrInvalidAnnType(u)),
         }
     }
 }
 
 pub enum Ann<V: VectorSerialize + Clone> {
     HNSW(HNSW<V>),
+    ANNOY(Annoy<V>),
+    FLAT(Flat<V>),
+    IvfFlat(IvfFlat<V>),
+    IvfPQ(IvfPQ<V>),
 }
 
 impl<V: VectorSerialize + Clone> VectorSerialize for Ann<V> {
     fn vector_deserialize<R: Read + GyRead>(reader: &mut R, entry: &TensorEntry) -> GyResult<Self> {
         let n = usize::binary_deserialize(reader)?;
-        let ann_type = AnnType::from_usize(n);
+        let ann_type = AnnType::from_usize(n)?;
         match ann_type {
             AnnType::HNSW => Ok(Ann::HNSW(HNSW::<V>::vector_deserialize(reader, entry)?)),
-            _ => todo!(),
+            AnnType::ANNOY => Ok(Ann::ANNOY(Annoy::<V>::vector_deserialize(reader, entry)?)),
+            AnnType::FLAT => Ok(Ann::FLAT(Flat::<V>::vector_deserialize(reader, entry)?)),
+            AnnType::IvfFlat => Ok(Ann::IvfFlat(IvfFlat::<V>::vector_deserialize(
+                reader, entry,
+            )?)),
+            AnnType::IvfPQ => Ok(Ann::IvfPQ(IvfPQ::<V>::vector_deserialize(reader, entry)?)),
         }
     }
     fn vector_serialize<W: Write + GyWrite>(&self, writer: &mut W) -> GyResult<()> {
@@ -54,39 +95,337 @@ impl<V: VectorSerialize + Clone> VectorSerialize for Ann<V> {
                 AnnType::HNSW.to_usize().binary_serialize(writer)?;
                 v.vector_serialize(writer)
             }
-            _ => todo!(),
+            Ann::ANNOY(v) => {
+                AnnType::ANNOY.to_usize().binary_serialize(writer)?;
+                v.vector_serialize(writer)
+            }
+            Ann::FLAT(v) => {
+                AnnType::FLAT.to_usize().binary_serialize(writer)?;
+                v.vector_serialize(writer)
+            }
+            Ann::IvfFlat(v) => {
+                AnnType::IvfFlat.to_usize().binary_serialize(writer)?;
+                v.vector_serialize(writer)
+            }
+            Ann::IvfPQ(v) => {
+                AnnType::IvfPQ.to_usize().binary_serialize(writer)?;
+                v.vector_serialize(writer)
+            }
         }
     }
 }
 
 impl<V: VectorSerialize + Clone> Ann<V>
 where
-    V: Metric<V>,
+    V: Metric<V> + VectorElems,
 {
+    pub fn new(ann_type: AnnType) -> Ann<V> {
+        match ann_type {
+            AnnType::HNSW => Ann::HNSW(HNSW::<V>::new(hnsw::DEFAULT_M)),
+            AnnType::ANNOY => Ann::ANNOY(Annoy::<V>::new(annoy::DEFAULT_TREES)),
+            AnnType::FLAT => Ann::FLAT(Flat::<V>::new()),
+            AnnType::IvfFlat => {
+                Ann::IvfFlat(IvfFlat::<V>::new(ivf::DEFAULT_NLIST, ivf::DEFAULT_NPROBE))
+            }
+            AnnType::IvfPQ => {
+                Ann::IvfPQ(IvfPQ::<V>::new(ivf::DEFAULT_NLIST, ivf::DEFAULT_NPROBE, 0))
+            }
+        }
+    }
+
+    // 按 schema 中的向量字段配置创建索引, 目前只有 HNSW 和 Flat 支持量化
+    pub fn from_entry(entry: &VectorEntry) -> Ann<V> {
+        let metric = entry.metric();
+        match entry.index_type() {
+            AnnType::HNSW => Ann::HNSW(
+                HNSW::<V>::with_config(entry.hnsw_config(), entry.quantizer())
+                    .with_sq_range(entry.sq_range())
+                    .with_metric(metric),
+            ),
+            AnnType::ANNOY => Ann::ANNOY(Annoy::<V>::new(annoy::DEFAULT_TREES).with_metric(metric)),
+            AnnType::FLAT => Ann::FLAT(
+                Flat::<V>::with_quantizer(entry.quantizer())
+                    .with_sq_range(entry.sq_range())
+                    .with_metric(metric),
+            ),
+            AnnType::IvfFlat => {
+                let ivf = entry.ivf_config();
+                Ann::IvfFlat(IvfFlat::<V>::new(ivf.nlist, ivf.nprobe).with_metric(metric))
+            }
+            AnnType::IvfPQ => {
+                let (ivf, pq) = (entry.ivf_config(), entry.pq_config());
+                Ann::IvfPQ(
+                    IvfPQ::<V>::new(ivf.nlist, ivf.nprobe, pq.m)
+                        .with_rerank(pq.rerank)
+                        .with_metric(metric),
+                )
+            }
+        }
+    }
+
+    pub fn metric(&self) -> MetricType {
+        match self {
+            Ann::HNSW(v) => v.metric(),
+            Ann::ANNOY(v) => v.metric(),
+            Ann::FLAT(v) => v.metric(),
+            Ann::IvfFlat(v) => v.metric(),
+            Ann::IvfPQ(v) => v.metric(),
+        }
+    }
+
     pub fn insert(&mut self, q: V) -> GyResult<usize> {
         match self {
             Ann::HNSW(v) => v.insert(q),
-            _ => todo!(),
+            Ann::ANNOY(v) => v.insert(q),
+            Ann::FLAT(v) => v.insert(q),
+            Ann::IvfFlat(v) => v.insert(q),
+            Ann::IvfPQ(v) => v.insert(q),
         }
     }
 
     pub fn query(&self, q: &V, k: usize) -> GyResult<Vec<Neighbor>> {
         match self {
             Ann::HNSW(v) => v.query(q, k),
-            _ => todo!(),
+            Ann::ANNOY(v) => v.query(q, k),
+            Ann::FLAT(v) => v.query(q, k),
+            Ann::IvfFlat(v) => v.query(q, k),
+            Ann::IvfPQ(v) => v.query(q, k),
+        }
+    }
+
+    // 查询时临时指定的参数, ef 只对 HNSW 生效, nprobe 只对 IVF 生效
+    pub fn query_with(&self, q: &V, k: usize, params: SearchParams) -> GyResult<Vec<Neighbor>> {
+        match self {
+            Ann::HNSW(v) => v.search(q, k, params.ef.unwrap_or(v.ef_search())),
+            Ann::IvfFlat(v) => v.search(q, k, params.nprobe.unwrap_or(v.nprobe())),
+            Ann::IvfPQ(v) => v.search(q, k, params.nprobe.unwrap_or(v.nprobe())),
+            _ => self.query(q, k),
+        }
+    }
+
+    // 只返回 allow 中的文档
+    // HNSW 和 Flat 在遍历时过滤, 其它索引扩大候选集后再过滤
+    pub fn query_filtered(
+        &self,
+        q: &V,
+        k: usize,
+        params: SearchParams,
+        allow: &BitMap,
+    ) -> GyResult<Vec<Neighbor>> {
+        if k == 0 {
+            return Ok(Vec::new());
+        }
+        match self {
+            Ann::HNSW(v) => {
+                v.search_filtered(q, k, params.ef.unwrap_or(v.ef_search()), Some(allow))
+            }
+            Ann::FLAT(v) => v.search(q, k, Some(allow)),
+            _ => {
+                let mut n = k * FILTER_OVERFETCH;
+                loop {
+                    let candidates = self.query_with(q, n, params)?;
+                    // 索引返回的点不足 n 个时说明已经没有更多候选
+                    let exhausted = candidates.len() < n;
+                    let mut results: Vec<Neighbor> = candidates
+                        .into_iter()
+                        .filter(|x| allow.contains(x.id))
+                        .collect();
+                    if results.len() >= k || exhausted {
+                        results.truncate(k);
+                        return Ok(results);
+                    }
+                    n *= 2;
+                }
+            }
+        }
+    }
+
+    // 返回距离不超过 radius 的所有点, 按距离从近到远排序, limit 限制最多返回的个数
+    // radius 与 Neighbor::distance 同一单位, 内积为负的内积
+    pub fn query_radius(
+        &self,
+        q: &V,
+        radius: f32,
+        limit: Option<usize>,
+    ) -> GyResult<Vec<Neighbor>> {
+        match self {
+            Ann::HNSW(v) => v.search_radius(q, radius, limit),
+            Ann::FLAT(v) => v.search_radius(q, radius, limit),
+            _ => expand_radius(|n| self.query(q, n), RADIUS_INITIAL_K, radius, limit),
+        }
+    }
+
+    // 压缩索引的半径查询, vector 按文档 id 读取原始向量
+    // 近似距离可能偏大, 不能用 radius 预先过滤, 取到的候选全部用原始向量重新计算距离
+    pub fn query_radius_rerank<F>(
+        &self,
+        q: &V,
+        radius: f32,
+        limit: Option<usize>,
+        mut vector: F,
+    ) -> GyResult<Vec<Neighbor>>
+    where
+        F: FnMut(DocID) -> GyResult<V>,
+    {
+        let metric = self.metric();
+        expand_radius(
+            |n| rerank(self.query(q, n)?, q, n, metric, &mut vector),
+            RADIUS_INITIAL_K,
+            radius,
+            limit,
+        )
+    }
+
+    // 压缩索引返回的是近似距离, 需要用原始向量重排, 0 表示不需要
+    pub fn rerank_factor(&self) -> usize {
+        match self {
+            Ann::IvfPQ(v) => v.rerank_factor(),
+            _ => 0,
         }
     }
 
     pub fn merge(&self, other: &Self) -> GyResult<Self> {
         match (self, other) {
             (Ann::HNSW(a), Ann::HNSW(b)) => Ok(Ann::HNSW(a.merge(b)?)),
-            _ => todo!(),
+            (Ann::ANNOY(a), Ann::ANNOY(b)) => Ok(Ann::ANNOY(a.merge(b)?)),
+            (Ann::FLAT(a), Ann::FLAT(b)) => Ok(Ann::FLAT(a.merge(b)?)),
+            (Ann::IvfFlat(a), Ann::IvfFlat(b)) => Ok(Ann::IvfFlat(a.merge(b)?)),
+            (Ann::IvfPQ(a), Ann::IvfPQ(b)) => Ok(Ann::IvfPQ(a.merge(b)?)),
+            _ => Err(GyError::ErrAnnTypeMismatch),
         }
     }
 }
 
+// 查询时覆盖索引中保存的参数, None 表示使用索引自己的配置
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SearchParams {
+    pub ef: Option<usize>,
+    pub nprobe: Option<usize>,
+}
+
 pub trait Metric<P = Self> {
-    fn distance(&self, b: &P) -> f32;
+    fn distance(&self, b: &P, metric: MetricType) -> f32;
+}
+
+// 按 f32 分量读写向量, 用于训练聚类中心
+pub trait VectorElems: Sized {
+    fn to_elems(&self) -> Vec<f32>;
+    fn from_elems(v: Vec<f32>) -> Self;
+}
+
+impl VectorElems for Vec<f32> {
+    fn to_elems(&self) -> Vec<f32> {
+        self.clone()
+    }
+
+    fn from_elems(v: Vec<f32>) -> Self {
+        v
+    }
+}
+
+// 按 GGUF_DEFAULT_ALIGNMENT 对齐, 向量块可以直接 mmap 读取
+pub(crate) fn write_align<W: Write + GyWrite>(writer: &mut W) -> GyResult<()> {
+    let position = writer.get_pos()?;
+    let next_position = position - (position % GGUF_DEFAULT_ALIGNMENT) + GGUF_DEFAULT_ALIGNMENT;
+    writer.write(&vec![0u8; next_position - position])?;
+    Ok(())
+}
+
+pub(crate) fn read_align<R: GyRead>(reader: &mut R) -> GyResult<()> {
+    let position = reader.offset();
+    let next_position = position - (position % GGUF_DEFAULT_ALIGNMENT) + GGUF_DEFAULT_ALIGNMENT;
+    reader.read_bytes(next_position - position)?;
+    Ok(())
+}
+
+// 用原始向量重新计算候选集的精确距离, 取前 k 个
+pub(crate) fn rerank<V, F>(
+    candidates: Vec<Neighbor>,
+    q: &V,
+    k: usize,
+    metric: MetricType,
+    mut vector: F,
+) -> GyResult<Vec<Neighbor>>
+where
+    V: Metric<V>,
+    F: FnMut(DocID) -> GyResult<V>,
+{
+    let mut results = Vec::with_capacity(candidates.len());
+    for n in candidates {
+        let v = vector(n.doc_id())?;
+        results.push(Neighbor {
+            id: n.id,
+            d: q.distance(&v, metric),
+        });
+    }
+    results.sort_by(|a, b| a.d.partial_cmp(&b.d).unwrap());
+    results.truncate(k);
+    Ok(results)
+}
+
+// 半径查询第一次取的候选个数
+const RADIUS_INITIAL_K: usize = 16;
+
+// 半径查询: search(n) 返回按距离排序的 n 个候选
+// 候选全部落在半径内说明可能还有遗漏, n 翻倍后重新查询, 直到有候选落在半径外或候选已经取完
+pub(crate) fn expand_radius<F>(
+    mut search: F,
+    start: usize,
+    radius: f32,
+    limit: Option<usize>,
+) -> GyResult<Vec<Neighbor>>
+where
+    F: FnMut(usize) -> GyResult<Vec<Neighbor>>,
+{
+    let mut n = start.max(1);
+    loop {
+        let mut candidates = search(n)?;
+        let exhausted = candidates.len() < n;
+        candidates.retain(|x| x.d <= radius);
+        if exhausted || candidates.len() < n || limit.map_or(false, |l| candidates.len() >= l) {
+            if let Some(l) = limit {
+                candidates.truncate(l);
+            }
+            return Ok(candidates);
+        }
+        n *= 2;
+    }
+}
+
+// 不支持遍历时过滤的索引每次多取的倍数
+const FILTER_OVERFETCH: usize = 4;
+// 允许的文档少于总数的这个比例时, 遍历图要经过大量不满足条件的点, 直接暴力计算
+const FILTER_BRUTE_FORCE_RATIO: f64 = 0.01;
+
+pub(crate) fn filter_brute_force(allow: &BitMap, k: usize) -> bool {
+    let n = allow.count();
+    n <= k || (n as f64) < allow.len() as f64 * FILTER_BRUTE_FORCE_RATIO
+}
+
+// 对 allow 中的文档逐个计算精确距离, 取前 k 个
+pub(crate) fn brute_force<V, F>(
+    allow: &BitMap,
+    q: &V,
+    k: usize,
+    metric: MetricType,
+    vector: F,
+) -> GyResult<Vec<Neighbor>>
+where
+    V: Metric<V>,
+    F: FnMut(DocID) -> GyResult<V>,
+{
+    let candidates = allow.iter().map(|id| Neighbor { id: id, d: 0.0 }).collect();
+    rerank(candidates, q, k, metric, vector)
+}
+
+// 测试用的随机向量, 分量在 [0, 1) 内
+#[cfg(test)]
+pub(crate) fn random_vectors(count: usize, dim: usize) -> Vec<Vec<f32>> {
+    use rand::Rng;
+    let mut rng = rand::thread_rng();
+    (0..count)
+        .map(|_| (0..dim).map(|_| rng.gen::<f32>()).collect())
+        .collect()
 }
 
 pub trait VectorCreate {
@@ -103,6 +442,15 @@ impl Neighbor {
     pub fn doc_id(&self) -> DocID {
         self.id as DocID
     }
+
+    // 越小越相似, 内积和余弦需要用 MetricType::score 转换成相似度
+    pub fn distance(&self) -> f32 {
+        self.d
+    }
+
+    pub fn score(&self, metric: MetricType) -> f32 {
+        metric.score(self.d)
+    }
 }
 
 impl Ord for Neighbor {
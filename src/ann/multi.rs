@@ -0,0 +1,141 @@
+use super::{Metric, MetricType, Neighbor, VectorElems};
+use crate::schema::{BinarySerialize, DocID};
+use crate::util::error::GyResult;
+use std::io::{Read, Write};
+
+// 每个查询向量在索引中取 k 的这个倍数个最近的向量作为候选
+const MULTI_VECTOR_CANDIDATES: usize = 4;
+
+// 多向量域中每个文档的向量在索引中编号连续, starts[d] 为文档 d 的第一个向量的编号
+#[derive(Debug, Default, Clone)]
+pub(crate) struct MultiVectorDocs {
+    starts: Vec<usize>,
+    count: usize,
+}
+
+impl MultiVectorDocs {
+    pub(crate) fn new() -> MultiVectorDocs {
+        MultiVectorDocs::default()
+    }
+
+    // 加入一个有 rows 个向量的文档, 返回第一个向量的编号
+    pub(crate) fn push(&mut self, rows: usize) -> usize {
+        let start = self.count;
+        self.starts.push(start);
+        self.count += rows;
+        start
+    }
+
+    pub(crate) fn doc_id(&self, id: usize) -> DocID {
+        (self.starts.partition_point(|s| *s <= id) - 1) as DocID
+    }
+
+    // other 中的向量编号排在 self 之后, 与 Ann::merge 一致
+    pub(crate) fn merge(&self, other: &MultiVectorDocs) -> MultiVectorDocs {
+        let mut starts = self.starts.clone();
+        starts.extend(other.starts.iter().map(|s| s + self.count));
+        MultiVectorDocs {
+            starts: starts,
+            count: self.count + other.count,
+        }
+    }
+}
+
+impl BinarySerialize for MultiVectorDocs {
+    fn binary_serialize<W: Write>(&self, writer: &mut W) -> GyResult<()> {
+        self.starts.binary_serialize(writer)?;
+        self.count.binary_serialize(writer)
+    }
+
+    fn binary_deserialize<R: Read>(reader: &mut R) -> GyResult<Self> {
+        let starts = Vec::<usize>::binary_deserialize(reader)?;
+        let count = usize::binary_deserialize(reader)?;
+        Ok(MultiVectorDocs {
+            starts: starts,
+            count: count,
+        })
+    }
+}
+
+// 按行拆分多向量, 每行 dim 个分量
+pub(crate) fn split_rows<V: VectorElems>(v: &V, dim: usize) -> Vec<V> {
+    v.to_elems()
+        .chunks(dim)
+        .map(|x| V::from_elems(x.to_vec()))
+        .collect()
+}
+
+// MaxSim: 每个查询向量取与文档中最近的向量的距离, 再求和
+// 内积下为负的 MaxSim 得分, 越小越相似
+pub(crate) fn max_sim<V: Metric<V>>(q: &[V], d: &[V], metric: MetricType) -> f32 {
+    q.iter()
+        .map(|x| {
+            d.iter()
+                .map(|y| x.distance(y, metric))
+                .fold(f32::INFINITY, f32::min)
+        })
+        .sum()
+}
+
+// 每个查询向量在索引中查找候选向量, 映射到文档去重后按 MaxSim 精确计算, 取前 k 个文档
+pub(crate) fn max_sim_search<V, S, F>(
+    q: &[V],
+    k: usize,
+    metric: MetricType,
+    docs: &MultiVectorDocs,
+    mut search: S,
+    mut doc_rows: F,
+) -> GyResult<Vec<Neighbor>>
+where
+    V: Metric<V>,
+    S: FnMut(&V, usize) -> GyResult<Vec<Neighbor>>,
+    F: FnMut(DocID) -> GyResult<Vec<V>>,
+{
+    let mut candidates: Vec<DocID> = Vec::new();
+    for x in q.iter() {
+        for n in search(x, k * MULTI_VECTOR_CANDIDATES)? {
+            candidates.push(docs.doc_id(n.id));
+        }
+    }
+    candidates.sort();
+    candidates.dedup();
+    let mut results = Vec::with_capacity(candidates.len());
+    for doc_id in candidates {
+        let d = doc_rows(doc_id)?;
+        results.push(Neighbor {
+            id: doc_id as usize,
+            d: max_sim(q, &d, metric),
+        });
+    }
+    results.sort_by(|a, b| a.d.partial_cmp(&b.d).unwrap());
+    results.truncate(k);
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multi_vector_docs() {
+        let mut docs = MultiVectorDocs::new();
+        assert_eq!(docs.push(2), 0);
+        assert_eq!(docs.push(0), 2);
+        assert_eq!(docs.push(3), 2);
+        assert_eq!(docs.doc_id(1), 0);
+        assert_eq!(docs.doc_id(2), 2);
+        assert_eq!(docs.doc_id(4), 2);
+        let merged = docs.merge(&docs);
+        assert_eq!(merged.doc_id(5), 3);
+        assert_eq!(merged.doc_id(6), 5);
+        let mut bytes: Vec<u8> = Vec::new();
+        merged.binary_serialize(&mut bytes).unwrap();
+        let d = MultiVectorDocs::binary_deserialize(&mut bytes.as_slice()).unwrap();
+        assert_eq!(d.doc_id(9), 5);
+
+        let q = vec![vec![1.0f32, 0.0], vec![0.0, 1.0]];
+        let d = vec![vec![0.5f32, 0.0], vec![0.0, 2.0], vec![1.0, 1.0]];
+        // 每个查询向量取最大内积: 1.0 + 2.0
+        assert_eq!(max_sim(&q, &d, MetricType::InnerProduct), -3.0);
+    }
+}
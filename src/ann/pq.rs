@@ -0,0 +1,612 @@
+use super::super::util::error::GyResult;
+use super::ivf::{kmeans, KMEANS_ITERS, MAX_POINTS_PER_CENTROID, MIN_POINTS_PER_CENTROID};
+use super::metric::normalize;
+use super::{read_align, write_align, AnnIndex, Metric, MetricType, Neighbor, VectorElems};
+use crate::disk::{GyRead, GyWrite};
+use crate::schema::BinarySerialize;
+use crate::TensorEntry;
+use crate::VectorSerialize;
+use rand::seq::index::sample;
+use serde::{Deserialize, Serialize};
+use std::collections::BinaryHeap;
+use std::io::{Read, Write};
+
+// 每个子空间 256 个码字, 编码为一个 u8
+const KSUB: usize = 256;
+const MAX_PQ_TRAIN_POINTS: usize = KSUB * 64;
+pub(crate) const DEFAULT_RERANK: usize = 4;
+
+// 乘积量化参数
+// m 为子空间个数, 0 表示训练时根据维度自动选择, 不能整除维度时同样自动选择
+// rerank 为用原始向量重排时候选集放大的倍数, 0 表示不重排
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct PqConfig {
+    pub m: usize,
+    pub rerank: usize,
+}
+
+impl Default for PqConfig {
+    fn default() -> Self {
+        PqConfig {
+            m: 0,
+            rerank: DEFAULT_RERANK,
+        }
+    }
+}
+
+fn l2_sqr(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b.iter()).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+// 子空间维度优先取 8, 768 维 f32 向量压缩为 96 字节
+fn default_m(dim: usize) -> usize {
+    for dsub in [8, 4, 2, 1] {
+        if dim % dsub == 0 {
+            return dim / dsub;
+        }
+    }
+    dim
+}
+
+// 乘积量化编码器
+// 把向量切成 m 段, 每段用独立训练的码本量化成一个字节
+pub(crate) struct ProductQuantizer {
+    m: usize,
+    dsub: usize,
+    codebooks: Vec<Vec<Vec<f32>>>, // m --> ksub --> dsub
+}
+
+impl BinarySerialize for ProductQuantizer {
+    fn binary_serialize<W: Write>(&self, writer: &mut W) -> GyResult<()> {
+        self.m.binary_serialize(writer)?;
+        self.dsub.binary_serialize(writer)?;
+        self.codebooks.binary_serialize(writer)
+    }
+
+    fn binary_deserialize<R: Read>(reader: &mut R) -> GyResult<Self> {
+        let m = usize::binary_deserialize(reader)?;
+        let dsub = usize::binary_deserialize(reader)?;
+        let codebooks = Vec::<Vec<Vec<f32>>>::binary_deserialize(reader)?;
+        Ok(ProductQuantizer {
+            m: m,
+            dsub: dsub,
+            codebooks: codebooks,
+        })
+    }
+}
+
+impl ProductQuantizer {
+    pub(crate) fn train(m: usize, samples: &[Vec<f32>]) -> ProductQuantizer {
+        let dim = samples.first().map(|s| s.len()).unwrap_or(0);
+        let m = if m == 0 || m > dim || dim % m != 0 {
+            default_m(dim)
+        } else {
+            m
+        };
+        let dsub = if m == 0 { 0 } else { dim / m };
+        let codebooks = (0..m)
+            .map(|j| {
+                let subs: Vec<Vec<f32>> = samples
+                    .iter()
+                    .map(|s| s[j * dsub..(j + 1) * dsub].to_vec())
+                    .collect();
+                let refs: Vec<&Vec<f32>> = subs.iter().collect();
+                kmeans(&refs, KSUB, KMEANS_ITERS, MetricType::L2)
+            })
+            .collect();
+        ProductQuantizer {
+            m: m,
+            dsub: dsub,
+            codebooks: codebooks,
+        }
+    }
+
+    pub(crate) fn code_size(&self) -> usize {
+        self.m
+    }
+
+    pub(crate) fn encode(&self, x: &[f32]) -> Vec<u8> {
+        self.codebooks
+            .iter()
+            .enumerate()
+            .map(|(j, codebook)| {
+                let sub = &x[j * self.dsub..(j + 1) * self.dsub];
+                let mut best = (0, f32::MAX);
+                for (c, word) in codebook.iter().enumerate() {
+                    let d = l2_sqr(sub, word);
+                    if d < best.1 {
+                        best = (c, d);
+                    }
+                }
+                best.0 as u8
+            })
+            .collect()
+    }
+
+    pub(crate) fn decode(&self, code: &[u8]) -> Vec<f32> {
+        let mut x = Vec::with_capacity(self.m * self.dsub);
+        for (j, c) in code.iter().enumerate() {
+            x.extend_from_slice(&self.codebooks[j][*c as usize]);
+        }
+        x
+    }
+
+    // 非对称距离表 table[j * KSUB + c] 为 x 第 j 段与码字 c 的可累加距离
+    pub(crate) fn distance_table(&self, x: &[f32], metric: MetricType) -> Vec<f32> {
+        let mut table = vec![f32::MAX; self.m * KSUB];
+        for (j, codebook) in self.codebooks.iter().enumerate() {
+            let sub = &x[j * self.dsub..(j + 1) * self.dsub];
+            for (c, word) in codebook.iter().enumerate() {
+                table[j * KSUB + c] = metric.partial(sub, word);
+            }
+        }
+        table
+    }
+
+    pub(crate) fn adc(&self, table: &[f32], code: &[u8]) -> f32 {
+        code.iter()
+            .enumerate()
+            .map(|(j, c)| table[j * KSUB + *c as usize])
+            .sum()
+    }
+}
+
+// 倒排表 codes 按 code_size 连续存放
+struct PqList {
+    ids: Vec<usize>,
+    codes: Vec<u8>,
+}
+
+impl PqList {
+    fn new() -> PqList {
+        PqList {
+            ids: Vec::new(),
+            codes: Vec::new(),
+        }
+    }
+}
+
+impl BinarySerialize for PqList {
+    fn binary_serialize<W: Write>(&self, writer: &mut W) -> GyResult<()> {
+        self.ids.binary_serialize(writer)?;
+        self.codes.binary_serialize(writer)
+    }
+
+    fn binary_deserialize<R: Read>(reader: &mut R) -> GyResult<Self> {
+        let ids = Vec::<usize>::binary_deserialize(reader)?;
+        let codes = Vec::<u8>::binary_deserialize(reader)?;
+        Ok(PqList {
+            ids: ids,
+            codes: codes,
+        })
+    }
+}
+
+// 倒排 + 乘积量化
+// 向量减去所属聚类中心后的残差用 PQ 编码, 索引中不保存原始向量,
+// 精确重排依赖 doc 块中的原始向量, 由 EngineReader / DiskStoreReader 完成
+pub struct IvfPQ<V: VectorSerialize + Clone> {
+    nlist: usize,
+    nprobe: usize,
+    m: usize,
+    rerank: usize,
+    metric: MetricType,
+    n_items: usize,
+    centroids: Vec<Vec<f32>>,
+    pq: Option<ProductQuantizer>,
+    lists: Vec<PqList>,
+    pending: Vec<V>, // 训练前的原始向量 下标即 id
+}
+
+impl<V: VectorSerialize + Clone> VectorSerialize for IvfPQ<V> {
+    fn vector_deserialize<R: Read + GyRead>(reader: &mut R, entry: &TensorEntry) -> GyResult<Self> {
+        let nlist = usize::binary_deserialize(reader)?;
+        let nprobe = usize::binary_deserialize(reader)?;
+        let m = usize::binary_deserialize(reader)?;
+        let rerank = usize::binary_deserialize(reader)?;
+        let metric = MetricType::binary_deserialize(reader)?;
+        let n_items = usize::binary_deserialize(reader)?;
+        let centroids = Vec::<Vec<f32>>::binary_deserialize(reader)?;
+        let pq = if u8::binary_deserialize(reader)? == 1 {
+            Some(ProductQuantizer::binary_deserialize(reader)?)
+        } else {
+            None
+        };
+        let lists = Vec::<PqList>::binary_deserialize(reader)?;
+        let pending_len = usize::binary_deserialize(reader)?;
+        read_align(reader)?;
+        let mut pending: Vec<V> = Vec::with_capacity(pending_len);
+        for _ in 0..pending_len {
+            pending.push(V::vector_deserialize(reader, entry)?);
+        }
+        Ok(IvfPQ {
+            nlist: nlist,
+            nprobe: nprobe,
+            m: m,
+            rerank: rerank,
+            metric: metric,
+            n_items: n_items,
+            centroids: centroids,
+            pq: pq,
+            lists: lists,
+            pending: pending,
+        })
+    }
+
+    fn vector_serialize<W: Write + GyWrite>(&self, writer: &mut W) -> GyResult<()> {
+        self.nlist.binary_serialize(writer)?;
+        self.nprobe.binary_serialize(writer)?;
+        self.m.binary_serialize(writer)?;
+        self.rerank.binary_serialize(writer)?;
+        self.metric.binary_serialize(writer)?;
+        self.n_items.binary_serialize(writer)?;
+        self.centroids.binary_serialize(writer)?;
+        match &self.pq {
+            Some(pq) => {
+                1u8.binary_serialize(writer)?;
+                pq.binary_serialize(writer)?;
+            }
+            None => 0u8.binary_serialize(writer)?,
+        }
+        self.lists.binary_serialize(writer)?;
+        self.pending.len().binary_serialize(writer)?;
+        write_align(writer)?;
+        for v in self.pending.iter() {
+            v.vector_serialize(writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl<V: VectorSerialize + Clone> AnnIndex<V> for IvfPQ<V>
+where
+    V: Metric<V> + VectorElems,
+{
+    fn insert(&mut self, q: V) -> GyResult<usize> {
+        let id = self.n_items;
+        self.n_items += 1;
+        if self.pq.is_none() {
+            self.pending.push(q);
+            if self.n_items >= self.nlist * MIN_POINTS_PER_CENTROID {
+                self.train();
+            }
+        } else {
+            self.add_encoded(id, &self.prepare_elems(&q));
+        }
+        Ok(id)
+    }
+
+    fn query(&self, q: &V, k: usize) -> GyResult<Vec<Neighbor>> {
+        self.search(q, k, self.nprobe)
+    }
+}
+
+impl<V: VectorSerialize + Clone> IvfPQ<V>
+where
+    V: Metric<V> + VectorElems,
+{
+    // m 为 0 时训练时根据维度自动选择
+    pub fn new(nlist: usize, nprobe: usize, m: usize) -> IvfPQ<V> {
+        Self {
+            nlist: nlist.max(1),
+            nprobe: nprobe.max(1),
+            m: m,
+            rerank: DEFAULT_RERANK,
+            metric: MetricType::L2,
+            n_items: 0,
+            centroids: Vec::new(),
+            pq: None,
+            lists: Vec::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn with_rerank(mut self, rerank: usize) -> IvfPQ<V> {
+        self.rerank = rerank;
+        self
+    }
+
+    pub fn with_metric(mut self, metric: MetricType) -> IvfPQ<V> {
+        self.metric = metric;
+        self
+    }
+
+    pub fn metric(&self) -> MetricType {
+        self.metric
+    }
+
+    pub fn is_trained(&self) -> bool {
+        self.pq.is_some()
+    }
+
+    pub fn set_nprobe(&mut self, nprobe: usize) {
+        self.nprobe = nprobe.max(1);
+    }
+
+    pub fn nprobe(&self) -> usize {
+        self.nprobe
+    }
+
+    // 重排时候选集放大的倍数, 0 表示不重排
+    pub fn set_rerank(&mut self, rerank: usize) {
+        self.rerank = rerank;
+    }
+
+    pub fn rerank_factor(&self) -> usize {
+        if self.is_trained() {
+            self.rerank
+        } else {
+            0
+        }
+    }
+
+    // 返回的距离是 PQ 近似距离
+    pub fn search(&self, q: &V, k: usize, nprobe: usize) -> GyResult<Vec<Neighbor>> {
+        if k == 0 {
+            return Ok(Vec::new());
+        }
+        let mut results: BinaryHeap<Neighbor> = BinaryHeap::with_capacity(k + 1);
+        let mut push = |id: usize, d: f32| {
+            if results.len() < k {
+                results.push(Neighbor { id: id, d: d });
+            } else if d < results.peek().unwrap().d {
+                results.pop();
+                results.push(Neighbor { id: id, d: d });
+            }
+        };
+        match &self.pq {
+            None => {
+                for (id, v) in self.pending.iter().enumerate() {
+                    push(id, q.distance(v, self.metric));
+                }
+            }
+            Some(pq) => {
+                let x = self.prepare_elems(q);
+                let mut probes: BinaryHeap<Neighbor> = BinaryHeap::with_capacity(nprobe + 1);
+                for (i, c) in self.centroids.iter().enumerate() {
+                    probes.push(Neighbor {
+                        id: i,
+                        d: self.metric.distance(&x, c),
+                    });
+                    if probes.len() > nprobe.max(1) {
+                        probes.pop();
+                    }
+                }
+                let code_size = pq.code_size();
+                let metric = self.metric;
+                for p in probes.into_iter() {
+                    let centroid = &self.centroids[p.id];
+                    // 内积: <q, c + r> = <q, c> + <q, r>, 其它度量直接比较 q - c 与残差
+                    let (base, table) = if metric.is_inner_product() {
+                        (metric.partial(&x, centroid), pq.distance_table(&x, metric))
+                    } else {
+                        let residual: Vec<f32> =
+                            x.iter().zip(centroid.iter()).map(|(a, b)| a - b).collect();
+                        (0.0, pq.distance_table(&residual, metric))
+                    };
+                    let list = &self.lists[p.id];
+                    for (i, id) in list.ids.iter().enumerate() {
+                        let code = &list.codes[i * code_size..(i + 1) * code_size];
+                        push(*id, metric.finish(base + pq.adc(&table, code)));
+                    }
+                }
+            }
+        }
+        Ok(results.into_sorted_vec())
+    }
+
+    // 优先沿用已训练一侧的量化器, b 的 id 整体偏移 a.n_items
+    pub fn merge(&self, other: &Self) -> GyResult<IvfPQ<V>> {
+        let mut new_pq = IvfPQ::<V>::new(self.nlist, self.nprobe, self.m);
+        new_pq.rerank = self.rerank;
+        new_pq.metric = self.metric;
+        let base = if self.is_trained() {
+            Some(self)
+        } else if other.is_trained() {
+            Some(other)
+        } else {
+            None
+        };
+        match base {
+            None => {
+                for v in self.pending.iter().chain(other.pending.iter()) {
+                    new_pq.insert(v.clone())?;
+                }
+            }
+            Some(base) => {
+                new_pq.centroids = base.centroids.clone();
+                new_pq.pq = base.pq.as_ref().map(|pq| ProductQuantizer {
+                    m: pq.m,
+                    dsub: pq.dsub,
+                    codebooks: pq.codebooks.clone(),
+                });
+                new_pq.lists = (0..base.centroids.len()).map(|_| PqList::new()).collect();
+                new_pq.append(self, 0, std::ptr::eq(self, base));
+                new_pq.append(other, self.n_items, std::ptr::eq(other, base));
+                new_pq.n_items = self.n_items + other.n_items;
+            }
+        }
+        Ok(new_pq)
+    }
+
+    // 共用量化器时直接拷贝编码, 否则解码后重新编码
+    fn append(&mut self, other: &Self, offset: usize, same_quantizer: bool) {
+        match &other.pq {
+            None => {
+                for (id, v) in other.pending.iter().enumerate() {
+                    self.add_encoded(id + offset, &self.prepare_elems(v));
+                }
+            }
+            Some(pq) => {
+                let code_size = pq.code_size();
+                for (c, list) in other.lists.iter().enumerate() {
+                    for (i, id) in list.ids.iter().enumerate() {
+                        let code = &list.codes[i * code_size..(i + 1) * code_size];
+                        if same_quantizer {
+                            self.lists[c].ids.push(id + offset);
+                            self.lists[c].codes.extend_from_slice(code);
+                        } else {
+                            let x: Vec<f32> = pq
+                                .decode(code)
+                                .iter()
+                                .zip(other.centroids[c].iter())
+                                .map(|(r, b)| r + b)
+                                .collect();
+                            self.add_encoded(id + offset, &x);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn add_encoded(&mut self, id: usize, x: &[f32]) {
+        let c = self.nearest_centroid(x);
+        let residual: Vec<f32> = x
+            .iter()
+            .zip(self.centroids[c].iter())
+            .map(|(a, b)| a - b)
+            .collect();
+        let code = self.pq.as_ref().expect("pq not trained").encode(&residual);
+        self.lists[c].ids.push(id);
+        self.lists[c].codes.extend(code);
+    }
+
+    // 余弦距离先归一化, 之后按内积处理
+    fn prepare_elems(&self, v: &V) -> Vec<f32> {
+        let mut x = v.to_elems();
+        if self.metric == MetricType::Cosine {
+            normalize(&mut x);
+        }
+        x
+    }
+
+    fn nearest_centroid(&self, x: &[f32]) -> usize {
+        let mut best = (0, f32::MAX);
+        for (i, c) in self.centroids.iter().enumerate() {
+            let d = l2_sqr(x, c);
+            if d < best.1 {
+                best = (i, d);
+            }
+        }
+        best.0
+    }
+
+    // 先训练粗聚类中心, 再用残差训练 PQ 码本, 最后编码所有暂存向量
+    fn train(&mut self) {
+        let elems: Vec<Vec<f32>> = self.pending.iter().map(|v| self.prepare_elems(v)).collect();
+        let mut rng = rand::thread_rng();
+        let coarse_sample: Vec<&Vec<f32>> = if elems.len() > self.nlist * MAX_POINTS_PER_CENTROID {
+            sample(&mut rng, elems.len(), self.nlist * MAX_POINTS_PER_CENTROID)
+                .into_iter()
+                .map(|i| &elems[i])
+                .collect()
+        } else {
+            elems.iter().collect()
+        };
+        self.centroids = kmeans(&coarse_sample, self.nlist, KMEANS_ITERS, MetricType::L2);
+        let pq_sample: Vec<Vec<f32>> =
+            sample(&mut rng, elems.len(), elems.len().min(MAX_PQ_TRAIN_POINTS))
+                .into_iter()
+                .map(|i| {
+                    let c = self.nearest_centroid(&elems[i]);
+                    elems[i]
+                        .iter()
+                        .zip(self.centroids[c].iter())
+                        .map(|(a, b)| a - b)
+                        .collect()
+                })
+                .collect();
+        self.pq = Some(ProductQuantizer::train(self.m, &pq_sample));
+        self.lists = (0..self.centroids.len()).map(|_| PqList::new()).collect();
+        for (id, x) in elems.iter().enumerate() {
+            self.add_encoded(id, x);
+        }
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ann::random_vectors;
+    use crate::ann::Flat;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_pq_codec() {
+        let samples = random_vectors(1000, 16);
+        let pq = ProductQuantizer::train(0, &samples);
+        assert_eq!(pq.code_size(), 2);
+        let code = pq.encode(&samples[3]);
+        let table = pq.distance_table(&samples[3], MetricType::L2);
+        let d = pq.adc(&table, &code);
+        assert!(d <= l2_sqr(&samples[3], &pq.decode(&code)) + 1e-4);
+    }
+
+    #[test]
+    fn test_ivf_pq_search() {
+        let mut ivf_pq = IvfPQ::<Vec<f32>>::new(4, 4, 0);
+        let features = random_vectors(2000, 32);
+        for f in features.iter() {
+            ivf_pq.insert(f.clone()).unwrap();
+        }
+        assert!(ivf_pq.is_trained());
+        assert!(ivf_pq.pending.is_empty());
+        let neighbors = ivf_pq.query(&features[10], 20).unwrap();
+        assert!(neighbors.iter().any(|n| n.doc_id() == 10));
+    }
+
+    #[test]
+    fn test_ivf_pq_config() {
+        let mut ivf_pq = IvfPQ::<Vec<f32>>::new(4, 4, 4).with_rerank(0);
+        assert_eq!(ivf_pq.rerank_factor(), 0);
+        for f in random_vectors(2000, 32) {
+            ivf_pq.insert(f).unwrap();
+        }
+        assert!(ivf_pq.is_trained());
+        // 32 维切成 4 段, 每个向量编码为 4 字节
+        assert_eq!(ivf_pq.pq.as_ref().unwrap().code_size(), 4);
+        assert_eq!(ivf_pq.rerank_factor(), 0);
+        let merged = ivf_pq.merge(&ivf_pq).unwrap();
+        assert_eq!(merged.m, 4);
+        assert_eq!(merged.rerank_factor(), 0);
+    }
+
+    #[test]
+    fn test_ivf_pq_size() {
+        let features = random_vectors(4000, 32);
+        let mut ivf_pq = IvfPQ::<Vec<f32>>::new(4, 4, 0);
+        let mut flat = Flat::<Vec<f32>>::new();
+        for f in features {
+            ivf_pq.insert(f.clone()).unwrap();
+            flat.insert(f).unwrap();
+        }
+        let mut pq_bytes: Vec<u8> = Vec::new();
+        ivf_pq
+            .vector_serialize(&mut Cursor::new(&mut pq_bytes))
+            .unwrap();
+        let mut flat_bytes: Vec<u8> = Vec::new();
+        flat.vector_serialize(&mut Cursor::new(&mut flat_bytes))
+            .unwrap();
+        assert!(pq_bytes.len() * 4 < flat_bytes.len());
+    }
+
+    #[test]
+    fn test_ivf_pq_merge() {
+        let mut a = IvfPQ::<Vec<f32>>::new(2, 2, 0);
+        let mut b = IvfPQ::<Vec<f32>>::new(2, 2, 0);
+        let features = random_vectors(250, 8);
+        for f in features[..200].iter() {
+            a.insert(f.clone()).unwrap();
+        }
+        for f in features[200..].iter() {
+            b.insert(f.clone()).unwrap();
+        }
+        assert!(a.is_trained());
+        assert!(!b.is_trained());
+        let ivf_pq = a.merge(&b).unwrap();
+        assert_eq!(ivf_pq.n_items, 250);
+        assert_eq!(ivf_pq.lists.iter().map(|l| l.ids.len()).sum::<usize>(), 250);
+    }
+}
@@ -0,0 +1,241 @@
+use super::Neighbor;
+use crate::schema::{BinarySerialize, DocID, VUInt, VarIntSerialize};
+use crate::util::error::GyResult;
+use std::collections::BinaryHeap;
+use std::io::{Read, Write};
+
+// 稀疏向量, 只保存非零维度, 按维度升序
+// 每个维度作为一个 term 写入倒排表, 倒排表中保存文档在该维度的权重
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct SparseVector {
+    dims: Vec<(u32, f32)>,
+}
+
+impl SparseVector {
+    // 重复的维度权重相加, 去掉权重为 0 的维度
+    pub fn new(mut dims: Vec<(u32, f32)>) -> SparseVector {
+        dims.sort_by_key(|x| x.0);
+        let mut v: Vec<(u32, f32)> = Vec::with_capacity(dims.len());
+        for (dim, w) in dims {
+            match v.last_mut() {
+                Some(last) if last.0 == dim => last.1 += w,
+                _ => v.push((dim, w)),
+            }
+        }
+        v.retain(|x| x.1 != 0.0);
+        SparseVector { dims: v }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(u32, f32)> {
+        self.dims.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.dims.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dims.is_empty()
+    }
+
+    pub fn dot(&self, other: &SparseVector) -> f32 {
+        let (mut i, mut j) = (0, 0);
+        let mut s = 0.0;
+        while i < self.dims.len() && j < other.dims.len() {
+            let (a, b) = (&self.dims[i], &other.dims[j]);
+            if a.0 == b.0 {
+                s += a.1 * b.1;
+                i += 1;
+                j += 1;
+            } else if a.0 < b.0 {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        s
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        varintrs::vint_size!(self.dims.len()) as usize + self.dims.len() * 8
+    }
+}
+
+impl BinarySerialize for SparseVector {
+    fn binary_serialize<W: Write>(&self, writer: &mut W) -> GyResult<()> {
+        VUInt(self.dims.len() as u64).binary_serialize(writer)?;
+        for (dim, w) in self.dims.iter() {
+            dim.binary_serialize(writer)?;
+            w.binary_serialize(writer)?;
+        }
+        Ok(())
+    }
+
+    fn binary_deserialize<R: Read>(reader: &mut R) -> GyResult<Self> {
+        let n = VUInt::binary_deserialize(reader)?.0.val() as usize;
+        let mut dims = Vec::with_capacity(n);
+        for _ in 0..n {
+            let dim = u32::binary_deserialize(reader)?;
+            let w = f32::binary_deserialize(reader)?;
+            dims.push((dim, w));
+        }
+        Ok(SparseVector { dims: dims })
+    }
+}
+
+// 维度对应的 term, 大端保证 fst 中按维度排序
+pub(crate) fn dim_term(dim: u32) -> Vec<u8> {
+    dim.to_be_bytes().to_vec()
+}
+
+// 查询向量一个维度在倒排表上的游标
+pub(crate) struct SparseCursor<'a> {
+    iter: Box<dyn Iterator<Item = (DocID, f32)> + 'a>,
+    cur: Option<(DocID, f32)>,
+    weight: f32,
+    // 该维度得分的上界, max_weight 为倒排表中权重绝对值的最大值
+    ub: f32,
+}
+
+impl<'a> SparseCursor<'a> {
+    pub(crate) fn new<I: Iterator<Item = (DocID, f32)> + 'a>(
+        iter: I,
+        weight: f32,
+        max_weight: f32,
+    ) -> SparseCursor<'a> {
+        let mut iter: Box<dyn Iterator<Item = (DocID, f32)> + 'a> = Box::new(iter);
+        let cur = iter.next();
+        SparseCursor {
+            iter: iter,
+            cur: cur,
+            weight: weight,
+            ub: weight.abs() * max_weight,
+        }
+    }
+
+    fn doc(&self) -> DocID {
+        self.cur.map_or(DocID::MAX, |x| x.0)
+    }
+
+    fn score(&self) -> f32 {
+        self.cur.map_or(0.0, |x| x.1 * self.weight)
+    }
+
+    fn next(&mut self) {
+        self.cur = self.iter.next();
+    }
+
+    // 倒排表没有跳表, 只能顺序前进到第一个不小于 target 的文档
+    fn seek(&mut self, target: DocID) {
+        while self.doc() < target {
+            self.next();
+        }
+    }
+}
+
+// WAND: 游标按当前文档排序, 依次累加上界直到超过第 k 个结果的得分, 该游标的文档为 pivot
+// pivot 之前的文档即使命中所有维度也进不了前 k, 直接跳过
+// 返回的 Neighbor 距离为负的内积, 与 MetricType::InnerProduct 一致
+pub(crate) fn wand(mut cursors: Vec<SparseCursor>, k: usize) -> Vec<Neighbor> {
+    // 大顶堆 堆顶为当前 k 个结果中得分最低的文档
+    let mut results: BinaryHeap<Neighbor> = BinaryHeap::with_capacity(k + 1);
+    if k == 0 {
+        return Vec::new();
+    }
+    cursors.retain(|c| c.cur.is_some());
+    while !cursors.is_empty() {
+        cursors.sort_by_key(|c| c.doc());
+        let threshold = if results.len() < k {
+            f32::NEG_INFINITY
+        } else {
+            -results.peek().unwrap().d
+        };
+        let mut acc = 0.0;
+        let pivot = match cursors.iter().position(|c| {
+            acc += c.ub;
+            acc > threshold
+        }) {
+            Some(p) => p,
+            None => break,
+        };
+        let pivot_doc = cursors[pivot].doc();
+        if cursors[0].doc() == pivot_doc {
+            let mut score = 0.0;
+            for c in cursors.iter_mut() {
+                if c.doc() != pivot_doc {
+                    break;
+                }
+                score += c.score();
+                c.next();
+            }
+            if results.len() < k {
+                results.push(Neighbor {
+                    id: pivot_doc as usize,
+                    d: -score,
+                });
+            } else if score > threshold {
+                results.pop();
+                results.push(Neighbor {
+                    id: pivot_doc as usize,
+                    d: -score,
+                });
+            }
+        } else {
+            cursors[..pivot].iter_mut().for_each(|c| c.seek(pivot_doc));
+        }
+        cursors.retain(|c| c.cur.is_some());
+    }
+    let mut results = results.into_vec();
+    results.sort_by(|a, b| a.d.partial_cmp(&b.d).unwrap());
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn test_wand() {
+        let mut rng = rand::thread_rng();
+        let docs: Vec<SparseVector> = (0..300)
+            .map(|_| {
+                SparseVector::new(
+                    (0..8)
+                        .map(|_| (rng.gen_range(0..50), rng.gen_range(0.0..1.0)))
+                        .collect(),
+                )
+            })
+            .collect();
+        let q = SparseVector::new(vec![(1, 0.5), (7, 1.0), (20, 0.3), (49, 2.0)]);
+        // 按维度建倒排表
+        let postings: Vec<(f32, f32, Vec<(DocID, f32)>)> = q
+            .iter()
+            .map(|(dim, qw)| {
+                let p: Vec<(DocID, f32)> = docs
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, d)| d.iter().find(|x| x.0 == *dim).map(|x| (i as DocID, x.1)))
+                    .collect();
+                let max = p.iter().fold(0.0f32, |m, x| m.max(x.1.abs()));
+                (*qw, max, p)
+            })
+            .collect();
+        let cursors = postings
+            .iter()
+            .map(|(qw, max, p)| SparseCursor::new(p.iter().cloned(), *qw, *max))
+            .collect();
+        let results = wand(cursors, 5);
+        let mut expect: Vec<(usize, f32)> = docs
+            .iter()
+            .enumerate()
+            .map(|(i, d)| (i, q.dot(d)))
+            .filter(|x| x.1 != 0.0)
+            .collect();
+        expect.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        assert_eq!(results.len(), 5);
+        for (n, e) in results.iter().zip(expect.iter()) {
+            assert!((-n.distance() - e.1).abs() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,486 @@
+use super::super::util::error::GyResult;
+use super::{read_align, write_align, Metric, MetricType, VectorElems};
+use crate::disk::{GyRead, GyWrite};
+use crate::schema::BinarySerialize;
+use crate::TensorEntry;
+use crate::VectorSerialize;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+// 攒够这么多向量后训练每一维的取值范围
+const SQ8_TRAIN_SIZE: usize = 1024;
+const SQ8_LEVELS: f32 = 255.0;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
+pub enum Quantizer {
+    #[default]
+    None,
+    SQ8,
+}
+
+impl Quantizer {
+    fn to_u8(&self) -> u8 {
+        match self {
+            Quantizer::None => 0,
+            Quantizer::SQ8 => 1,
+        }
+    }
+
+    fn from_u8(u: u8) -> GyResult<Self> {
+        match u {
+            0 => Ok(Quantizer::None),
+            1 => Ok(Quantizer::SQ8),
+            _ => Err("invalid quantizer".into()),
+        }
+    }
+}
+
+// SQ8 每一维的取值范围, 超出范围的值都会截断
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
+pub enum SqRange {
+    // 用最先写入的 SQ8_TRAIN_SIZE 个向量训练
+    #[default]
+    Train,
+    // 所有维度使用固定的 [min, max], 不需要训练
+    // 各个段的量化器相同, 合并时直接复制编码
+    Fixed {
+        min: f32,
+        max: f32,
+    },
+}
+
+// 8bit 标量量化
+// 每一维按训练得到的 [min, max] 线性映射到 0..=255, 超出范围的值截断
+#[derive(Clone, PartialEq)]
+pub(crate) struct ScalarQuantizer {
+    vmin: Vec<f32>,
+    vdiff: Vec<f32>,
+}
+
+impl BinarySerialize for ScalarQuantizer {
+    fn binary_serialize<W: Write>(&self, writer: &mut W) -> GyResult<()> {
+        self.vmin.binary_serialize(writer)?;
+        self.vdiff.binary_serialize(writer)
+    }
+
+    fn binary_deserialize<R: Read>(reader: &mut R) -> GyResult<Self> {
+        let vmin = Vec::<f32>::binary_deserialize(reader)?;
+        let vdiff = Vec::<f32>::binary_deserialize(reader)?;
+        Ok(ScalarQuantizer {
+            vmin: vmin,
+            vdiff: vdiff,
+        })
+    }
+}
+
+impl ScalarQuantizer {
+    pub(crate) fn train(samples: &[Vec<f32>]) -> ScalarQuantizer {
+        let dim = samples.first().map(|s| s.len()).unwrap_or(0);
+        let mut vmin = vec![f32::MAX; dim];
+        let mut vmax = vec![f32::MIN; dim];
+        for s in samples.iter() {
+            for (d, x) in s.iter().enumerate() {
+                vmin[d] = vmin[d].min(*x);
+                vmax[d] = vmax[d].max(*x);
+            }
+        }
+        let vdiff = vmin.iter().zip(vmax.iter()).map(|(a, b)| b - a).collect();
+        ScalarQuantizer {
+            vmin: vmin,
+            vdiff: vdiff,
+        }
+    }
+
+    pub(crate) fn fixed(dim: usize, min: f32, max: f32) -> ScalarQuantizer {
+        ScalarQuantizer {
+            vmin: vec![min; dim],
+            vdiff: vec![max - min; dim],
+        }
+    }
+
+    pub(crate) fn dim(&self) -> usize {
+        self.vmin.len()
+    }
+
+    pub(crate) fn encode(&self, x: &[f32], codes: &mut Vec<u8>) {
+        for (d, v) in x.iter().enumerate() {
+            let code = if self.vdiff[d] > 0.0 {
+                ((v - self.vmin[d]) / self.vdiff[d] * SQ8_LEVELS)
+                    .round()
+                    .clamp(0.0, SQ8_LEVELS)
+            } else {
+                0.0
+            };
+            codes.push(code as u8);
+        }
+    }
+
+    #[inline]
+    fn decode_at(&self, d: usize, code: u8) -> f32 {
+        self.vmin[d] + code as f32 / SQ8_LEVELS * self.vdiff[d]
+    }
+
+    pub(crate) fn decode(&self, code: &[u8]) -> Vec<f32> {
+        code.iter()
+            .enumerate()
+            .map(|(d, c)| self.decode_at(d, *c))
+            .collect()
+    }
+
+    pub(crate) fn distance(&self, q: &[f32], code: &[u8], metric: MetricType) -> f32 {
+        match metric {
+            MetricType::L2 => q
+                .iter()
+                .zip(code.iter())
+                .enumerate()
+                .map(|(d, (x, c))| (x - self.decode_at(d, *c)).powi(2))
+                .sum::<f32>()
+                .sqrt(),
+            _ => metric.distance(q, &self.decode(code)),
+        }
+    }
+
+    pub(crate) fn distance_codes(&self, a: &[u8], b: &[u8], metric: MetricType) -> f32 {
+        match metric {
+            MetricType::L2 => a
+                .iter()
+                .zip(b.iter())
+                .enumerate()
+                .map(|(d, (x, y))| (*x as f32 - *y as f32) * self.vdiff[d] / SQ8_LEVELS)
+                .map(|x| x * x)
+                .sum::<f32>()
+                .sqrt(),
+            _ => metric.distance(&self.decode(a), &self.decode(b)),
+        }
+    }
+}
+
+// 查询向量, 量化后提前展开成 f32 避免每次计算距离都转换一次
+pub(crate) enum QueryVec<'a, V> {
+    Raw(&'a V),
+    Elems(Vec<f32>),
+}
+
+// HNSW / Flat 的向量存储
+// 开启 SQ8 后先保存原始向量, 数量达到 SQ8_TRAIN_SIZE 时训练并全部转为 u8 编码
+// 固定取值范围时不需要训练, 写入时直接编码
+pub(crate) struct VectorStore<V: VectorSerialize + Clone> {
+    quantizer: Quantizer,
+    // 只影响之后写入的向量, 不写入段文件
+    range: SqRange,
+    metric: MetricType,
+    vectors: Vec<V>,
+    sq: Option<ScalarQuantizer>,
+    codes: Vec<u8>,
+    n_codes: usize,
+}
+
+impl<V: VectorSerialize + Clone> VectorSerialize for VectorStore<V> {
+    fn vector_deserialize<R: Read + GyRead>(reader: &mut R, entry: &TensorEntry) -> GyResult<Self> {
+        let quantizer = Quantizer::from_u8(u8::binary_deserialize(reader)?)?;
+        let metric = MetricType::binary_deserialize(reader)?;
+        let (sq, codes, n_codes) = if u8::binary_deserialize(reader)? == 1 {
+            let sq = ScalarQuantizer::binary_deserialize(reader)?;
+            let n_codes = usize::binary_deserialize(reader)?;
+            let codes = reader.read_bytes(n_codes * sq.dim())?.to_vec();
+            (Some(sq), codes, n_codes)
+        } else {
+            (None, Vec::new(), 0)
+        };
+        let vector_len = usize::binary_deserialize(reader)?;
+        read_align(reader)?;
+        let mut vectors: Vec<V> = Vec::with_capacity(vector_len);
+        for _ in 0..vector_len {
+            vectors.push(V::vector_deserialize(reader, entry)?);
+        }
+        Ok(VectorStore {
+            quantizer: quantizer,
+            range: SqRange::Train,
+            metric: metric,
+            vectors: vectors,
+            sq: sq,
+            codes: codes,
+            n_codes: n_codes,
+        })
+    }
+
+    fn vector_serialize<W: Write + GyWrite>(&self, writer: &mut W) -> GyResult<()> {
+        self.quantizer.to_u8().binary_serialize(writer)?;
+        self.metric.binary_serialize(writer)?;
+        match &self.sq {
+            Some(sq) => {
+                1u8.binary_serialize(writer)?;
+                sq.binary_serialize(writer)?;
+                self.n_codes.binary_serialize(writer)?;
+                writer.write_all(&self.codes)?;
+            }
+            None => 0u8.binary_serialize(writer)?,
+        }
+        self.vectors.len().binary_serialize(writer)?;
+        write_align(writer)?;
+        for v in self.vectors.iter() {
+            v.vector_serialize(writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl<V: VectorSerialize + Clone> VectorStore<V>
+where
+    V: Metric<V> + VectorElems,
+{
+    pub(crate) fn new(quantizer: Quantizer) -> VectorStore<V> {
+        Self {
+            quantizer: quantizer,
+            range: SqRange::Train,
+            metric: MetricType::L2,
+            vectors: Vec::with_capacity(10000),
+            sq: None,
+            codes: Vec::new(),
+            n_codes: 0,
+        }
+    }
+
+    pub(crate) fn quantizer(&self) -> Quantizer {
+        self.quantizer
+    }
+
+    pub(crate) fn metric(&self) -> MetricType {
+        self.metric
+    }
+
+    pub(crate) fn set_metric(&mut self, metric: MetricType) {
+        self.metric = metric;
+    }
+
+    pub(crate) fn set_range(&mut self, range: SqRange) {
+        self.range = range;
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.vectors.len() + self.n_codes
+    }
+
+    pub(crate) fn is_quantized(&self) -> bool {
+        self.sq.is_some()
+    }
+
+    pub(crate) fn push(&mut self, v: V) {
+        if let SqRange::Fixed { min, max } = self.range {
+            if self.quantizer == Quantizer::SQ8 && self.sq.is_none() {
+                self.sq = Some(ScalarQuantizer::fixed(v.to_elems().len(), min, max));
+            }
+        }
+        if let Some(sq) = &self.sq {
+            sq.encode(&v.to_elems(), &mut self.codes);
+            self.n_codes += 1;
+            return;
+        }
+        self.vectors.push(v);
+        if self.quantizer == Quantizer::SQ8 && self.vectors.len() >= SQ8_TRAIN_SIZE {
+            self.train();
+        }
+    }
+
+    // 未量化时返回原始向量
+    pub(crate) fn raw(&self) -> &[V] {
+        &self.vectors
+    }
+
+    // 量化后返回的是解码后的近似向量
+    pub(crate) fn get(&self, id: usize) -> V {
+        match &self.sq {
+            Some(sq) => V::from_elems(sq.decode(self.code(id))),
+            None => self.vectors.get(id).expect("get vector fail").clone(),
+        }
+    }
+
+    pub(crate) fn prepare<'a>(&self, q: &'a V) -> QueryVec<'a, V> {
+        match &self.sq {
+            Some(_) => QueryVec::Elems(q.to_elems()),
+            None => QueryVec::Raw(q),
+        }
+    }
+
+    pub(crate) fn prepare_id(&self, id: usize) -> QueryVec<'_, V> {
+        match &self.sq {
+            Some(sq) => QueryVec::Elems(sq.decode(self.code(id))),
+            None => QueryVec::Raw(self.vectors.get(id).expect("get vector fail")),
+        }
+    }
+
+    pub(crate) fn distance(&self, q: &QueryVec<V>, id: usize) -> f32 {
+        match (q, &self.sq) {
+            (QueryVec::Elems(x), Some(sq)) => sq.distance(x, self.code(id), self.metric),
+            (QueryVec::Raw(v), None) => {
+                v.distance(self.vectors.get(id).expect("get vector fail"), self.metric)
+            }
+            (QueryVec::Raw(v), Some(sq)) => sq.distance(&v.to_elems(), self.code(id), self.metric),
+            (QueryVec::Elems(x), None) => {
+                V::from_elems(x.clone()).distance(&self.vectors[id], self.metric)
+            }
+        }
+    }
+
+    pub(crate) fn distance_between(&self, a: usize, b: usize) -> f32 {
+        match &self.sq {
+            Some(sq) => sq.distance_codes(self.code(a), self.code(b), self.metric),
+            None => self.vectors[a].distance(&self.vectors[b], self.metric),
+        }
+    }
+
+    // 合并两个存储, other 的编号接在 self 之后
+    // 量化器相同的一侧直接复制编码, 未量化的原始向量只编码一次
+    // 两侧量化器不同时沿用编码较多一侧的量化器, 另一侧解码后重新编码
+    pub(crate) fn merge(&self, other: &Self) -> VectorStore<V> {
+        let mut store = VectorStore::new(self.quantizer);
+        store.set_range(self.range);
+        store.set_metric(self.metric);
+        let base = match (&self.sq, &other.sq) {
+            (Some(_), Some(b)) if other.n_codes > self.n_codes => Some(b.clone()),
+            (Some(a), _) => Some(a.clone()),
+            (None, Some(b)) => Some(b.clone()),
+            (None, None) => None,
+        };
+        let base = match base {
+            Some(base) => base,
+            None => {
+                for v in self.vectors.iter().chain(other.vectors.iter()) {
+                    store.push(v.clone());
+                }
+                return store;
+            }
+        };
+        for side in [self, other] {
+            match &side.sq {
+                Some(sq) if *sq == base => store.codes.extend_from_slice(&side.codes),
+                Some(sq) => {
+                    for id in 0..side.n_codes {
+                        base.encode(&sq.decode(side.code(id)), &mut store.codes);
+                    }
+                }
+                None => {
+                    for v in side.vectors.iter() {
+                        base.encode(&v.to_elems(), &mut store.codes);
+                    }
+                }
+            }
+        }
+        store.n_codes = self.len() + other.len();
+        store.sq = Some(base);
+        store
+    }
+
+    fn code(&self, id: usize) -> &[u8] {
+        let dim = self.sq.as_ref().map(|sq| sq.dim()).unwrap_or(0);
+        &self.codes[id * dim..(id + 1) * dim]
+    }
+
+    fn train(&mut self) {
+        let elems: Vec<Vec<f32>> = self.vectors.iter().map(|v| v.to_elems()).collect();
+        let sq = ScalarQuantizer::train(&elems);
+        self.codes = Vec::with_capacity(elems.len() * sq.dim());
+        for x in elems.iter() {
+            sq.encode(x, &mut self.codes);
+        }
+        self.n_codes = elems.len();
+        self.sq = Some(sq);
+        self.vectors = Vec::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ann::random_vectors;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_sq8_codec() {
+        let samples = random_vectors(100, 16);
+        let sq = ScalarQuantizer::train(&samples);
+        let mut codes = Vec::new();
+        sq.encode(&samples[7], &mut codes);
+        assert_eq!(codes.len(), 16);
+        for (a, b) in sq.decode(&codes).iter().zip(samples[7].iter()) {
+            assert!((a - b).abs() <= 1.0 / SQ8_LEVELS);
+        }
+        assert!(sq.distance(&samples[7], &codes, MetricType::L2) < 0.01);
+    }
+
+    #[test]
+    fn test_sq8_store() {
+        let mut store = VectorStore::<Vec<f32>>::new(Quantizer::SQ8);
+        let features = random_vectors(SQ8_TRAIN_SIZE + 100, 32);
+        for f in features.iter() {
+            store.push(f.clone());
+        }
+        assert!(store.is_quantized());
+        assert_eq!(store.len(), features.len());
+        assert!(store.raw().is_empty());
+        let q = store.prepare(&features[SQ8_TRAIN_SIZE + 50]);
+        assert!(store.distance(&q, SQ8_TRAIN_SIZE + 50) < 0.05);
+
+        let mut bytes: Vec<u8> = Vec::new();
+        store
+            .vector_serialize(&mut Cursor::new(&mut bytes))
+            .unwrap();
+        // 32 维 f32 压缩为 32 字节
+        assert!(bytes.len() < features.len() * 32 * 4 / 3);
+    }
+
+    // 合并时复制编码, 不会再量化一次
+    #[test]
+    fn test_sq8_merge() {
+        let mut a = VectorStore::<Vec<f32>>::new(Quantizer::SQ8);
+        let mut b = VectorStore::<Vec<f32>>::new(Quantizer::SQ8);
+        let mut c = VectorStore::<Vec<f32>>::new(Quantizer::SQ8);
+        let features = random_vectors(2 * SQ8_TRAIN_SIZE + 300, 16);
+        let (fa, rest) = features.split_at(SQ8_TRAIN_SIZE + 200);
+        let (fb, fc) = rest.split_at(SQ8_TRAIN_SIZE);
+        fa.iter().for_each(|f| a.push(f.clone()));
+        fb.iter().for_each(|f| b.push(f.clone()));
+        fc.iter().for_each(|f| c.push(f.clone()));
+        assert!(a.is_quantized() && b.is_quantized() && !c.is_quantized());
+
+        let ab = a.merge(&b);
+        assert_eq!(ab.len(), fa.len() + fb.len());
+        // 沿用 a 的量化器, a 的编码原样保留
+        assert!(ab.sq == a.sq);
+        assert_eq!(ab.codes[..a.codes.len()], a.codes[..]);
+        let merged = ab.merge(&c).merge(&ab);
+        assert_eq!(merged.len(), 2 * ab.len() + fc.len());
+        assert_eq!(merged.codes[..ab.codes.len()], ab.codes[..]);
+        assert_eq!(merged.codes[ab.codes.len() + fc.len() * 16..], ab.codes[..]);
+        // 未量化的一侧只编码一次
+        let sq = merged.sq.as_ref().unwrap();
+        for (i, f) in fc.iter().enumerate() {
+            let mut code = Vec::new();
+            sq.encode(f, &mut code);
+            assert_eq!(merged.code(ab.len() + i), &code[..]);
+        }
+    }
+
+    #[test]
+    fn test_sq8_fixed_range() {
+        let range = SqRange::Fixed { min: 0.0, max: 1.0 };
+        let mut a = VectorStore::<Vec<f32>>::new(Quantizer::SQ8);
+        let mut b = VectorStore::<Vec<f32>>::new(Quantizer::SQ8);
+        a.set_range(range);
+        b.set_range(range);
+        let features = random_vectors(200, 8);
+        features[..100].iter().for_each(|f| a.push(f.clone()));
+        features[100..].iter().for_each(|f| b.push(f.clone()));
+        // 不需要攒够训练样本
+        assert!(a.is_quantized() && b.is_quantized());
+        assert!(a.sq == b.sq);
+        let merged = a.merge(&b);
+        assert_eq!(merged.len(), 200);
+        assert_eq!(merged.codes[..a.codes.len()], a.codes[..]);
+        assert_eq!(merged.codes[a.codes.len()..], b.codes[..]);
+        // 超出范围的值截断
+        let mut code = Vec::new();
+        merged.sq.as_ref().unwrap().encode(&[2.0, -1.0], &mut code);
+        assert_eq!(code, vec![255, 0]);
+    }
+}
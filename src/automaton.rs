@@ -0,0 +1,793 @@
+use super::schema::{FieldType, Value};
+use super::util::error::{GyError, GyResult};
+use super::util::fst::FstReaderIter;
+use regex_automata::dfa::dense::{self, DFA};
+use regex_automata::dfa::Automaton as _;
+use regex_automata::util::primitives::StateID;
+use regex_automata::{Anchored, Input, MatchKind};
+use std::ops::Bound;
+
+// 正则自动机的大小上限, 防止病态的模式占用过多内存
+const DFA_SIZE_LIMIT: usize = 10 * 1024 * 1024;
+
+// 按字节匹配 term 的自动机, push/pop 维护当前前缀的状态
+pub trait Automaton {
+    // 前缀追加一个字节, 返回 false 表示以该前缀开头的 term 都不会匹配
+    fn push(&mut self, byte: u8) -> bool;
+    // 撤销最后一次成功的 push
+    fn pop(&mut self);
+    // 当前前缀本身是否匹配
+    fn is_match(&self) -> bool;
+    // 可能匹配的 term 所在的区间, 求交时只遍历这个区间
+    fn bounds(&self) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+        (Bound::Unbounded, Bound::Unbounded)
+    }
+}
+
+// 按字典序遍历 term 词典, 可以向后跳过
+pub(crate) trait TermCursor {
+    fn next_term(&mut self) -> Option<Vec<u8>>;
+    // 跳到不小于 key 的第一个 term
+    fn seek(&mut self, key: &[u8]);
+}
+
+impl<'a> TermCursor for FstReaderIter<'a> {
+    fn next_term(&mut self) -> Option<Vec<u8>> {
+        self.next().map(|(term, _)| term)
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        FstReaderIter::seek(self, key)
+    }
+}
+
+// 所有以 prefix 开头的 term 之后的第一个 key, prefix 全是 0xff 时没有
+pub(crate) fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut next = prefix.to_vec();
+    while let Some(b) = next.pop() {
+        if b < u8::MAX {
+            next.push(b + 1);
+            return Some(next);
+        }
+    }
+    None
+}
+
+// 与按字典序排列的 term 求交, 相邻 term 共享的前缀只计算一次
+// 某个前缀不可能匹配时, 直接跳到所有以它开头的 term 之后
+// 匹配的 term 超过 limit 个时返回错误
+pub(crate) fn intersect(
+    terms: &mut dyn TermCursor,
+    automaton: &mut dyn Automaton,
+    limit: usize,
+) -> GyResult<Vec<Vec<u8>>> {
+    let mut results = Vec::new();
+    // 自动机当前接受的前缀
+    let mut prefix: Vec<u8> = Vec::new();
+    while let Some(term) = terms.next_term() {
+        let common = prefix
+            .iter()
+            .zip(term.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        while prefix.len() > common {
+            prefix.pop();
+            automaton.pop();
+        }
+        let mut alive = true;
+        for b in term[common..].iter() {
+            if !automaton.push(*b) {
+                let mut dead = prefix.clone();
+                dead.push(*b);
+                match prefix_successor(&dead) {
+                    Some(next) => terms.seek(&next),
+                    None => return Ok(results),
+                }
+                alive = false;
+                break;
+            }
+            prefix.push(*b);
+        }
+        if alive && automaton.is_match() {
+            if results.len() >= limit {
+                return Err(GyError::ErrTooManyTerms(limit));
+            }
+            results.push(term);
+        }
+    }
+    Ok(results)
+}
+
+// 匹配以 prefix 开头的 term
+pub struct PrefixAutomaton<'a> {
+    prefix: &'a [u8],
+    depth: usize,
+}
+
+impl<'a> PrefixAutomaton<'a> {
+    pub fn new(prefix: &'a [u8]) -> PrefixAutomaton<'a> {
+        PrefixAutomaton {
+            prefix: prefix,
+            depth: 0,
+        }
+    }
+}
+
+impl<'a> Automaton for PrefixAutomaton<'a> {
+    fn push(&mut self, byte: u8) -> bool {
+        if self.depth < self.prefix.len() && self.prefix[self.depth] != byte {
+            return false;
+        }
+        self.depth += 1;
+        true
+    }
+
+    fn pop(&mut self) {
+        self.depth -= 1;
+    }
+
+    fn is_match(&self) -> bool {
+        self.depth >= self.prefix.len()
+    }
+
+    fn bounds(&self) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+        (
+            Bound::Included(self.prefix.to_vec()),
+            prefix_successor(self.prefix).map_or(Bound::Unbounded, Bound::Excluded),
+        )
+    }
+}
+
+// 编译好的正则, 必须匹配整个 term
+#[derive(Clone)]
+pub struct Regex {
+    dfa: DFA<Vec<u32>>,
+    start: StateID,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> GyResult<Regex> {
+        let dfa = dense::Builder::new()
+            .configure(
+                DFA::config()
+                    .match_kind(MatchKind::All)
+                    .dfa_size_limit(Some(DFA_SIZE_LIMIT))
+                    .determinize_size_limit(Some(DFA_SIZE_LIMIT)),
+            )
+            .build(pattern)
+            .map_err(|e| GyError::ErrInvalidPattern(e.to_string()))?;
+        let start = dfa
+            .start_state_forward(&Input::new("").anchored(Anchored::Yes))
+            .map_err(|e| GyError::ErrInvalidPattern(e.to_string()))?;
+        Ok(Regex {
+            dfa: dfa,
+            start: start,
+        })
+    }
+
+    // 通配符: * 匹配任意个字符, ? 匹配一个字符
+    pub fn wildcard(pattern: &str) -> GyResult<Regex> {
+        let mut re = String::from("(?s)");
+        for c in pattern.chars() {
+            match c {
+                '*' => re.push_str(".*"),
+                '?' => re.push('.'),
+                _ => re.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        Regex::new(&re)
+    }
+
+    pub fn automaton(&self) -> RegexAutomaton {
+        RegexAutomaton {
+            regex: self,
+            states: vec![self.start],
+        }
+    }
+}
+
+pub struct RegexAutomaton<'a> {
+    regex: &'a Regex,
+    states: Vec<StateID>,
+}
+
+impl<'a> Automaton for RegexAutomaton<'a> {
+    fn push(&mut self, byte: u8) -> bool {
+        let state = self
+            .regex
+            .dfa
+            .next_state(*self.states.last().unwrap(), byte);
+        if self.regex.dfa.is_dead_state(state) || self.regex.dfa.is_quit_state(state) {
+            return false;
+        }
+        self.states.push(state);
+        true
+    }
+
+    fn pop(&mut self) {
+        self.states.pop();
+    }
+
+    fn is_match(&self) -> bool {
+        let state = self.regex.dfa.next_eoi_state(*self.states.last().unwrap());
+        self.regex.dfa.is_match_state(state)
+    }
+}
+
+// 匹配按字节序落在 [lower, upper] 内的 term, 边界值按 Value::to_vec 编码
+// 边界值的类型须与域的类型一致, 数值的编码保序且定长, 长度不同的 term 不命中
+pub struct RangeAutomaton {
+    lower: Bound<Vec<u8>>,
+    upper: Bound<Vec<u8>>,
+    len: Option<usize>,
+    // 当前前缀是否仍与下界, 上界的前缀相同
+    states: Vec<(bool, bool)>,
+}
+
+impl RangeAutomaton {
+    pub fn new(
+        field_type: &FieldType,
+        lower: &Bound<Value>,
+        upper: &Bound<Value>,
+    ) -> GyResult<RangeAutomaton> {
+        let mut len = None;
+        let mut encode = |b: &Bound<Value>| -> GyResult<Bound<Vec<u8>>> {
+            let (v, included) = match b {
+                Bound::Included(v) => (v, true),
+                Bound::Excluded(v) => (v, false),
+                Bound::Unbounded => return Ok(Bound::Unbounded),
+            };
+            if !field_type.accepts(v) {
+                return Err(GyError::ErrInvalidValueType);
+            }
+            let bytes = v.to_vec()?;
+            if !matches!(v, Value::Str(_) | Value::String(_) | Value::Bytes(_)) {
+                len = Some(bytes.len());
+            }
+            Ok(if included {
+                Bound::Included(bytes)
+            } else {
+                Bound::Excluded(bytes)
+            })
+        };
+        let (lower, upper) = (encode(lower)?, encode(upper)?);
+        let start = (
+            !matches!(lower, Bound::Unbounded),
+            !matches!(upper, Bound::Unbounded),
+        );
+        Ok(RangeAutomaton {
+            lower: lower,
+            upper: upper,
+            len: len,
+            states: vec![start],
+        })
+    }
+}
+
+fn bound_bytes(b: &Bound<Vec<u8>>) -> &[u8] {
+    match b {
+        Bound::Included(v) | Bound::Excluded(v) => v,
+        Bound::Unbounded => &[],
+    }
+}
+
+impl Automaton for RangeAutomaton {
+    fn push(&mut self, byte: u8) -> bool {
+        let depth = self.states.len() - 1;
+        if self.len.map_or(false, |l| depth >= l) {
+            return false;
+        }
+        let (mut on_lower, mut on_upper) = *self.states.last().unwrap();
+        if on_lower {
+            match bound_bytes(&self.lower).get(depth) {
+                // 已经比下界长, 一定大于下界
+                None => on_lower = false,
+                Some(b) if byte < *b => return false,
+                Some(b) => on_lower = byte == *b,
+            }
+        }
+        if on_upper {
+            match bound_bytes(&self.upper).get(depth) {
+                None => return false,
+                Some(b) if byte > *b => return false,
+                Some(b) => on_upper = byte == *b,
+            }
+        }
+        self.states.push((on_lower, on_upper));
+        true
+    }
+
+    fn pop(&mut self) {
+        self.states.pop();
+    }
+
+    fn is_match(&self) -> bool {
+        let depth = self.states.len() - 1;
+        if self.len.map_or(false, |l| depth != l) {
+            return false;
+        }
+        let (on_lower, on_upper) = *self.states.last().unwrap();
+        // 仍在边界上时, 前缀比边界短说明小于边界, 一样长说明等于边界
+        let lower_ok = !on_lower
+            || match &self.lower {
+                Bound::Included(v) => depth == v.len(),
+                _ => false,
+            };
+        let upper_ok = !on_upper
+            || match &self.upper {
+                Bound::Included(v) => depth <= v.len(),
+                Bound::Excluded(v) => depth < v.len(),
+                Bound::Unbounded => true,
+            };
+        lower_ok && upper_ok
+    }
+    fn bounds(&self) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+        (self.lower.clone(), self.upper.clone())
+    }
+}
+
+// 编辑距离, 按字符计算, transposition 为 true 时相邻字符交换算一次编辑
+pub(crate) fn edit_distance(a: &str, b: &str, transposition: bool) -> u32 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<u32> = Vec::new();
+    let mut row: Vec<u32> = (0..=a.len() as u32).collect();
+    for (i, c) in b.iter().enumerate() {
+        let next = next_row(
+            &a,
+            &row,
+            &prev,
+            *c,
+            b.get(i.wrapping_sub(1)).cloned(),
+            transposition,
+        );
+        prev = std::mem::replace(&mut row, next);
+    }
+    row[a.len()]
+}
+
+// Levenshtein 距离矩阵的下一行, row 为已输入 last 之后的行, prev 为再上一行
+fn next_row(
+    query: &[char],
+    row: &[u32],
+    prev: &[u32],
+    c: char,
+    last: Option<char>,
+    transposition: bool,
+) -> Vec<u32> {
+    let mut next = Vec::with_capacity(row.len());
+    next.push(row[0] + 1);
+    for j in 1..row.len() {
+        let cost = if query[j - 1] == c { 0 } else { 1 };
+        let mut d = (row[j] + 1).min(next[j - 1] + 1).min(row[j - 1] + cost);
+        if transposition && j > 1 && last == Some(query[j - 1]) && c == query[j - 2] {
+            d = d.min(prev[j - 2] + 1);
+        }
+        next.push(d);
+    }
+    next
+}
+
+#[derive(Clone)]
+struct LevenshteinState {
+    row: Vec<u32>,
+    prev: Vec<u32>,
+    // 已输入的字符数和最后一个字符
+    chars: usize,
+    last: Option<char>,
+    // 未凑成完整字符的 utf-8 字节
+    pending: Vec<u8>,
+}
+
+// 匹配与 query 编辑距离不超过 distance 的 term, 前 prefix_len 个字符必须相同
+pub struct LevenshteinAutomaton {
+    query: Vec<char>,
+    distance: u32,
+    transposition: bool,
+    prefix_len: usize,
+    states: Vec<LevenshteinState>,
+}
+
+impl LevenshteinAutomaton {
+    pub fn new(
+        query: &str,
+        distance: u32,
+        transposition: bool,
+        prefix_len: usize,
+    ) -> LevenshteinAutomaton {
+        let query: Vec<char> = query.chars().collect();
+        let start = LevenshteinState {
+            row: (0..=query.len() as u32).collect(),
+            prev: Vec::new(),
+            chars: 0,
+            last: None,
+            pending: Vec::new(),
+        };
+        LevenshteinAutomaton {
+            query: query,
+            distance: distance,
+            transposition: transposition,
+            prefix_len: prefix_len,
+            states: vec![start],
+        }
+    }
+}
+
+impl Automaton for LevenshteinAutomaton {
+    fn push(&mut self, byte: u8) -> bool {
+        let top = self.states.last().unwrap();
+        let mut pending = top.pending.clone();
+        pending.push(byte);
+        let c = match std::str::from_utf8(&pending) {
+            Ok(s) => s.chars().next().unwrap(),
+            // 字符还没有输入完整
+            Err(e) if e.error_len().is_none() => {
+                let mut state = top.clone();
+                state.pending = pending;
+                self.states.push(state);
+                return true;
+            }
+            Err(_) => return false,
+        };
+        if top.chars < self.prefix_len && self.query.get(top.chars) != Some(&c) {
+            return false;
+        }
+        let row = next_row(
+            &self.query,
+            &top.row,
+            &top.prev,
+            c,
+            top.last,
+            self.transposition,
+        );
+        if row.iter().min().cloned().unwrap() > self.distance {
+            return false;
+        }
+        let state = LevenshteinState {
+            prev: top.row.clone(),
+            row: row,
+            chars: top.chars + 1,
+            last: Some(c),
+            pending: Vec::new(),
+        };
+        self.states.push(state);
+        true
+    }
+
+    fn pop(&mut self) {
+        self.states.pop();
+    }
+
+    fn is_match(&self) -> bool {
+        let top = self.states.last().unwrap();
+        top.pending.is_empty()
+            && top.chars >= self.prefix_len.min(self.query.len())
+            && top.row[self.query.len()] <= self.distance
+    }
+
+    // 只遍历以 query 前 prefix_len 个字符开头的 term
+    fn bounds(&self) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+        let prefix: String = self.query.iter().take(self.prefix_len).collect();
+        (
+            Bound::Included(prefix.as_bytes().to_vec()),
+            prefix_successor(prefix.as_bytes()).map_or(Bound::Unbounded, Bound::Excluded),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::iter::Peekable;
+
+    // 有序的 term 迭代器, 跳过时逐个比较, 不经过自动机
+    struct SortedTerms<T: AsRef<[u8]>, I: Iterator<Item = T>>(Peekable<I>);
+
+    impl<T: AsRef<[u8]>, I: Iterator<Item = T>> SortedTerms<T, I> {
+        fn new(iter: I) -> SortedTerms<T, I> {
+            SortedTerms(iter.peekable())
+        }
+    }
+
+    impl<T: AsRef<[u8]>, I: Iterator<Item = T>> TermCursor for SortedTerms<T, I> {
+        fn next_term(&mut self) -> Option<Vec<u8>> {
+            self.0.next().map(|t| t.as_ref().to_vec())
+        }
+
+        fn seek(&mut self, key: &[u8]) {
+            while self.0.next_if(|t| t.as_ref() < key).is_some() {}
+        }
+    }
+
+    fn matches(automaton: &mut dyn Automaton, terms: &[&str]) -> Vec<String> {
+        let mut terms: Vec<&[u8]> = terms.iter().map(|t| t.as_bytes()).collect();
+        terms.sort();
+        intersect(&mut SortedTerms::new(terms.into_iter()), automaton, 10)
+            .unwrap()
+            .into_iter()
+            .map(|t| String::from_utf8(t).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_automaton() {
+        let terms = ["apple", "app", "apply", "banana", "band", "bandana", "ape"];
+        let mut a = PrefixAutomaton::new(b"app");
+        assert_eq!(matches(&mut a, &terms), vec!["app", "apple", "apply"]);
+        let re = Regex::wildcard("ban*a").unwrap();
+        assert_eq!(
+            matches(&mut re.automaton(), &terms),
+            vec!["banana", "bandana"]
+        );
+        let re = Regex::wildcard("b?nd").unwrap();
+        assert_eq!(matches(&mut re.automaton(), &terms), vec!["band"]);
+        let re = Regex::new("ap(e|ply)").unwrap();
+        assert_eq!(matches(&mut re.automaton(), &terms), vec!["ape", "apply"]);
+        // 要匹配整个 term
+        let re = Regex::new("ban").unwrap();
+        assert!(matches(&mut re.automaton(), &terms).is_empty());
+        let re = Regex::wildcard("??").unwrap();
+        assert_eq!(
+            matches(&mut re.automaton(), &["中文", "中", "ab"]),
+            vec!["ab", "中文"]
+        );
+        let mut a = PrefixAutomaton::new(b"");
+        assert!(matches!(
+            intersect(
+                &mut SortedTerms::new(terms.iter().map(|t| t.as_bytes())),
+                &mut a,
+                3
+            ),
+            Err(GyError::ErrTooManyTerms(3))
+        ));
+        assert!(Regex::new("(").is_err());
+    }
+
+    // 记录遍历过的 term 个数
+    struct Counting<'a>(
+        SortedTerms<&'a Vec<u8>, std::slice::Iter<'a, Vec<u8>>>,
+        usize,
+    );
+
+    impl<'a> TermCursor for Counting<'a> {
+        fn next_term(&mut self) -> Option<Vec<u8>> {
+            self.1 += 1;
+            self.0.next_term()
+        }
+
+        fn seek(&mut self, key: &[u8]) {
+            self.0.seek(key)
+        }
+    }
+
+    #[test]
+    fn test_intersect_seek() {
+        assert_eq!(prefix_successor(b"ab"), Some(b"ac".to_vec()));
+        assert_eq!(prefix_successor(&[b'a', 0xff, 0xff]), Some(b"b".to_vec()));
+        assert_eq!(prefix_successor(&[0xff]), None);
+        let mut terms: Vec<Vec<u8>> = (0..1000)
+            .flat_map(|i| vec![format!("a{:04}", i), format!("c{:04}", i)])
+            .map(|t| t.into_bytes())
+            .collect();
+        terms.push(b"b1".to_vec());
+        terms.push(b"b2".to_vec());
+        terms.sort();
+        // 不可能匹配的前缀 a 和 c 各只访问一次
+        let re = Regex::new("b.").unwrap();
+        let mut cursor = Counting(SortedTerms::new(terms.iter()), 0);
+        let res = intersect(&mut cursor, &mut re.automaton(), 10).unwrap();
+        assert_eq!(res, vec![b"b1".to_vec(), b"b2".to_vec()]);
+        assert_eq!(cursor.1, 5);
+        // 前缀内的 term 一个都不能漏
+        let re = Regex::new("a00[0-1]5").unwrap();
+        let mut cursor = Counting(SortedTerms::new(terms.iter()), 0);
+        let res = intersect(&mut cursor, &mut re.automaton(), 10).unwrap();
+        assert_eq!(res, vec![b"a0005".to_vec(), b"a0015".to_vec()]);
+        assert!(cursor.1 < 50);
+        let a = PrefixAutomaton::new(b"a0");
+        assert_eq!(
+            a.bounds(),
+            (
+                Bound::Included(b"a0".to_vec()),
+                Bound::Excluded(b"a1".to_vec())
+            )
+        );
+    }
+
+    #[test]
+    fn test_range() {
+        let mut values: Vec<Vec<u8>> = [-300i64, -5, 0, 7, 100, 1 << 40]
+            .iter()
+            .map(|x| Value::I64(*x).to_vec().unwrap())
+            .collect();
+        values.sort();
+        let range = |lower: Bound<Value>, upper: Bound<Value>| -> Vec<i64> {
+            let mut a = RangeAutomaton::new(&FieldType::I64, &lower, &upper).unwrap();
+            intersect(&mut SortedTerms::new(values.iter()), &mut a, 10)
+                .unwrap()
+                .iter()
+                .map(|t| {
+                    crate::util::common::u64_to_i64(u64::from_be_bytes(t[..].try_into().unwrap()))
+                })
+                .collect()
+        };
+        assert_eq!(
+            range(
+                Bound::Included(Value::I64(-5)),
+                Bound::Included(Value::I64(100))
+            ),
+            vec![-5, 0, 7, 100]
+        );
+        assert_eq!(
+            range(
+                Bound::Excluded(Value::I64(-5)),
+                Bound::Excluded(Value::I64(100))
+            ),
+            vec![0, 7]
+        );
+        assert_eq!(
+            range(Bound::Unbounded, Bound::Excluded(Value::I64(0))),
+            vec![-300, -5]
+        );
+        assert_eq!(
+            range(Bound::Excluded(Value::I64(7)), Bound::Unbounded),
+            vec![100, 1 << 40]
+        );
+        // 浮点数编码同样保序
+        let mut values: Vec<Vec<u8>> = [-2.5f64, -0.5, 0.0, 1.5, 3.0]
+            .iter()
+            .map(|x| Value::F64(*x).to_vec().unwrap())
+            .collect();
+        values.sort();
+        let mut a = RangeAutomaton::new(
+            &FieldType::F64,
+            &Bound::Included(Value::F64(-1.0)),
+            &Bound::Excluded(Value::F64(3.0)),
+        )
+        .unwrap();
+        assert_eq!(
+            intersect(&mut SortedTerms::new(values.iter()), &mut a, 10)
+                .unwrap()
+                .len(),
+            3
+        );
+        // 字符串按字节序
+        let mut a = RangeAutomaton::new(
+            &FieldType::Str,
+            &Bound::Included(Value::Str("b")),
+            &Bound::Excluded(Value::Str("c")),
+        )
+        .unwrap();
+        assert_eq!(
+            matches(&mut a, &["a", "b", "banana", "c", "ca"]),
+            vec!["b", "banana"]
+        );
+        assert_eq!(
+            a.bounds(),
+            (
+                Bound::Included(b"b".to_vec()),
+                Bound::Excluded(b"c".to_vec())
+            )
+        );
+        // 边界值的类型须与域的类型一致, 上下界类型不同时同样报错
+        assert!(RangeAutomaton::new(
+            &FieldType::Str,
+            &Bound::Included(Value::Str("b")),
+            &Bound::Excluded(Value::String("c".to_string())),
+        )
+        .is_ok());
+        assert!(matches!(
+            RangeAutomaton::new(
+                &FieldType::I64,
+                &Bound::Unbounded,
+                &Bound::Included(Value::I32(0)),
+            ),
+            Err(GyError::ErrInvalidValueType)
+        ));
+        assert!(matches!(
+            RangeAutomaton::new(
+                &FieldType::I64,
+                &Bound::Included(Value::I64(0)),
+                &Bound::Included(Value::F64(1.0)),
+            ),
+            Err(GyError::ErrInvalidValueType)
+        ));
+    }
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(edit_distance("red", "redd", false), 1);
+        assert_eq!(edit_distance("kitten", "sitting", false), 3);
+        assert_eq!(edit_distance("abcd", "acbd", false), 2);
+        assert_eq!(edit_distance("abcd", "acbd", true), 1);
+        assert_eq!(edit_distance("苹果", "平果", false), 1);
+        let terms = ["red", "reed", "bed", "read", "rde", "green", "苹果", "平果"];
+        let mut a = LevenshteinAutomaton::new("redd", 1, false, 0);
+        assert_eq!(matches(&mut a, &terms), vec!["read", "red", "reed"]);
+        let mut a = LevenshteinAutomaton::new("red", 1, false, 0);
+        assert_eq!(matches(&mut a, &terms), vec!["bed", "read", "red", "reed"]);
+        // 前缀必须相同
+        let mut a = LevenshteinAutomaton::new("red", 1, false, 1);
+        assert_eq!(matches(&mut a, &terms), vec!["read", "red", "reed"]);
+        let mut a = LevenshteinAutomaton::new("red", 1, true, 0);
+        assert_eq!(
+            matches(&mut a, &terms),
+            vec!["bed", "rde", "read", "red", "reed"]
+        );
+        let mut a = LevenshteinAutomaton::new("苹果", 1, false, 0);
+        assert_eq!(matches(&mut a, &terms), vec!["平果", "苹果"]);
+    }
+
+    #[test]
+    fn test_levenshtein_transposition() {
+        // 相邻字符交换不算两次替换
+        assert_eq!(edit_distance("red", "rde", false), 2);
+        assert_eq!(edit_distance("red", "rde", true), 1);
+        assert_eq!(edit_distance("ab", "ba", true), 1);
+        // 交换过的字符之间不能再插入
+        assert_eq!(edit_distance("abc", "bxac", true), 3);
+        assert_eq!(edit_distance("abc", "bac", true), 1);
+        let terms = ["erd", "rde", "der", "dre"];
+        let mut a = LevenshteinAutomaton::new("red", 1, true, 0);
+        assert_eq!(matches(&mut a, &terms), vec!["erd", "rde"]);
+        let mut a = LevenshteinAutomaton::new("red", 2, true, 0);
+        assert_eq!(matches(&mut a, &terms), vec!["der", "dre", "erd", "rde"]);
+        // 交换前缀中的字符也不能匹配
+        let mut a = LevenshteinAutomaton::new("red", 1, true, 1);
+        assert_eq!(matches(&mut a, &terms), vec!["rde"]);
+    }
+
+    #[test]
+    fn test_levenshtein_prefix() {
+        use std::ops::RangeBounds;
+        let a = LevenshteinAutomaton::new("red", 1, false, 2);
+        assert_eq!(
+            a.bounds(),
+            (
+                Bound::Included(b"re".to_vec()),
+                Bound::Excluded(b"rf".to_vec())
+            )
+        );
+        let a = LevenshteinAutomaton::new("苹果", 1, false, 1);
+        assert_eq!(
+            a.bounds(),
+            (
+                Bound::Included("苹".as_bytes().to_vec()),
+                Bound::Excluded(prefix_successor("苹".as_bytes()).unwrap())
+            )
+        );
+        let a = LevenshteinAutomaton::new("red", 1, false, 0);
+        assert_eq!(a.bounds(), (Bound::Included(Vec::new()), Bound::Unbounded));
+        // 只访问前缀区间内的 term, 区间外与 query 距离为 1 的 x123 也不会展开
+        let mut terms: Vec<Vec<u8>> = (0..500)
+            .flat_map(|i| vec![format!("r{:03}", i), format!("x{:03}", i)])
+            .map(|t| t.into_bytes())
+            .collect();
+        terms.sort();
+        let expand = |prefix_len: usize| {
+            let mut a = LevenshteinAutomaton::new("r123", 1, false, prefix_len);
+            let range: Vec<Vec<u8>> = terms
+                .iter()
+                .filter(|t| a.bounds().contains(*t))
+                .cloned()
+                .collect();
+            let mut cursor = Counting(SortedTerms::new(range.iter()), 0);
+            let res: Vec<String> = intersect(&mut cursor, &mut a, 100)
+                .unwrap()
+                .into_iter()
+                .map(|t| String::from_utf8(t).unwrap())
+                .collect();
+            (res, cursor.1)
+        };
+        let (res0, visited0) = expand(0);
+        let (res1, visited1) = expand(1);
+        let (res3, visited3) = expand(3);
+        assert!(res0.contains(&"x123".to_string()));
+        assert!(res1.iter().all(|t| t.starts_with('r')));
+        assert_eq!(res1.len(), res0.len() - 1);
+        assert_eq!(res3.len(), 10);
+        assert!(res3.iter().all(|t| t.starts_with("r12")));
+        assert!(visited1 < visited0);
+        assert!(visited3 <= 12);
+    }
+}
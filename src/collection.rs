@@ -137,5 +137,5 @@ impl CollectionImpl {
 
     pub fn compact_mem() {}
 
-    pub fn query(&self, v: &Tensor, k: usize) {}
+    pub fn query(&self, field: &str, v: &Tensor, k: usize) {}
 }
@@ -1,6 +1,9 @@
+use crate::schema::Schema;
+use crate::util::error::{GyError, GyResult};
 use crate::FieldEntry;
 use crate::IOType;
 use crate::Meta;
+use crate::MetricType;
 use crate::PathBuf;
 use crate::TensorEntry;
 use crate::DEFAULT_WAL_FILE_SIZE;
@@ -12,6 +15,12 @@ pub(crate) const DATA_FILE: &'static str = "data.gy"; // 数据
 pub(crate) const META_FILE: &'static str = "meta.json"; // index 元数据
 pub(crate) const DELETE_FILE: &'static str = "ids.del"; // 被删除的id
 
+// 段文件格式版本
+// 1: 数值和日期的 term 使用保序编码, 见 Value::to_vec
+// 2: term 词典改用 fst crate 的格式, 支持按区间定位
+// 3: IVF 聚类中心按 f32 保存, 与域的元素类型无关
+pub(crate) const SEGMENT_VERSION: u32 = 3;
+
 pub struct ConfigBuilder {
     collect_name: String,
     io_type: IOType,
@@ -140,14 +149,46 @@ pub(crate) struct DiskFileMeta {
     meta: Meta,
     parent: Vec<String>,
     level: i32,
+    // 旧的段没有这个字段, 读出来是 0
+    #[serde(default)]
+    version: u32,
 }
 
 impl DiskFileMeta {
-    pub fn tensor_entry(&self) -> &TensorEntry {
-        self.meta.tensor_entry()
+    pub fn new(schema: &Schema) -> DiskFileMeta {
+        Self {
+            meta: Meta::new(schema.clone()),
+            parent: Vec::new(),
+            level: 0,
+            version: SEGMENT_VERSION,
+        }
+    }
+
+    // 旧版本段的 term 编码或词典格式不同, 需要重建
+    pub fn check_version(&self) -> GyResult<()> {
+        if self.version != SEGMENT_VERSION {
+            return Err(GyError::ErrVersionMismatch);
+        }
+        Ok(())
+    }
+
+    pub fn tensor_entries(&self) -> Vec<TensorEntry> {
+        self.meta.tensor_entries()
+    }
+
+    pub fn get_vector_field(&self, field_name: &str) -> Option<usize> {
+        self.meta.get_vector_field(field_name)
+    }
+
+    pub fn metric(&self, i: usize) -> MetricType {
+        self.meta.metric(i)
     }
 
     pub fn get_fields(&self) -> &[FieldEntry] {
         self.meta.get_fields()
     }
+
+    pub fn same_vector_layout(&self, other: &DiskFileMeta) -> bool {
+        self.meta.same_vector_layout(&other.meta)
+    }
 }
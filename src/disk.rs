@@ -1,28 +1,41 @@
 use super::schema::{BinarySerialize, FieldID, Schema, TensorEntry, VectorSerialize};
 use super::schema::{DocID, Document};
+use super::schema::{ValueSized, VectorBase, VectorOps};
 use super::util::error::{GyError, GyResult};
 use super::util::fs::{self};
 use super::util::fst::{FstBuilder, FstReader, FstReaderIter};
-use super::{EngineReader, IndexReader, Meta};
+use super::{EngineReaderBase, IndexReader, Meta};
+use crate::ann;
+use crate::ann::multi::MultiVectorDocs;
+use crate::ann::sparse::SparseCursor;
+use crate::ann::{Metric, SearchParams, SparseVector, VectorElems};
+use crate::automaton::{self, Automaton};
 use crate::config::DiskFileMeta;
 use crate::config::DATA_FILE;
 use crate::config::META_FILE;
 use crate::fs::FileManager;
+use crate::hybrid::{self, Fusion, HybridHit};
 use crate::iocopy;
+use crate::query::{Filter, FilterReader, Query};
+use crate::schema::DocWeight;
+use crate::schema::FieldType;
 use crate::schema::VUInt;
 use crate::schema::VarIntSerialize;
+use crate::schema::{read_positions, write_positions};
+use crate::searcher::Searcher;
+use crate::similarity::{self, FieldStats, ScoreDoc, Similarity, BM25};
+use crate::tokenize::{self, Analyzer};
+use crate::util::bitmap::BitMap;
 use crate::util::bloom::GyBloom;
 use crate::util::fs::GyFile;
 use crate::Ann;
 use crate::DocFreq;
 use crate::FieldEntry;
+use crate::MetricType;
 use crate::Neighbor;
 use crate::Term;
-use crate::Vector;
-use art_tree::Key;
 use bytes::BytesMut;
 use bytes::{Buf, BufMut};
-use furze::fst::Cow;
 use galois::Tensor;
 use memmap2::Mmap;
 use std::borrow::Borrow;
@@ -60,6 +73,8 @@ use std::iter::Peekable;
 //  |       Fst           |
 //  |                     |
 //  +---------------------+
+//  |       Norms         |
+//  +---------------------+
 //  |       ......        |
 //  +---------------------+
 //  |                     |
@@ -155,7 +170,18 @@ fn open_file_stream(fname: &str) -> GyResult<BufWriter<File>> {
     Ok(buf_writer)
 }
 
-pub fn merge(a: &DiskStoreReader, b: &DiskStoreReader, new_fname: &Path) -> GyResult<()> {
+pub fn merge<V: VectorSerialize + ValueSized + VectorOps + Clone + 'static>(
+    a: &DiskStoreReaderBase<V>,
+    b: &DiskStoreReaderBase<V>,
+    new_fname: &Path,
+) -> GyResult<()>
+where
+    V: Metric<V> + VectorElems,
+{
+    // 向量块按下标合并, 两个段的向量域必须一一对应
+    if !a.meta.same_vector_layout(&b.meta) {
+        return Err(GyError::ErrVectorLayoutMismatch);
+    }
     let mut writer = DiskStoreWriter::new(new_fname)?;
     let mut doc_meta: Vec<usize> = Vec::with_capacity(a.doc_size() + b.doc_size());
     writer.write_doc_block(a.doc_block())?;
@@ -168,7 +194,15 @@ pub fn merge(a: &DiskStoreReader, b: &DiskStoreReader, new_fname: &Path) -> GyRe
     }
     writer.doc_end = a.doc_block().len() + b.doc_block().len();
 
-    writer.write_vector(&a.vector_field.merge(&b.vector_field)?)?;
+    for i in 0..a.vector_fields.len() {
+        let docs = match (&a.multi_docs[i], &b.multi_docs[i]) {
+            (Some(x), Some(y)) => Some(x.merge(y)),
+            _ => None,
+        };
+        let index = a.vector_fields[i].merge(&b.vector_fields[i])?;
+        writer.write_vector(&index, docs.as_ref())?;
+    }
+    writer.write_vector_meta()?;
 
     let mut bytes = BytesMut::with_capacity(4 * 1024).writer();
     let reader_merger = CompactionMerger::new(a.iter().peekable(), b.iter().peekable());
@@ -180,18 +214,38 @@ pub fn merge(a: &DiskStoreReader, b: &DiskStoreReader, new_fname: &Path) -> GyRe
                     CompactionMerger::new(r1.iter().peekable(), r2.iter().peekable());
                 let mut bloom = GyBloom::new(r1.get_term_count() + r2.get_term_count());
                 let mut term_count: usize = 0;
+                let sparse = r1.is_sparse();
                 field_merger.merge().try_for_each(|e1| -> GyResult<()> {
+                    if sparse {
+                        let item = e1.0.as_ref().or(e1.1.as_ref()).unwrap();
+                        let term = item.term().to_vec();
+                        let offset = merge_weights(
+                            &mut writer,
+                            e1.0.as_ref().map(|x| x.posting_reader()),
+                            e1.1.as_ref().map(|x| x.posting_reader()),
+                            a.doc_size() as u64,
+                        )?;
+                        bloom.set(&term);
+                        writer.add_term(&term, offset)?;
+                        term_count += 1;
+                        return Ok(());
+                    }
                     let mut disk_poting_writer = DiskPostingWriter::new(&mut bytes);
-                    match (e1.0, e1.1) {
+                    let (term, offset) = match (e1.0, e1.1) {
                         (Some(item1), Some(item2)) => {
                             let (p1, p2) = (item1.posting_reader(), item2.posting_reader());
-                            for doc_freq in p1.iter() {
-                                disk_poting_writer.add(doc_freq.doc_id(), doc_freq.freq())?;
+                            for (doc_freq, positions) in p1.positions() {
+                                disk_poting_writer.add(
+                                    doc_freq.doc_id(),
+                                    doc_freq.freq(),
+                                    &positions,
+                                )?;
                             }
-                            for doc_freq in p2.iter() {
+                            for (doc_freq, positions) in p2.positions() {
                                 disk_poting_writer.add(
                                     doc_freq.doc_id() + a.doc_size() as u64,
                                     doc_freq.freq(),
+                                    &positions,
                                 )?;
                             }
                             let offset = writer.write_posting(
@@ -204,44 +258,55 @@ pub fn merge(a: &DiskStoreReader, b: &DiskStoreReader, new_fname: &Path) -> GyRe
                                 unsafe { std::str::from_utf8_unchecked(item1.term()) },
                                 bytes.get_ref()
                             );
-                            writer.add_term(item1.term(), offset)?;
-                            term_count += 1;
+                            (item1.term().to_vec(), offset)
                         }
                         (Some(item1), None) => {
                             let p1 = item1.posting_reader();
-                            for doc_freq in p1.iter() {
-                                disk_poting_writer.add(doc_freq.doc_id(), doc_freq.freq())?;
+                            for (doc_freq, positions) in p1.positions() {
+                                disk_poting_writer.add(
+                                    doc_freq.doc_id(),
+                                    doc_freq.freq(),
+                                    &positions,
+                                )?;
                             }
 
                             let offset =
                                 writer.write_posting(p1.get_doc_count(), bytes.get_ref())?;
-                            writer.add_term(item1.term(), offset)?;
-                            term_count += 1;
+                            (item1.term().to_vec(), offset)
                         }
                         (None, Some(item2)) => {
                             let p2 = item2.posting_reader();
-                            for doc_freq in p2.iter() {
+                            for (doc_freq, positions) in p2.positions() {
                                 disk_poting_writer.add(
                                     doc_freq.doc_id() + a.doc_size() as u64,
                                     doc_freq.freq(),
+                                    &positions,
                                 )?;
                             }
                             let offset =
                                 writer.write_posting(p2.get_doc_count(), bytes.get_ref())?;
-                            writer.add_term(item2.term(), offset)?;
-                            term_count += 1;
+                            (item2.term().to_vec(), offset)
                         }
                         (None, None) => {
-                            println!("none")
+                            println!("none");
+                            return Ok(());
                         }
-                    }
-
+                    };
+                    // 合并后的 term 都要进布隆过滤器, 否则 find 会被误拒
+                    bloom.set(&term);
+                    writer.add_term(&term, offset)?;
+                    term_count += 1;
                     bytes.get_mut().clear();
                     Ok(())
                 })?;
                 let bh1 = writer.write_bloom(&bloom)?;
                 let bh2 = writer.write_fst()?;
-                writer.finish_field(term_count, bh1, bh2)?;
+                // b 中的文档排在 a 的 doc_size 个文档之后
+                let norms = (0..a.doc_size())
+                    .map(|i| r1.doc_len(i as DocID))
+                    .chain((0..b.doc_size()).map(|i| r2.doc_len(i as DocID)));
+                let bh3 = writer.write_norms(norms)?;
+                writer.finish_field(term_count, bh1, bh2, bh3, r1.stats().merge(&r2.stats()))?;
             }
             (None, Some(r2)) => println!("a:{},b{}", "none", r2.get_field_name()),
             (Some(r1), None) => {
@@ -257,11 +322,51 @@ pub fn merge(a: &DiskStoreReader, b: &DiskStoreReader, new_fname: &Path) -> GyRe
     // 写入每个域的 meta
     writer.write_field_meta()?;
     writer.close()?;
+    // 合并后的段与 a 使用同样的 schema 和格式版本
+    if let Some(dir_path) = new_fname.parent() {
+        FileManager::to_json_file(&a.meta, dir_path.join(META_FILE))?;
+    }
     Ok(())
 }
 
+// 合并稀疏向量域同一维度的两个倒排表, b 中的文档 id 加上 a_doc_size
+fn merge_weights(
+    writer: &mut DiskStoreWriter,
+    p1: Option<&DiskPostingReader>,
+    p2: Option<&DiskPostingReader>,
+    a_doc_size: u64,
+) -> GyResult<usize> {
+    let mut max_weight = 0.0f32;
+    let mut doc_count = 0;
+    for p in p1.iter().chain(p2.iter()) {
+        max_weight = max_weight.max(p.max_weight()?);
+        doc_count += p.get_doc_count();
+    }
+    let mut buf: Vec<u8> = Vec::new();
+    max_weight.binary_serialize(&mut buf)?;
+    let mut disk_poting_writer = DiskPostingWriter::new(&mut buf);
+    if let Some(p1) = p1 {
+        for doc_weight in p1.weights()? {
+            disk_poting_writer.add_weight(doc_weight.doc_id(), doc_weight.weight())?;
+        }
+    }
+    if let Some(p2) = p2 {
+        for doc_weight in p2.weights()? {
+            disk_poting_writer.add_weight(doc_weight.doc_id() + a_doc_size, doc_weight.weight())?;
+        }
+    }
+    writer.write_posting(doc_count, &buf)
+}
+
 //合并索引
-pub fn persist_collection(reader: &EngineReader, refname: PathBuf) -> GyResult<()> {
+pub fn persist_collection<V: VectorSerialize + ValueSized + VectorOps + Clone + 'static>(
+    reader: &EngineReaderBase<V>,
+    schema: &Schema,
+    refname: PathBuf,
+) -> GyResult<()>
+where
+    V: Metric<V> + VectorElems,
+{
     let index_reader = reader.index_reader();
     let fname = index_reader.get_wal_path();
     let doc_end = index_reader.offset()?;
@@ -270,41 +375,60 @@ pub fn persist_collection(reader: &EngineReader, refname: PathBuf) -> GyResult<(
         doc_end,
         index_reader.get_index_base().doc_offset.get_borrow().len(),
     )?;
-    writer.write_vector(reader.vector_field.0.read()?.borrow())?;
+    let vector_fields = &reader.vector_fields;
+    for (index, docs) in vector_fields
+        .indexes()
+        .iter()
+        .zip(vector_fields.multi_docs())
+    {
+        match docs {
+            Some(docs) => writer.write_vector(index.0.read()?.borrow(), Some(&*docs.read()?))?,
+            None => writer.write_vector(index.0.read()?.borrow(), None)?,
+        }
+    }
+    writer.write_vector_meta()?;
     let mut buf = Vec::with_capacity(4 * KB);
     // write field
     for field in index_reader.iter() {
         let mut term_offset_cache: HashMap<Vec<u8>, usize> = HashMap::new();
         for (b, p) in field.indexs.read()?.iter() {
-            let (doc_count, start_addr, end_addr) = {
+            let (doc_count, start_addr, end_addr, max_weight) = {
                 let posting = (*p).read()?;
                 (
                     posting.doc_count,
                     posting.byte_addr.load(Ordering::SeqCst),
                     posting.doc_freq_addr.load(Ordering::SeqCst),
+                    posting.max_weight,
                 )
             };
+            // 稀疏向量域的倒排表以最大权重开头, 查询时作为 WAND 的上界
+            if field.is_sparse() {
+                max_weight.binary_serialize(&mut buf)?;
+            }
             // write posting
             let posting_buffer = field.posting_buffer(start_addr, end_addr)?;
             for rb in posting_buffer.iter() {
                 buf.write(rb)?;
             }
             let offset = writer.write_posting(doc_count, buf.as_slice())?;
-            term_offset_cache.insert(b.to_bytes(), offset);
+            term_offset_cache.insert(b.clone(), offset);
             buf.clear();
         }
         // write bloom todo
         let mut bloom = GyBloom::new(field.get_term_count().max(1));
         // write fst
         for (b, _) in field.indexs.read()?.iter() {
-            let v: &[u8] = b.borrow();
+            let v = b.as_slice();
             bloom.set(v);
             let offset = term_offset_cache.get(v).unwrap();
             writer.add_term(v, *offset)?;
         }
         let bh1 = writer.write_bloom(&bloom)?;
         let bh2 = writer.write_fst()?;
-        writer.finish_field(field.get_term_count(), bh1, bh2)?;
+        let norms = field.norms(index_reader.doc_count as usize)?;
+        let bh3 = writer.write_norms(norms.into_iter())?;
+        let stats = field.stats(index_reader.doc_count as usize)?;
+        writer.finish_field(field.get_term_count(), bh1, bh2, bh3, stats)?;
     }
 
     // 写入文档和偏移量关系 meta
@@ -316,21 +440,26 @@ pub fn persist_collection(reader: &EngineReader, refname: PathBuf) -> GyResult<(
     drop(writer);
     // index_reader.reopen_wal(newfsize as usize)?;
     if let Some(dir_path) = fname.parent() {
-        std::fs::rename(&fname, refname)?;
+        std::fs::rename(&fname, &refname)?;
         // std::fs::rename(&fname, dir_path.join(DATA_FILE))?;
-        // crate::fs::to_json_file(
-        //     index_reader.get_index_base().get_meta(),
-        //     dir_path.join(META_FILE),
-        // )?;
+    }
+    if let Some(dir_path) = refname.parent() {
+        FileManager::to_json_file(&DiskFileMeta::new(schema), dir_path.join(META_FILE))?;
     }
     Ok(())
 }
 
-pub struct DiskStoreReader {
+pub struct DiskStoreReaderBase<V: VectorSerialize + Clone> {
     meta: DiskFileMeta,
-    vector_field: Arc<Ann<Tensor>>,
+    // 每个向量域一个 ANN 块, 顺序与 Schema::vector_entries 一致
+    vector_fields: Vec<Arc<Ann<V>>>,
+    // 多向量域的向量块在索引之后保存编号到文档的映射
+    multi_docs: Vec<Option<MultiVectorDocs>>,
+    entries: Vec<TensorEntry>,
     fields_meta: Vec<FieldHandle>,
     blooms: Vec<Arc<GyBloom>>,
+    // 按 schema 中的分词配置构建, 查询时切分文本
+    analyzers: Vec<Option<Analyzer>>,
     doc_meta: Vec<usize>,
     doc_end: usize,
     file: GyFile,
@@ -338,11 +467,17 @@ pub struct DiskStoreReader {
     mmap: Arc<Mmap>,
 }
 
-impl DiskStoreReader {
-    pub fn open<P: AsRef<Path>>(path: P) -> GyResult<DiskStoreReader> {
+pub type DiskStoreReader = DiskStoreReaderBase<Tensor>;
+
+impl<V: VectorSerialize + ValueSized + VectorOps + Clone + 'static> DiskStoreReaderBase<V>
+where
+    V: Metric<V> + VectorElems,
+{
+    pub fn open<P: AsRef<Path>>(path: P) -> GyResult<DiskStoreReaderBase<V>> {
         let data_path = PathBuf::new().join(path.as_ref()).join(DATA_FILE);
         let meta_path = PathBuf::new().join(path).join(META_FILE);
         let meta: DiskFileMeta = FileManager::from_json_file(&meta_path)?;
+        meta.check_version()?;
         let file = GyFile::open(data_path)?; //OpenOptions::new().read(true).open(data_path)?;
         let file_size = file.fsize()?;
         if file_size < FOOTER_LEN {
@@ -361,11 +496,11 @@ impl DiskStoreReader {
         let doc_meta_bh: BlockHandle = BlockHandle::binary_deserialize(&mut c)?;
         let field_meta_bh = BlockHandle::binary_deserialize(&mut c)?;
         let vector_meta_bh = BlockHandle::binary_deserialize(&mut c)?;
-        let mmap: Mmap = unsafe {
+        let mmap: Arc<Mmap> = Arc::new(unsafe {
             memmap2::MmapOptions::new()
                 .map(file.file())
                 .map_err(|e| format!("mmap failed: {}", e))?
-        };
+        });
 
         let fields_meta = Self::read_at_bh::<Vec<FieldHandle>>(&mmap, field_meta_bh)?;
         let doc_meta = Self::read_at_bh::<Vec<usize>>(&mmap, doc_meta_bh)?;
@@ -377,21 +512,49 @@ impl DiskStoreReader {
             blooms.push(Arc::new(bloom));
         }
 
-        let mut mmap_reader = MmapReader::new(&mmap, vector_meta_bh.start(), vector_meta_bh.end());
-        let vector_index =
-            Self::read_vector_index::<Ann<Tensor>>(&mut mmap_reader, meta.tensor_entry())?;
+        let entries = meta.tensor_entries();
+        let vector_bhs = Self::read_at_bh::<Vec<BlockHandle>>(&mmap, vector_meta_bh)?;
+        if vector_bhs.len() != entries.len() {
+            return Err(GyError::ErrVectorFieldMismatch(
+                entries.len(),
+                vector_bhs.len(),
+            ));
+        }
+        let mut vector_fields: Vec<Arc<Ann<V>>> = Vec::with_capacity(vector_bhs.len());
+        let mut multi_docs: Vec<Option<MultiVectorDocs>> = Vec::with_capacity(vector_bhs.len());
+        for (bh, entry) in vector_bhs.iter().zip(entries.iter()) {
+            let mut mmap_reader = MmapReader::shared(&mmap, bh.start(), bh.end());
+            let vector_index =
+                Self::read_vector_index::<Ann<V>>(&mut mmap_reader, &entry.row_entry())?;
+            vector_fields.push(Arc::new(vector_index));
+            if entry.is_multi() {
+                multi_docs.push(Some(MultiVectorDocs::binary_deserialize(&mut mmap_reader)?));
+            } else {
+                multi_docs.push(None);
+            }
+        }
 
         assert!(fields_meta.len() == meta.get_fields().len());
+        let mut analyzers = Vec::with_capacity(fields_meta.len());
+        for field in meta.get_fields().iter() {
+            analyzers.push(match field.analyzer() {
+                Some(config) => Some(config.build()?),
+                None => None,
+            });
+        }
         Ok(Self {
             meta: meta,
-            vector_field: Arc::new(vector_index),
+            vector_fields: vector_fields,
+            multi_docs: multi_docs,
+            entries: entries,
             fields_meta: fields_meta,
             blooms: blooms,
+            analyzers: analyzers,
             doc_meta: doc_meta,
             doc_end: doc_end,
             file: file,
             fsize: file_size as usize,
-            mmap: Arc::new(mmap),
+            mmap: mmap,
         })
     }
 
@@ -419,7 +582,7 @@ impl DiskStoreReader {
     fn read_vector<T: VectorSerialize>(&self, offset: usize) -> GyResult<T> {
         //  let mut r = self.mmap[offset..].reader();
         let mut mmap_reader = MmapReader::new(&self.mmap, offset, self.fsize);
-        let v = T::vector_deserialize(&mut mmap_reader, self.meta.tensor_entry())?;
+        let v = T::vector_deserialize_fields(&mut mmap_reader, &self.entries)?;
         Ok(v)
     }
 
@@ -438,9 +601,11 @@ impl DiskStoreReader {
         field_handle: &FieldHandle,
     ) -> GyResult<DiskFieldReader<'a>> {
         let fst =
-            FstReader::load(&self.mmap[field_handle.fst_bh.start()..field_handle.fst_bh.end()]);
+            FstReader::load(&self.mmap[field_handle.fst_bh.start()..field_handle.fst_bh.end()])?;
         Ok(DiskFieldReader {
             term_count: field_handle.term_count,
+            norms: &self.mmap[field_handle.norms_bh.start()..field_handle.norms_bh.end()],
+            stats: field_handle.stats,
             fst: fst,
             bloom: self.blooms[field_entry.get_field_id().id() as usize].clone(),
             mmap: self.mmap.clone(),
@@ -448,8 +613,184 @@ impl DiskStoreReader {
         })
     }
 
-    pub fn query(&self, v: &Tensor, k: usize) -> GyResult<Vec<Neighbor>> {
-        self.vector_field.query(v, k)
+    // field 为向量域的名字
+    pub fn query(&self, field: &str, v: &V, k: usize) -> GyResult<Vec<Neighbor>> {
+        self.query_inner(field, v, k, SearchParams::default(), None)
+    }
+
+    // 覆盖段中保存的 ef_search
+    pub fn query_with_ef(
+        &self,
+        field: &str,
+        v: &V,
+        k: usize,
+        ef: usize,
+    ) -> GyResult<Vec<Neighbor>> {
+        self.query_inner(
+            field,
+            v,
+            k,
+            SearchParams {
+                ef: Some(ef),
+                nprobe: None,
+            },
+            None,
+        )
+    }
+
+    // 覆盖段中保存的 nprobe
+    pub fn query_with_nprobe(
+        &self,
+        field: &str,
+        v: &V,
+        k: usize,
+        nprobe: usize,
+    ) -> GyResult<Vec<Neighbor>> {
+        self.query_inner(
+            field,
+            v,
+            k,
+            SearchParams {
+                ef: None,
+                nprobe: Some(nprobe),
+            },
+            None,
+        )
+    }
+
+    // 只在满足 filter 的文档中查找最近邻
+    pub fn query_filtered(
+        &self,
+        field: &str,
+        v: &V,
+        k: usize,
+        filter: &Filter,
+    ) -> GyResult<Vec<Neighbor>> {
+        let i = self.single_field(field)?;
+        let allow = filter.allow_list(self)?;
+        if ann::filter_brute_force(&allow, k) {
+            return ann::brute_force(&allow, v, k, self.meta.metric(i), |doc_id| {
+                Ok(self.vector(doc_id)?.into_vector(i))
+            });
+        }
+        self.query_inner(field, v, k, SearchParams::default(), Some(&allow))
+    }
+
+    // 返回距离不超过 radius 的所有文档, limit 限制最多返回的个数
+    pub fn query_radius(
+        &self,
+        field: &str,
+        v: &V,
+        radius: f32,
+        limit: Option<usize>,
+    ) -> GyResult<Vec<Neighbor>> {
+        let i = self.single_field(field)?;
+        let index = &self.vector_fields[i];
+        if index.rerank_factor() == 0 {
+            return index.query_radius(v, radius, limit);
+        }
+        // 向量块只保存 PQ 编码, 用 doc 块中的原始向量重新计算距离
+        index.query_radius_rerank(v, radius, limit, |doc_id| {
+            Ok(self.vector(doc_id)?.into_vector(i))
+        })
+    }
+
+    fn query_inner(
+        &self,
+        field: &str,
+        v: &V,
+        k: usize,
+        params: SearchParams,
+        allow: Option<&BitMap>,
+    ) -> GyResult<Vec<Neighbor>> {
+        let i = self.single_field(field)?;
+        let index = &self.vector_fields[i];
+        let ann_query = |k: usize| match allow {
+            Some(allow) => index.query_filtered(v, k, params, allow),
+            None => index.query_with(v, k, params),
+        };
+        let factor = index.rerank_factor();
+        if factor == 0 {
+            return ann_query(k);
+        }
+        // 向量块只保存 PQ 编码, 用 doc 块中的原始向量重排
+        let candidates = ann_query(k * factor)?;
+        ann::rerank(candidates, v, k, self.meta.metric(i), |doc_id| {
+            Ok(self.vector(doc_id)?.into_vector(i))
+        })
+    }
+
+    // 文本和向量两路查询融合, 见 EngineReaderBase::query_hybrid
+    pub fn query_hybrid(
+        &self,
+        terms: &[Term],
+        field: &str,
+        v: &V,
+        k: usize,
+        fusion: &Fusion,
+    ) -> GyResult<Vec<HybridHit>> {
+        let window = k * hybrid::HYBRID_WINDOW;
+        let text = hybrid::text_scores(self, terms, window)?;
+        let vector = self.query(field, v, window)?;
+        Ok(hybrid::fuse(&text, &vector, fusion, k))
+    }
+
+    // 多向量域按 MaxSim 查询, 见 EngineReaderBase::query_multi
+    pub fn query_multi(&self, field: &str, q: &V, k: usize) -> GyResult<Vec<Neighbor>> {
+        let i = self.vector_field(field)?;
+        let docs = match &self.multi_docs[i] {
+            Some(docs) => docs,
+            None => return Err(GyError::ErrVectorFieldKind(field.to_string())),
+        };
+        let dim = self.entries[i].dims()[0];
+        let index = &self.vector_fields[i];
+        ann::multi::max_sim_search(
+            &ann::multi::split_rows(q, dim),
+            k,
+            self.meta.metric(i),
+            docs,
+            |x, n| index.query(x, n),
+            |doc_id| {
+                let v = self.vector(doc_id)?.into_vector(i);
+                Ok(ann::multi::split_rows(&v, dim))
+            },
+        )
+    }
+
+    fn vector_field(&self, field: &str) -> GyResult<usize> {
+        self.meta
+            .get_vector_field(field)
+            .ok_or_else(|| GyError::ErrVectorFieldNotFound(field.to_string()))
+    }
+
+    // 普通向量域, 多向量域只能用 query_multi
+    fn single_field(&self, field: &str) -> GyResult<usize> {
+        let i = self.vector_field(field)?;
+        if self.multi_docs[i].is_some() {
+            return Err(GyError::ErrVectorFieldKind(field.to_string()));
+        }
+        Ok(i)
+    }
+
+    // 稀疏向量域上按内积取前 k 个文档, Neighbor 的距离为负的内积
+    pub fn query_sparse(
+        &self,
+        field: FieldID,
+        q: &SparseVector,
+        k: usize,
+    ) -> GyResult<Vec<Neighbor>> {
+        let field_reader = self.field_reader(field.id())?;
+        if !field_reader.is_sparse() {
+            return Err(GyError::ErrNotSparseField(field.id()));
+        }
+        let mut cursors = Vec::with_capacity(q.len());
+        for (dim, weight) in q.iter() {
+            if let Some(p) = field_reader.try_find(&ann::sparse::dim_term(*dim))? {
+                let iter = p.weights()?.map(|x| (x.doc_id(), x.weight()));
+                cursors.push(SparseCursor::new(iter, *weight, p.max_weight()?));
+            }
+        }
+        Ok(ann::sparse::wand(cursors, k))
     }
 
     pub fn search(&self, term: Term) -> GyResult<DiskPostingReader> {
@@ -458,15 +799,51 @@ impl DiskStoreReader {
         field_reader.find(term.bytes_value())
     }
 
+    // 按 BM25 打分, 返回得分最高的 k 个文档
+    pub fn search_top_k(&self, query: &dyn Query, k: usize) -> GyResult<Vec<ScoreDoc>> {
+        self.search_top_k_with(query, k, &BM25::default())
+    }
+
+    pub fn search_top_k_with(
+        &self,
+        query: &dyn Query,
+        k: usize,
+        similarity: &dyn Similarity,
+    ) -> GyResult<Vec<ScoreDoc>> {
+        let docs = query.scores(self, similarity)?;
+        Ok(similarity::top_k(docs, k))
+    }
+
+    pub fn searcher(&self) -> Searcher<Self> {
+        Searcher::new(self)
+    }
+
+    // 按域的分词配置把查询文本切分成 term
+    pub fn analyze(&self, field: FieldID, text: &str) -> Vec<Term> {
+        let analyzer = self.analyzers[field.id() as usize].as_ref();
+        tokenize::text_terms(field, analyzer, text)
+    }
+
+    // 切分成 term 和它在文本中的位置, 用于构造 PhraseQuery
+    pub fn analyze_positions(&self, field: FieldID, text: &str) -> Vec<(Term, u32)> {
+        let analyzer = self.analyzers[field.id() as usize].as_ref();
+        tokenize::text_term_positions(field, analyzer, text)
+    }
+
     pub fn doc_size(&self) -> usize {
         self.doc_meta.len()
     }
 
+    // 段元数据中保存的距离度量
+    pub fn metric(&self, field: &str) -> GyResult<MetricType> {
+        Ok(self.meta.metric(self.vector_field(field)?))
+    }
+
     pub(crate) fn doc_block(&self) -> &[u8] {
         &self.mmap[0..self.doc_end]
     }
 
-    pub fn doc_reader<'a>(&'a self) -> GyResult<DiskDocReader<'a>> {
+    pub fn doc_reader<'a>(&'a self) -> GyResult<DiskDocReader<'a, V>> {
         Ok(DiskDocReader { reader: self })
     }
 
@@ -476,13 +853,13 @@ impl DiskStoreReader {
         Ok(doc)
     }
 
-    pub fn vector(&self, doc_id: DocID) -> GyResult<Vector> {
+    pub fn vector(&self, doc_id: DocID) -> GyResult<VectorBase<V>> {
         let doc_offset = self.doc_meta[doc_id as usize];
-        let doc = self.read_vector::<Vector>(doc_offset)?;
+        let doc = self.read_vector::<VectorBase<V>>(doc_offset)?;
         Ok(doc)
     }
 
-    pub fn iter(&self) -> DiskStoreReaderIter {
+    pub fn iter(&self) -> DiskStoreReaderIter<V> {
         let handle_iter = self.fields_meta.iter();
         let field_iter = self.meta.get_fields().iter();
         DiskStoreReaderIter {
@@ -493,9 +870,83 @@ impl DiskStoreReader {
     }
 }
 
+impl<V: VectorSerialize + ValueSized + VectorOps + Clone + 'static> FilterReader
+    for DiskStoreReaderBase<V>
+where
+    V: Metric<V> + VectorElems,
+{
+    fn doc_size(&self) -> usize {
+        self.doc_meta.len()
+    }
+
+    fn term_docs(&self, term: &Term, docs: &mut BitMap) -> GyResult<()> {
+        let field_reader = self.field_reader(term.field_id().id())?;
+        if let Some(p) = field_reader.try_find(term.bytes_value())? {
+            p.iter()
+                .for_each(|doc_freq| docs.insert(doc_freq.doc_id() as usize));
+        }
+        Ok(())
+    }
+
+    fn term_postings(&self, term: &Term, f: &mut dyn FnMut(DocFreq)) -> GyResult<()> {
+        let field_reader = self.field_reader(term.field_id().id())?;
+        if let Some(p) = field_reader.try_find(term.bytes_value())? {
+            p.iter().for_each(f);
+        }
+        Ok(())
+    }
+
+    fn term_positions(&self, term: &Term, f: &mut dyn FnMut(DocFreq, Vec<u32>)) -> GyResult<()> {
+        let field_reader = self.field_reader(term.field_id().id())?;
+        if let Some(p) = field_reader.try_find(term.bytes_value())? {
+            p.positions()
+                .for_each(|(doc_freq, positions)| f(doc_freq, positions));
+        }
+        Ok(())
+    }
+
+    fn expand_terms(
+        &self,
+        field: &FieldID,
+        automaton: &mut dyn Automaton,
+        limit: usize,
+    ) -> GyResult<Vec<Term>> {
+        let field_reader = self.field_reader(field.id())?;
+        let terms = field_reader.expand(automaton, limit)?;
+        Ok(terms
+            .iter()
+            .map(|t| Term::from_field_bytes(*field, t))
+            .collect())
+    }
+
+    fn field_stats(&self, field: &FieldID) -> GyResult<FieldStats> {
+        Ok(self.fields_meta[field.id() as usize].stats)
+    }
+
+    fn doc_len(&self, field: &FieldID, doc_id: DocID) -> GyResult<u32> {
+        let bh = self.fields_meta[field.id() as usize].norms_bh;
+        Ok(doc_len(&self.mmap[bh.start()..bh.end()], doc_id))
+    }
+
+    fn field_type(&self, field: &FieldID) -> GyResult<FieldType> {
+        Ok(self.meta.get_fields()[field.id() as usize]
+            .get_field_type()
+            .clone())
+    }
+}
+
+// norms 块中每个文档的 token 数为定长的 u32, 块之外的文档不含该域
+fn doc_len(norms: &[u8], doc_id: DocID) -> u32 {
+    let i = doc_id as usize * 4;
+    if i + 4 > norms.len() {
+        return 0;
+    }
+    u32::from_be_bytes(norms[i..i + 4].try_into().unwrap())
+}
+
 use core::slice::Iter;
-pub struct DiskStoreReaderIter<'a> {
-    reader: &'a DiskStoreReader,
+pub struct DiskStoreReaderIter<'a, V: VectorSerialize + Clone> {
+    reader: &'a DiskStoreReaderBase<V>,
     handle_iter: Iter<'a, FieldHandle>,
     field_iter: Iter<'a, FieldEntry>,
 }
@@ -520,7 +971,11 @@ impl<'a> Ord for DiskFieldReader<'a> {
     }
 }
 
-impl<'a> Iterator for DiskStoreReaderIter<'a> {
+impl<'a, V: VectorSerialize + ValueSized + VectorOps + Clone + 'static> Iterator
+    for DiskStoreReaderIter<'a, V>
+where
+    V: Metric<V> + VectorElems,
+{
     type Item = DiskFieldReader<'a>;
     fn next(&mut self) -> Option<Self::Item> {
         let handle = self.handle_iter.next()?;
@@ -529,16 +984,20 @@ impl<'a> Iterator for DiskStoreReaderIter<'a> {
     }
 }
 
-pub struct DiskDocReader<'a> {
-    reader: &'a DiskStoreReader,
+pub struct DiskDocReader<'a, V: VectorSerialize + Clone> {
+    reader: &'a DiskStoreReaderBase<V>,
 }
 
-pub struct DiskDocReaderIter<'a> {
-    reader: &'a DiskStoreReader,
+pub struct DiskDocReaderIter<'a, V: VectorSerialize + Clone> {
+    reader: &'a DiskStoreReaderBase<V>,
     i: usize,
 }
 
-impl<'a> Iterator for DiskDocReaderIter<'a> {
+impl<'a, V: VectorSerialize + ValueSized + VectorOps + Clone + 'static> Iterator
+    for DiskDocReaderIter<'a, V>
+where
+    V: Metric<V> + VectorElems,
+{
     type Item = Document;
     fn next(&mut self) -> Option<Self::Item> {
         if self.i >= self.reader.doc_size() {
@@ -552,6 +1011,8 @@ impl<'a> Iterator for DiskDocReaderIter<'a> {
 
 pub struct DiskFieldReader<'a> {
     term_count: usize,
+    norms: &'a [u8],
+    stats: FieldStats,
     fst: FstReader<'a>,
     bloom: Arc<GyBloom>,
     mmap: Arc<Mmap>,
@@ -565,10 +1026,25 @@ impl<'a> DiskFieldReader<'a> {
                 std::str::from_utf8(term).unwrap().to_string(),
             ));
         }
-        let offset = self.fst.get(term)?;
+        let offset = self
+            .fst
+            .get(term)
+            .ok_or_else(|| GyError::ErrNotFoundTerm(String::from_utf8_lossy(term).to_string()))?;
         self.get(offset as usize)
     }
 
+    // term 不存在时返回 None
+    pub fn try_find(&self, term: &[u8]) -> GyResult<Option<DiskPostingReader>> {
+        if !self.bloom.check(term) {
+            return Ok(None);
+        }
+        match self.fst.get(term) {
+            Some(offset) => Ok(Some(self.get(offset as usize)?)),
+            // 布隆过滤器误判, fst 中没有这个 term
+            None => Ok(None),
+        }
+    }
+
     pub fn iter(&self) -> DiskFieldReaderIter {
         DiskFieldReaderIter {
             iter: self.fst.iter(),
@@ -576,6 +1052,17 @@ impl<'a> DiskFieldReader<'a> {
         }
     }
 
+    // 与自动机求交, 返回匹配的 term
+    // 从自动机的下界开始遍历 fst, 越过上界即停止, 不可能匹配的前缀直接跳过
+    pub fn expand(&self, automaton: &mut dyn Automaton, limit: usize) -> GyResult<Vec<Vec<u8>>> {
+        let (lower, upper) = automaton.bounds();
+        let mut iter = self.fst.range(
+            lower.as_ref().map(|k| k.as_slice()),
+            upper.as_ref().map(|k| k.as_slice()),
+        );
+        automaton::intersect(&mut iter, automaton, limit)
+    }
+
     pub fn get_field_name(&self) -> &str {
         &self.field_entry.get_name()
     }
@@ -588,6 +1075,18 @@ impl<'a> DiskFieldReader<'a> {
         self.term_count
     }
 
+    pub fn is_sparse(&self) -> bool {
+        matches!(self.field_entry.get_field_type(), FieldType::Sparse)
+    }
+
+    pub fn doc_len(&self, doc_id: DocID) -> u32 {
+        doc_len(self.norms, doc_id)
+    }
+
+    pub fn stats(&self) -> FieldStats {
+        self.stats
+    }
+
     fn get(&self, offset: usize) -> GyResult<DiskPostingReader> {
         Ok(DiskPostingReader::new(self.mmap.clone(), offset)?)
     }
@@ -598,11 +1097,11 @@ pub struct DiskFieldReaderIter<'a> {
     mmap: Arc<Mmap>,
 }
 
-pub struct FieldItem(Cow, DiskPostingReader);
+pub struct FieldItem(Vec<u8>, DiskPostingReader);
 
 impl FieldItem {
     pub fn term(&self) -> &[u8] {
-        self.0.as_ref()
+        &self.0
     }
 
     pub fn posting_reader(&self) -> &DiskPostingReader {
@@ -615,7 +1114,7 @@ impl<'a> Iterator for DiskFieldReaderIter<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         let item = self.iter.next()?;
         Some(FieldItem(
-            item.0.clone(),
+            item.0,
             DiskPostingReader::new(self.mmap.clone(), item.1 as usize).unwrap(),
         ))
     }
@@ -623,13 +1122,13 @@ impl<'a> Iterator for DiskFieldReaderIter<'a> {
 
 impl PartialOrd for FieldItem {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.0.as_ref().partial_cmp(other.0.as_ref())
+        self.0.partial_cmp(&other.0)
     }
 }
 
 impl PartialEq for FieldItem {
     fn eq(&self, other: &Self) -> bool {
-        self.0.as_ref() == other.0.as_ref()
+        self.0 == other.0
     }
 }
 
@@ -637,7 +1136,7 @@ impl Eq for FieldItem {}
 
 impl Ord for FieldItem {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.0.as_ref().cmp(other.0.as_ref())
+        self.0.cmp(&other.0)
     }
 }
 
@@ -656,8 +1155,15 @@ impl<'a, T: Write> DiskPostingWriter<'a, T> {
 }
 
 impl<'a, T: Write> DiskPostingWriter<'a, T> {
-    fn add(&mut self, doc_id: DocID, freq: u32) -> GyResult<()> {
+    fn add(&mut self, doc_id: DocID, freq: u32, positions: &[u32]) -> GyResult<()> {
         DocFreq(doc_id - self.last_docid, freq).binary_serialize(&mut self.w)?;
+        write_positions(&mut self.w, positions)?;
+        self.last_docid = doc_id;
+        Ok(())
+    }
+
+    fn add_weight(&mut self, doc_id: DocID, weight: f32) -> GyResult<()> {
+        DocWeight(doc_id - self.last_docid, weight).binary_serialize(&mut self.w)?;
         self.last_docid = doc_id;
         Ok(())
     }
@@ -688,6 +1194,47 @@ impl DiskPostingReader {
             snapshot: self.snapshot.clone(),
         }
     }
+
+    // 每个文档连同 term 在文档中的位置
+    pub fn positions(&self) -> DiskPostingPositionIter {
+        DiskPostingPositionIter {
+            last_docid: 0,
+            snapshot: self.snapshot.clone(),
+        }
+    }
+
+    // 稀疏向量域的倒排表以权重绝对值的最大值开头
+    pub fn max_weight(&self) -> GyResult<f32> {
+        f32::binary_deserialize(&mut self.snapshot.clone())
+    }
+
+    pub fn weights(&self) -> GyResult<DiskPostingWeightIter> {
+        let mut snapshot = self.snapshot.clone();
+        f32::binary_deserialize(&mut snapshot)?;
+        Ok(DiskPostingWeightIter {
+            last_docid: 0,
+            snapshot: snapshot,
+        })
+    }
+}
+
+pub struct DiskPostingWeightIter {
+    last_docid: DocID,
+    snapshot: DiskSnapshotReader,
+}
+
+impl Iterator for DiskPostingWeightIter {
+    type Item = DocWeight;
+    fn next(&mut self) -> Option<Self::Item> {
+        match DocWeight::binary_deserialize(&mut self.snapshot) {
+            Ok(mut doc_weight) => {
+                self.last_docid += doc_weight.doc_id();
+                doc_weight.0 = self.last_docid;
+                Some(doc_weight)
+            }
+            Err(_) => None,
+        }
+    }
 }
 
 pub struct DiskPostingReaderIter {
@@ -702,6 +1249,8 @@ impl Iterator for DiskPostingReaderIter {
             Ok(mut doc_freq) => {
                 self.last_docid += doc_freq.doc_id() >> 1;
                 doc_freq.0 = self.last_docid;
+                // 跳过位置
+                read_positions(&mut self.snapshot, doc_freq.freq()).ok()?;
                 Some(doc_freq)
             }
             Err(_) => None,
@@ -709,6 +1258,22 @@ impl Iterator for DiskPostingReaderIter {
     }
 }
 
+pub struct DiskPostingPositionIter {
+    last_docid: DocID,
+    snapshot: DiskSnapshotReader,
+}
+
+impl Iterator for DiskPostingPositionIter {
+    type Item = (DocFreq, Vec<u32>);
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut doc_freq = DocFreq::binary_deserialize(&mut self.snapshot).ok()?;
+        self.last_docid += doc_freq.doc_id() >> 1;
+        doc_freq.0 = self.last_docid;
+        let positions = read_positions(&mut self.snapshot, doc_freq.freq()).ok()?;
+        Some((doc_freq, positions))
+    }
+}
+
 #[derive(Clone)]
 struct DiskSnapshotReader {
     mmap: Arc<Mmap>,
@@ -765,6 +1330,7 @@ pub struct DiskStoreWriter {
     doc_meta_bh: BlockHandle,
     field_meta_bh: BlockHandle,
     vector_meta_bh: BlockHandle,
+    vector_bhs: Vec<BlockHandle>,
     file: File,
     fname: PathBuf,
     doc_end: usize,
@@ -775,23 +1341,33 @@ struct FieldHandle {
     term_count: usize,
     fst_bh: BlockHandle,
     bloom_bh: BlockHandle,
+    norms_bh: BlockHandle,
+    stats: FieldStats,
 }
 
 impl BinarySerialize for FieldHandle {
     fn binary_serialize<W: Write>(&self, writer: &mut W) -> GyResult<()> {
         self.term_count.binary_serialize(writer)?;
         self.fst_bh.binary_serialize(writer)?;
-        self.bloom_bh.binary_serialize(writer)
+        self.bloom_bh.binary_serialize(writer)?;
+        self.norms_bh.binary_serialize(writer)?;
+        self.stats.doc_count().binary_serialize(writer)?;
+        self.stats.total_len().binary_serialize(writer)
     }
 
     fn binary_deserialize<R: Read>(reader: &mut R) -> GyResult<Self> {
         let term_count = usize::binary_deserialize(reader)?;
         let fst_bh = BlockHandle::binary_deserialize(reader)?;
         let bloom_bh = BlockHandle::binary_deserialize(reader)?;
+        let norms_bh = BlockHandle::binary_deserialize(reader)?;
+        let doc_count = u64::binary_deserialize(reader)?;
+        let total_len = u64::binary_deserialize(reader)?;
         Ok(FieldHandle {
             term_count,
             fst_bh,
             bloom_bh,
+            norms_bh,
+            stats: FieldStats::new(doc_count, total_len),
         })
     }
 }
@@ -838,6 +1414,7 @@ impl DiskStoreWriter {
             doc_meta_bh: BlockHandle::default(),
             field_meta_bh: BlockHandle::default(),
             vector_meta_bh: BlockHandle::default(),
+            vector_bhs: Vec::new(),
             file: file,
             fname: fname.to_path_buf(),
             doc_end: 0,
@@ -868,9 +1445,27 @@ impl DiskStoreWriter {
         Ok(())
     }
 
-    fn write_vector(&mut self, vector_index: &Ann<Tensor>) -> GyResult<()> {
+    fn write_vector<V: VectorSerialize + Clone>(
+        &mut self,
+        vector_index: &Ann<V>,
+        multi_docs: Option<&MultiVectorDocs>,
+    ) -> GyResult<()> {
         let offset = self.offset;
         vector_index.vector_serialize(&mut self.file)?;
+        if let Some(docs) = multi_docs {
+            docs.binary_serialize(&mut self.file)?;
+        }
+        self.flush()?;
+        self.offset = self.get_cursor()? as usize;
+        self.vector_bhs
+            .push(BlockHandle(offset, self.offset - offset));
+        Ok(())
+    }
+
+    // 所有向量块的位置, footer 中的 vector_meta_bh 指向这里
+    fn write_vector_meta(&mut self) -> GyResult<()> {
+        let offset = self.offset;
+        self.vector_bhs.binary_serialize(&mut self.file)?;
         self.flush()?;
         self.offset = self.get_cursor()? as usize;
         self.vector_meta_bh = BlockHandle(offset, self.offset - offset);
@@ -910,16 +1505,33 @@ impl DiskStoreWriter {
         self.finish_index_block()
     }
 
+    // 每个文档在域中的 token 数
+    fn write_norms<I: Iterator<Item = u32>>(&mut self, norms: I) -> GyResult<BlockHandle> {
+        let offset = self.offset;
+        let mut buf: Vec<u8> = Vec::new();
+        for n in norms {
+            n.binary_serialize(&mut buf)?;
+        }
+        self.file.write_all(&buf)?;
+        self.flush()?;
+        self.offset = self.get_cursor()? as usize;
+        Ok(BlockHandle(offset, self.offset - offset))
+    }
+
     fn finish_field(
         &mut self,
         term_count: usize,
         bloom_bh: BlockHandle,
         fst_bh: BlockHandle,
+        norms_bh: BlockHandle,
+        stats: FieldStats,
     ) -> GyResult<()> {
         self.field_bhs.push(FieldHandle {
             term_count: term_count,
             fst_bh: fst_bh,
             bloom_bh: bloom_bh,
+            norms_bh: norms_bh,
+            stats: stats,
         });
         Ok(())
     }
@@ -1080,6 +1692,97 @@ pub trait GyRead {
     fn offset(&self) -> usize;
 
     fn cursor(&self) -> &[u8];
+
+    // 读取 n 个字节并持有它们, 从段文件的 mmap 读取时不拷贝
+    fn read_shared(&mut self, n: usize) -> GyResult<SharedBytes> {
+        let offset = self.offset();
+        Ok(SharedBytes::Owned {
+            data: self.read_bytes(n)?.to_vec(),
+            offset: offset,
+        })
+    }
+}
+
+// 索引中需要长期持有的一段数据
+// Mapped 持有 mmap 的引用计数, 段被关闭或合并后仍然有效
+pub enum SharedBytes {
+    Owned {
+        data: Vec<u8>,
+        offset: usize,
+    },
+    Mapped {
+        mmap: Arc<Mmap>,
+        start: usize,
+        end: usize,
+    },
+}
+
+impl SharedBytes {
+    // 数据在原文件中的偏移, 读取时按它对齐
+    pub fn offset(&self) -> usize {
+        match self {
+            SharedBytes::Owned { offset, .. } => *offset,
+            SharedBytes::Mapped { start, .. } => *start,
+        }
+    }
+}
+
+impl std::ops::Deref for SharedBytes {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        match self {
+            SharedBytes::Owned { data, .. } => data,
+            SharedBytes::Mapped { mmap, start, end } => &mmap[*start..*end],
+        }
+    }
+}
+
+// 按原文件中的偏移读取一段数据, 与 MmapReader 的对齐方式一致
+pub struct SliceReader<'a> {
+    data: &'a [u8],
+    base: usize,
+    pos: usize,
+}
+
+impl<'a> SliceReader<'a> {
+    pub fn new(data: &'a [u8], base: usize) -> SliceReader<'a> {
+        SliceReader {
+            data: data,
+            base: base,
+            pos: 0,
+        }
+    }
+}
+
+impl<'a> GyRead for SliceReader<'a> {
+    fn read_bytes(&mut self, n: usize) -> GyResult<&[u8]> {
+        if self.pos + n > self.data.len() {
+            return Err(GyError::EOF);
+        }
+        let v = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(v)
+    }
+
+    fn cursor(&self) -> &[u8] {
+        &self.data[self.pos..]
+    }
+
+    fn offset(&self) -> usize {
+        self.base + self.pos
+    }
+}
+
+impl<'a> Read for SliceReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let buf_len = buf.len();
+        if self.pos + buf_len > self.data.len() {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        buf.copy_from_slice(&self.data[self.pos..self.pos + buf_len]);
+        self.pos += buf_len;
+        Ok(buf_len)
+    }
 }
 
 pub trait GyWrite {
@@ -1089,6 +1792,8 @@ pub trait GyWrite {
 
 pub struct MmapReader<'a> {
     mmap: &'a Mmap,
+    // 打开段时传入, 索引可以持有 mmap 中的数据
+    shared: Option<Arc<Mmap>>,
     offset: usize,
     file_size: usize,
 }
@@ -1110,12 +1815,41 @@ impl<'a> GyRead for MmapReader<'a> {
     fn offset(&self) -> usize {
         self.offset
     }
+
+    fn read_shared(&mut self, n: usize) -> GyResult<SharedBytes> {
+        let start = self.offset;
+        match self.shared.clone() {
+            Some(mmap) => {
+                self.read_bytes(n)?;
+                Ok(SharedBytes::Mapped {
+                    mmap: mmap,
+                    start: start,
+                    end: start + n,
+                })
+            }
+            None => Ok(SharedBytes::Owned {
+                data: self.read_bytes(n)?.to_vec(),
+                offset: start,
+            }),
+        }
+    }
 }
 
 impl<'a> MmapReader<'a> {
     pub fn new(mmap: &'a Mmap, offset: usize, file_size: usize) -> MmapReader {
         Self {
             mmap: mmap,
+            shared: None,
+            offset: offset,
+            file_size: file_size,
+        }
+    }
+
+    // 读出的 SharedBytes 直接引用 mmap
+    pub fn shared(mmap: &'a Arc<Mmap>, offset: usize, file_size: usize) -> MmapReader {
+        Self {
+            mmap: mmap,
+            shared: Some(mmap.clone()),
             offset: offset,
             file_size: file_size,
         }
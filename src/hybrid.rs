@@ -0,0 +1,146 @@
+use super::ann::Neighbor;
+use super::query::{FilterReader, Query, Term, TermQuery};
+use super::schema::DocID;
+use super::similarity::{self, ScoreDoc, BM25};
+use super::util::error::GyResult;
+use std::collections::HashMap;
+
+// 每一路查询取 k 的这个倍数个结果参与融合
+pub(crate) const HYBRID_WINDOW: usize = 4;
+
+// 文本和向量两路结果的融合方式
+#[derive(Debug, Clone, Copy)]
+pub enum Fusion {
+    // 倒数排名融合, 每一路贡献 1 / (k + rank), rank 从 1 开始
+    Rrf(f32),
+    // 每一路按 min-max 归一化到 [0, 1] 后加权求和
+    Weighted { text: f32, vector: f32 },
+}
+
+impl Default for Fusion {
+    fn default() -> Self {
+        Fusion::Rrf(60.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HybridHit {
+    doc_id: DocID,
+    score: f32,
+    text_score: Option<f32>,
+    vector_distance: Option<f32>,
+}
+
+impl HybridHit {
+    pub fn doc_id(&self) -> DocID {
+        self.doc_id
+    }
+
+    // 融合后的得分, 越大越相关
+    pub fn score(&self) -> f32 {
+        self.score
+    }
+
+    // 文本部分的得分, 不在文本结果中时为 None
+    pub fn text_score(&self) -> Option<f32> {
+        self.text_score
+    }
+
+    // 向量部分的距离, 与 Neighbor::distance 相同, 不在向量结果中时为 None
+    pub fn vector_distance(&self) -> Option<f32> {
+        self.vector_distance
+    }
+}
+
+// 文本部分: 每个 term 按 BM25 累加得分, 按得分从高到低取前 n 个
+pub(crate) fn text_scores<R: FilterReader>(
+    reader: &R,
+    terms: &[Term],
+    n: usize,
+) -> GyResult<Vec<ScoreDoc>> {
+    let bm25 = BM25::default();
+    let mut scores: HashMap<DocID, f32> = HashMap::new();
+    for term in terms.iter() {
+        let query = TermQuery::new(term.clone());
+        for d in query.scores(reader, &bm25)? {
+            *scores.entry(d.doc_id()).or_insert(0.0) += d.score();
+        }
+    }
+    let docs = scores
+        .into_iter()
+        .map(|(doc_id, score)| ScoreDoc::new(doc_id, score))
+        .collect();
+    Ok(similarity::top_k(docs, n))
+}
+
+// min-max 归一化, 所有值相同时都为 1
+fn normalize(v: &[f32]) -> Vec<f32> {
+    let min = v.iter().cloned().fold(f32::INFINITY, f32::min);
+    let max = v.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    v.iter()
+        .map(|x| {
+            if max > min {
+                (x - min) / (max - min)
+            } else {
+                1.0
+            }
+        })
+        .collect()
+}
+
+// text 按得分从高到低, vector 按距离从近到远, 融合后取前 k 个
+pub(crate) fn fuse(
+    text: &[ScoreDoc],
+    vector: &[Neighbor],
+    fusion: &Fusion,
+    k: usize,
+) -> Vec<HybridHit> {
+    let (text_parts, vector_parts): (Vec<f32>, Vec<f32>) = match fusion {
+        Fusion::Rrf(c) => (
+            (0..text.len())
+                .map(|i| 1.0 / (c + (i + 1) as f32))
+                .collect(),
+            (0..vector.len())
+                .map(|i| 1.0 / (c + (i + 1) as f32))
+                .collect(),
+        ),
+        Fusion::Weighted {
+            text: wt,
+            vector: wv,
+        } => {
+            let t: Vec<f32> = text.iter().map(|x| x.score()).collect();
+            // 距离越小越相似, 取负后再归一化
+            let v: Vec<f32> = vector.iter().map(|x| -x.distance()).collect();
+            (
+                normalize(&t).iter().map(|x| x * wt).collect(),
+                normalize(&v).iter().map(|x| x * wv).collect(),
+            )
+        }
+    };
+    let mut hits: HashMap<DocID, HybridHit> = HashMap::new();
+    let hit = |doc_id: DocID| HybridHit {
+        doc_id: doc_id,
+        score: 0.0,
+        text_score: None,
+        vector_distance: None,
+    };
+    for (d, part) in text.iter().zip(text_parts.iter()) {
+        let h = hits.entry(d.doc_id()).or_insert_with(|| hit(d.doc_id()));
+        h.score += part;
+        h.text_score = Some(d.score());
+    }
+    for (n, part) in vector.iter().zip(vector_parts.iter()) {
+        let h = hits.entry(n.doc_id()).or_insert_with(|| hit(n.doc_id()));
+        h.score += part;
+        h.vector_distance = Some(n.distance());
+    }
+    let mut results: Vec<HybridHit> = hits.into_values().collect();
+    results.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap()
+            .then(a.doc_id.cmp(&b.doc_id))
+    });
+    results.truncate(k);
+    results
+}
@@ -1,24 +1,35 @@
 pub mod ann;
+pub mod automaton;
 mod buffer;
 pub mod collection;
 pub mod config;
 pub mod disk;
+pub mod hybrid;
 pub mod query;
 pub mod schema;
-mod searcher;
-mod tokenize;
+pub mod searcher;
+pub mod similarity;
+pub mod tokenize;
 pub mod util;
 use crate::config::Config;
 use crate::config::EngineConfig;
+use ann::multi::MultiVectorDocs;
+use ann::sparse::SparseCursor;
 use ann::Neighbor;
-use art_tree::{Art, ByteString};
+use ann::SearchParams;
+use automaton::{Automaton, TermCursor};
 use core::cell::UnsafeCell;
 use disk::GyRead;
 use galois::Tensor;
-use query::Term;
+use hybrid::{Fusion, HybridHit};
+use query::{Filter, FilterReader, Query, Term};
 use schema::TensorEntry;
 use schema::ValueSized;
+use searcher::Searcher;
+use similarity::{FieldStats, ScoreDoc, Similarity, BM25};
 use std::sync::atomic::AtomicU64;
+use tokenize::Analyzer;
+use util::bitmap::BitMap;
 use util::error::{GyError, GyResult};
 use util::fs::FileManager;
 use wal::ThreadWal;
@@ -27,30 +38,36 @@ mod macros;
 use crate::buffer::SafeAddr;
 use crate::schema::VectorBase;
 pub mod wal;
+use self::schema::DocFreq;
+use self::schema::DocWeight;
+use self::util::fs;
 use crate::ann::Ann;
-use crate::ann::HNSW;
 use crate::disk::GyWrite;
+use crate::schema::FieldEntry;
+use crate::schema::FieldType;
+use crate::schema::Vector;
 use crate::schema::VectorOps;
 use crate::schema::VectorSerialize;
+use crate::util::time::Time;
+use crate::wal::WalReader;
+pub use ann::BinaryVector;
 use ann::Metric;
+pub use ann::MetricType;
+pub use ann::SparseVector;
+use ann::VectorElems;
 use buffer::{
     Addr, ByteBlockPool, RingBuffer, RingBufferReader, SnapshotReader, SnapshotReaderIter,
     BLOCK_SIZE_CLASS,
 };
-use schema::{BinarySerialize, DocID, Document, Schema, Value};
-use serde::{Deserialize, Serialize};
-//use jiebars::Jieba;
-use self::schema::DocFreq;
-use self::util::fs;
-use crate::schema::FieldEntry;
-use crate::schema::Vector;
-use crate::util::time::Time;
-use crate::wal::WalReader;
 use lock_api::RawMutex;
 use once_cell::sync::Lazy;
 use parking_lot::Mutex;
+use schema::{BinarySerialize, DocID, Document, FieldID, Schema, Value};
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
+use std::collections::{btree_map, BTreeMap};
 use std::io::Write;
+use std::ops::Bound;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock, Weak};
@@ -102,44 +119,211 @@ unsafe impl<V: VectorSerialize + ValueSized + VectorOps + Clone + 'static> Sync
 {
 }
 
-pub struct EngineReader {
-    pub(crate) vector_field: Arc<VectorIndexBase<Tensor>>,
+pub struct EngineReaderBase<V: VectorSerialize + Clone> {
+    pub(crate) vector_fields: Arc<VectorFields<V>>,
     index_reader: IndexReader,
-    entry: TensorEntry,
 }
 
-impl EngineReader {
-    pub fn query(&self, v: &Tensor, k: usize) -> GyResult<Vec<Neighbor>> {
-        self.vector_field.query(v, k)
+pub type EngineReader = EngineReaderBase<Tensor>;
+
+impl<V: VectorSerialize + ValueSized + VectorOps + Clone + 'static> EngineReaderBase<V>
+where
+    V: Metric<V> + VectorElems,
+{
+    // field 为向量域的名字
+    pub fn query(&self, field: &str, v: &V, k: usize) -> GyResult<Vec<Neighbor>> {
+        self.query_inner(field, v, k, SearchParams::default(), None)
+    }
+
+    // 覆盖 schema 中的 ef_search, 更大的 ef 召回率更高但更慢
+    pub fn query_with_ef(
+        &self,
+        field: &str,
+        v: &V,
+        k: usize,
+        ef: usize,
+    ) -> GyResult<Vec<Neighbor>> {
+        self.query_inner(
+            field,
+            v,
+            k,
+            SearchParams {
+                ef: Some(ef),
+                nprobe: None,
+            },
+            None,
+        )
+    }
+
+    // 覆盖 schema 中的 nprobe, 只对 IvfFlat 和 IvfPQ 生效
+    pub fn query_with_nprobe(
+        &self,
+        field: &str,
+        v: &V,
+        k: usize,
+        nprobe: usize,
+    ) -> GyResult<Vec<Neighbor>> {
+        self.query_inner(
+            field,
+            v,
+            k,
+            SearchParams {
+                ef: None,
+                nprobe: Some(nprobe),
+            },
+            None,
+        )
+    }
+
+    // 只在满足 filter 的文档中查找最近邻
+    pub fn query_filtered(
+        &self,
+        field: &str,
+        v: &V,
+        k: usize,
+        filter: &Filter,
+    ) -> GyResult<Vec<Neighbor>> {
+        let (i, index) = self.vector_fields.field(field)?;
+        let allow = filter.allow_list(&self.index_reader)?;
+        if ann::filter_brute_force(&allow, k) {
+            return ann::brute_force(&allow, v, k, index.metric()?, |doc_id| {
+                Ok(self.vector(doc_id)?.into_vector(i))
+            });
+        }
+        self.query_inner(field, v, k, SearchParams::default(), Some(&allow))
+    }
+
+    // 返回距离不超过 radius 的所有文档, limit 限制最多返回的个数
+    pub fn query_radius(
+        &self,
+        field: &str,
+        v: &V,
+        radius: f32,
+        limit: Option<usize>,
+    ) -> GyResult<Vec<Neighbor>> {
+        let (i, index) = self.vector_fields.field(field)?;
+        if index.rerank_factor()? == 0 {
+            return index.query_radius(v, radius, limit);
+        }
+        index.query_radius_rerank(v, radius, limit, |doc_id| {
+            Ok(self.vector(doc_id)?.into_vector(i))
+        })
+    }
+
+    fn query_inner(
+        &self,
+        field: &str,
+        v: &V,
+        k: usize,
+        params: SearchParams,
+        allow: Option<&BitMap>,
+    ) -> GyResult<Vec<Neighbor>> {
+        let (i, index) = self.vector_fields.field(field)?;
+        let factor = index.rerank_factor()?;
+        if factor == 0 {
+            return index.query(v, k, params, allow);
+        }
+        let candidates = index.query(v, k * factor, params, allow)?;
+        let metric = index.metric()?;
+        ann::rerank(candidates, v, k, metric, |doc_id| {
+            Ok(self.vector(doc_id)?.into_vector(i))
+        })
+    }
+
+    // 稀疏向量域上按内积取前 k 个文档, Neighbor 的距离为负的内积
+    pub fn query_sparse(
+        &self,
+        field: FieldID,
+        q: &SparseVector,
+        k: usize,
+    ) -> GyResult<Vec<Neighbor>> {
+        self.index_reader.query_sparse(field, q, k)
+    }
+
+    // 多向量域按 MaxSim 查询, q 的每一行为一个查询向量
+    // Neighbor 的距离为每个查询向量到文档中最近向量的距离之和, 内积下 score 即 MaxSim 得分
+    pub fn query_multi(&self, field: &str, q: &V, k: usize) -> GyResult<Vec<Neighbor>> {
+        let (i, index, docs) = self.vector_fields.multi_field(field)?;
+        let dim = self.vector_fields.entries()[i].dims()[0];
+        let metric = index.metric()?;
+        let docs = docs.read()?;
+        ann::multi::max_sim_search(
+            &ann::multi::split_rows(q, dim),
+            k,
+            metric,
+            &docs,
+            |x, n| index.query(x, n, None, None),
+            |doc_id| {
+                let v = self.vector(doc_id)?.into_vector(i);
+                Ok(ann::multi::split_rows(&v, dim))
+            },
+        )
+    }
+
+    // terms 的文本得分和 field 向量域的最近邻按 fusion 融合成一个结果列表
+    pub fn query_hybrid(
+        &self,
+        terms: &[Term],
+        field: &str,
+        v: &V,
+        k: usize,
+        fusion: &Fusion,
+    ) -> GyResult<Vec<HybridHit>> {
+        let window = k * hybrid::HYBRID_WINDOW;
+        let text = hybrid::text_scores(&self.index_reader, terms, window)?;
+        let vector = self.query(field, v, window)?;
+        Ok(hybrid::fuse(&text, &vector, fusion, k))
+    }
+
+    // Neighbor 的距离按这个度量计算
+    pub fn metric(&self, field: &str) -> GyResult<MetricType> {
+        self.vector_fields.indexes()[self.vector_fields.position(field)?].metric()
     }
 
     pub fn search(&self, term: Term) -> GyResult<PostingReader> {
         self.index_reader.search(term)
     }
 
-    pub fn vector_iter<'a>(&'a self, entry: TensorEntry) -> WalIter<'a, Vector> {
-        // let entry = self
-        //     .index_reader
-        //     .get_index_base()
-        //     .get_meta()
-        //     .tensor_entry()
-        //     .clone();
+    // 按 BM25 打分, 返回得分最高的 k 个文档
+    pub fn search_top_k(&self, query: &dyn Query, k: usize) -> GyResult<Vec<ScoreDoc>> {
+        self.search_top_k_with(query, k, &BM25::default())
+    }
+
+    pub fn search_top_k_with(
+        &self,
+        query: &dyn Query,
+        k: usize,
+        similarity: &dyn Similarity,
+    ) -> GyResult<Vec<ScoreDoc>> {
+        let docs = query.scores(&self.index_reader, similarity)?;
+        Ok(similarity::top_k(docs, k))
+    }
+
+    pub fn searcher(&self) -> Searcher<IndexReader> {
+        self.index_reader.searcher()
+    }
+
+    pub fn analyze(&self, field: FieldID, text: &str) -> Vec<Term> {
+        self.index_reader.analyze(field, text)
+    }
+
+    pub fn analyze_positions(&self, field: FieldID, text: &str) -> Vec<(Term, u32)> {
+        self.index_reader.analyze_positions(field, text)
+    }
+
+    pub fn vector_iter<'a>(&'a self) -> WalIter<'a, VectorBase<V>> {
         self.index_reader
             .get_index_base()
             .get_wal_mut()
-            .iter::<Vector>(entry)
-    }
-
-    fn tensor_entry(&self) -> &TensorEntry {
-        &self.entry
+            .iter::<VectorBase<V>>(self.vector_fields.entries().to_vec())
     }
 
-    pub fn vector(&self, doc_id: DocID) -> GyResult<Vector> {
+    pub fn vector(&self, doc_id: DocID) -> GyResult<VectorBase<V>> {
         let doc_offset = self.index_reader.index_base.doc_offset(doc_id)?;
-        let v: Vector = {
+        let v: VectorBase<V> = {
             let wal = self.index_reader.wal.get_borrow();
             let mut wal_read = WalReader::new(wal, doc_offset, wal.offset());
-            Vector::vector_deserialize(&mut wal_read, self.tensor_entry())?
+            VectorBase::<V>::vector_deserialize_fields(&mut wal_read, self.vector_fields.entries())?
         };
         Ok(v)
     }
@@ -150,26 +334,37 @@ impl EngineReader {
 }
 
 #[derive(Clone)]
-pub struct Engine(Arc<VectorEngine<Tensor>>);
+pub struct EngineBase<V: VectorSerialize + ValueSized + VectorOps + Clone + 'static>(
+    Arc<VectorEngine<V>>,
+)
+where
+    V: Metric<V>;
+
+pub type Engine = EngineBase<Tensor>;
 
-impl Engine {
-    pub fn reader(&self) -> EngineReader {
-        EngineReader {
-            vector_field: self.0.vector_field.clone(),
+// 按位打包的二值向量, 配合 MetricType::Hamming 使用
+pub type BinaryEngine = EngineBase<BinaryVector>;
+
+impl<V: VectorSerialize + ValueSized + VectorOps + Clone + 'static> EngineBase<V>
+where
+    V: Metric<V> + VectorElems,
+{
+    pub fn reader(&self) -> EngineReaderBase<V> {
+        EngineReaderBase {
+            vector_fields: self.0.vector_fields.clone(),
             index_reader: IndexReader::new(self.0.index_base.clone()),
-            entry: self.0.entry.clone(),
         }
     }
 
-    pub fn new(schema: &Schema, config: EngineConfig) -> GyResult<Engine> {
-        Ok(Engine(Arc::new(VectorEngine::new(schema, config)?)))
+    pub fn new(schema: &Schema, config: EngineConfig) -> GyResult<EngineBase<V>> {
+        Ok(EngineBase(Arc::new(VectorEngine::new(schema, config)?)))
     }
 
-    pub fn open(schema: &Schema, config: EngineConfig) -> GyResult<Engine> {
-        Ok(Engine(Arc::new(VectorEngine::open(schema, config)?)))
+    pub fn open(schema: &Schema, config: EngineConfig) -> GyResult<EngineBase<V>> {
+        Ok(EngineBase(Arc::new(VectorEngine::open(schema, config)?)))
     }
 
-    pub fn add(&self, v: Vector) -> GyResult<DocID> {
+    pub fn add(&self, v: VectorBase<V>) -> GyResult<DocID> {
         self.0.add(v)
     }
 
@@ -182,21 +377,206 @@ struct VectorIndexBase<V: VectorSerialize + Clone>(RwLock<Ann<V>>);
 
 impl<V: VectorSerialize + Clone> VectorIndexBase<V>
 where
-    V: Metric<V>,
+    V: Metric<V> + VectorElems,
 {
-    pub fn query(&self, v: &V, k: usize) -> GyResult<Vec<Neighbor>> {
-        self.0.read()?.query(&v, k)
+    pub fn query(
+        &self,
+        v: &V,
+        k: usize,
+        params: SearchParams,
+        allow: Option<&BitMap>,
+    ) -> GyResult<Vec<Neighbor>> {
+        match allow {
+            Some(allow) => self.0.read()?.query_filtered(&v, k, params, allow),
+            None => self.0.read()?.query_with(&v, k, params),
+        }
+    }
+
+    pub fn query_radius(
+        &self,
+        v: &V,
+        radius: f32,
+        limit: Option<usize>,
+    ) -> GyResult<Vec<Neighbor>> {
+        self.0.read()?.query_radius(&v, radius, limit)
+    }
+
+    pub fn query_radius_rerank<F>(
+        &self,
+        v: &V,
+        radius: f32,
+        limit: Option<usize>,
+        vector: F,
+    ) -> GyResult<Vec<Neighbor>>
+    where
+        F: FnMut(DocID) -> GyResult<V>,
+    {
+        self.0
+            .read()?
+            .query_radius_rerank(&v, radius, limit, vector)
     }
 
     pub fn insert(&self, v: V) -> GyResult<usize> {
         self.0.write()?.insert(v)
     }
 
+    pub fn rerank_factor(&self) -> GyResult<usize> {
+        Ok(self.0.read()?.rerank_factor())
+    }
+
+    pub fn metric(&self) -> GyResult<MetricType> {
+        Ok(self.0.read()?.metric())
+    }
+
     pub fn merge(&self, other: &Self) -> Self {
         todo!()
     }
 }
 
+// 每个向量域一个 ANN 索引, 下标与 Schema::vector_entries 的顺序一致
+pub(crate) struct VectorFields<V: VectorSerialize + Clone> {
+    indexes: Vec<VectorIndexBase<V>>,
+    names: Vec<String>,
+    entries: Vec<TensorEntry>,
+    // 多向量域中索引编号到文档的映射, 普通向量域为 None
+    multi: Vec<Option<RwLock<MultiVectorDocs>>>,
+}
+
+impl<V: VectorSerialize + Clone> VectorFields<V>
+where
+    V: Metric<V> + VectorElems,
+{
+    // 每个向量域的元素类型必须能用 V 保存, 二值向量只能用 BinaryEngine
+    fn new(schema: &Schema) -> GyResult<VectorFields<V>>
+    where
+        V: VectorOps,
+    {
+        for e in schema.vector_entries() {
+            let t = e.tensor_entry().vector_type();
+            if !V::supports(t) {
+                return Err(GyError::ErrUnsupportedVectorType(format!(
+                    "{:?} in field {}",
+                    t,
+                    e.name()
+                )));
+            }
+        }
+        Ok(Self {
+            indexes: schema
+                .vector_entries()
+                .map(|e| VectorIndexBase(RwLock::new(Ann::from_entry(e))))
+                .collect(),
+            names: schema
+                .vector_entries()
+                .map(|e| e.name().to_string())
+                .collect(),
+            entries: schema.tensor_entries(),
+            multi: schema
+                .tensor_entries()
+                .iter()
+                .map(|e| e.is_multi().then(|| RwLock::new(MultiVectorDocs::new())))
+                .collect(),
+        })
+    }
+
+    pub(crate) fn position(&self, name: &str) -> GyResult<usize> {
+        self.names
+            .iter()
+            .position(|n| n == name)
+            .ok_or_else(|| GyError::ErrVectorFieldNotFound(name.to_string()))
+    }
+
+    // 普通向量域, 多向量域只能用 multi_field
+    pub(crate) fn field(&self, name: &str) -> GyResult<(usize, &VectorIndexBase<V>)> {
+        let i = self.position(name)?;
+        if self.multi[i].is_some() {
+            return Err(GyError::ErrVectorFieldKind(name.to_string()));
+        }
+        Ok((i, &self.indexes[i]))
+    }
+
+    pub(crate) fn multi_field(
+        &self,
+        name: &str,
+    ) -> GyResult<(usize, &VectorIndexBase<V>, &RwLock<MultiVectorDocs>)> {
+        let i = self.position(name)?;
+        match &self.multi[i] {
+            Some(docs) => Ok((i, &self.indexes[i], docs)),
+            None => Err(GyError::ErrVectorFieldKind(name.to_string())),
+        }
+    }
+
+    pub(crate) fn multi_docs(&self) -> &[Option<RwLock<MultiVectorDocs>>] {
+        &self.multi
+    }
+
+    pub(crate) fn indexes(&self) -> &[VectorIndexBase<V>] {
+        &self.indexes
+    }
+
+    pub(crate) fn entries(&self) -> &[TensorEntry] {
+        &self.entries
+    }
+
+    fn check(&self, v: &VectorBase<V>) -> GyResult<()>
+    where
+        V: ValueSized + VectorOps,
+    {
+        if v.vector_count() != self.indexes.len() {
+            return Err(GyError::ErrVectorFieldMismatch(
+                self.indexes.len(),
+                v.vector_count(),
+            ));
+        }
+        // 写入 WAL 的向量按域的类型和大小读回, 不一致的记录会让重放提前结束
+        for (i, entry) in self.entries.iter().enumerate() {
+            let vector = v.vector_at(i).unwrap();
+            let t = vector.vector_type();
+            if t.as_ref() != Some(entry.vector_type()) {
+                return Err(GyError::ErrVectorTypeMismatch(
+                    format!("{:?}", entry.vector_type()),
+                    format!("{:?}", t),
+                ));
+            }
+            let size = vector.bytes_size();
+            if entry.is_multi() {
+                if size % entry.row_nbytes() != 0 {
+                    return Err(GyError::ErrInvalidMultiVector(size, entry.row_nbytes()));
+                }
+            } else if size != entry.nbytes() {
+                return Err(GyError::ErrVectorSizeMismatch(entry.nbytes(), size));
+            }
+        }
+        Ok(())
+    }
+
+    // 每个向量插入对应的索引, 普通向量域中的编号等于文档 id
+    // 多向量域按行拆开后逐个插入, 编号记录在 MultiVectorDocs 中
+    fn insert(&self, doc_id: DocID, v: V, vectors: Vec<V>) -> GyResult<()> {
+        for (i, v) in std::iter::once(v).chain(vectors).enumerate() {
+            match &self.multi[i] {
+                Some(docs) => {
+                    let rows = ann::multi::split_rows(&v, self.entries[i].dims()[0]);
+                    let start = docs.write()?.push(rows.len());
+                    for (j, row) in rows.into_iter().enumerate() {
+                        let id = self.indexes[i].insert(row)?;
+                        if id != start + j {
+                            return Err(GyError::ErrVectorIdMismatch(start + j, id));
+                        }
+                    }
+                }
+                None => {
+                    let id = self.indexes[i].insert(v)?;
+                    if id as DocID != doc_id {
+                        return Err(GyError::ErrVectorIdMismatch(doc_id as usize, id));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 impl<V: VectorSerialize + Clone> VectorSerialize for VectorIndexBase<V> {
     fn vector_deserialize<R: std::io::Read + GyRead>(
         reader: &mut R,
@@ -215,37 +595,34 @@ pub struct VectorEngine<V: VectorSerialize + ValueSized + VectorOps + Clone + 's
 where
     V: Metric<V>,
 {
-    vector_field: Arc<VectorIndexBase<V>>,
+    vector_fields: Arc<VectorFields<V>>,
     index_base: Arc<IndexBase>,
-    entry: TensorEntry,
     rw_lock: Mutex<()>,
 }
 
 impl<V: VectorSerialize + ValueSized + VectorOps + Clone + 'static> VectorEngine<V>
 where
-    V: Metric<V>,
+    V: Metric<V> + VectorElems,
 {
     fn new(schema: &Schema, config: EngineConfig) -> GyResult<VectorEngine<V>> {
         Ok(Self {
-            vector_field: Arc::new(VectorIndexBase(RwLock::new(Ann::HNSW(HNSW::<V>::new(32))))),
+            vector_fields: Arc::new(VectorFields::new(schema)?),
             index_base: Arc::new(IndexBase::new(schema, config)?),
-            entry: schema.tensor_entry().clone(),
             rw_lock: Mutex::new(()),
         })
     }
 
     pub fn open(schema: &Schema, config: EngineConfig) -> GyResult<VectorEngine<V>> {
-        let tensor_entry = schema.tensor_entry().clone();
+        let tensor_entries = schema.tensor_entries();
         let colletion = Self {
-            vector_field: Arc::new(VectorIndexBase(RwLock::new(Ann::HNSW(HNSW::<V>::new(32))))),
+            vector_fields: Arc::new(VectorFields::new(schema)?),
             index_base: Arc::new(IndexBase::open(schema, config)?),
-            entry: schema.tensor_entry().clone(),
             rw_lock: Mutex::new(()),
         };
         {
             let wal = colletion.index_base.get_wal_mut();
             let offset = {
-                let mut wal_iter = wal.iter::<VectorBase<V>>(tensor_entry);
+                let mut wal_iter = wal.iter::<VectorBase<V>>(tensor_entries);
                 while let Some((doc_offset, v)) = wal_iter.next() {
                     colletion.quick_add(doc_offset, v)?;
                 }
@@ -264,15 +641,20 @@ where
         self.index_base
             .last_offset
             .store(doc_offset, Ordering::SeqCst);
-        let id = self.vector_field.insert(v.v)? as DocID;
-        assert!(id == doc_id);
+        let VectorBase {
+            v,
+            vectors,
+            payload,
+        } = v;
+        self.vector_fields.insert(doc_id, v, vectors)?;
         self.index_base.doc_id.fetch_add(1, Ordering::SeqCst);
-        self.index_base.inner_add(id, &v.payload)?;
+        self.index_base.inner_add(doc_id, &payload)?;
         self.index_base.commit()?;
         Ok(doc_id)
     }
 
     pub fn batch_add(&self, v: VectorBase<V>) -> GyResult<()> {
+        self.vector_fields.check(&v)?;
         unsafe {
             self.rw_lock.raw().lock();
         }
@@ -298,6 +680,7 @@ where
     }
 
     pub fn add(&self, v: VectorBase<V>) -> GyResult<DocID> {
+        self.vector_fields.check(&v)?;
         unsafe {
             self.rw_lock.raw().lock();
         }
@@ -320,7 +703,7 @@ where
         let offset = {
             let w = self.index_base.wal.get_borrow_mut();
             let offset = w.offset();
-            v.vector_serialize(w)?;
+            v.vector_serialize_fields(w, self.vector_fields.entries())?;
             w.flush()?;
             offset
         };
@@ -377,8 +760,23 @@ impl Meta {
         &self.schema.fields
     }
 
-    pub fn tensor_entry(&self) -> &TensorEntry {
-        self.schema.vector_field.tensor_entry()
+    pub fn tensor_entries(&self) -> Vec<TensorEntry> {
+        self.schema.tensor_entries()
+    }
+
+    pub fn get_vector_field(&self, field_name: &str) -> Option<usize> {
+        self.schema.get_vector_field(field_name)
+    }
+
+    pub fn metric(&self, i: usize) -> MetricType {
+        self.schema.vector_entries().nth(i).unwrap().metric()
+    }
+
+    // 向量域的名字, 索引类型, 形状和度量都一致时两个段的向量块才能按下标合并
+    pub fn same_vector_layout(&self, other: &Meta) -> bool {
+        self.schema
+            .vector_entries()
+            .eq(other.schema.vector_entries())
     }
 }
 
@@ -390,8 +788,8 @@ impl IndexBase {
 
         let buffer_pool = Arc::new(RingBuffer::new());
         let mut field_cache: Vec<FieldCache> = Vec::new();
-        for _ in 0..schema.fields.len() {
-            field_cache.push(FieldCache::new(Arc::downgrade(&buffer_pool)));
+        for field in schema.fields.iter() {
+            field_cache.push(FieldCache::new(Arc::downgrade(&buffer_pool), field)?);
         }
         Ok(Self {
             fields: field_cache,
@@ -416,8 +814,8 @@ impl IndexBase {
         }
         let buffer_pool = Arc::new(RingBuffer::new());
         let mut field_cache: Vec<FieldCache> = Vec::new();
-        for _ in 0..schema.fields.len() {
-            field_cache.push(FieldCache::new(Arc::downgrade(&buffer_pool)));
+        for field in schema.fields.iter() {
+            field_cache.push(FieldCache::new(Arc::downgrade(&buffer_pool), field)?);
         }
         let wal = Wal::open(
             &config.get_wal_path(), //&index_path.join(&config.wal_fname),
@@ -449,7 +847,10 @@ impl IndexBase {
         for field in doc.field_values.iter() {
             // println!("field.field_id().0:{}", field.field_id().id());
             let fw = self.fields.get(field.field_id().id() as usize).unwrap();
-            fw.add(doc_id, field.value())?;
+            match field.value() {
+                Value::Sparse(v) => fw.add_sparse(doc_id, v)?,
+                value => fw.add(doc_id, value)?,
+            }
         }
         Ok(())
     }
@@ -530,6 +931,11 @@ pub struct _Posting {
     doc_freq_addr: SafeAddr,
     doc_count: usize,
     freq: u32,
+    // 稀疏向量域: 未提交文档的权重和权重绝对值的最大值
+    weight: f32,
+    max_weight: f32,
+    // 未提交文档中 term 出现的位置
+    positions: Vec<u32>,
     add_commit: bool,
 }
 
@@ -543,6 +949,9 @@ impl _Posting {
             doc_count: 0,
             add_commit: false,
             freq: 0,
+            weight: 0.0,
+            max_weight: 0.0,
+            positions: Vec::new(),
         }
     }
 }
@@ -550,38 +959,86 @@ impl _Posting {
 // 用于数值索引
 pub(crate) struct TrieIntCache {}
 
-//用于字符串索引
+//用于字符串索引, 按字典序保存 term, 可以按区间查找
 pub(crate) struct ArtCache {
-    cache: Art<ByteString, Posting>,
+    cache: BTreeMap<Vec<u8>, Posting>,
 }
 
 impl ArtCache {
     pub(crate) fn new() -> ArtCache {
-        Self { cache: Art::new() }
+        Self {
+            cache: BTreeMap::new(),
+        }
     }
 }
 
 impl ArtCache {
     fn contains_key(&self, k: &[u8]) -> bool {
-        let a = self.cache.iter();
-        match self.cache.get(&ByteString::new(&k)) {
-            Some(_) => true,
-            None => false,
-        }
+        self.cache.contains_key(k)
     }
 
     fn insert(&mut self, k: Vec<u8>, v: Posting) -> Option<Posting> {
-        self.cache.upsert(ByteString::new(&k), v.clone());
+        self.cache.insert(k, v.clone());
         Some(v)
     }
 
     fn get(&self, k: &[u8]) -> Option<&Posting> {
-        self.cache.get(&ByteString::new(&k))
+        self.cache.get(k)
     }
 
-    fn iter(&self) -> impl DoubleEndedIterator<Item = (&ByteString, &Posting)> {
+    fn iter(&self) -> impl DoubleEndedIterator<Item = (&Vec<u8>, &Posting)> {
         self.cache.iter()
     }
+
+    // 按字典序遍历 [lower, upper] 内的 term, 跳过时从目标 key 重新查找
+    fn cursor<'a>(&'a self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> ArtCursor<'a> {
+        let upper = upper.map(|k| k.to_vec());
+        ArtCursor {
+            iter: Self::range(&self.cache, lower, upper.as_ref().map(|k| k.as_slice())),
+            cache: &self.cache,
+            upper: upper,
+        }
+    }
+
+    // 下界大于上界时 BTreeMap::range 会 panic, 这里返回空区间
+    fn range<'a>(
+        cache: &'a BTreeMap<Vec<u8>, Posting>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> btree_map::Range<'a, Vec<u8>, Posting> {
+        let empty = match (lower, upper) {
+            (Bound::Included(l), Bound::Included(u)) => l > u,
+            (Bound::Included(l) | Bound::Excluded(l), Bound::Included(u) | Bound::Excluded(u)) => {
+                l >= u
+            }
+            _ => false,
+        };
+        if empty {
+            return cache.range::<[u8], _>((Bound::Unbounded, Bound::Excluded(&[][..])));
+        }
+        cache.range::<[u8], _>((lower, upper))
+    }
+}
+
+pub(crate) struct ArtCursor<'a> {
+    cache: &'a BTreeMap<Vec<u8>, Posting>,
+    upper: Bound<Vec<u8>>,
+    iter: btree_map::Range<'a, Vec<u8>, Posting>,
+}
+
+impl<'a> TermCursor for ArtCursor<'a> {
+    fn next_term(&mut self) -> Option<Vec<u8>> {
+        self.iter.next().map(|(k, _)| k.clone())
+    }
+
+    // 只会向后跳, 上界不变
+    fn seek(&mut self, key: &[u8]) {
+        self.iter = ArtCache::range(
+            self.cache,
+            Bound::Included(key),
+            self.upper.as_ref().map(|k| k.as_slice()),
+        );
+    }
 }
 
 pub(crate) struct FieldCache {
@@ -589,16 +1046,73 @@ pub(crate) struct FieldCache {
     share_bytes_block: Weak<RingBuffer>,
     commit_posting: RefCell<Vec<Posting>>,
     term_count: AtomicUsize,
+    field_type: FieldType,
+    // 稀疏向量域, 倒排表中保存 DocWeight
+    sparse: bool,
+    // 每个文档在该域的 token 数, 用于打分时的长度归一化
+    norms: Arc<RwLock<Norms>>,
+    // 文本域的分词器, 没有时整个值作为一个 term
+    analyzer: Option<Arc<Analyzer>>,
+    // 正在加入的文档和它的下一个 token 的位置
+    next_pos: RefCell<(DocID, u32)>,
+}
+
+// 每个文档的 token 数, 以及按文档 id 累加的域统计, 打分时不用重新扫描
+#[derive(Default)]
+struct Norms {
+    lens: Vec<u32>,
+    // stats[i] 为前 i + 1 个文档的统计
+    stats: Vec<FieldStats>,
 }
 
+impl Norms {
+    fn add_token(&mut self, doc_id: DocID) {
+        let i = doc_id as usize;
+        if self.lens.len() <= i {
+            let last = self.stats.last().cloned().unwrap_or_default();
+            self.lens.resize(i + 1, 0);
+            self.stats.resize(i + 1, last);
+        }
+        self.lens[i] += 1;
+        let new_doc = (self.lens[i] == 1) as u64;
+        // 文档按 id 顺序加入, 通常只更新最后一项
+        for s in self.stats[i..].iter_mut() {
+            *s = FieldStats::new(s.doc_count() + new_doc, s.total_len() + 1);
+        }
+    }
+
+    fn doc_len(&self, doc_id: DocID) -> u32 {
+        self.lens.get(doc_id as usize).cloned().unwrap_or(0)
+    }
+
+    fn stats(&self, doc_count: usize) -> FieldStats {
+        match doc_count.min(self.stats.len()) {
+            0 => FieldStats::default(),
+            n => self.stats[n - 1],
+        }
+    }
+}
+
+// 同一文档的多个值之间空出的位置, 短语查询不会跨值命中
+const POSITION_GAP: u32 = 100;
+
 impl FieldCache {
-    fn new(pool: Weak<RingBuffer>) -> FieldCache {
-        Self {
+    fn new(pool: Weak<RingBuffer>, field: &FieldEntry) -> GyResult<FieldCache> {
+        let analyzer = match field.analyzer() {
+            Some(config) => Some(Arc::new(config.build()?)),
+            None => None,
+        };
+        Ok(Self {
             indexs: Arc::new(RwLock::new(ArtCache::new())),
             share_bytes_block: pool,
             commit_posting: RefCell::new(Vec::new()),
             term_count: AtomicUsize::new(0),
-        }
+            field_type: field.get_field_type().clone(),
+            sparse: matches!(field.get_field_type(), FieldType::Sparse),
+            norms: Arc::new(RwLock::new(Norms::default())),
+            analyzer: analyzer,
+            next_pos: RefCell::new((0, 0)),
+        })
     }
 
     fn reader(&self) -> FieldReader {
@@ -606,6 +1120,8 @@ impl FieldCache {
             self.indexs.clone(),
             self.share_bytes_block.clone(),
             self.term_count.load(Ordering::Acquire),
+            self.sparse,
+            self.norms.clone(),
         )
     }
 
@@ -616,7 +1132,11 @@ impl FieldCache {
             .iter()
             .try_for_each(|posting| -> GyResult<()> {
                 let p = &mut posting.write().unwrap();
-                Self::write_doc_freq(p, &mut *pool.get_borrow_mut())?;
+                if self.sparse {
+                    Self::write_doc_weight(p, &mut *pool.get_borrow_mut())?;
+                } else {
+                    Self::write_doc_freq(p, &mut *pool.get_borrow_mut())?;
+                }
                 p.add_commit = false;
                 p.freq = 0;
                 Ok(())
@@ -625,9 +1145,82 @@ impl FieldCache {
         Ok(())
     }
 
-    //添加 token 单词
+    // 配置了分词器的文本值切分后逐个 token 加入
     pub fn add(&self, doc_id: DocID, value: &Value) -> GyResult<()> {
-        let v = value.to_vec()?;
+        let text = match value {
+            Value::Str(s) => Some(*s),
+            Value::String(s) => Some(s.as_str()),
+            _ => None,
+        };
+        match (&self.analyzer, text) {
+            (Some(analyzer), Some(text)) => {
+                let tokens = analyzer.analyze(text);
+                let n = tokens.last().map_or(0, |t| t.position as u32 + 1);
+                let base = self.reserve_pos(doc_id, n);
+                for token in tokens {
+                    let pos = base + token.position as u32;
+                    self.add_token(doc_id, token.text.into_bytes(), pos)?;
+                }
+                Ok(())
+            }
+            _ => {
+                let pos = self.reserve_pos(doc_id, 1);
+                self.add_token(doc_id, value.to_vec()?, pos)
+            }
+        }
+    }
+
+    // 为文档的一个值预留 n 个位置, 返回第一个位置
+    fn reserve_pos(&self, doc_id: DocID, n: u32) -> u32 {
+        let mut next_pos = self.next_pos.borrow_mut();
+        if next_pos.0 != doc_id {
+            *next_pos = (doc_id, 0);
+        }
+        let base = next_pos.1;
+        next_pos.1 = base + n + POSITION_GAP;
+        base
+    }
+
+    //添加 token 单词
+    fn add_token(&self, doc_id: DocID, v: Vec<u8>, pos: u32) -> GyResult<()> {
+        let p = self.posting(v)?;
+        // 获取bytes 池
+        let pool = self.share_bytes_block.upgrade().unwrap();
+        // 倒排表中加入文档id
+        {
+            let mut posting = p.write()?;
+            Self::add_doc(doc_id, &mut *posting, &mut *pool.get_borrow_mut())?;
+            Self::add_pos(pos, &mut *posting);
+        }
+        if !(*p).read()?.add_commit {
+            self.commit_posting.borrow_mut().push(p.clone());
+            (*p).write()?.add_commit = true;
+        }
+        self.norms.write()?.add_token(doc_id);
+        Ok(())
+    }
+
+    // 稀疏向量的每个维度作为一个 term
+    pub fn add_sparse(&self, doc_id: DocID, value: &SparseVector) -> GyResult<()> {
+        let pool = self.share_bytes_block.upgrade().unwrap();
+        for (dim, weight) in value.iter() {
+            let p = self.posting(ann::sparse::dim_term(*dim))?;
+            Self::add_doc_weight(
+                doc_id,
+                *weight,
+                &mut *p.write()?,
+                &mut *pool.get_borrow_mut(),
+            )?;
+            if !(*p).read()?.add_commit {
+                self.commit_posting.borrow_mut().push(p.clone());
+                (*p).write()?.add_commit = true;
+            }
+        }
+        Ok(())
+    }
+
+    // 获取词典的倒排表, 不存在时创建
+    fn posting(&self, v: Vec<u8>) -> GyResult<Posting> {
         if !self.indexs.read()?.contains_key(&v) {
             let pool = self.share_bytes_block.upgrade().unwrap();
             let pos = (*pool).get_borrow_mut().alloc_bytes(0, None);
@@ -639,23 +1232,13 @@ impl FieldCache {
             self.term_count.fetch_add(1, Ordering::Acquire);
             // println!("get term_count:{}", self.term_count.load(Ordering::Acquire))
         }
-        // 获取词典的倒排表
         let p: Posting = self
             .indexs
             .read()?
             .get(&v)
             .expect("get term posting list fail")
             .clone();
-        // 获取bytes 池
-        let pool = self.share_bytes_block.upgrade().unwrap();
-        // 倒排表中加入文档id
-        Self::add_doc(doc_id, &mut *p.write()?, &mut *pool.get_borrow_mut())?;
-        if !(*p).read()?.add_commit {
-            self.commit_posting.borrow_mut().push(p.clone());
-            (*p).write()?.add_commit = true;
-        }
-
-        Ok(())
+        Ok(p)
     }
 
     // 添加vec
@@ -684,25 +1267,57 @@ impl FieldCache {
     fn write_doc_freq(posting: &mut _Posting, block_pool: &mut ByteBlockPool) -> GyResult<()> {
         block_pool.set_pos(posting.doc_freq_addr.load(Ordering::SeqCst));
         DocFreq(posting.doc_delta, posting.freq).binary_serialize(block_pool)?;
+        schema::write_positions(block_pool, &posting.positions)?;
+        posting.positions.clear();
         posting
             .doc_freq_addr
             .store(block_pool.get_pos(), Ordering::SeqCst);
         Ok(())
     }
 
-    fn add_pos(
-        pos: usize,
-        posting: &mut Posting,
-        pool: Arc<RefCell<ByteBlockPool>>,
+    // 同一文档重复出现的维度权重相加
+    fn add_doc_weight(
+        doc_id: DocID,
+        weight: f32,
+        posting: &mut _Posting,
+        block_pool: &mut ByteBlockPool,
     ) -> GyResult<()> {
+        if posting.add_commit && posting.last_doc_id == doc_id {
+            posting.weight += weight;
+        } else {
+            if posting.add_commit {
+                Self::write_doc_weight(posting, block_pool)?;
+            }
+            posting.doc_delta = doc_id - posting.last_doc_id;
+            posting.last_doc_id = doc_id;
+            posting.doc_count += 1;
+            posting.weight = weight;
+        }
+        posting.max_weight = posting.max_weight.max(posting.weight.abs());
+        Ok(())
+    }
+
+    fn write_doc_weight(posting: &mut _Posting, block_pool: &mut ByteBlockPool) -> GyResult<()> {
+        block_pool.set_pos(posting.doc_freq_addr.load(Ordering::SeqCst));
+        DocWeight(posting.doc_delta, posting.weight).binary_serialize(block_pool)?;
+        posting
+            .doc_freq_addr
+            .store(block_pool.get_pos(), Ordering::SeqCst);
         Ok(())
     }
+
+    // 位置在写入 DocFreq 时跟在后面一起写入
+    fn add_pos(pos: u32, posting: &mut _Posting) {
+        posting.positions.push(pos);
+    }
 }
 
 pub struct FieldReader {
     indexs: Arc<RwLock<ArtCache>>,
     share_bytes_block: Weak<RingBuffer>,
     term_count: usize,
+    sparse: bool,
+    norms: Arc<RwLock<Norms>>,
 }
 
 impl FieldReader {
@@ -710,14 +1325,37 @@ impl FieldReader {
         indexs: Arc<RwLock<ArtCache>>,
         share_bytes_block: Weak<RingBuffer>,
         term_count: usize,
+        sparse: bool,
+        norms: Arc<RwLock<Norms>>,
     ) -> FieldReader {
         Self {
             indexs: indexs,
             share_bytes_block: share_bytes_block,
             term_count: term_count,
+            sparse: sparse,
+            norms: norms,
         }
     }
 
+    pub fn is_sparse(&self) -> bool {
+        self.sparse
+    }
+
+    fn doc_len(&self, doc_id: DocID) -> GyResult<u32> {
+        Ok(self.norms.read()?.doc_len(doc_id))
+    }
+
+    // 前 doc_count 个文档的 token 数, 之后加入的文档不可见
+    fn norms(&self, doc_count: usize) -> GyResult<Vec<u32>> {
+        let norms = self.norms.read()?;
+        Ok((0..doc_count).map(|i| norms.doc_len(i as DocID)).collect())
+    }
+
+    // 前 doc_count 个文档的域统计
+    fn stats(&self, doc_count: usize) -> GyResult<FieldStats> {
+        Ok(self.norms.read()?.stats(doc_count))
+    }
+
     fn posting_buffer(&self, start_addr: Addr, end_addr: Addr) -> GyResult<RingBufferReader> {
         Ok(RingBufferReader::new(
             self.share_bytes_block.upgrade().unwrap(),
@@ -749,6 +1387,48 @@ impl FieldReader {
         self.posting(start_addr, end_addr)
     }
 
+    // term 不存在时返回 None
+    fn find(&self, term: &[u8]) -> GyResult<Option<PostingReader>> {
+        let addr = {
+            let index = self.indexs.read()?;
+            match index.get(term) {
+                Some(posting) => Some((
+                    (*posting).read()?.byte_addr.load(Ordering::SeqCst),
+                    (*posting).read()?.doc_freq_addr.load(Ordering::SeqCst),
+                )),
+                None => None,
+            }
+        };
+        match addr {
+            Some((start_addr, end_addr)) => Ok(Some(self.posting(start_addr, end_addr)?)),
+            None => Ok(None),
+        }
+    }
+
+    // 稀疏向量域的维度, 同时返回倒排表中权重绝对值的最大值
+    fn find_sparse(&self, dim: u32) -> GyResult<Option<(PostingReader, f32)>> {
+        let posting = {
+            let index = self.indexs.read()?;
+            match index.get(&ann::sparse::dim_term(dim)) {
+                Some(posting) => {
+                    let p = (*posting).read()?;
+                    Some((
+                        p.byte_addr.load(Ordering::SeqCst),
+                        p.doc_freq_addr.load(Ordering::SeqCst),
+                        p.max_weight,
+                    ))
+                }
+                None => None,
+            }
+        };
+        match posting {
+            Some((start_addr, end_addr, max_weight)) => {
+                Ok(Some((self.posting(start_addr, end_addr)?, max_weight)))
+            }
+            None => Ok(None),
+        }
+    }
+
     fn get_term_count(&self) -> usize {
         self.term_count
     }
@@ -769,20 +1449,57 @@ impl PostingReader {
             snap_iter: self.snap.iter(),
         }
     }
+
+    // 每个文档连同 term 在文档中的位置
+    pub fn positions<'a>(&'a self) -> PostingPositionIter<'a> {
+        PostingPositionIter {
+            last_docid: 0,
+            snap_iter: self.snap.iter(),
+        }
+    }
+
+    // 稀疏向量域的倒排表
+    pub fn weights<'a>(&'a self) -> PostingWeightIter<'a> {
+        PostingWeightIter {
+            last_docid: 0,
+            snap_iter: self.snap.iter(),
+        }
+    }
 }
 
-pub struct PostingReaderIter<'a> {
+pub struct PostingWeightIter<'a> {
     last_docid: DocID,
     snap_iter: SnapshotReaderIter<'a>,
 }
 
-impl<'b, 'a> Iterator for PostingReaderIter<'a> {
-    type Item = DocFreq;
+impl<'a> Iterator for PostingWeightIter<'a> {
+    type Item = DocWeight;
     fn next(&mut self) -> Option<Self::Item> {
-        return match DocFreq::binary_deserialize(&mut self.snap_iter) {
-            Ok(mut doc_freq) => {
+        match DocWeight::binary_deserialize(&mut self.snap_iter) {
+            Ok(mut doc_weight) => {
+                self.last_docid += doc_weight.doc_id();
+                doc_weight.0 = self.last_docid;
+                Some(doc_weight)
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+pub struct PostingReaderIter<'a> {
+    last_docid: DocID,
+    snap_iter: SnapshotReaderIter<'a>,
+}
+
+impl<'b, 'a> Iterator for PostingReaderIter<'a> {
+    type Item = DocFreq;
+    fn next(&mut self) -> Option<Self::Item> {
+        return match DocFreq::binary_deserialize(&mut self.snap_iter) {
+            Ok(mut doc_freq) => {
                 self.last_docid += doc_freq.doc_id() >> 1;
                 doc_freq.0 = self.last_docid;
+                // 跳过位置
+                schema::read_positions(&mut self.snap_iter, doc_freq.freq()).ok()?;
                 Some(doc_freq)
             }
             Err(_) => None,
@@ -790,6 +1507,22 @@ impl<'b, 'a> Iterator for PostingReaderIter<'a> {
     }
 }
 
+pub struct PostingPositionIter<'a> {
+    last_docid: DocID,
+    snap_iter: SnapshotReaderIter<'a>,
+}
+
+impl<'a> Iterator for PostingPositionIter<'a> {
+    type Item = (DocFreq, Vec<u32>);
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut doc_freq = DocFreq::binary_deserialize(&mut self.snap_iter).ok()?;
+        self.last_docid += doc_freq.doc_id() >> 1;
+        doc_freq.0 = self.last_docid;
+        let positions = schema::read_positions(&mut self.snap_iter, doc_freq.freq()).ok()?;
+        Some((doc_freq, positions))
+    }
+}
+
 pub struct IndexReaderIter {
     reader: Arc<IndexBase>,
     i: usize,
@@ -858,6 +1591,57 @@ impl IndexReader {
         field_reader.get(term.bytes_value())
     }
 
+    // 按域的分词配置把查询文本切分成 term
+    pub fn analyze(&self, field: FieldID, text: &str) -> Vec<Term> {
+        let analyzer = self.index_base.fields[field.id() as usize]
+            .analyzer
+            .as_deref();
+        tokenize::text_terms(field, analyzer, text)
+    }
+
+    // 切分成 term 和它在文本中的位置, 用于构造 PhraseQuery
+    pub fn analyze_positions(&self, field: FieldID, text: &str) -> Vec<(Term, u32)> {
+        let analyzer = self.index_base.fields[field.id() as usize]
+            .analyzer
+            .as_deref();
+        tokenize::text_term_positions(field, analyzer, text)
+    }
+
+    pub fn searcher(&self) -> Searcher<IndexReader> {
+        Searcher::new(self)
+    }
+
+    pub fn query_sparse(
+        &self,
+        field: FieldID,
+        q: &SparseVector,
+        k: usize,
+    ) -> GyResult<Vec<Neighbor>> {
+        let field_reader = self.index_base.field_reader(field.id())?;
+        if !field_reader.is_sparse() {
+            return Err(GyError::ErrNotSparseField(field.id()));
+        }
+        let mut postings = Vec::with_capacity(q.len());
+        for (dim, weight) in q.iter() {
+            if let Some((p, max_weight)) = field_reader.find_sparse(*dim)? {
+                postings.push((p, *weight, max_weight));
+            }
+        }
+        // 打开 reader 之后加入的文档不可见
+        let doc_count = self.doc_count;
+        let cursors = postings
+            .iter()
+            .map(|(p, weight, max_weight)| {
+                let iter = p
+                    .weights()
+                    .map(|x| (x.doc_id(), x.weight()))
+                    .take_while(move |x| x.0 < doc_count);
+                SparseCursor::new(iter, *weight, *max_weight)
+            })
+            .collect();
+        Ok(ann::sparse::wand(cursors, k))
+    }
+
     pub fn doc(&self, doc_id: DocID) -> GyResult<Document> {
         assert!(doc_id < self.doc_count);
         let doc_offset = self.index_base.doc_offset(doc_id)?;
@@ -879,6 +1663,77 @@ impl IndexReader {
     }
 }
 
+impl FilterReader for IndexReader {
+    fn doc_size(&self) -> usize {
+        self.doc_count as usize
+    }
+
+    fn term_docs(&self, term: &Term, docs: &mut BitMap) -> GyResult<()> {
+        let field_reader = self.index_base.field_reader(term.field_id().id())?;
+        if let Some(p) = field_reader.find(term.bytes_value())? {
+            p.iter()
+                .for_each(|doc_freq| docs.insert(doc_freq.doc_id() as usize));
+        }
+        Ok(())
+    }
+
+    fn term_postings(&self, term: &Term, f: &mut dyn FnMut(DocFreq)) -> GyResult<()> {
+        let field_reader = self.index_base.field_reader(term.field_id().id())?;
+        if let Some(p) = field_reader.find(term.bytes_value())? {
+            p.iter()
+                .take_while(|doc_freq| doc_freq.doc_id() < self.doc_count)
+                .for_each(f);
+        }
+        Ok(())
+    }
+
+    fn term_positions(&self, term: &Term, f: &mut dyn FnMut(DocFreq, Vec<u32>)) -> GyResult<()> {
+        let field_reader = self.index_base.field_reader(term.field_id().id())?;
+        if let Some(p) = field_reader.find(term.bytes_value())? {
+            p.positions()
+                .take_while(|x| x.0.doc_id() < self.doc_count)
+                .for_each(|(doc_freq, positions)| f(doc_freq, positions));
+        }
+        Ok(())
+    }
+
+    fn expand_terms(
+        &self,
+        field: &FieldID,
+        automaton: &mut dyn Automaton,
+        limit: usize,
+    ) -> GyResult<Vec<Term>> {
+        let field_reader = self.index_base.field_reader(field.id())?;
+        let index = field_reader.indexs.read()?;
+        let (lower, upper) = automaton.bounds();
+        let mut cursor = index.cursor(
+            lower.as_ref().map(|k| k.as_slice()),
+            upper.as_ref().map(|k| k.as_slice()),
+        );
+        let terms = automaton::intersect(&mut cursor, automaton, limit)?;
+        Ok(terms
+            .iter()
+            .map(|t| Term::from_field_bytes(*field, t))
+            .collect())
+    }
+
+    fn field_stats(&self, field: &FieldID) -> GyResult<FieldStats> {
+        self.index_base
+            .field_reader(field.id())?
+            .stats(self.doc_count as usize)
+    }
+
+    fn doc_len(&self, field: &FieldID, doc_id: DocID) -> GyResult<u32> {
+        self.index_base.field_reader(field.id())?.doc_len(doc_id)
+    }
+
+    fn field_type(&self, field: &FieldID) -> GyResult<FieldType> {
+        Ok(self.index_base.fields[field.id() as usize]
+            .field_type
+            .clone())
+    }
+}
+
 pub struct IndexWriter {
     writer: Arc<IndexBase>,
 }
@@ -903,6 +1758,7 @@ mod tests {
     use super::{schema::FieldEntry, *};
     use crate::config::ConfigBuilder;
     use galois::Shape;
+    use rand::Rng;
     use schema::{BinarySerialize, VectorEntry};
     use std::thread;
     use tests::disk::DiskStoreReader;
@@ -995,41 +1851,41 @@ mod tests {
             let mut d1 = Document::new();
             d1.add_text(field_id_title.clone(), "aa");
 
-            let v1 = Vector::from_array([0.0, 0.0, 0.0, 1.0], d1);
+            let v1 = Vector::from_array([0.0f32, 0.0, 0.0, 1.0], d1);
 
             collect.add(v1).unwrap();
 
             let mut d2 = Document::new();
             d2.add_text(field_id_title.clone(), "cc");
-            let v2 = Vector::from_array([0.0, 0.0, 1.0, 0.0], d2);
+            let v2 = Vector::from_array([0.0f32, 0.0, 1.0, 0.0], d2);
 
             collect.add(v2).unwrap();
 
             let mut d3 = Document::new();
             d3.add_text(field_id_title.clone(), "aa");
 
-            let v3 = Vector::from_array([0.0, 1.0, 0.0, 0.0], d3);
+            let v3 = Vector::from_array([0.0f32, 1.0, 0.0, 0.0], d3);
 
             collect.add(v3).unwrap();
 
             let mut d4 = Document::new();
             d4.add_text(field_id_title.clone(), "bb");
-            let v4 = Vector::from_array([1.0, 0.0, 0.0, 0.0], d4);
+            let v4 = Vector::from_array([1.0f32, 0.0, 0.0, 0.0], d4);
             collect.add(v4).unwrap();
 
             // let mut d5 = Document::new();
             // d5.add_text(field_id_title.clone(), "cc");
-            // let v5 = Vector::from_array([0.0, 0.0, 1.0, 1.0], d5);
+            // let v5 = Vector::from_array([0.0f32, 0.0, 1.0, 1.0], d5);
             // collect.add(v5).unwrap();
 
             // let mut d6 = Document::new();
             // d6.add_text(field_id_title.clone(), "aa");
-            // let v6 = Vector::from_array([0.0, 1.0, 1.0, 0.0], d6);
+            // let v6 = Vector::from_array([0.0f32, 1.0, 1.0, 0.0], d6);
             // collect.add(v6).unwrap();
 
             // let mut d7 = Document::new();
             // d7.add_text(field_id_title.clone(), "ff");
-            // let v7 = Vector::from_array([1.0, 0.0, 0.0, 1.0], d7);
+            // let v7 = Vector::from_array([1.0f32, 0.0, 0.0, 1.0], d7);
             // collect.add(v7).unwrap();
 
             //  collect.commit().unwrap();
@@ -1038,6 +1894,7 @@ mod tests {
         let reader = collect.reader();
         let p = reader
             .query(
+                "vector",
                 &Tensor::from_vec(vec![1.0f32, 0.0, 0.0, 1.0], 1, Shape::from_array([4])),
                 4,
             )
@@ -1074,46 +1931,46 @@ mod tests {
             // let mut d1 = Document::new();
             // d1.add_text(field_id_title.clone(), "aa");
 
-            // let v1 = Vector::from_array([0.0, 0.0, 0.0, 1.0], d1);
+            // let v1 = Vector::from_array([0.0f32, 0.0, 0.0, 1.0], d1);
 
             // collect.add(v1).unwrap();
 
             // let mut d2 = Document::new();
             // d2.add_text(field_id_title.clone(), "cc");
-            // let v2 = Vector::from_array([0.0, 0.0, 1.0, 0.0], d2);
+            // let v2 = Vector::from_array([0.0f32, 0.0, 1.0, 0.0], d2);
 
             // collect.add(v2).unwrap();
 
             // let mut d3 = Document::new();
             // d3.add_text(field_id_title.clone(), "aa");
 
-            // let v3 = Vector::from_array([0.0, 1.0, 0.0, 0.0], d3);
+            // let v3 = Vector::from_array([0.0f32, 1.0, 0.0, 0.0], d3);
 
             // collect.add(v3).unwrap();
 
             // let mut d4 = Document::new();
             // d4.add_text(field_id_title.clone(), "aa");
-            // let v4 = Vector::from_array([1.0, 0.0, 0.0, 0.0], d4);
+            // let v4 = Vector::from_array([1.0f32, 0.0, 0.0, 0.0], d4);
             // collect.add(v4).unwrap();
 
             let mut d5 = Document::new();
             d5.add_text(field_id_title.clone(), "cc");
-            let v5 = Vector::from_array([0.0, 0.0, 1.0, 1.0], d5);
+            let v5 = Vector::from_array([0.0f32, 0.0, 1.0, 1.0], d5);
             collect.add(v5).unwrap();
 
             let mut d6 = Document::new();
             d6.add_text(field_id_title.clone(), "aa");
-            let v6 = Vector::from_array([0.0, 1.0, 1.0, 0.0], d6);
+            let v6 = Vector::from_array([0.0f32, 1.0, 1.0, 0.0], d6);
             collect.add(v6).unwrap();
 
             let mut d7 = Document::new();
             d7.add_text(field_id_title.clone(), "ff");
-            let v7 = Vector::from_array([1.0, 0.0, 0.0, 1.0], d7);
+            let v7 = Vector::from_array([1.0f32, 0.0, 0.0, 1.0], d7);
             collect.add(v7).unwrap();
 
             let mut d8 = Document::new();
             d8.add_text(field_id_title.clone(), "gg");
-            let d8 = Vector::from_array([1.0, 1.0, 0.0, 0.0], d8);
+            let d8 = Vector::from_array([1.0f32, 1.0, 0.0, 0.0], d8);
             collect.add(d8).unwrap();
 
             //  collect.commit().unwrap();
@@ -1122,6 +1979,7 @@ mod tests {
         let reader = collect.reader();
         let p = reader
             .query(
+                "vector1",
                 &Tensor::from_vec(vec![0.0f32, 0.0, 1.0, 0.0], 1, Shape::from_array([4])),
                 4,
             )
@@ -1138,176 +1996,2102 @@ mod tests {
     }
 
     #[test]
-    fn test_search_doc() {
+    fn test_query_filtered() {
         let mut schema = Schema::with_vector(VectorEntry::new(
             "vector1",
             AnnType::HNSW,
             TensorEntry::new(1, [4], schema::VectorType::F32),
         ));
-        schema.add_field(FieldEntry::str("body"));
-        schema.add_field(FieldEntry::i32("title"));
-
-        let field_id_title = schema.get_field("title").unwrap();
-        let disk_reader = DiskStoreReader::open(PathBuf::from("./data1/my_index")).unwrap();
-        let p = disk_reader
-            .search(Term::from_field_text(field_id_title, "aa"))
-            .unwrap();
-        // println!("doc_size:{:?}", p.get_doc_count());
-        for n in p.iter() {
-            let doc = disk_reader.vector(n.doc_id()).unwrap();
-            println!("docid:{},doc{:?}", n.doc_id(), unsafe {
-                doc.v.as_slice::<f32>()
-            });
+        schema.add_field(FieldEntry::str("color"));
+        schema.add_field(FieldEntry::i64("price"));
+        let color = schema.get_field("color").unwrap();
+        let price = schema.get_field("price").unwrap();
+        let config = ConfigBuilder::default()
+            .data_path(PathBuf::from("./data_filter"))
+            .build();
+        let collect = Engine::new(&schema, config.get_engine_config(PathBuf::from(""))).unwrap();
+        let items = [
+            ([0.0, 0.0, 1.0, 0.0], "red", 200),
+            ([0.0, 0.0, 1.0, 1.0], "blue", 50),
+            ([0.0, 1.0, 1.0, 0.0], "red", 80),
+            ([1.0, 0.0, 0.0, 0.0], "red", 10),
+        ];
+        for (v, c, p) in items.iter() {
+            let mut d = Document::new();
+            d.add_text(color.clone(), c);
+            d.add_i64(price.clone(), *p);
+            collect.add(Vector::from_array(*v, d)).unwrap();
         }
+        let reader = collect.reader();
+        let filter = Filter::And(vec![
+            Filter::Term(Term::from_field_text(color.clone(), "red")),
+            Filter::range(
+                price.clone(),
+                std::ops::Bound::Unbounded,
+                std::ops::Bound::Excluded(Value::I64(100)),
+            ),
+        ]);
+        let q = Tensor::from_vec(vec![0.0f32, 0.0, 1.0, 0.0], 1, Shape::from_array([4]));
+        let p = reader.query_filtered("vector1", &q, 4, &filter).unwrap();
+        let ids: Vec<DocID> = p.iter().map(|n| n.doc_id()).collect();
+        assert_eq!(ids, vec![2, 3]);
+    }
 
-        let p = disk_reader
-            .query(
-                &Tensor::from_vec(vec![0.0f32, 0.0, 1.0, 0.0], 1, Shape::from_array([4])),
-                4,
+    // 把内存中的数据写成段文件后重新打开, dir 下生成 data.gy 和 meta.json
+    fn persist_segment<V: VectorSerialize + ValueSized + VectorOps + Clone + 'static>(
+        collect: &EngineBase<V>,
+        schema: &Schema,
+        dir: &str,
+    ) -> disk::DiskStoreReaderBase<V>
+    where
+        V: Metric<V> + VectorElems,
+    {
+        let dir = PathBuf::from(dir);
+        FileManager::mkdir(&dir).unwrap();
+        disk::persist_collection(&collect.reader(), schema, dir.join(config::DATA_FILE)).unwrap();
+        disk::DiskStoreReaderBase::<V>::open(&dir).unwrap()
+    }
+
+    // 合并测试用的段: docs 全部写入 all, [..split] 和 [split..] 分别写入 a 和 b
+    // a 和 b 持久化后再合并, WAL 和段文件都放在 dir 下, 测试结束时一起删除
+    struct MergedSegments {
+        all: Engine,
+        a: Engine,
+        seg_a: disk::DiskStoreReader,
+        seg_b: disk::DiskStoreReader,
+        merged: disk::DiskStoreReader,
+    }
+
+    fn merged_segments<T>(
+        schema: &Schema,
+        dir: &str,
+        docs: &[T],
+        split: usize,
+        to_vector: impl Fn(&T) -> Vector,
+    ) -> MergedSegments {
+        let dir = PathBuf::from(dir);
+        FileManager::mkdir(&dir).unwrap();
+        let new_engine = |wal: &str, docs: &[T]| {
+            let collect = Engine::new(
+                schema,
+                ConfigBuilder::default()
+                    .build()
+                    .get_engine_config(dir.join(wal)),
             )
             .unwrap();
-
-        for n in p.iter() {
-            let doc = disk_reader.vector(n.doc_id()).unwrap();
-            println!("docid:{},doc{:?}", n.doc_id(), unsafe {
-                doc.v.as_slice::<f32>()
-            });
+            for d in docs.iter() {
+                collect.add(to_vector(d)).unwrap();
+            }
+            collect
+        };
+        let all = new_engine("all.wal", docs);
+        let a = new_engine("a.wal", &docs[..split]);
+        let b = new_engine("b.wal", &docs[split..]);
+        let seg_a = persist_segment(&a, schema, dir.join("a").to_str().unwrap());
+        let seg_b = persist_segment(&b, schema, dir.join("b").to_str().unwrap());
+        FileManager::mkdir(&dir.join("ab")).unwrap();
+        disk::merge(&seg_a, &seg_b, &dir.join("ab").join(config::DATA_FILE)).unwrap();
+        let merged = disk::DiskStoreReader::open(dir.join("ab")).unwrap();
+        MergedSegments {
+            all: all,
+            a: a,
+            seg_a: seg_a,
+            seg_b: seg_b,
+            merged: merged,
         }
     }
 
     #[test]
-    fn test_search_vector() {
-        let mut schema = Schema::with_vector(VectorEntry::new(
+    fn test_segment_version() {
+        let schema = Schema::with_vector(VectorEntry::new(
             "vector1",
             AnnType::HNSW,
             TensorEntry::new(1, [4], schema::VectorType::F32),
         ));
-        schema.add_field(FieldEntry::str("body"));
-        schema.add_field(FieldEntry::i32("title"));
-
-        let field_id_title = schema.get_field("title").unwrap();
-        let disk_reader = DiskStoreReader::open(PathBuf::from("./data3/my_index")).unwrap();
-        let p = disk_reader
-            .query(
-                &Tensor::from_vec(vec![1.0f32, 0.0, 0.0, 1.0], 1, Shape::from_array([4])),
-                4,
-            )
-            .unwrap();
-        // println!("doc_size:{:?}", p.get_doc_count());
-        for n in p.iter() {
-            let doc = disk_reader.vector(n.doc_id()).unwrap();
-            println!("docid:{},doc{:?}", n.doc_id(), unsafe {
-                doc.v.as_slice::<f32>()
-            });
-        }
+        let meta = config::DiskFileMeta::new(&schema);
+        assert!(meta.check_version().is_ok());
+        // 没有版本号的旧段需要重建
+        let mut value = serde_json::to_value(&meta).unwrap();
+        value.as_object_mut().unwrap().remove("version");
+        let old: config::DiskFileMeta = serde_json::from_value(value).unwrap();
+        assert!(matches!(
+            old.check_version(),
+            Err(GyError::ErrVersionMismatch)
+        ));
     }
 
+    // 命中一半文档的过滤条件不走暴力搜索, 在 HNSW 遍历时过滤
     #[test]
-    fn test_add_doc2() {
-        let mut schema = Schema::new();
-        schema.add_field(FieldEntry::str("body"));
-        schema.add_field(FieldEntry::i32("title"));
-        let field_id_title = schema.get_field("title").unwrap();
+    fn test_query_filtered_traversal() {
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector1",
+            AnnType::HNSW,
+            TensorEntry::new(1, [4], schema::VectorType::F32),
+        ));
+        schema.add_field(FieldEntry::str("parity"));
+        let parity = schema.get_field("parity").unwrap();
         let config = ConfigBuilder::default()
-            .data_path(PathBuf::from("/opt/rsproject/chappie/searchlite/data2"))
+            .data_path(PathBuf::from("./data_filter_traversal"))
             .build();
-        let mut index = Index::new(&schema, config.get_engine_config(PathBuf::from(""))).unwrap();
-        let mut writer1 = index.writer().unwrap();
-        {
+        let collect = Engine::new(
+            &schema,
+            config.get_engine_config(PathBuf::from("./data_filter_traversal.wal")),
+        )
+        .unwrap();
+        let mut rng = rand::thread_rng();
+        let features: Vec<[f32; 4]> = (0..200)
+            .map(|_| [rng.gen(), rng.gen(), rng.gen(), rng.gen()])
+            .collect();
+        for (i, f) in features.iter().enumerate() {
             let mut d = Document::new();
-            d.add_text(field_id_title.clone(), "bb");
-            writer1.add(1, &d).unwrap();
-
-            let mut d1 = Document::new();
-            d1.add_text(field_id_title.clone(), "aa");
-            writer1.add(2, &d1).unwrap();
-
-            let mut d2 = Document::new();
-            d2.add_text(field_id_title.clone(), "cc");
-            writer1.add(3, &d2).unwrap();
-
-            let mut d3 = Document::new();
-            d3.add_text(field_id_title.clone(), "aa");
-            writer1.add(4, &d3).unwrap();
-
-            let mut d1 = Document::new();
-            d1.add_text(field_id_title.clone(), "aa");
-            writer1.add(5, &d1).unwrap();
-
-            let mut d2 = Document::new();
-            d2.add_text(field_id_title.clone(), "cc");
-            writer1.add(6, &d2).unwrap();
+            d.add_text(parity.clone(), if i % 2 == 0 { "even" } else { "odd" });
+            collect.add(Vector::from_array(*f, d)).unwrap();
+        }
+        let filter = Filter::Term(Term::from_field_text(parity.clone(), "even"));
+        let q = features[17];
+        // 满足条件的文档中与 q 最近的一个
+        let nearest = (0..features.len())
+            .filter(|i| i % 2 == 0)
+            .min_by(|a, b| {
+                let d = |i: &usize| -> f32 {
+                    features[*i]
+                        .iter()
+                        .zip(q.iter())
+                        .map(|(x, y)| (x - y) * (x - y))
+                        .sum()
+                };
+                d(a).partial_cmp(&d(b)).unwrap()
+            })
+            .unwrap() as DocID;
+        let q = Tensor::from_vec(q.to_vec(), 1, Shape::from_array([4]));
+        let check = |p: Vec<Neighbor>| {
+            assert_eq!(p.len(), 10);
+            assert!(p.iter().all(|n| n.doc_id() % 2 == 0));
+            assert_eq!(p[0].doc_id(), nearest);
+        };
 
-            let mut d3 = Document::new();
-            d3.add_text(field_id_title.clone(), "aa");
-            writer1.add(7, &d3).unwrap();
+        let reader = collect.reader();
+        let allow = filter.allow_list(reader.index_reader()).unwrap();
+        assert!(!ann::filter_brute_force(&allow, 10));
+        check(reader.query_filtered("vector1", &q, 10, &filter).unwrap());
+
+        let disk_reader = persist_segment(&collect, &schema, "./data_filter_traversal/seg");
+        let allow = filter.allow_list(&disk_reader).unwrap();
+        assert!(!ann::filter_brute_force(&allow, 10));
+        check(
+            disk_reader
+                .query_filtered("vector1", &q, 10, &filter)
+                .unwrap(),
+        );
+        drop(disk_reader);
+        std::fs::remove_dir_all("./data_filter_traversal").unwrap();
+    }
 
-            // writer1.commit().unwrap();
+    // PQ 索引的半径查询用原始向量重新计算距离, 内存和段文件的结果一致
+    #[test]
+    fn test_query_radius_rerank() {
+        let schema = Schema::with_vector(VectorEntry::new(
+            "vector1",
+            AnnType::IvfPQ,
+            TensorEntry::new(1, [8], schema::VectorType::F32),
+        ));
+        let config = ConfigBuilder::default()
+            .data_path(PathBuf::from("./data_radius"))
+            .fsize(16 << 20)
+            .build();
+        let collect = Engine::new(
+            &schema,
+            config.get_engine_config(PathBuf::from("./data_radius.wal")),
+        )
+        .unwrap();
+        let mut rng = rand::thread_rng();
+        let features: Vec<[f32; 8]> = (0..ann::ivf::DEFAULT_NLIST
+            * ann::ivf::MIN_POINTS_PER_CENTROID)
+            .map(|_| [0; 8].map(|_| rng.gen::<f32>()))
+            .collect();
+        for f in features.iter() {
+            collect
+                .add(Vector::from_array(*f, Document::new()))
+                .unwrap();
         }
+        let reader = collect.reader();
+        assert!(
+            reader
+                .vector_fields
+                .field("vector1")
+                .unwrap()
+                .1
+                .rerank_factor()
+                .unwrap()
+                > 0
+        );
 
-        let reader = index.reader().unwrap();
-        let p = reader
-            .search(Term::from_field_text(field_id_title, "aa"))
-            .unwrap();
+        let q = features[42];
+        let exact = |i: usize| -> f32 { MetricType::L2.distance(&features[i], &q) };
+        let mut dists: Vec<f32> = (0..features.len()).map(exact).collect();
+        dists.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let radius = dists[10];
+        let check = |p: &Vec<Neighbor>| {
+            assert_eq!(p[0].doc_id(), 42);
+            assert!(p.len() > 1);
+            for n in p.iter() {
+                // 返回的是精确距离
+                assert!((n.distance() - exact(n.doc_id() as usize)).abs() < 1e-4);
+                assert!(n.distance() <= radius);
+            }
+        };
+        let q = Tensor::from_vec(q.to_vec(), 1, Shape::from_array([8]));
+        let p = reader.query_radius("vector1", &q, radius, None).unwrap();
+        check(&p);
 
-        for doc_freq in p.iter() {
-            let doc = reader.doc(doc_freq.doc_id()).unwrap();
-            println!("docid:{},doc{:?}", doc_freq.doc_id(), doc);
-        }
-        println!("doc vec:{:?}", reader.get_doc_offset());
-        //  disk::persist_index(&reader).unwrap();
+        let disk_reader = persist_segment(&collect, &schema, "./data_radius/seg");
+        let p2 = disk_reader
+            .query_radius("vector1", &q, radius, None)
+            .unwrap();
+        check(&p2);
+        assert_eq!(
+            p.iter().map(|n| n.doc_id()).collect::<Vec<_>>(),
+            p2.iter().map(|n| n.doc_id()).collect::<Vec<_>>()
+        );
+        let p = disk_reader
+            .query_radius("vector1", &q, radius, Some(2))
+            .unwrap();
+        assert_eq!(p.len(), 2);
+        drop(disk_reader);
+        std::fs::remove_dir_all("./data_radius").unwrap();
     }
-    use crate::fs::FileManager;
-    #[test]
-    fn test_merge_store() {
-        let disk_reader1 = DiskStoreReader::open(PathBuf::from(
-            "/opt/rsproject/chappie/searchlite/data1/my_index",
-        ))
-        .unwrap();
 
-        let disk_reader2 = DiskStoreReader::open(PathBuf::from(
-            "/opt/rsproject/chappie/searchlite/data2/my_index",
-        ))
-        .unwrap();
-        FileManager::mkdir(&PathBuf::from(
-            "/opt/rsproject/chappie/searchlite/data3/my_index",
-        ))
-        .unwrap();
-        disk::merge(
-            &disk_reader1,
-            &disk_reader2,
-            &PathBuf::from("/opt/rsproject/chappie/searchlite/data3/my_index/data.gy"),
+    // nlist 和 nprobe 按域配置, 查询时可以临时覆盖 nprobe
+    #[test]
+    fn test_ivf_config() {
+        let schema = Schema::with_vector(
+            VectorEntry::new(
+                "vector1",
+                AnnType::IvfFlat,
+                TensorEntry::new(1, [8], schema::VectorType::F32),
+            )
+            .with_ivf(ann::IvfConfig {
+                nlist: 4,
+                nprobe: 1,
+            }),
+        );
+        let config = ConfigBuilder::default()
+            .data_path(PathBuf::from("./data_ivf_config"))
+            .fsize(16 << 20)
+            .build();
+        FileManager::mkdir(&PathBuf::from("./data_ivf_config")).unwrap();
+        let collect = Engine::new(
+            &schema,
+            config.get_engine_config(PathBuf::from("./data_ivf_config/data.wal")),
         )
         .unwrap();
+        let features = ann::random_vectors(4 * ann::ivf::MIN_POINTS_PER_CENTROID + 10, 8);
+        for f in features.iter() {
+            collect.add(Vector::with(Tensor::arr(f.clone()))).unwrap();
+        }
+        let q = features[7].clone();
+        let mut exact: Vec<(f32, u64)> = features
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (MetricType::L2.distance(f, &q), i as u64))
+            .collect();
+        exact.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let exact: Vec<u64> = exact.iter().take(10).map(|(_, id)| *id).collect();
+        let ids = |p: Vec<Neighbor>| p.iter().map(|n| n.doc_id()).collect::<Vec<_>>();
+        let q = Tensor::from_vec(q, 1, Shape::from_array([8]));
+
+        let reader = collect.reader();
+        assert_eq!(
+            ids(reader.query_with_nprobe("vector1", &q, 10, 4).unwrap()),
+            exact
+        );
+        // 只扫描一个倒排表
+        assert_eq!(reader.query("vector1", &q, 1).unwrap()[0].doc_id(), 7);
+
+        let disk_reader = persist_segment(&collect, &schema, "./data_ivf_config/seg");
+        assert_eq!(
+            ids(disk_reader.query_with_nprobe("vector1", &q, 10, 4).unwrap()),
+            exact
+        );
+        assert_eq!(disk_reader.query("vector1", &q, 1).unwrap()[0].doc_id(), 7);
+        drop(disk_reader);
+        std::fs::remove_dir_all("./data_ivf_config").unwrap();
     }
 
     #[test]
-    fn test_read() {
-        let mut schema = Schema::new();
+    fn test_binary_engine() {
+        let code = |a: u64, b: u64| {
+            let mut bytes = a.to_le_bytes().to_vec();
+            bytes.extend_from_slice(&b.to_le_bytes());
+            BinaryVector::from_bytes(&bytes, 128).unwrap()
+        };
+        let mut schema = Schema::with_vector(
+            VectorEntry::new(
+                "hash",
+                AnnType::HNSW,
+                TensorEntry::new(1, [128], schema::VectorType::Binary),
+            )
+            .with_metric(MetricType::Hamming),
+        );
         schema.add_field(FieldEntry::str("body"));
-        schema.add_field(FieldEntry::i32("title"));
-        let field_id_title = schema.get_field("title").unwrap();
-        let disk_reader = DiskStoreReader::open(PathBuf::from(
-            "/opt/rsproject/chappie/searchlite/data3/my_index",
-        ))
-        .unwrap();
-        let p = disk_reader
-            .search(Term::from_field_text(field_id_title, "aa"))
-            .unwrap();
-        println!("doc_size:{:?}", p.get_doc_count());
-        for doc_freq in p.iter() {
-            println!("{:?}", doc_freq);
+        let body = schema.get_field("body").unwrap();
+        let config = ConfigBuilder::default()
+            .data_path(PathBuf::from("./data_binary"))
+            .build();
+        let collect =
+            BinaryEngine::new(&schema, config.get_engine_config(PathBuf::from(""))).unwrap();
+        let codes = [
+            code(0, 0),
+            code(u64::MAX, 0),
+            code(0xff, 0xff00),
+            code(u64::MAX, u64::MAX),
+        ];
+        for (i, c) in codes.iter().enumerate() {
+            let mut d = Document::new();
+            d.add_text(body.clone(), &format!("doc{}", i));
+            collect.add(VectorBase::new(c.clone(), d)).unwrap();
         }
-        //    let doc_reader = disk_reader.doc_reader().unwrap();
+        let reader = collect.reader();
+        let q = code(0xf, 0xff00);
+        let p = reader.query("hash", &q, 2).unwrap();
+        assert_eq!(p[0].doc_id(), 2);
+        assert_eq!(p[0].distance(), 4.0);
+        assert_eq!(p[1].doc_id(), 0);
+        // 从 WAL 中读回的向量与写入时一致
+        let v = reader.vector(1).unwrap();
+        assert_eq!(v.vector(), &codes[1]);
+        assert_eq!(v.vector().bits(), 128);
+
+        // 二值向量域只能用 BinaryEngine, 其它类型只能用 Engine
+        assert!(matches!(
+            Engine::new(&schema, config.get_engine_config(PathBuf::from(""))),
+            Err(GyError::ErrUnsupportedVectorType(_))
+        ));
+        let schema = Schema::with_vector(VectorEntry::new(
+            "vector1",
+            AnnType::HNSW,
+            TensorEntry::new(1, [4], schema::VectorType::F32),
+        ));
+        assert!(matches!(
+            BinaryEngine::new(&schema, config.get_engine_config(PathBuf::from(""))),
+            Err(GyError::ErrUnsupportedVectorType(_))
+        ));
     }
 
     #[test]
-    fn test_read_iter() {
-        let mut schema = Schema::new();
-        schema.add_field(FieldEntry::str("body"));
-        schema.add_field(FieldEntry::i32("title"));
-        let field_id_title = schema.get_field("title").unwrap();
-        let disk_reader = DiskStoreReader::open(PathBuf::from(
+    fn test_multi_vector_field() {
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "title",
+            AnnType::HNSW,
+            TensorEntry::new(1, [4], schema::VectorType::F32),
+        ));
+        schema
+            .add_vector_field(
+                VectorEntry::new(
+                    "image",
+                    AnnType::FLAT,
+                    TensorEntry::new(1, [2], schema::VectorType::F32),
+                )
+                .with_metric(MetricType::Cosine),
+            )
+            .unwrap();
+        schema.add_field(FieldEntry::str("body"));
+        assert_eq!(schema.get_vector_field("image"), Some(1));
+        let config = ConfigBuilder::default()
+            .data_path(PathBuf::from("./data_multi_vector"))
+            .build();
+        let collect = Engine::new(&schema, config.get_engine_config(PathBuf::from(""))).unwrap();
+        let items = [
+            ([0.0f32, 0.0, 1.0, 0.0], [1.0f32, 0.0]),
+            ([1.0, 0.0, 0.0, 0.0], [0.0, 1.0]),
+            ([0.0, 1.0, 0.0, 0.0], [1.0, 1.0]),
+        ];
+        for (title, image) in items.iter() {
+            let mut v = Vector::from_array(*title, Document::new());
+            v.add_vector(Tensor::arr_array(*image));
+            collect.add(v).unwrap();
+        }
+        // 缺少 image 向量的文档不能写入
+        assert!(matches!(
+            collect.add(Vector::from_array([0.0f32; 4], Document::new())),
+            Err(GyError::ErrVectorFieldMismatch(2, 1))
+        ));
+        // 向量的大小和元素类型必须与域一致
+        let mut v = Vector::from_array([0.0f32; 4], Document::new());
+        v.add_vector(Tensor::arr_array([1.0f32, 0.0, 0.0]));
+        assert!(matches!(
+            collect.add(v),
+            Err(GyError::ErrVectorSizeMismatch(8, 12))
+        ));
+        let mut v = Vector::from_array([half::f16::ZERO; 4], Document::new());
+        v.add_vector(Tensor::arr_array([1.0f32, 0.0]));
+        assert!(matches!(
+            collect.add(v),
+            Err(GyError::ErrVectorTypeMismatch(..))
+        ));
+        let reader = collect.reader();
+        let q = Tensor::arr_array([0.9f32, 0.0, 0.1, 0.0]);
+        assert_eq!(reader.query("title", &q, 1).unwrap()[0].doc_id(), 1);
+        let q = Tensor::arr_array([0.1f32, 1.0]);
+        let p = reader.query("image", &q, 3).unwrap();
+        assert_eq!(p[0].doc_id(), 1);
+        assert_eq!(p[2].doc_id(), 0);
+        assert_eq!(reader.metric("image").unwrap(), MetricType::Cosine);
+        assert!(matches!(
+            reader.query("body", &q, 1),
+            Err(GyError::ErrVectorFieldNotFound(_))
+        ));
+        // WAL 中每个向量域按声明顺序保存
+        let v = reader.vector(2).unwrap();
+        assert_eq!(v.vector_count(), 2);
+        assert_eq!(v.vector_at(1).unwrap().to_elems(), vec![1.0, 1.0]);
+    }
+
+    fn multi_field_schema() -> Schema {
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "title",
+            AnnType::HNSW,
+            TensorEntry::new(1, [4], schema::VectorType::F32),
+        ));
+        schema
+            .add_vector_field(
+                VectorEntry::new(
+                    "image",
+                    AnnType::FLAT,
+                    TensorEntry::new(1, [2], schema::VectorType::F32),
+                )
+                .with_metric(MetricType::Cosine),
+            )
+            .unwrap();
+        schema.add_field(FieldEntry::str("body"));
+        schema
+    }
+
+    fn multi_field_vector(item: &([f32; 4], [f32; 2])) -> Vector {
+        let mut v = Vector::from_array(item.0, Document::new());
+        v.add_vector(Tensor::arr_array(item.1));
+        v
+    }
+
+    #[test]
+    fn test_multi_vector_field_disk() {
+        let mut schema = multi_field_schema();
+        // 向量域不能重名
+        assert!(matches!(
+            schema.add_vector_field(VectorEntry::new(
+                "image",
+                AnnType::FLAT,
+                TensorEntry::new(1, [2], schema::VectorType::F32),
+            )),
+            Err(GyError::ErrDuplicateVectorField(_))
+        ));
+        let items = [
+            ([0.0, 0.0, 1.0, 0.0], [1.0, 0.0]),
+            ([1.0, 0.0, 0.0, 0.0], [0.0, 1.0]),
+            ([0.0, 1.0, 0.0, 0.0], [1.0, 1.0]),
+            ([0.0, 0.0, 0.0, 1.0], [-1.0, 0.0]),
+        ];
+        let MergedSegments {
+            seg_a,
+            seg_b,
+            merged,
+            ..
+        } = merged_segments(&schema, "./data_multi_field", &items, 2, multi_field_vector);
+        // 重新打开的段中每个向量域各自查询
+        let title = Tensor::arr_array([0.9f32, 0.0, 0.1, 0.0]);
+        let image = Tensor::arr_array([0.1f32, 1.0]);
+        assert_eq!(seg_a.query("title", &title, 1).unwrap()[0].doc_id(), 1);
+        assert_eq!(seg_a.query("image", &image, 1).unwrap()[0].doc_id(), 1);
+        assert_eq!(seg_b.query("image", &image, 1).unwrap()[0].doc_id(), 0);
+        assert_eq!(
+            seg_b.vector(1).unwrap().vector_at(1).unwrap().to_elems(),
+            vec![-1.0, 0.0]
+        );
+
+        // 合并后 b 的文档排在 a 之后
+        let q = Tensor::arr_array([0.0f32, 0.0, 0.1, 0.9]);
+        assert_eq!(merged.query("title", &q, 1).unwrap()[0].doc_id(), 3);
+        let p = merged.query("image", &image, 4).unwrap();
+        assert_eq!(p[0].doc_id(), 1);
+        assert_eq!(p[3].doc_id(), 3);
+        assert_eq!(
+            merged.vector(2).unwrap().vector_at(1).unwrap().to_elems(),
+            vec![1.0, 1.0]
+        );
+
+        // 向量域不一致的段不能合并
+        let other = Schema::with_vector(VectorEntry::new(
+            "title",
+            AnnType::HNSW,
+            TensorEntry::new(1, [4], schema::VectorType::F32),
+        ));
+        let c = Engine::new(
+            &other,
+            ConfigBuilder::default()
+                .build()
+                .get_engine_config(PathBuf::from("./data_multi_field/c.wal")),
+        )
+        .unwrap();
+        c.add(Vector::from_array([1.0f32, 0.0, 0.0, 0.0], Document::new()))
+            .unwrap();
+        let seg_c = persist_segment(&c, &other, "./data_multi_field/c");
+        assert!(matches!(
+            disk::merge(
+                &seg_a,
+                &seg_c,
+                &PathBuf::from("./data_multi_field/ac").join(config::DATA_FILE),
+            ),
+            Err(GyError::ErrVectorLayoutMismatch)
+        ));
+        drop((seg_a, seg_b, seg_c, merged));
+        std::fs::remove_dir_all("./data_multi_field").unwrap();
+    }
+
+    #[test]
+    fn test_multi_vector_maxsim() {
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector",
+            AnnType::FLAT,
+            TensorEntry::new(1, [2], schema::VectorType::F32),
+        ));
+        schema
+            .add_vector_field(
+                VectorEntry::new(
+                    "tokens",
+                    AnnType::HNSW,
+                    TensorEntry::multi(2, schema::VectorType::F32),
+                )
+                .with_metric(MetricType::InnerProduct),
+            )
+            .unwrap();
+        let config = ConfigBuilder::default()
+            .data_path(PathBuf::from("./data_multi_maxsim"))
+            .build();
+        let collect = Engine::new(&schema, config.get_engine_config(PathBuf::from(""))).unwrap();
+        let items = [
+            vec![1.0f32, 0.0, 0.0, 1.0],
+            vec![1.0, 0.0],
+            vec![0.5, 0.5, 0.0, 2.0, 2.0, 0.0],
+        ];
+        for tokens in items.iter() {
+            let rows = tokens.len() / 2;
+            let mut v = Vector::from_array([0.0f32, 1.0], Document::new());
+            v.add_vector(Tensor::from_vec(
+                tokens.clone(),
+                2,
+                Shape::from_array([2, rows]),
+            ));
+            collect.add(v).unwrap();
+        }
+        // 多向量的长度必须是行大小的整数倍
+        let mut v = Vector::from_array([0.0f32, 1.0], Document::new());
+        v.add_vector(Tensor::arr(vec![1.0f32, 0.0, 1.0]));
+        assert!(matches!(
+            collect.add(v),
+            Err(GyError::ErrInvalidMultiVector(12, 8))
+        ));
+        let reader = collect.reader();
+        let q = Tensor::from_vec(vec![1.0f32, 0.0, 0.0, 1.0], 2, Shape::from_array([2, 2]));
+        let p = reader.query_multi("tokens", &q, 3).unwrap();
+        let ids: Vec<DocID> = p.iter().map(|n| n.doc_id()).collect();
+        assert_eq!(ids, vec![2, 0, 1]);
+        assert_eq!(p[0].score(MetricType::InnerProduct), 4.0);
+        assert!(matches!(
+            reader.query("tokens", &q, 1),
+            Err(GyError::ErrVectorFieldKind(_))
+        ));
+        // WAL 中保存行数, 读出的多向量保持原来的形状
+        assert_eq!(
+            reader.vector(2).unwrap().vector_at(1).unwrap().to_elems(),
+            items[2]
+        );
+    }
+
+    #[test]
+    fn test_multi_vector_maxsim_disk() {
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector",
+            AnnType::FLAT,
+            TensorEntry::new(1, [2], schema::VectorType::F32),
+        ));
+        schema
+            .add_vector_field(
+                VectorEntry::new(
+                    "tokens",
+                    AnnType::HNSW,
+                    TensorEntry::multi(2, schema::VectorType::F32),
+                )
+                .with_metric(MetricType::InnerProduct),
+            )
+            .unwrap();
+        let items = [
+            vec![1.0f32, 0.0, 0.0, 1.0],
+            vec![1.0, 0.0],
+            vec![0.5, 0.5, 0.0, 2.0, 2.0, 0.0],
+        ];
+        let MergedSegments {
+            seg_a,
+            seg_b,
+            merged,
+            ..
+        } = merged_segments(&schema, "./data_multi_maxsim_disk", &items, 2, |tokens| {
+            let rows = tokens.len() / 2;
+            let mut v = Vector::from_array([0.0f32, 1.0], Document::new());
+            v.add_vector(Tensor::from_vec(
+                tokens.clone(),
+                2,
+                Shape::from_array([2, rows]),
+            ));
+            v
+        });
+        let q = Tensor::from_vec(vec![1.0f32, 0.0, 0.0, 1.0], 2, Shape::from_array([2, 2]));
+        let p = seg_a.query_multi("tokens", &q, 2).unwrap();
+        let ids: Vec<DocID> = p.iter().map(|n| n.doc_id()).collect();
+        assert_eq!(ids, vec![0, 1]);
+        assert_eq!(p[0].score(MetricType::InnerProduct), 2.0);
+        assert_eq!(
+            seg_a.vector(1).unwrap().vector_at(1).unwrap().to_elems(),
+            items[1]
+        );
+        assert!(matches!(
+            seg_b.query("tokens", &q, 1),
+            Err(GyError::ErrVectorFieldKind(_))
+        ));
+
+        // 合并后每个文档的行数仍然正确, b 的文档排在 a 之后
+        let p = merged.query_multi("tokens", &q, 3).unwrap();
+        let ids: Vec<DocID> = p.iter().map(|n| n.doc_id()).collect();
+        assert_eq!(ids, vec![2, 0, 1]);
+        assert_eq!(p[0].score(MetricType::InnerProduct), 4.0);
+        assert_eq!(
+            merged.vector(2).unwrap().vector_at(1).unwrap().to_elems(),
+            items[2]
+        );
+        drop((seg_a, seg_b, merged));
+        std::fs::remove_dir_all("./data_multi_maxsim_disk").unwrap();
+    }
+
+    #[test]
+    fn test_hybrid_query() {
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector",
+            AnnType::FLAT,
+            TensorEntry::new(1, [2], schema::VectorType::F32),
+        ));
+        schema.add_field(FieldEntry::str("body"));
+        let field_body = schema.get_field("body").unwrap();
+        let config = ConfigBuilder::default()
+            .data_path(PathBuf::from("./data_hybrid"))
+            .build();
+        let collect = Engine::new(&schema, config.get_engine_config(PathBuf::from(""))).unwrap();
+        let items = [
+            ("red", [1.0f32, 0.0]),
+            ("blue", [0.0, 1.0]),
+            ("red", [0.9, 0.1]),
+            ("green", [0.0, -1.0]),
+        ];
+        for (body, v) in items.iter() {
+            let mut d = Document::new();
+            d.add_text(field_body, body);
+            collect.add(Vector::from_array(*v, d)).unwrap();
+        }
+        let reader = collect.reader();
+        let terms = [Term::from_field_text(field_body, "red")];
+        let q = Tensor::arr_array([0.0f32, 1.0]);
+        let hits = reader
+            .query_hybrid(&terms, "vector", &q, 4, &Fusion::default())
+            .unwrap();
+        let ids: Vec<DocID> = hits.iter().map(|h| h.doc_id()).collect();
+        assert_eq!(ids, vec![0, 2, 1, 3]);
+        assert!(hits[0].text_score().is_some());
+        assert!(hits[2].text_score().is_none());
+        assert_eq!(hits[2].vector_distance(), Some(0.0));
+        // 向量权重为 0 时只按文本排序
+        let fusion = Fusion::Weighted {
+            text: 1.0,
+            vector: 0.0,
+        };
+        let hits = reader
+            .query_hybrid(&terms, "vector", &q, 2, &fusion)
+            .unwrap();
+        let ids: Vec<DocID> = hits.iter().map(|h| h.doc_id()).collect();
+        assert_eq!(ids, vec![0, 2]);
+    }
+
+    fn text_vector(schema: &Schema, item: &(Vec<&str>, [f32; 2])) -> Vector {
+        let field_body = schema.get_field("body").unwrap();
+        let mut d = Document::new();
+        for t in item.0.iter() {
+            d.add_text(field_body, t);
+        }
+        Vector::from_array(item.1, d)
+    }
+
+    #[test]
+    fn test_hybrid_query_disk() {
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector",
+            AnnType::FLAT,
+            TensorEntry::new(1, [2], schema::VectorType::F32),
+        ));
+        schema.add_field(FieldEntry::str("body"));
+        let field_body = schema.get_field("body").unwrap();
+        let items = [
+            (vec!["red"], [1.0f32, 0.0]),
+            (vec!["red", "red", "blue"], [0.0, 1.0]),
+            (vec![], [0.5, 0.5]),
+            (vec!["blue"], [0.9, 0.1]),
+            (vec!["red", "green", "green", "green"], [0.0, -1.0]),
+        ];
+        let MergedSegments {
+            all,
+            a,
+            seg_a,
+            seg_b,
+            merged,
+        } = merged_segments(&schema, "./data_hybrid_disk", &items, 3, |item| {
+            text_vector(&schema, item)
+        });
+        let reader = all.reader();
+
+        // 段上和合并后的混合查询与内存中的结果一致
+        let terms = [Term::from_field_text(field_body, "red")];
+        let q = Tensor::arr_array([0.0f32, 1.0]);
+        let hybrid_ids =
+            |hits: Vec<HybridHit>| -> Vec<DocID> { hits.iter().map(|h| h.doc_id()).collect() };
+        let expect = hybrid_ids(
+            reader
+                .query_hybrid(&terms, "vector", &q, 5, &Fusion::default())
+                .unwrap(),
+        );
+        let hits = merged
+            .query_hybrid(&terms, "vector", &q, 5, &Fusion::default())
+            .unwrap();
+        assert_eq!(hits[0].doc_id(), 1);
+        assert!(hits[0].text_score().is_some());
+        assert_eq!(hybrid_ids(hits), expect);
+        let expect = hybrid_ids(
+            a.reader()
+                .query_hybrid(&terms, "vector", &q, 3, &Fusion::default())
+                .unwrap(),
+        );
+        let hits = seg_a
+            .query_hybrid(&terms, "vector", &q, 3, &Fusion::default())
+            .unwrap();
+        assert_eq!(hybrid_ids(hits), expect);
+        drop((seg_a, seg_b, merged));
+        std::fs::remove_dir_all("./data_hybrid_disk").unwrap();
+    }
+
+    #[test]
+    fn test_search_top_k() {
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector",
+            AnnType::FLAT,
+            TensorEntry::new(1, [2], schema::VectorType::F32),
+        ));
+        schema.add_field(FieldEntry::str("body"));
+        let field_body = schema.get_field("body").unwrap();
+        let config = ConfigBuilder::default()
+            .data_path(PathBuf::from("./data_bm25"))
+            .build();
+        let collect = Engine::new(&schema, config.get_engine_config(PathBuf::from(""))).unwrap();
+        let items = [
+            vec!["red"],
+            vec!["red", "red", "blue"],
+            vec!["blue"],
+            vec!["red", "green", "green", "green"],
+        ];
+        for tokens in items.iter() {
+            let mut d = Document::new();
+            for t in tokens.iter() {
+                d.add_text(field_body, t);
+            }
+            collect.add(Vector::from_array([0.0f32, 1.0], d)).unwrap();
+        }
+        let reader = collect.reader();
+        let stats = reader.index_reader().field_stats(&field_body).unwrap();
+        assert_eq!(stats.doc_count(), 4);
+        assert_eq!(stats.total_len(), 9);
+        let query = query::TermQuery::new(Term::from_field_text(field_body, "red"));
+        // 短文档中的一次命中高于长文档中的两次
+        let hits = reader.search_top_k(&query, 10).unwrap();
+        let ids: Vec<DocID> = hits.iter().map(|h| h.doc_id()).collect();
+        assert_eq!(ids, vec![0, 1, 3]);
+        let hits = reader
+            .search_top_k_with(&query, 2, &BM25::new(1.2, 0.0))
+            .unwrap();
+        let ids: Vec<DocID> = hits.iter().map(|h| h.doc_id()).collect();
+        assert_eq!(ids, vec![1, 0]);
+    }
+
+    #[test]
+    fn test_search_top_k_disk() {
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector",
+            AnnType::FLAT,
+            TensorEntry::new(1, [2], schema::VectorType::F32),
+        ));
+        schema.add_field(FieldEntry::str("body"));
+        let field_body = schema.get_field("body").unwrap();
+        // 第三个文档不含 body, 不计入域统计
+        let items = [
+            (vec!["red"], [1.0f32, 0.0]),
+            (vec!["red", "red", "blue"], [0.0, 1.0]),
+            (vec![], [0.5, 0.5]),
+            (vec!["blue"], [0.9, 0.1]),
+            (vec!["red", "green", "green", "green"], [0.0, -1.0]),
+        ];
+        let MergedSegments {
+            all,
+            a,
+            seg_a,
+            seg_b,
+            merged,
+        } = merged_segments(&schema, "./data_bm25_disk", &items, 3, |item| {
+            text_vector(&schema, item)
+        });
+
+        // 内存中的统计在建索引时累加, 与写入段中的一致
+        let stats = a.reader().index_reader().field_stats(&field_body).unwrap();
+        assert_eq!((stats.doc_count(), stats.total_len()), (2, 4));
+        let stats = seg_a.field_stats(&field_body).unwrap();
+        assert_eq!((stats.doc_count(), stats.total_len()), (2, 4));
+        assert_eq!(seg_a.doc_len(&field_body, 1).unwrap(), 3);
+        assert_eq!(seg_a.doc_len(&field_body, 2).unwrap(), 0);
+        let stats = seg_b.field_stats(&field_body).unwrap();
+        assert_eq!((stats.doc_count(), stats.total_len()), (2, 5));
+
+        let stats = merged.field_stats(&field_body).unwrap();
+        assert_eq!((stats.doc_count(), stats.total_len()), (4, 9));
+        assert_eq!(merged.doc_len(&field_body, 4).unwrap(), 4);
+
+        // 合并后的段与一次写入全部文档的打分相同
+        let reader = all.reader();
+        let query = query::TermQuery::new(Term::from_field_text(field_body, "red"));
+        let expect = reader.search_top_k(&query, 10).unwrap();
+        let hits = merged.search_top_k(&query, 10).unwrap();
+        let ids: Vec<DocID> = hits.iter().map(|h| h.doc_id()).collect();
+        assert_eq!(ids, vec![0, 1, 4]);
+        for (h, e) in hits.iter().zip(expect.iter()) {
+            assert_eq!(h.doc_id(), e.doc_id());
+            assert!((h.score() - e.score()).abs() < 1e-6);
+        }
+        assert_eq!(hits.len(), expect.len());
+        drop((seg_a, seg_b, merged));
+        std::fs::remove_dir_all("./data_bm25_disk").unwrap();
+    }
+
+    #[test]
+    fn test_boolean_query() {
+        use query::{BooleanQuery, TermQuery};
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector",
+            AnnType::FLAT,
+            TensorEntry::new(1, [2], schema::VectorType::F32),
+        ));
+        schema.add_field(FieldEntry::str("body"));
+        let field_body = schema.get_field("body").unwrap();
+        let config = ConfigBuilder::default()
+            .data_path(PathBuf::from("./data_boolean"))
+            .build();
+        let collect = Engine::new(&schema, config.get_engine_config(PathBuf::from(""))).unwrap();
+        let items = [
+            vec!["red", "car"],
+            vec!["blue", "car"],
+            vec!["red", "bike"],
+            vec!["green", "bike"],
+        ];
+        for tokens in items.iter() {
+            let mut d = Document::new();
+            for t in tokens.iter() {
+                d.add_text(field_body, t);
+            }
+            collect.add(Vector::from_array([0.0f32, 1.0], d)).unwrap();
+        }
+        let reader = collect.reader();
+        let searcher = reader.searcher();
+        let term = |s: &str| TermQuery::new(Term::from_field_text(field_body, s));
+        let ids = |q: &BooleanQuery| -> Vec<DocID> {
+            let mut ids: Vec<DocID> = searcher
+                .search(q, 10)
+                .unwrap()
+                .iter()
+                .map(|h| h.doc_id())
+                .collect();
+            ids.sort();
+            ids
+        };
+        let q = BooleanQuery::new().must(term("car")).must_not(term("blue"));
+        assert_eq!(ids(&q), vec![0]);
+        let q = BooleanQuery::new().should(term("red")).should(term("bike"));
+        assert_eq!(ids(&q), vec![0, 2, 3]);
+        // 两个 should 都命中的文档得分最高
+        assert_eq!(searcher.search(&q, 1).unwrap()[0].doc_id(), 2);
+        let q = q.with_minimum_should_match(2);
+        assert_eq!(ids(&q), vec![2]);
+        let q = BooleanQuery::new().must(term("car")).should(term("red"));
+        let hits = searcher.search(&q, 10).unwrap();
+        let hit_ids: Vec<DocID> = hits.iter().map(|h| h.doc_id()).collect();
+        assert_eq!(hit_ids, vec![0, 1]);
+        assert!(hits[0].score() > hits[1].score());
+    }
+
+    #[test]
+    fn test_analyzer_field() {
+        use query::{BooleanQuery, TermQuery};
+        use tokenize::AnalyzerConfig;
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector",
+            AnnType::FLAT,
+            TensorEntry::new(1, [2], schema::VectorType::F32),
+        ));
+        schema.add_field(FieldEntry::str("body").with_analyzer(AnalyzerConfig::standard()));
+        let field_body = schema.get_field("body").unwrap();
+        let config = ConfigBuilder::default()
+            .data_path(PathBuf::from("./data_analyzer"))
+            .build();
+        let collect = Engine::new(&schema, config.get_engine_config(PathBuf::from(""))).unwrap();
+        for body in ["Red car", "The blue CAR", "Café on the corner"].iter() {
+            let mut d = Document::new();
+            d.add_text(field_body, body);
+            collect.add(Vector::from_array([0.0f32, 1.0], d)).unwrap();
+        }
+        let reader = collect.reader();
+        let search = |text: &str| -> Vec<DocID> {
+            let mut q = BooleanQuery::new();
+            for term in reader.analyze(field_body, text) {
+                q = q.should(TermQuery::new(term));
+            }
+            let mut ids: Vec<DocID> = reader
+                .search_top_k(&q, 10)
+                .unwrap()
+                .iter()
+                .map(|h| h.doc_id())
+                .collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(search("car"), vec![0, 1]);
+        assert_eq!(search("RED"), vec![0]);
+        assert_eq!(search("cafe"), vec![2]);
+        // 停用词不建索引
+        assert!(search("the").is_empty());
+        let stats = reader.index_reader().field_stats(&field_body).unwrap();
+        assert_eq!(stats.total_len(), 6);
+    }
+
+    #[test]
+    fn test_jieba_field() {
+        use query::TermQuery;
+        use tokenize::{AnalyzerConfig, JiebaMode};
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector",
+            AnnType::FLAT,
+            TensorEntry::new(1, [2], schema::VectorType::F32),
+        ));
+        schema.add_field(
+            FieldEntry::str("title").with_analyzer(AnalyzerConfig::jieba(JiebaMode::Index, None)),
+        );
+        let field_title = schema.get_field("title").unwrap();
+        let config = ConfigBuilder::default()
+            .data_path(PathBuf::from("./data_jieba"))
+            .build();
+        let collect = Engine::new(&schema, config.get_engine_config(PathBuf::from(""))).unwrap();
+        for title in ["华为手机壳", "苹果笔记本电脑", "小米手机"].iter() {
+            let mut d = Document::new();
+            d.add_text(field_title, title);
+            collect.add(Vector::from_array([0.0f32, 1.0], d)).unwrap();
+        }
+        let reader = collect.reader();
+        let hits = reader
+            .search_top_k(
+                &TermQuery::new(Term::from_field_text(field_title, "手机")),
+                10,
+            )
+            .unwrap();
+        let mut ids: Vec<DocID> = hits.iter().map(|h| h.doc_id()).collect();
+        ids.sort();
+        assert_eq!(ids, vec![0, 2]);
+        let terms = reader.analyze(field_title, "笔记本电脑");
+        assert!(terms.iter().any(|t| t.text() == "电脑"));
+    }
+
+    #[test]
+    fn test_jieba_phrase_query() {
+        use query::PhraseQuery;
+        use tokenize::{AnalyzerConfig, JiebaMode};
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector",
+            AnnType::FLAT,
+            TensorEntry::new(1, [2], schema::VectorType::F32),
+        ));
+        schema.add_field(
+            FieldEntry::str("title").with_analyzer(AnalyzerConfig::jieba(JiebaMode::Index, None)),
+        );
+        let field_title = schema.get_field("title").unwrap();
+        let config = ConfigBuilder::default()
+            .data_path(PathBuf::from("./data_jieba_phrase"))
+            .build();
+        let collect = Engine::new(&schema, config.get_engine_config(PathBuf::from(""))).unwrap();
+        for title in [
+            "华为手机壳",
+            "小米手机",
+            // 标点不建索引, 但仍占一个位置
+            "华为，手机",
+            "中国科学院计算所",
+        ]
+        .iter()
+        {
+            let mut d = Document::new();
+            d.add_text(field_title, title);
+            collect.add(Vector::from_array([0.0f32, 1.0], d)).unwrap();
+        }
+        let reader = collect.reader();
+        let phrase = |text: &str| PhraseQuery::new(reader.analyze_positions(field_title, text));
+        let ids = |q: &PhraseQuery| -> Vec<DocID> {
+            let mut ids: Vec<DocID> = reader
+                .search_top_k(q, 10)
+                .unwrap()
+                .iter()
+                .map(|h| h.doc_id())
+                .collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids(&phrase("华为手机")), vec![0]);
+        assert_eq!(ids(&phrase("华为手机").with_slop(1)), vec![0, 2]);
+        assert_eq!(ids(&phrase("华为手机壳")), vec![0]);
+        // 长词中切出的短词与长词位置相同, 与后一个词相邻
+        let q = PhraseQuery::new(vec![
+            (Term::from_field_text(field_title, "科学院"), 0),
+            (Term::from_field_text(field_title, "计算所"), 1),
+        ]);
+        assert_eq!(ids(&q), vec![3]);
+        assert!(ids(&phrase("手机华为")).is_empty());
+    }
+
+    #[test]
+    fn test_phrase_query() {
+        use query::PhraseQuery;
+        use tokenize::AnalyzerConfig;
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector",
+            AnnType::FLAT,
+            TensorEntry::new(1, [2], schema::VectorType::F32),
+        ));
+        schema.add_field(FieldEntry::str("body").with_analyzer(AnalyzerConfig::standard()));
+        let field_body = schema.get_field("body").unwrap();
+        let config = ConfigBuilder::default()
+            .data_path(PathBuf::from("./data_phrase"))
+            .build();
+        let collect = Engine::new(&schema, config.get_engine_config(PathBuf::from(""))).unwrap();
+        let items = [
+            vec!["new york city"],
+            vec!["york is new"],
+            vec!["new big york"],
+            vec!["new york new york"],
+            // 多个值之间的位置不相邻
+            vec!["new", "york"],
+            // 停用词被去掉, 但仍占一个位置
+            vec!["Bank of America"],
+            vec!["bank america"],
+        ];
+        for values in items.iter() {
+            let mut d = Document::new();
+            for v in values.iter() {
+                d.add_text(field_body, v);
+            }
+            collect.add(Vector::from_array([0.0f32, 1.0], d)).unwrap();
+        }
+        let reader = collect.reader();
+        let phrase = |text: &str| PhraseQuery::new(reader.analyze_positions(field_body, text));
+        let ids = |q: &PhraseQuery| -> Vec<DocID> {
+            let mut ids: Vec<DocID> = reader
+                .search_top_k(q, 10)
+                .unwrap()
+                .iter()
+                .map(|h| h.doc_id())
+                .collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids(&phrase("new york")), vec![0, 3]);
+        assert_eq!(ids(&phrase("York New")), vec![3]);
+        // 出现两次的文档得分更高
+        assert_eq!(
+            reader.search_top_k(&phrase("new york"), 1).unwrap()[0].doc_id(),
+            3
+        );
+        assert_eq!(ids(&phrase("new york").with_slop(1)), vec![0, 2, 3]);
+        assert!(ids(&phrase("new york city hall")).is_empty());
+        assert_eq!(ids(&phrase("bank of america")), vec![5]);
+        assert_eq!(ids(&phrase("bank america")), vec![6]);
+        assert_eq!(ids(&phrase("bank america").with_slop(1)), vec![5, 6]);
+        let q = PhraseQuery::new(vec![
+            (Term::from_field_text(field_body, "america"), 2),
+            (Term::from_field_text(field_body, "bank"), 0),
+        ]);
+        assert_eq!(ids(&q), vec![5]);
+    }
+
+    #[test]
+    fn test_phrase_query_disk() {
+        use query::PhraseQuery;
+        use tokenize::AnalyzerConfig;
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector",
+            AnnType::FLAT,
+            TensorEntry::new(1, [2], schema::VectorType::F32),
+        ));
+        schema.add_field(FieldEntry::str("body").with_analyzer(AnalyzerConfig::standard()));
+        let field_body = schema.get_field("body").unwrap();
+        let items = [
+            vec!["new york city"],
+            vec!["Bank of America"],
+            vec!["new york new york"],
+            vec!["new", "york"],
+            vec!["bank america", "the new big york"],
+        ];
+        let MergedSegments {
+            all,
+            seg_a,
+            seg_b,
+            merged,
+            ..
+        } = merged_segments(&schema, "./data_phrase_disk", &items, 2, |values| {
+            let mut d = Document::new();
+            for v in values.iter() {
+                d.add_text(field_body, v);
+            }
+            Vector::from_array([0.0f32, 1.0], d)
+        });
+
+        // 合并后读出的位置与一次写入全部文档时相同, 包括多值之间的间隔
+        let reader = all.reader();
+        let positions = |r: &dyn FilterReader, text: &str| {
+            let mut v = Vec::new();
+            r.term_positions(&Term::from_field_text(field_body, text), &mut |p, pos| {
+                v.push((p.doc_id(), pos))
+            })
+            .unwrap();
+            v
+        };
+        for text in ["new", "york", "bank", "america"] {
+            assert_eq!(
+                positions(&merged, text),
+                positions(reader.index_reader(), text)
+            );
+        }
+        assert_eq!(positions(&seg_a, "america"), vec![(1, vec![2])]);
+        assert_eq!(
+            positions(&seg_b, "york"),
+            vec![(0, vec![1, 3]), (1, vec![101]), (2, vec![105])]
+        );
+
+        let ids = |r: &dyn FilterReader, q: &PhraseQuery| -> Vec<DocID> {
+            let mut ids: Vec<DocID> = q
+                .scores(r, &BM25::default())
+                .unwrap()
+                .iter()
+                .map(|h| h.doc_id())
+                .collect();
+            ids.sort();
+            ids
+        };
+        let q = PhraseQuery::new(merged.analyze_positions(field_body, "bank of america"));
+        assert_eq!(ids(&seg_a, &q), vec![1]);
+        assert_eq!(ids(&merged, &q), vec![1]);
+        let q = PhraseQuery::new(merged.analyze_positions(field_body, "new york"));
+        assert_eq!(ids(&seg_b, &q), vec![0]);
+        assert_eq!(ids(&merged, &q), vec![0, 2]);
+        assert_eq!(ids(&merged, &q), ids(reader.index_reader(), &q));
+        let q = q.with_slop(1);
+        assert_eq!(ids(&merged, &q), vec![0, 2, 4]);
+        drop((seg_a, seg_b, merged));
+        std::fs::remove_dir_all("./data_phrase_disk").unwrap();
+    }
+
+    #[test]
+    fn test_art_cache_cursor() {
+        let mut cache = ArtCache::new();
+        for i in 0..1000 {
+            cache.insert(
+                format!("a{:03}", i).into_bytes(),
+                Arc::new(RwLock::new(_Posting::new(0, 0))),
+            );
+        }
+        cache.insert(b"b".to_vec(), Arc::new(RwLock::new(_Posting::new(0, 0))));
+        let next =
+            |cursor: &mut ArtCursor| cursor.next_term().map(|t| String::from_utf8(t).unwrap());
+        let mut cursor = cache.cursor(Bound::Included(&b"a500"[..]), Bound::Excluded(&b"a503"[..]));
+        assert_eq!(next(&mut cursor).as_deref(), Some("a500"));
+        // 直接定位到不小于 key 的第一个 term
+        cursor.seek(b"a5015");
+        assert_eq!(next(&mut cursor).as_deref(), Some("a502"));
+        assert_eq!(next(&mut cursor), None);
+        // 跳过上界后没有 term
+        cursor.seek(b"b");
+        assert_eq!(next(&mut cursor), None);
+        let mut cursor = cache.cursor(Bound::Excluded(&b"a999"[..]), Bound::Unbounded);
+        assert_eq!(next(&mut cursor).as_deref(), Some("b"));
+        // 下界不小于上界时为空区间
+        let mut cursor = cache.cursor(Bound::Included(&b"b"[..]), Bound::Excluded(&b"a"[..]));
+        assert_eq!(next(&mut cursor), None);
+        let mut cursor = cache.cursor(Bound::Excluded(&b"b"[..]), Bound::Included(&b"b"[..]));
+        assert_eq!(next(&mut cursor), None);
+    }
+
+    #[test]
+    fn test_multi_term_query() {
+        use query::{PrefixQuery, RegexQuery, WildcardQuery};
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector",
+            AnnType::FLAT,
+            TensorEntry::new(1, [2], schema::VectorType::F32),
+        ));
+        schema.add_field(FieldEntry::str("tag"));
+        let field_tag = schema.get_field("tag").unwrap();
+        let config = ConfigBuilder::default()
+            .data_path(PathBuf::from("./data_multi_term"))
+            .build();
+        let collect = Engine::new(&schema, config.get_engine_config(PathBuf::from(""))).unwrap();
+        for tag in ["apple", "apply", "ape", "banana", "bandana", "app"].iter() {
+            let mut d = Document::new();
+            d.add_text(field_tag, tag);
+            collect.add(Vector::from_array([0.0f32, 1.0], d)).unwrap();
+        }
+        let reader = collect.reader();
+        let ids = |q: &dyn Query| -> Vec<DocID> {
+            let mut ids: Vec<DocID> = reader
+                .search_top_k(q, 10)
+                .unwrap()
+                .iter()
+                .map(|h| h.doc_id())
+                .collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids(&PrefixQuery::new(field_tag, "app")), vec![0, 1, 5]);
+        assert_eq!(
+            ids(&WildcardQuery::new(field_tag, "ban*a").unwrap()),
+            vec![3, 4]
+        );
+        assert_eq!(
+            ids(&WildcardQuery::new(field_tag, "ap?").unwrap()),
+            vec![2, 5]
+        );
+        assert_eq!(
+            ids(&RegexQuery::new(field_tag, "ap(e|pl[ey])").unwrap()),
+            vec![0, 1, 2]
+        );
+        assert!(ids(&PrefixQuery::new(field_tag, "cherry")).is_empty());
+        assert!(matches!(
+            reader.search_top_k(&PrefixQuery::new(field_tag, "a").with_max_expansions(2), 10),
+            Err(GyError::ErrTooManyTerms(2))
+        ));
+        assert!(RegexQuery::new(field_tag, "(").is_err());
+    }
+
+    #[test]
+    fn test_multi_term_query_disk() {
+        use automaton::PrefixAutomaton;
+        use query::{PrefixQuery, RegexQuery, WildcardQuery};
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector",
+            AnnType::FLAT,
+            TensorEntry::new(1, [2], schema::VectorType::F32),
+        ));
+        schema.add_field(FieldEntry::str("tag"));
+        let field_tag = schema.get_field("tag").unwrap();
+        let tags = ["apple", "apply", "ape", "banana", "bandana", "app", "ban"];
+        let MergedSegments {
+            all,
+            seg_a,
+            seg_b,
+            merged,
+            ..
+        } = merged_segments(&schema, "./data_multi_term_disk", &tags, 3, |tag| {
+            let mut d = Document::new();
+            d.add_text(field_tag, tag);
+            Vector::from_array([0.0f32, 1.0], d)
+        });
+
+        let reader = all.reader();
+        let ids = |r: &dyn FilterReader, q: &dyn Query| -> Vec<DocID> {
+            q.scores(r, &BM25::default())
+                .unwrap()
+                .iter()
+                .map(|h| h.doc_id())
+                .collect()
+        };
+        let queries: Vec<(Box<dyn Query>, Vec<DocID>)> = vec![
+            (Box::new(PrefixQuery::new(field_tag, "app")), vec![0, 1, 5]),
+            (Box::new(PrefixQuery::new(field_tag, "ban")), vec![3, 4, 6]),
+            (
+                Box::new(WildcardQuery::new(field_tag, "ban*a").unwrap()),
+                vec![3, 4],
+            ),
+            (
+                Box::new(RegexQuery::new(field_tag, "ap(e|pl[ey])").unwrap()),
+                vec![0, 1, 2],
+            ),
+            (Box::new(PrefixQuery::new(field_tag, "cherry")), vec![]),
+        ];
+        for (q, expect) in queries.iter() {
+            assert_eq!(&ids(reader.index_reader(), q.as_ref()), expect);
+            assert_eq!(&ids(&merged, q.as_ref()), expect);
+        }
+        assert_eq!(
+            ids(&seg_b, &WildcardQuery::new(field_tag, "ban*").unwrap()),
+            vec![0, 1, 3]
+        );
+        assert!(ids(&seg_a, &PrefixQuery::new(field_tag, "ban")).is_empty());
+
+        // 只展开前缀区间内的 term, 超过上限时报错
+        let expand = |r: &dyn FilterReader, prefix: &str, limit: usize| {
+            r.expand_terms(
+                &field_tag,
+                &mut PrefixAutomaton::new(prefix.as_bytes()),
+                limit,
+            )
+        };
+        for r in [reader.index_reader() as &dyn FilterReader, &merged] {
+            let terms: Vec<String> = expand(r, "ap", 10)
+                .unwrap()
+                .iter()
+                .map(|t| t.text().to_string())
+                .collect();
+            assert_eq!(terms, vec!["ape", "app", "apple", "apply"]);
+            assert_eq!(expand(r, "", 10).unwrap().len(), tags.len());
+            assert!(matches!(
+                expand(r, "ban", 2),
+                Err(GyError::ErrTooManyTerms(2))
+            ));
+        }
+        drop((seg_a, seg_b, merged));
+        std::fs::remove_dir_all("./data_multi_term_disk").unwrap();
+    }
+
+    #[test]
+    fn test_fuzzy_query() {
+        use query::FuzzyQuery;
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector",
+            AnnType::FLAT,
+            TensorEntry::new(1, [2], schema::VectorType::F32),
+        ));
+        schema.add_field(FieldEntry::str("color"));
+        let field_color = schema.get_field("color").unwrap();
+        let config = ConfigBuilder::default()
+            .data_path(PathBuf::from("./data_fuzzy"))
+            .build();
+        let collect = Engine::new(&schema, config.get_engine_config(PathBuf::from(""))).unwrap();
+        for color in ["red", "blue", "reed", "green", "bread"].iter() {
+            let mut d = Document::new();
+            d.add_text(field_color, color);
+            collect.add(Vector::from_array([0.0f32, 1.0], d)).unwrap();
+        }
+        let reader = collect.reader();
+        let ids = |q: &FuzzyQuery| -> Vec<DocID> {
+            let mut ids: Vec<DocID> = reader
+                .search_top_k(q, 10)
+                .unwrap()
+                .iter()
+                .map(|h| h.doc_id())
+                .collect();
+            ids.sort();
+            ids
+        };
+        let fuzzy =
+            |text: &str, distance: u32| FuzzyQuery::new(field_color, text, distance).unwrap();
+        assert_eq!(ids(&fuzzy("redd", 1)), vec![0, 2]);
+        // 编辑距离越小得分越高
+        let hits = reader.search_top_k(&fuzzy("red", 1), 10).unwrap();
+        assert_eq!(hits[0].doc_id(), 0);
+        assert_eq!(hits[1].doc_id(), 2);
+        assert!(hits[0].score() > hits[1].score());
+        assert!(ids(&fuzzy("rde", 1)).is_empty());
+        assert_eq!(ids(&fuzzy("rde", 1).with_transposition(true)), vec![0]);
+        assert_eq!(ids(&fuzzy("bred", 1)), vec![0, 4]);
+        assert_eq!(ids(&fuzzy("bred", 1).with_prefix_len(1)), vec![4]);
+        assert!(matches!(
+            FuzzyQuery::new(field_color, "red", 3),
+            Err(GyError::ErrInvalidFuzzyDistance(3))
+        ));
+    }
+
+    #[test]
+    fn test_fuzzy_query_disk() {
+        use automaton::LevenshteinAutomaton;
+        use query::FuzzyQuery;
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector",
+            AnnType::FLAT,
+            TensorEntry::new(1, [2], schema::VectorType::F32),
+        ));
+        schema.add_field(FieldEntry::str("color"));
+        let field_color = schema.get_field("color").unwrap();
+        let colors = ["red", "blue", "reed", "green", "bread", "rde", "erd"];
+        let MergedSegments {
+            all,
+            seg_a,
+            seg_b,
+            merged,
+            ..
+        } = merged_segments(&schema, "./data_fuzzy_disk", &colors, 3, |color| {
+            let mut d = Document::new();
+            d.add_text(field_color, color);
+            Vector::from_array([0.0f32, 1.0], d)
+        });
+
+        let reader = all.reader();
+        let ids = |r: &dyn FilterReader, q: &FuzzyQuery| -> Vec<DocID> {
+            q.scores(r, &BM25::default())
+                .unwrap()
+                .iter()
+                .map(|h| h.doc_id())
+                .collect()
+        };
+        let fuzzy =
+            |text: &str, distance: u32| FuzzyQuery::new(field_color, text, distance).unwrap();
+        let queries = [
+            (fuzzy("redd", 1), vec![0, 2]),
+            (fuzzy("red", 1), vec![0, 2]),
+            // 相邻字符交换算一次编辑
+            (fuzzy("red", 1).with_transposition(true), vec![0, 2, 5, 6]),
+            (
+                fuzzy("red", 1).with_transposition(true).with_prefix_len(1),
+                vec![0, 2, 5],
+            ),
+            (fuzzy("bred", 1), vec![0, 4]),
+            (fuzzy("bred", 1).with_prefix_len(1), vec![4]),
+        ];
+        for (q, expect) in queries.iter() {
+            assert_eq!(&ids(reader.index_reader(), q), expect);
+            assert_eq!(&ids(&merged, q), expect);
+        }
+        assert_eq!(
+            ids(&seg_b, &fuzzy("red", 1).with_transposition(true)),
+            vec![2, 3]
+        );
+
+        // 前缀之外的 term 不展开, 编辑距离越小得分越高
+        for r in [reader.index_reader() as &dyn FilterReader, &merged] {
+            let terms: Vec<String> = r
+                .expand_terms(
+                    &field_color,
+                    &mut LevenshteinAutomaton::new("red", 2, true, 2),
+                    10,
+                )
+                .unwrap()
+                .iter()
+                .map(|t| t.text().to_string())
+                .collect();
+            assert_eq!(terms, vec!["red", "reed"]);
+            let hits = fuzzy("red", 1).scores(r, &BM25::default()).unwrap();
+            assert!(hits[0].score() > hits[1].score());
+        }
+        drop((seg_a, seg_b, merged));
+        std::fs::remove_dir_all("./data_fuzzy_disk").unwrap();
+    }
+
+    #[test]
+    fn test_range_query() {
+        use chrono::{TimeZone, Utc};
+        use query::{RangeQuery, TermQuery};
+        use std::ops::Bound;
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector",
+            AnnType::FLAT,
+            TensorEntry::new(1, [2], schema::VectorType::F32),
+        ));
+        schema.add_field(FieldEntry::i64("balance"));
+        schema.add_field(FieldEntry::f64("price"));
+        schema.add_field(FieldEntry::date("created"));
+        let balance = schema.get_field("balance").unwrap();
+        let price = schema.get_field("price").unwrap();
+        let created = schema.get_field("created").unwrap();
+        let config = ConfigBuilder::default()
+            .data_path(PathBuf::from("./data_range"))
+            .build();
+        let collect = Engine::new(&schema, config.get_engine_config(PathBuf::from(""))).unwrap();
+        let items = [
+            (-300i64, 9.5f64, 1),
+            (-5, 10.0, 5),
+            (42, 99.9, 10),
+            (1000, 100.0, 20),
+        ];
+        for (b, p, day) in items.iter() {
+            let mut d = Document::new();
+            d.add_i64(balance, *b);
+            d.add_f64(price, *p);
+            d.add_date(
+                created,
+                Utc.with_ymd_and_hms(2024, 1, *day, 0, 0, 0).unwrap(),
+            );
+            collect.add(Vector::from_array([0.0f32, 1.0], d)).unwrap();
+        }
+        let reader = collect.reader();
+        let ids = |q: RangeQuery| -> Vec<DocID> {
+            let mut ids: Vec<DocID> = reader
+                .search_top_k(&q, 10)
+                .unwrap()
+                .iter()
+                .map(|h| h.doc_id())
+                .collect();
+            ids.sort();
+            ids
+        };
+        // price BETWEEN 10 AND 100
+        let q = RangeQuery::new(
+            price,
+            Bound::Included(Value::F64(10.0)),
+            Bound::Included(Value::F64(100.0)),
+        );
+        assert_eq!(ids(q), vec![1, 2, 3]);
+        let q = RangeQuery::new(
+            price,
+            Bound::Excluded(Value::F64(10.0)),
+            Bound::Excluded(Value::F64(100.0)),
+        );
+        assert_eq!(ids(q), vec![2]);
+        // 负数排在正数前面
+        let q = RangeQuery::new(balance, Bound::Unbounded, Bound::Excluded(Value::I64(0)));
+        assert_eq!(ids(q), vec![0, 1]);
+        let q = RangeQuery::new(
+            balance,
+            Bound::Included(Value::I64(-5)),
+            Bound::Included(Value::I64(42)),
+        );
+        assert_eq!(ids(q), vec![1, 2]);
+        // 下界大于上界时为空, 内存词典按区间查找时不会出错
+        let q = RangeQuery::new(
+            balance,
+            Bound::Included(Value::I64(42)),
+            Bound::Included(Value::I64(-5)),
+        );
+        assert!(ids(q).is_empty());
+        let q = RangeQuery::new(
+            balance,
+            Bound::Excluded(Value::I64(42)),
+            Bound::Excluded(Value::I64(42)),
+        );
+        assert!(ids(q).is_empty());
+        let q = RangeQuery::new(
+            created,
+            Bound::Included(Value::Date(
+                Utc.with_ymd_and_hms(2024, 1, 5, 0, 0, 0).unwrap(),
+            )),
+            Bound::Unbounded,
+        );
+        assert_eq!(ids(q), vec![1, 2, 3]);
+        // 类型与域不匹配时报错, 上下界类型不同时同样报错
+        let q = RangeQuery::new(balance, Bound::Unbounded, Bound::Included(Value::I32(0)));
+        assert!(matches!(
+            reader.search_top_k(&q, 10),
+            Err(GyError::ErrInvalidValueType)
+        ));
+        let q = RangeQuery::new(
+            balance,
+            Bound::Included(Value::I64(0)),
+            Bound::Included(Value::F64(1.0)),
+        );
+        assert!(matches!(
+            reader.search_top_k(&q, 10),
+            Err(GyError::ErrInvalidValueType)
+        ));
+        let hits = reader
+            .search_top_k(&TermQuery::new(Term::from_field_i64(balance, -5)), 10)
+            .unwrap();
+        assert_eq!(hits[0].doc_id(), 1);
+    }
+
+    #[test]
+    fn test_range_query_disk() {
+        use chrono::{TimeZone, Utc};
+        use query::RangeQuery;
+        fn date(day: u32) -> Value {
+            Value::Date(Utc.with_ymd_and_hms(2024, 1, day, 0, 0, 0).unwrap())
+        }
+        fn filter_ids<R: FilterReader>(r: &R, f: &Filter) -> Vec<DocID> {
+            f.allow_list(r)
+                .unwrap()
+                .iter()
+                .map(|i| i as DocID)
+                .collect()
+        }
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector",
+            AnnType::FLAT,
+            TensorEntry::new(1, [2], schema::VectorType::F32),
+        ));
+        schema.add_field(FieldEntry::i64("balance"));
+        schema.add_field(FieldEntry::f64("price"));
+        schema.add_field(FieldEntry::date("created"));
+        let balance = schema.get_field("balance").unwrap();
+        let price = schema.get_field("price").unwrap();
+        let created = schema.get_field("created").unwrap();
+        let items = [
+            (-300i64, 9.5f64, 1),
+            (-5, 10.0, 5),
+            (42, 99.9, 10),
+            (1000, 100.0, 20),
+            (7, -2.5, 3),
+            (-5, 100.0, 28),
+        ];
+        let MergedSegments {
+            all,
+            seg_a,
+            seg_b,
+            merged,
+            ..
+        } = merged_segments(&schema, "./data_range_disk", &items, 3, |(b, p, day)| {
+            let mut d = Document::new();
+            d.add_i64(balance, *b);
+            d.add_f64(price, *p);
+            d.add_date(
+                created,
+                Utc.with_ymd_and_hms(2024, 1, *day, 0, 0, 0).unwrap(),
+            );
+            Vector::from_array([0.0f32, 1.0], d)
+        });
+
+        let reader = all.reader();
+        type Bounds = fn() -> (Bound<Value>, Bound<Value>);
+        let ranges: [(FieldID, Bounds, Vec<DocID>); 7] = [
+            (
+                price,
+                || {
+                    (
+                        Bound::Included(Value::F64(10.0)),
+                        Bound::Included(Value::F64(100.0)),
+                    )
+                },
+                vec![1, 2, 3, 5],
+            ),
+            (
+                price,
+                || {
+                    (
+                        Bound::Excluded(Value::F64(10.0)),
+                        Bound::Excluded(Value::F64(100.0)),
+                    )
+                },
+                vec![2],
+            ),
+            (
+                price,
+                || (Bound::Unbounded, Bound::Excluded(Value::F64(0.0))),
+                vec![4],
+            ),
+            (
+                balance,
+                || (Bound::Unbounded, Bound::Excluded(Value::I64(0))),
+                vec![0, 1, 5],
+            ),
+            (
+                balance,
+                || {
+                    (
+                        Bound::Included(Value::I64(-5)),
+                        Bound::Included(Value::I64(42)),
+                    )
+                },
+                vec![1, 2, 4, 5],
+            ),
+            (
+                created,
+                || (Bound::Included(date(5)), Bound::Unbounded),
+                vec![1, 2, 3, 5],
+            ),
+            (
+                created,
+                || (Bound::Included(date(3)), Bound::Excluded(date(10))),
+                vec![1, 4],
+            ),
+        ];
+        let query_ids = |r: &dyn FilterReader, q: &RangeQuery| -> Vec<DocID> {
+            q.scores(r, &BM25::default())
+                .unwrap()
+                .iter()
+                .map(|h| h.doc_id())
+                .collect()
+        };
+        // 内存索引, 合并前后的段结果相同, 合并后 b 的文档 id 加上 a 的文档数
+        for (field, bounds, expect) in ranges.iter() {
+            let (lower, upper) = bounds();
+            let q = RangeQuery::new(*field, lower, upper);
+            let (lower, upper) = bounds();
+            let f = Filter::range(*field, lower, upper);
+            assert_eq!(&query_ids(reader.index_reader(), &q), expect);
+            assert_eq!(&query_ids(&merged, &q), expect);
+            assert_eq!(&filter_ids(reader.index_reader(), &f), expect);
+            assert_eq!(&filter_ids(&merged, &f), expect);
+            let mut split = query_ids(&seg_a, &q);
+            split.extend(query_ids(&seg_b, &q).iter().map(|id| id + 3));
+            assert_eq!(&split, expect);
+            assert_eq!(filter_ids(&seg_b, &f), query_ids(&seg_b, &q));
+        }
+        let f = Filter::And(vec![
+            Filter::range(balance, Bound::Unbounded, Bound::Excluded(Value::I64(0))),
+            Filter::range(price, Bound::Included(Value::F64(100.0)), Bound::Unbounded),
+        ]);
+        assert_eq!(filter_ids(&merged, &f), vec![5]);
+        assert_eq!(filter_ids(reader.index_reader(), &f), vec![5]);
+        // 类型与域不匹配时报错
+        let q = RangeQuery::new(balance, Bound::Unbounded, Bound::Included(Value::I32(0)));
+        for r in [reader.index_reader() as &dyn FilterReader, &seg_a, &merged] {
+            assert!(matches!(
+                q.scores(r, &BM25::default()),
+                Err(GyError::ErrInvalidValueType)
+            ));
+        }
+        let f = Filter::range(
+            balance,
+            Bound::Included(Value::I64(0)),
+            Bound::Included(Value::F64(1.0)),
+        );
+        assert!(matches!(
+            f.allow_list(reader.index_reader()),
+            Err(GyError::ErrInvalidValueType)
+        ));
+        assert!(matches!(
+            f.allow_list(&merged),
+            Err(GyError::ErrInvalidValueType)
+        ));
+        drop((seg_a, seg_b, merged));
+        std::fs::remove_dir_all("./data_range_disk").unwrap();
+    }
+
+    #[test]
+    fn test_sparse_field() {
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector",
+            AnnType::FLAT,
+            TensorEntry::new(1, [2], schema::VectorType::F32),
+        ));
+        schema.add_field(FieldEntry::str("body"));
+        schema.add_field(FieldEntry::sparse("terms"));
+        let field_body = schema.get_field("body").unwrap();
+        let field_terms = schema.get_field("terms").unwrap();
+        let config = ConfigBuilder::default()
+            .data_path(PathBuf::from("./data_sparse"))
+            .build();
+        let collect = Engine::new(&schema, config.get_engine_config(PathBuf::from(""))).unwrap();
+        let items = [
+            vec![(1, 0.5), (3, 1.0)],
+            vec![(1, 2.0), (7, 0.1)],
+            vec![(3, 0.2), (7, 3.0), (9, 1.0)],
+            vec![(2, 1.0)],
+        ];
+        for dims in items.iter() {
+            let mut d = Document::new();
+            d.add_sparse(field_terms, SparseVector::new(dims.clone()));
+            collect.add(Vector::from_array([0.0f32, 1.0], d)).unwrap();
+        }
+        let reader = collect.reader();
+        let q = SparseVector::new(vec![(1, 1.0), (3, 1.0), (7, 0.5)]);
+        let p = reader.query_sparse(field_terms, &q, 2).unwrap();
+        assert_eq!(p.len(), 2);
+        assert_eq!(p[0].doc_id(), 1);
+        assert_eq!(p[1].doc_id(), 2);
+        assert!((p[1].score(MetricType::InnerProduct) - 1.7).abs() < 1e-6);
+        // 没有命中任何维度的文档不返回
+        let p = reader.query_sparse(field_terms, &q, 10).unwrap();
+        assert_eq!(p.len(), 3);
+        assert!(matches!(
+            reader.query_sparse(field_body, &q, 1),
+            Err(GyError::ErrNotSparseField(_))
+        ));
+    }
+
+    #[test]
+    fn test_sparse_field_disk() {
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector",
+            AnnType::FLAT,
+            TensorEntry::new(1, [2], schema::VectorType::F32),
+        ));
+        schema.add_field(FieldEntry::sparse("terms"));
+        let field_terms = schema.get_field("terms").unwrap();
+        let items = [
+            vec![(1, 0.5), (3, 1.0)],
+            vec![(1, 2.0), (7, 0.1)],
+            vec![(3, 0.2), (7, 3.0), (9, 1.0)],
+            vec![(2, 1.0)],
+        ];
+        let MergedSegments {
+            a,
+            seg_a,
+            seg_b,
+            merged,
+            ..
+        } = merged_segments(&schema, "./data_sparse_disk", &items, 2, |dims| {
+            let mut d = Document::new();
+            d.add_sparse(field_terms, SparseVector::new(dims.clone()));
+            Vector::from_array([0.0f32, 1.0], d)
+        });
+        let q = SparseVector::new(vec![(1, 1.0), (3, 1.0), (7, 0.5)]);
+
+        // 重新打开的段与内存中的结果一致
+        let mem: Vec<DocID> = a
+            .reader()
+            .query_sparse(field_terms, &q, 10)
+            .unwrap()
+            .iter()
+            .map(|n| n.doc_id())
+            .collect();
+        let p = seg_a.query_sparse(field_terms, &q, 10).unwrap();
+        let ids: Vec<DocID> = p.iter().map(|n| n.doc_id()).collect();
+        assert_eq!(ids, mem);
+        assert_eq!(ids, vec![1, 0]);
+        assert!((p[0].score(MetricType::InnerProduct) - 2.05).abs() < 1e-6);
+
+        // 倒排表开头的最大权重供 WAND 剪枝
+        let max_weight = |seg: &disk::DiskStoreReader, dim: u32| {
+            seg.field_reader(field_terms.id())
+                .unwrap()
+                .try_find(&ann::sparse::dim_term(dim))
+                .unwrap()
+                .unwrap()
+                .max_weight()
+                .unwrap()
+        };
+        assert_eq!(max_weight(&seg_a, 1), 2.0);
+        assert_eq!(max_weight(&seg_a, 7), 0.1);
+        assert_eq!(max_weight(&seg_b, 7), 3.0);
+
+        // 合并后 b 的文档 id 加上 a 的文档数, 最大权重取两段中较大的
+        assert_eq!(max_weight(&merged, 7), 3.0);
+        assert_eq!(max_weight(&merged, 3), 1.0);
+        let weights: Vec<(DocID, f32)> = merged
+            .field_reader(field_terms.id())
+            .unwrap()
+            .find(&ann::sparse::dim_term(7))
+            .unwrap()
+            .weights()
+            .unwrap()
+            .map(|x| (x.doc_id(), x.weight()))
+            .collect();
+        assert_eq!(weights, vec![(1, 0.1), (2, 3.0)]);
+        let p = merged.query_sparse(field_terms, &q, 2).unwrap();
+        let ids: Vec<DocID> = p.iter().map(|n| n.doc_id()).collect();
+        assert_eq!(ids, vec![1, 2]);
+        assert!((p[1].score(MetricType::InnerProduct) - 1.7).abs() < 1e-6);
+        assert_eq!(merged.query_sparse(field_terms, &q, 10).unwrap().len(), 3);
+        drop((seg_a, seg_b, merged));
+        std::fs::remove_dir_all("./data_sparse_disk").unwrap();
+    }
+
+    #[test]
+    fn test_merge_term_bloom() {
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector",
+            AnnType::FLAT,
+            TensorEntry::new(1, [2], schema::VectorType::F32),
+        ));
+        schema.add_field(FieldEntry::str("title"));
+        let field_title = schema.get_field("title").unwrap();
+        let titles = ["aa", "bb", "bb", "cc"];
+        let MergedSegments {
+            seg_a,
+            seg_b,
+            merged,
+            ..
+        } = merged_segments(&schema, "./data_merge_bloom", &titles, 2, |t| {
+            let mut d = Document::new();
+            d.add_text(field_title.clone(), t);
+            Vector::from_array([0.0f32, 1.0], d)
+        });
+        // 只在 a, 两段共有, 只在 b 中的 term 合并后都能查到
+        for (t, expect) in [("aa", vec![0]), ("bb", vec![1, 2]), ("cc", vec![3])] {
+            let p = merged
+                .search(Term::from_field_text(field_title.clone(), t))
+                .unwrap();
+            let ids: Vec<DocID> = p.iter().map(|n| n.doc_id()).collect();
+            assert_eq!(ids, expect);
+        }
+        assert!(merged
+            .search(Term::from_field_text(field_title.clone(), "dd"))
+            .is_err());
+        drop((seg_a, seg_b, merged));
+        std::fs::remove_dir_all("./data_merge_bloom").unwrap();
+    }
+
+    #[test]
+    fn test_search_doc() {
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector1",
+            AnnType::HNSW,
+            TensorEntry::new(1, [4], schema::VectorType::F32),
+        ));
+        schema.add_field(FieldEntry::str("body"));
+        schema.add_field(FieldEntry::i32("title"));
+
+        let field_id_title = schema.get_field("title").unwrap();
+        let disk_reader = DiskStoreReader::open(PathBuf::from("./data1/my_index")).unwrap();
+        let p = disk_reader
+            .search(Term::from_field_text(field_id_title, "aa"))
+            .unwrap();
+        // println!("doc_size:{:?}", p.get_doc_count());
+        for n in p.iter() {
+            let doc = disk_reader.vector(n.doc_id()).unwrap();
+            println!("docid:{},doc{:?}", n.doc_id(), unsafe {
+                doc.v.as_slice::<f32>()
+            });
+        }
+
+        let p = disk_reader
+            .query(
+                "vector1",
+                &Tensor::from_vec(vec![0.0f32, 0.0, 1.0, 0.0], 1, Shape::from_array([4])),
+                4,
+            )
+            .unwrap();
+
+        for n in p.iter() {
+            let doc = disk_reader.vector(n.doc_id()).unwrap();
+            println!("docid:{},doc{:?}", n.doc_id(), unsafe {
+                doc.v.as_slice::<f32>()
+            });
+        }
+    }
+
+    #[test]
+    fn test_search_vector() {
+        let mut schema = Schema::with_vector(VectorEntry::new(
+            "vector1",
+            AnnType::HNSW,
+            TensorEntry::new(1, [4], schema::VectorType::F32),
+        ));
+        schema.add_field(FieldEntry::str("body"));
+        schema.add_field(FieldEntry::i32("title"));
+
+        let field_id_title = schema.get_field("title").unwrap();
+        let disk_reader = DiskStoreReader::open(PathBuf::from("./data3/my_index")).unwrap();
+        let p = disk_reader
+            .query(
+                "vector1",
+                &Tensor::from_vec(vec![1.0f32, 0.0, 0.0, 1.0], 1, Shape::from_array([4])),
+                4,
+            )
+            .unwrap();
+        // println!("doc_size:{:?}", p.get_doc_count());
+        for n in p.iter() {
+            let doc = disk_reader.vector(n.doc_id()).unwrap();
+            println!("docid:{},doc{:?}", n.doc_id(), unsafe {
+                doc.v.as_slice::<f32>()
+            });
+        }
+    }
+
+    #[test]
+    fn test_add_doc2() {
+        let mut schema = Schema::new();
+        schema.add_field(FieldEntry::str("body"));
+        schema.add_field(FieldEntry::i32("title"));
+        let field_id_title = schema.get_field("title").unwrap();
+        let config = ConfigBuilder::default()
+            .data_path(PathBuf::from("/opt/rsproject/chappie/searchlite/data2"))
+            .build();
+        let mut index = Index::new(&schema, config.get_engine_config(PathBuf::from(""))).unwrap();
+        let mut writer1 = index.writer().unwrap();
+        {
+            let mut d = Document::new();
+            d.add_text(field_id_title.clone(), "bb");
+            writer1.add(1, &d).unwrap();
+
+            let mut d1 = Document::new();
+            d1.add_text(field_id_title.clone(), "aa");
+            writer1.add(2, &d1).unwrap();
+
+            let mut d2 = Document::new();
+            d2.add_text(field_id_title.clone(), "cc");
+            writer1.add(3, &d2).unwrap();
+
+            let mut d3 = Document::new();
+            d3.add_text(field_id_title.clone(), "aa");
+            writer1.add(4, &d3).unwrap();
+
+            let mut d1 = Document::new();
+            d1.add_text(field_id_title.clone(), "aa");
+            writer1.add(5, &d1).unwrap();
+
+            let mut d2 = Document::new();
+            d2.add_text(field_id_title.clone(), "cc");
+            writer1.add(6, &d2).unwrap();
+
+            let mut d3 = Document::new();
+            d3.add_text(field_id_title.clone(), "aa");
+            writer1.add(7, &d3).unwrap();
+
+            // writer1.commit().unwrap();
+        }
+
+        let reader = index.reader().unwrap();
+        let p = reader
+            .search(Term::from_field_text(field_id_title, "aa"))
+            .unwrap();
+
+        for doc_freq in p.iter() {
+            let doc = reader.doc(doc_freq.doc_id()).unwrap();
+            println!("docid:{},doc{:?}", doc_freq.doc_id(), doc);
+        }
+        println!("doc vec:{:?}", reader.get_doc_offset());
+        //  disk::persist_index(&reader).unwrap();
+    }
+    use crate::fs::FileManager;
+    #[test]
+    fn test_merge_store() {
+        let disk_reader1 = DiskStoreReader::open(PathBuf::from(
+            "/opt/rsproject/chappie/searchlite/data1/my_index",
+        ))
+        .unwrap();
+
+        let disk_reader2 = DiskStoreReader::open(PathBuf::from(
+            "/opt/rsproject/chappie/searchlite/data2/my_index",
+        ))
+        .unwrap();
+        FileManager::mkdir(&PathBuf::from(
+            "/opt/rsproject/chappie/searchlite/data3/my_index",
+        ))
+        .unwrap();
+        disk::merge(
+            &disk_reader1,
+            &disk_reader2,
+            &PathBuf::from("/opt/rsproject/chappie/searchlite/data3/my_index/data.gy"),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_read() {
+        let mut schema = Schema::new();
+        schema.add_field(FieldEntry::str("body"));
+        schema.add_field(FieldEntry::i32("title"));
+        let field_id_title = schema.get_field("title").unwrap();
+        let disk_reader = DiskStoreReader::open(PathBuf::from(
+            "/opt/rsproject/chappie/searchlite/data3/my_index",
+        ))
+        .unwrap();
+        let p = disk_reader
+            .search(Term::from_field_text(field_id_title, "aa"))
+            .unwrap();
+        println!("doc_size:{:?}", p.get_doc_count());
+        for doc_freq in p.iter() {
+            println!("{:?}", doc_freq);
+        }
+        //    let doc_reader = disk_reader.doc_reader().unwrap();
+    }
+
+    #[test]
+    fn test_read_iter() {
+        let mut schema = Schema::new();
+        schema.add_field(FieldEntry::str("body"));
+        schema.add_field(FieldEntry::i32("title"));
+        let field_id_title = schema.get_field("title").unwrap();
+        let disk_reader = DiskStoreReader::open(PathBuf::from(
             "/opt/rsproject/chappie/searchlite/data/my_index",
         ))
         .unwrap();
@@ -1398,7 +4182,8 @@ mod tests {
     #[test]
     fn test_fieldcache1() {
         let buffer_pool = Arc::new(RingBuffer::new());
-        let mut field = FieldCache::new(Arc::downgrade(&buffer_pool));
+        let mut field =
+            FieldCache::new(Arc::downgrade(&buffer_pool), &FieldEntry::str("title")).unwrap();
         let mut doc_id: DocID = 0;
         let valuea = Value::Str("aa");
 
@@ -1416,11 +4201,7 @@ mod tests {
         field.add(doc_id, &valuea).unwrap();
         field.commit().unwrap();
 
-        let field_reader = FieldReader::new(
-            field.indexs.clone(),
-            field.share_bytes_block.clone(),
-            field.term_count.load(Ordering::Acquire),
-        );
+        let field_reader = field.reader();
 
         println!("search aa");
         let mut p = field_reader.get("aa".as_bytes()).unwrap();
@@ -1432,7 +4213,8 @@ mod tests {
     #[test]
     fn test_fieldcache2() {
         let buffer_pool = Arc::new(RingBuffer::new());
-        let mut field = FieldCache::new(Arc::downgrade(&buffer_pool));
+        let mut field =
+            FieldCache::new(Arc::downgrade(&buffer_pool), &FieldEntry::str("title")).unwrap();
         let mut doc_id: DocID = 0;
         let valuea = Value::Str("aa");
         let valueb = Value::Str("bb");
@@ -1524,26 +4306,26 @@ mod tests {
             let mut d1 = Document::new();
             d1.add_text(field_id_title.clone(), "aa");
 
-            let v1 = Vector::from_array([0.0, 0.0, 0.0, 1.0], d1);
+            let v1 = Vector::from_array([0.0f32, 0.0, 0.0, 1.0], d1);
 
             collect.add(v1).unwrap();
 
             let mut d2 = Document::new();
             d2.add_text(field_id_title.clone(), "cc");
-            let v2 = Vector::from_array([0.0, 0.0, 1.0, 0.0], d2);
+            let v2 = Vector::from_array([0.0f32, 0.0, 1.0, 0.0], d2);
 
             collect.add(v2).unwrap();
 
             let mut d3 = Document::new();
             d3.add_text(field_id_title.clone(), "aa");
 
-            let v3 = Vector::from_array([0.0, 1.0, 0.0, 0.0], d3);
+            let v3 = Vector::from_array([0.0f32, 1.0, 0.0, 0.0], d3);
 
             collect.add(v3).unwrap();
 
             let mut d4 = Document::new();
             d4.add_text(field_id_title.clone(), "bb");
-            let v4 = Vector::from_array([1.0, 0.0, 0.0, 0.0], d4);
+            let v4 = Vector::from_array([1.0f32, 0.0, 0.0, 0.0], d4);
             collect.add(v4).unwrap();
         }
         println!(
@@ -1564,7 +4346,8 @@ mod tests {
         let p = PathBuf::from("./data_wal/my_index/data.wal");
         let wal = Wal::open(&p, DEFAULT_WAL_FILE_SIZE, &IOType::MMAP).unwrap();
 
-        let mut wal_iter = wal.iter::<Vector>(TensorEntry::new(1, [4], schema::VectorType::F32));
+        let mut wal_iter =
+            wal.iter::<Vector>(vec![TensorEntry::new(1, [4], schema::VectorType::F32)]);
         while let Some((doc_offset, v)) = wal_iter.next() {
             println!("{},{:?},{:?}", doc_offset, v.v.as_bytes(), v.payload);
         }
@@ -1590,6 +4373,7 @@ mod tests {
         let reader = collect.reader();
         let p = reader
             .query(
+                "vector1",
                 &Tensor::from_vec(vec![0.0f32, 0.0, 1.0, 0.0], 1, Shape::from_array([4])),
                 4,
             )
@@ -1604,22 +4388,22 @@ mod tests {
 
         let mut d5 = Document::new();
         d5.add_text(field_id_title.clone(), "cc");
-        let v5 = Vector::from_array([0.0, 0.0, 1.0, 1.0], d5);
+        let v5 = Vector::from_array([0.0f32, 0.0, 1.0, 1.0], d5);
         collect.add(v5).unwrap();
 
         let mut d6 = Document::new();
         d6.add_text(field_id_title.clone(), "aa");
-        let v6 = Vector::from_array([0.0, 1.0, 1.0, 0.0], d6);
+        let v6 = Vector::from_array([0.0f32, 1.0, 1.0, 0.0], d6);
         collect.add(v6).unwrap();
 
         let mut d7 = Document::new();
         d7.add_text(field_id_title.clone(), "ff");
-        let v7 = Vector::from_array([1.0, 0.0, 0.0, 1.0], d7);
+        let v7 = Vector::from_array([1.0f32, 0.0, 0.0, 1.0], d7);
         collect.add(v7).unwrap();
 
         let mut d8 = Document::new();
         d8.add_text(field_id_title.clone(), "gg");
-        let d8 = Vector::from_array([1.0, 1.0, 0.0, 0.0], d8);
+        let d8 = Vector::from_array([1.0f32, 1.0, 0.0, 0.0], d8);
         collect.add(d8).unwrap();
     }
 }
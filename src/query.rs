@@ -1,21 +1,579 @@
-use super::schema::FieldID;
-use super::searcher::Searcher;
+use super::automaton::{
+    self, Automaton, LevenshteinAutomaton, PrefixAutomaton, RangeAutomaton, Regex,
+};
+use super::schema::{DateTime, DocFreq, DocID, FieldID, FieldType, Value};
+use super::similarity::{FieldStats, ScoreDoc, Similarity};
+use super::util::bitmap::BitMap;
 use super::util::common;
-use super::{IndexReader, PostingReader};
+use super::util::error::{GyError, GyResult};
 use byteorder::{BigEndian, ByteOrder};
 const INT_TERM_LEN: usize = 4 + 8;
+use std::cmp::Ordering;
+use std::ops::Bound;
 use std::str;
 pub struct TermQuery {
     term: Term,
 }
 
+impl TermQuery {
+    pub fn new(term: Term) -> TermQuery {
+        TermQuery { term: term }
+    }
+}
+
 impl Query for TermQuery {
-    fn query(&self, reader: &IndexReader) -> PostingReader {
-        //reader.search(term);
-        todo!();
+    fn scores(
+        &self,
+        reader: &dyn FilterReader,
+        similarity: &dyn Similarity,
+    ) -> GyResult<Vec<ScoreDoc>> {
+        let field = self.term.field_id();
+        let stats = reader.field_stats(&field)?;
+        let mut postings = Vec::new();
+        reader.term_postings(&self.term, &mut |p| postings.push(p))?;
+        let weight = similarity.weight(&stats, postings.len() as u64);
+        postings
+            .iter()
+            .map(|p| {
+                let doc_len = reader.doc_len(&field, p.doc_id())?;
+                Ok(ScoreDoc::new(
+                    p.doc_id(),
+                    similarity.score(weight, p.freq(), doc_len, &stats),
+                ))
+            })
+            .collect()
+    }
+}
+
+// 子查询组合: must 全部命中, must_not 都不命中, should 至少命中 minimum_should_match 个
+// 没有 must 时至少命中一个 should, 得分为命中的 must 和 should 的得分之和
+#[derive(Default)]
+pub struct BooleanQuery {
+    must: Vec<Box<dyn Query>>,
+    should: Vec<Box<dyn Query>>,
+    must_not: Vec<Box<dyn Query>>,
+    minimum_should_match: usize,
+}
+
+impl BooleanQuery {
+    pub fn new() -> BooleanQuery {
+        BooleanQuery::default()
+    }
+
+    pub fn must<Q: Query + 'static>(mut self, query: Q) -> BooleanQuery {
+        self.must.push(Box::new(query));
+        self
+    }
+
+    pub fn should<Q: Query + 'static>(mut self, query: Q) -> BooleanQuery {
+        self.should.push(Box::new(query));
+        self
+    }
+
+    pub fn must_not<Q: Query + 'static>(mut self, query: Q) -> BooleanQuery {
+        self.must_not.push(Box::new(query));
+        self
+    }
+
+    pub fn with_minimum_should_match(mut self, n: usize) -> BooleanQuery {
+        self.minimum_should_match = n;
+        self
+    }
+}
+
+impl Query for BooleanQuery {
+    fn scores(
+        &self,
+        reader: &dyn FilterReader,
+        similarity: &dyn Similarity,
+    ) -> GyResult<Vec<ScoreDoc>> {
+        // should 的并集, 同时记录每个文档命中的子查询个数
+        let mut should: Vec<(ScoreDoc, usize)> = Vec::new();
+        for q in self.should.iter() {
+            let docs = q.scores(reader, similarity)?;
+            should = union(&should, &docs);
+        }
+        let min_should = if self.must.is_empty() {
+            self.minimum_should_match.max(1)
+        } else {
+            self.minimum_should_match
+        };
+        let mut docs = match self.must.split_first() {
+            Some((first, rest)) => {
+                let mut docs = first.scores(reader, similarity)?;
+                for q in rest.iter() {
+                    docs = intersect(&docs, &q.scores(reader, similarity)?);
+                }
+                // must 命中的文档加上 should 的得分
+                let mut j = 0;
+                let mut results = Vec::with_capacity(docs.len());
+                for d in docs.iter() {
+                    while j < should.len() && should[j].0.doc_id() < d.doc_id() {
+                        j += 1;
+                    }
+                    match should.get(j) {
+                        Some((s, n)) if s.doc_id() == d.doc_id() => {
+                            if *n >= min_should {
+                                results.push(ScoreDoc::new(d.doc_id(), d.score() + s.score()));
+                            }
+                        }
+                        _ => {
+                            if min_should == 0 {
+                                results.push(*d);
+                            }
+                        }
+                    }
+                }
+                results
+            }
+            None => should
+                .into_iter()
+                .filter(|(_, n)| *n >= min_should)
+                .map(|(d, _)| d)
+                .collect(),
+        };
+        for q in self.must_not.iter() {
+            let excluded = q.scores(reader, similarity)?;
+            docs = difference(&docs, &excluded);
+        }
+        Ok(docs)
+    }
+}
+
+// 短语查询: 所有 term 按顺序出现在同一个域中
+// slop 为相邻 term 之间允许插入的 token 总数, 0 表示必须紧邻
+pub struct PhraseQuery {
+    // term 和它在短语中的位置, 按位置排序
+    terms: Vec<(Term, u32)>,
+    slop: u32,
+}
+
+impl PhraseQuery {
+    // 位置一般由 analyze_positions 给出, 停用词占的位置保留, 如 "bank of america" 中 america 在 2
+    pub fn new(mut terms: Vec<(Term, u32)>) -> PhraseQuery {
+        terms.sort_by_key(|(_, pos)| *pos);
+        PhraseQuery {
+            terms: terms,
+            slop: 0,
+        }
+    }
+
+    pub fn with_slop(mut self, slop: u32) -> PhraseQuery {
+        self.slop = slop;
+        self
+    }
+}
+
+impl Query for PhraseQuery {
+    fn scores(
+        &self,
+        reader: &dyn FilterReader,
+        similarity: &dyn Similarity,
+    ) -> GyResult<Vec<ScoreDoc>> {
+        if self.terms.is_empty() {
+            return Ok(Vec::new());
+        }
+        let field = self.terms[0].0.field_id();
+        let stats = reader.field_stats(&field)?;
+        let mut weight = 0.0;
+        // 同时含有所有 term 的文档, 及每个 term 在文档中的位置
+        let mut docs: Option<Vec<(DocID, Vec<Vec<u32>>)>> = None;
+        for (term, _) in self.terms.iter() {
+            let mut postings = Vec::new();
+            reader.term_positions(term, &mut |p, positions| {
+                postings.push((p.doc_id(), positions))
+            })?;
+            weight += similarity.weight(&stats, postings.len() as u64);
+            docs = Some(match docs {
+                None => postings
+                    .into_iter()
+                    .map(|(doc_id, positions)| (doc_id, vec![positions]))
+                    .collect(),
+                Some(docs) => intersect_positions(docs, postings),
+            });
+        }
+        let offsets: Vec<u32> = self.terms.iter().map(|(_, pos)| *pos).collect();
+        let mut results = Vec::new();
+        for (doc_id, positions) in docs.unwrap_or_default() {
+            let freq = phrase_freq(&positions, &offsets, self.slop);
+            if freq == 0 {
+                continue;
+            }
+            let doc_len = reader.doc_len(&field, doc_id)?;
+            results.push(ScoreDoc::new(
+                doc_id,
+                similarity.score(weight, freq, doc_len, &stats),
+            ));
+        }
+        Ok(results)
+    }
+}
+
+fn intersect_positions(
+    a: Vec<(DocID, Vec<Vec<u32>>)>,
+    b: Vec<(DocID, Vec<u32>)>,
+) -> Vec<(DocID, Vec<Vec<u32>>)> {
+    let mut b = b.into_iter().peekable();
+    let mut results = Vec::new();
+    for (doc_id, mut positions) in a {
+        while b.peek().map_or(false, |(d, _)| *d < doc_id) {
+            b.next();
+        }
+        if let Some((_, p)) = b.next_if(|(d, _)| *d == doc_id) {
+            positions.push(p);
+            results.push((doc_id, positions));
+        }
+    }
+    results
+}
+
+// 以第一个 term 的每个位置为起点, 依次取后一个 term 与前一个的距离不小于短语中距离的最近位置
+// 多出的距离之和不超过 slop 即为一次命中, 返回命中次数
+fn phrase_freq(positions: &[Vec<u32>], offsets: &[u32], slop: u32) -> u32 {
+    let mut freq = 0;
+    'start: for p in positions[0].iter() {
+        let (mut prev, mut gaps) = (*p, 0);
+        for (next, w) in positions[1..].iter().zip(offsets.windows(2)) {
+            let d = w[1] - w[0];
+            let i = next.partition_point(|x| *x < prev + d);
+            if i == next.len() {
+                continue 'start;
+            }
+            gaps += next[i] - prev - d;
+            if gaps > slop {
+                continue 'start;
+            }
+            prev = next[i];
+        }
+        freq += 1;
+    }
+    freq
+}
+
+// 多 term 查询默认最多展开的 term 数
+pub const MAX_EXPANSIONS: usize = 1024;
+
+// 展开后的每个 term 按 TermQuery 打分, 命中多个 term 的文档得分相加
+fn expand_scores(
+    reader: &dyn FilterReader,
+    similarity: &dyn Similarity,
+    field: &FieldID,
+    automaton: &mut dyn Automaton,
+    max_expansions: usize,
+) -> GyResult<Vec<ScoreDoc>> {
+    let mut docs: Vec<(ScoreDoc, usize)> = Vec::new();
+    for term in reader.expand_terms(field, automaton, max_expansions)? {
+        let scores = TermQuery::new(term).scores(reader, similarity)?;
+        docs = union(&docs, &scores);
+    }
+    Ok(docs.into_iter().map(|(d, _)| d).collect())
+}
+
+// 前缀查询: 命中以 prefix 开头的所有 term
+pub struct PrefixQuery {
+    field: FieldID,
+    prefix: Vec<u8>,
+    max_expansions: usize,
+}
+
+impl PrefixQuery {
+    pub fn new(field: FieldID, prefix: &str) -> PrefixQuery {
+        PrefixQuery {
+            field: field,
+            prefix: prefix.as_bytes().to_vec(),
+            max_expansions: MAX_EXPANSIONS,
+        }
+    }
+
+    // 展开的 term 超过 max_expansions 个时查询返回 ErrTooManyTerms
+    pub fn with_max_expansions(mut self, max_expansions: usize) -> PrefixQuery {
+        self.max_expansions = max_expansions;
+        self
+    }
+}
+
+impl Query for PrefixQuery {
+    fn scores(
+        &self,
+        reader: &dyn FilterReader,
+        similarity: &dyn Similarity,
+    ) -> GyResult<Vec<ScoreDoc>> {
+        expand_scores(
+            reader,
+            similarity,
+            &self.field,
+            &mut PrefixAutomaton::new(&self.prefix),
+            self.max_expansions,
+        )
     }
 }
 
+// 通配符查询: * 匹配任意个字符, ? 匹配一个字符, 其余字符按原样匹配
+pub struct WildcardQuery {
+    field: FieldID,
+    regex: Regex,
+    max_expansions: usize,
+}
+
+impl WildcardQuery {
+    pub fn new(field: FieldID, pattern: &str) -> GyResult<WildcardQuery> {
+        Ok(WildcardQuery {
+            field: field,
+            regex: Regex::wildcard(pattern)?,
+            max_expansions: MAX_EXPANSIONS,
+        })
+    }
+
+    pub fn with_max_expansions(mut self, max_expansions: usize) -> WildcardQuery {
+        self.max_expansions = max_expansions;
+        self
+    }
+}
+
+impl Query for WildcardQuery {
+    fn scores(
+        &self,
+        reader: &dyn FilterReader,
+        similarity: &dyn Similarity,
+    ) -> GyResult<Vec<ScoreDoc>> {
+        expand_scores(
+            reader,
+            similarity,
+            &self.field,
+            &mut self.regex.automaton(),
+            self.max_expansions,
+        )
+    }
+}
+
+// 正则查询: 正则须匹配整个 term
+pub struct RegexQuery {
+    field: FieldID,
+    regex: Regex,
+    max_expansions: usize,
+}
+
+impl RegexQuery {
+    pub fn new(field: FieldID, pattern: &str) -> GyResult<RegexQuery> {
+        Ok(RegexQuery {
+            field: field,
+            regex: Regex::new(pattern)?,
+            max_expansions: MAX_EXPANSIONS,
+        })
+    }
+
+    pub fn with_max_expansions(mut self, max_expansions: usize) -> RegexQuery {
+        self.max_expansions = max_expansions;
+        self
+    }
+}
+
+impl Query for RegexQuery {
+    fn scores(
+        &self,
+        reader: &dyn FilterReader,
+        similarity: &dyn Similarity,
+    ) -> GyResult<Vec<ScoreDoc>> {
+        expand_scores(
+            reader,
+            similarity,
+            &self.field,
+            &mut self.regex.automaton(),
+            self.max_expansions,
+        )
+    }
+}
+
+// 从下界开始按 term 顺序取出落在范围内的 term, 越过上界即停止
+fn range_terms(
+    reader: &dyn FilterReader,
+    field: &FieldID,
+    lower: &Bound<Value>,
+    upper: &Bound<Value>,
+    limit: usize,
+) -> GyResult<Vec<Term>> {
+    let field_type = reader.field_type(field)?;
+    let mut automaton = RangeAutomaton::new(&field_type, lower, upper)?;
+    reader.expand_terms(field, &mut automaton, limit)
+}
+
+// 范围查询: 数值, 日期按保序编码比较, 字符串按字节序比较
+// 命中的文档得分都为 1
+pub struct RangeQuery {
+    field: FieldID,
+    lower: Bound<Value>,
+    upper: Bound<Value>,
+    max_expansions: usize,
+}
+
+impl RangeQuery {
+    pub fn new(field: FieldID, lower: Bound<Value>, upper: Bound<Value>) -> RangeQuery {
+        RangeQuery {
+            field: field,
+            lower: lower,
+            upper: upper,
+            max_expansions: usize::MAX,
+        }
+    }
+
+    // 默认不限制范围内的 term 数
+    pub fn with_max_expansions(mut self, max_expansions: usize) -> RangeQuery {
+        self.max_expansions = max_expansions;
+        self
+    }
+}
+
+impl Query for RangeQuery {
+    fn scores(
+        &self,
+        reader: &dyn FilterReader,
+        _similarity: &dyn Similarity,
+    ) -> GyResult<Vec<ScoreDoc>> {
+        let terms = range_terms(
+            reader,
+            &self.field,
+            &self.lower,
+            &self.upper,
+            self.max_expansions,
+        )?;
+        let mut docs: Vec<DocID> = Vec::new();
+        for term in terms.iter() {
+            reader.term_postings(term, &mut |p| docs.push(p.doc_id()))?;
+        }
+        docs.sort();
+        docs.dedup();
+        Ok(docs.into_iter().map(|d| ScoreDoc::new(d, 1.0)).collect())
+    }
+}
+
+// 模糊查询允许的最大编辑距离
+pub const MAX_FUZZY_DISTANCE: u32 = 2;
+
+// 模糊查询: 命中与 text 编辑距离不超过 distance 的 term
+// 每个 term 的得分乘以 1 / (1 + 编辑距离), 拼写完全正确的 term 排在前面
+pub struct FuzzyQuery {
+    field: FieldID,
+    text: String,
+    distance: u32,
+    transposition: bool,
+    prefix_len: usize,
+    max_expansions: usize,
+}
+
+impl FuzzyQuery {
+    pub fn new(field: FieldID, text: &str, distance: u32) -> GyResult<FuzzyQuery> {
+        if distance == 0 || distance > MAX_FUZZY_DISTANCE {
+            return Err(GyError::ErrInvalidFuzzyDistance(distance));
+        }
+        Ok(FuzzyQuery {
+            field: field,
+            text: text.to_string(),
+            distance: distance,
+            transposition: false,
+            prefix_len: 0,
+            max_expansions: MAX_EXPANSIONS,
+        })
+    }
+
+    // 相邻字符交换算一次编辑
+    pub fn with_transposition(mut self, transposition: bool) -> FuzzyQuery {
+        self.transposition = transposition;
+        self
+    }
+
+    // 前 prefix_len 个字符必须相同
+    pub fn with_prefix_len(mut self, prefix_len: usize) -> FuzzyQuery {
+        self.prefix_len = prefix_len;
+        self
+    }
+
+    pub fn with_max_expansions(mut self, max_expansions: usize) -> FuzzyQuery {
+        self.max_expansions = max_expansions;
+        self
+    }
+}
+
+impl Query for FuzzyQuery {
+    fn scores(
+        &self,
+        reader: &dyn FilterReader,
+        similarity: &dyn Similarity,
+    ) -> GyResult<Vec<ScoreDoc>> {
+        let mut automaton = LevenshteinAutomaton::new(
+            &self.text,
+            self.distance,
+            self.transposition,
+            self.prefix_len,
+        );
+        let terms = reader.expand_terms(&self.field, &mut automaton, self.max_expansions)?;
+        let mut docs: Vec<(ScoreDoc, usize)> = Vec::new();
+        for term in terms {
+            let d = automaton::edit_distance(&self.text, term.text(), self.transposition);
+            let boost = 1.0 / (1 + d) as f32;
+            let scores: Vec<ScoreDoc> = TermQuery::new(term)
+                .scores(reader, similarity)?
+                .iter()
+                .map(|x| ScoreDoc::new(x.doc_id(), x.score() * boost))
+                .collect();
+            docs = union(&docs, &scores);
+        }
+        Ok(docs.into_iter().map(|(d, _)| d).collect())
+    }
+}
+
+// 以下合并的都是按文档 id 升序的列表
+
+fn intersect(a: &[ScoreDoc], b: &[ScoreDoc]) -> Vec<ScoreDoc> {
+    let (mut i, mut j) = (0, 0);
+    let mut results = Vec::new();
+    while i < a.len() && j < b.len() {
+        match a[i].doc_id().cmp(&b[j].doc_id()) {
+            Ordering::Less => i += 1,
+            Ordering::Greater => j += 1,
+            Ordering::Equal => {
+                results.push(ScoreDoc::new(a[i].doc_id(), a[i].score() + b[j].score()));
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    results
+}
+
+fn union(a: &[(ScoreDoc, usize)], b: &[ScoreDoc]) -> Vec<(ScoreDoc, usize)> {
+    let (mut i, mut j) = (0, 0);
+    let mut results = Vec::with_capacity(a.len() + b.len());
+    while i < a.len() || j < b.len() {
+        if j >= b.len() || (i < a.len() && a[i].0.doc_id() < b[j].doc_id()) {
+            results.push(a[i]);
+            i += 1;
+        } else if i >= a.len() || b[j].doc_id() < a[i].0.doc_id() {
+            results.push((b[j], 1));
+            j += 1;
+        } else {
+            let d = ScoreDoc::new(a[i].0.doc_id(), a[i].0.score() + b[j].score());
+            results.push((d, a[i].1 + 1));
+            i += 1;
+            j += 1;
+        }
+    }
+    results
+}
+
+fn difference(a: &[ScoreDoc], b: &[ScoreDoc]) -> Vec<ScoreDoc> {
+    let mut j = 0;
+    a.iter()
+        .filter(|d| {
+            while j < b.len() && b[j].doc_id() < d.doc_id() {
+                j += 1;
+            }
+            !(j < b.len() && b[j].doc_id() == d.doc_id())
+        })
+        .cloned()
+        .collect()
+}
+
+#[derive(Clone)]
 pub struct Term(pub(crate) Vec<u8>);
 
 impl Term {
@@ -33,6 +591,20 @@ impl Term {
         term
     }
 
+    pub fn from_field_bytes(field: FieldID, val: &[u8]) -> Term {
+        let mut term = Term(Vec::with_capacity(4 + val.len()));
+        term.set_field(field);
+        term.set_bytes(val);
+        term
+    }
+
+    pub fn from_field_i64(field: FieldID, val: i64) -> Term {
+        let mut term = Term(vec![0u8; INT_TERM_LEN]);
+        term.set_field(field);
+        term.set_i64(val);
+        term
+    }
+
     pub fn from_field_u64(field: FieldID, val: u64) -> Term {
         let mut term = Term(vec![0u8; INT_TERM_LEN]);
         term.set_field(field);
@@ -40,6 +612,17 @@ impl Term {
         term
     }
 
+    pub fn from_field_f64(field: FieldID, val: f64) -> Term {
+        let mut term = Term(vec![0u8; INT_TERM_LEN]);
+        term.set_field(field);
+        term.set_u64(common::f64_to_u64(val));
+        term
+    }
+
+    pub fn from_field_date(field: FieldID, val: DateTime) -> Term {
+        Term::from_field_i64(field, val.timestamp_nanos())
+    }
+
     pub fn set_field(&mut self, field: FieldID) {
         if self.0.len() < 4 {
             self.0.resize(4, 0u8);
@@ -51,13 +634,18 @@ impl Term {
         BigEndian::write_u64(&mut self.0[4..], val);
     }
 
+    // 有符号数按 Value::to_vec 的保序编码写入
+    pub fn set_i64(&mut self, val: i64) {
+        BigEndian::write_u64(&mut self.0[4..], common::i64_to_u64(val));
+    }
+
     pub fn set_bytes(&mut self, bytes: &[u8]) {
         self.0.resize(4, 0u8);
         self.0.extend(bytes);
     }
 
     pub fn set_i32(&mut self, val: i32) {
-        BigEndian::write_i32(&mut self.0[4..], val);
+        BigEndian::write_u32(&mut self.0[4..], common::i32_to_u32(val));
     }
 
     pub fn field_id(&self) -> FieldID {
@@ -76,7 +664,96 @@ impl Term {
 }
 
 pub trait Query {
-    fn query(&self, searcher: &IndexReader) -> PostingReader;
+    // 命中的文档和得分, 按文档 id 升序
+    fn scores(
+        &self,
+        reader: &dyn FilterReader,
+        similarity: &dyn Similarity,
+    ) -> GyResult<Vec<ScoreDoc>>;
+}
+
+// 向量查询的过滤条件
+// 先求出满足条件的文档位图, 再在 ANN 遍历时只接受位图中的文档
+pub enum Filter {
+    Term(Term),
+    // 范围的类型由边界值决定, 按同样的类型解码 term
+    Range {
+        field: FieldID,
+        lower: Bound<Value>,
+        upper: Bound<Value>,
+    },
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+}
+
+// 过滤条件求值和打分需要的倒排表访问, 内存索引和磁盘段分别实现
+pub trait FilterReader {
+    // 文档数, 决定位图大小
+    fn doc_size(&self) -> usize;
+    // term 命中的文档写入位图, term 不存在时不写入
+    fn term_docs(&self, term: &Term, docs: &mut BitMap) -> GyResult<()>;
+    // term 的倒排表逐项交给 f, term 不存在时不调用
+    fn term_postings(&self, term: &Term, f: &mut dyn FnMut(DocFreq)) -> GyResult<()>;
+    // 同 term_postings, 同时给出 term 在文档中的位置
+    fn term_positions(&self, term: &Term, f: &mut dyn FnMut(DocFreq, Vec<u32>)) -> GyResult<()>;
+    // 域中被自动机接受的 term, 按字典序, 超过 limit 个时返回 ErrTooManyTerms
+    fn expand_terms(
+        &self,
+        field: &FieldID,
+        automaton: &mut dyn Automaton,
+        limit: usize,
+    ) -> GyResult<Vec<Term>>;
+    // 域的集合统计
+    fn field_stats(&self, field: &FieldID) -> GyResult<FieldStats>;
+    // 文档在域中的 token 数, 不含该域时为 0
+    fn doc_len(&self, field: &FieldID, doc_id: DocID) -> GyResult<u32>;
+    // 域的类型, 范围查询用来检查边界值的类型
+    fn field_type(&self, field: &FieldID) -> GyResult<FieldType>;
+}
+
+impl Filter {
+    pub fn range(field: FieldID, lower: Bound<Value>, upper: Bound<Value>) -> Filter {
+        Filter::Range {
+            field: field,
+            lower: lower,
+            upper: upper,
+        }
+    }
+
+    pub(crate) fn allow_list<R: FilterReader>(&self, reader: &R) -> GyResult<BitMap> {
+        match self {
+            Filter::Term(term) => {
+                let mut docs = BitMap::new(reader.doc_size());
+                reader.term_docs(term, &mut docs)?;
+                Ok(docs)
+            }
+            Filter::Range {
+                field,
+                lower,
+                upper,
+            } => {
+                let mut docs = BitMap::new(reader.doc_size());
+                for term in range_terms(reader, field, lower, upper, usize::MAX)? {
+                    reader.term_docs(&term, &mut docs)?;
+                }
+                Ok(docs)
+            }
+            Filter::And(filters) => {
+                let mut docs = BitMap::full(reader.doc_size());
+                for f in filters.iter() {
+                    docs.intersect(&f.allow_list(reader)?);
+                }
+                Ok(docs)
+            }
+            Filter::Or(filters) => {
+                let mut docs = BitMap::new(reader.doc_size());
+                for f in filters.iter() {
+                    docs.union(&f.allow_list(reader)?);
+                }
+                Ok(docs)
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -84,4 +761,54 @@ mod tests {
     use super::*;
 
     fn test_term() {}
+
+    // term 是否在范围内, 与 Filter::Range 和 RangeQuery 的判断相同
+    fn in_range(
+        field_type: &FieldType,
+        term: &[u8],
+        lower: &Bound<Value>,
+        upper: &Bound<Value>,
+    ) -> bool {
+        let mut a = RangeAutomaton::new(field_type, lower, upper).unwrap();
+        term.iter().all(|b| a.push(*b)) && a.is_match()
+    }
+
+    #[test]
+    fn test_in_range() {
+        let t = Value::I64(-5).to_vec().unwrap();
+        assert!(in_range(
+            &FieldType::I64,
+            &t,
+            &Bound::Included(Value::I64(-5)),
+            &Bound::Unbounded
+        ));
+        assert!(!in_range(
+            &FieldType::I64,
+            &t,
+            &Bound::Excluded(Value::I64(-5)),
+            &Bound::Unbounded
+        ));
+        assert!(in_range(
+            &FieldType::I64,
+            &t,
+            &Bound::Unbounded,
+            &Bound::Excluded(Value::I64(100))
+        ));
+        // 类型与域不匹配时报错
+        assert!(matches!(
+            RangeAutomaton::new(
+                &FieldType::I64,
+                &Bound::Unbounded,
+                &Bound::Excluded(Value::I32(100))
+            ),
+            Err(GyError::ErrInvalidValueType)
+        ));
+        let t = Value::Str("red").to_vec().unwrap();
+        assert!(in_range(
+            &FieldType::Str,
+            &t,
+            &Bound::Included(Value::Str("red")),
+            &Bound::Included(Value::Str("red"))
+        ));
+    }
 }
@@ -1,12 +1,18 @@
 // 每一行数据
-use super::ann::{AnnType, Metric};
+use super::ann::{
+    AnnType, HnswConfig, IvfConfig, Metric, MetricType, PqConfig, Quantizer, SparseVector, SqRange,
+    VectorElems,
+};
 use super::disk::{GyRead, GyWrite};
+use super::tokenize::AnalyzerConfig;
+use super::util::common;
 use super::util::error::{GyError, GyResult};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use chrono::{TimeZone, Utc};
 use galois::similarity::Similarity;
 use galois::{GGmlType, TensorType};
 use galois::{Shape, Tensor};
+use half::{bf16, f16};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::io::Read;
@@ -19,6 +25,21 @@ pub trait VectorSerialize: Sized {
     fn vector_serialize<W: Write + GyWrite>(&self, writer: &mut W) -> GyResult<()>;
     /// Deserialize
     fn vector_deserialize<R: Read + GyRead>(reader: &mut R, entry: &TensorEntry) -> GyResult<Self>;
+    /// Deserialize 每个向量域一个 entry, 按 schema 中的声明顺序
+    fn vector_deserialize_fields<R: Read + GyRead>(
+        reader: &mut R,
+        entries: &[TensorEntry],
+    ) -> GyResult<Self> {
+        Self::vector_deserialize(reader, &entries[0])
+    }
+    /// Serialize 与 vector_deserialize_fields 对应
+    fn vector_serialize_fields<W: Write + GyWrite>(
+        &self,
+        writer: &mut W,
+        _entries: &[TensorEntry],
+    ) -> GyResult<()> {
+        self.vector_serialize(writer)
+    }
 }
 
 pub trait ValueSized {
@@ -28,6 +49,10 @@ pub trait ValueSized {
 pub trait VectorOps {
     fn from_vec<T: TensorType>(v: Vec<T>) -> Self;
     fn from_arr<T: TensorType, const N: usize>(v: [T; N]) -> Self;
+    // 能否保存该元素类型的向量, 创建引擎时检查每个向量域
+    fn supports(t: &VectorType) -> bool;
+    // 向量的元素类型, 写入前与向量域的类型比较
+    fn vector_type(&self) -> Option<VectorType>;
 }
 
 pub trait BinarySerialize: Sized {
@@ -81,9 +106,59 @@ impl BinarySerialize for DocFreq {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+// 稀疏向量域的倒排表项, 用维度上的权重代替词频
+#[derive(Debug)]
+pub struct DocWeight(pub(crate) DocID, pub(crate) f32);
+
+impl DocWeight {
+    pub fn doc_id(&self) -> DocID {
+        self.0
+    }
+
+    pub fn weight(&self) -> f32 {
+        self.1
+    }
+}
+
+impl BinarySerialize for DocWeight {
+    fn binary_serialize<W: Write>(&self, writer: &mut W) -> GyResult<()> {
+        VUInt(self.doc_id()).binary_serialize(writer)?;
+        self.weight().binary_serialize(writer)?;
+        Ok(())
+    }
+    fn binary_deserialize<R: Read>(reader: &mut R) -> GyResult<Self> {
+        let doc_id = VUInt::binary_deserialize(reader)?.0.val();
+        let weight = f32::binary_deserialize(reader)?;
+        Ok(DocWeight(doc_id, weight))
+    }
+}
+
+// 文档中 term 出现的位置紧跟在 DocFreq 之后, 共 freq 个, 按与前一个位置的差值编码
+pub(crate) fn write_positions<W: Write>(writer: &mut W, positions: &[u32]) -> GyResult<()> {
+    let mut last = 0;
+    for p in positions.iter() {
+        VUInt((p - last) as u64).binary_serialize(writer)?;
+        last = *p;
+    }
+    Ok(())
+}
+
+pub(crate) fn read_positions<R: Read>(reader: &mut R, freq: u32) -> GyResult<Vec<u32>> {
+    let mut positions = Vec::with_capacity(freq as usize);
+    let mut last = 0;
+    for _ in 0..freq {
+        last += VUInt::binary_deserialize(reader)?.0.val() as u32;
+        positions.push(last);
+    }
+    Ok(positions)
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Schema {
     pub vector_field: VectorEntry,
+    // 其余的具名向量域, 排在 vector_field 之后
+    #[serde(default)]
+    pub vector_fields: Vec<VectorEntry>,
     pub fields: Vec<FieldEntry>,
     pub fields_map: HashMap<String, FieldID>,
 }
@@ -96,15 +171,41 @@ impl Schema {
     pub fn with_vector(vector_field: VectorEntry) -> Schema {
         Self {
             vector_field: vector_field,
+            vector_fields: Vec::new(),
             fields: Vec::new(),
             fields_map: HashMap::new(),
         }
     }
 
+    // 添加一个向量域, 文档需要按添加顺序为每个向量域提供向量
+    pub fn add_vector_field(&mut self, vector_field: VectorEntry) -> GyResult<()> {
+        if self.get_vector_field(vector_field.name()).is_some() {
+            return Err(GyError::ErrDuplicateVectorField(
+                vector_field.name().to_string(),
+            ));
+        }
+        self.vector_fields.push(vector_field);
+        Ok(())
+    }
+
+    pub fn vector_entries(&self) -> impl Iterator<Item = &VectorEntry> {
+        std::iter::once(&self.vector_field).chain(self.vector_fields.iter())
+    }
+
+    pub fn get_vector_field(&self, field_name: &str) -> Option<usize> {
+        self.vector_entries().position(|e| e.name() == field_name)
+    }
+
     pub fn tensor_entry(&self) -> &TensorEntry {
         self.vector_field.tensor_entry()
     }
 
+    pub fn tensor_entries(&self) -> Vec<TensorEntry> {
+        self.vector_entries()
+            .map(|e| e.tensor_entry().clone())
+            .collect()
+    }
+
     pub fn get_field(&self, field_name: &str) -> Option<FieldID> {
         self.fields_map.get(field_name).cloned()
     }
@@ -119,35 +220,105 @@ impl Schema {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
 pub enum VectorType {
     #[default]
     F32,
     F16,
+    BF16,
+    I8,
+    U8,
     I32,
+    // 按位打包的二值向量, dims 为位数
+    Binary,
 }
 
 impl VectorType {
-    fn to_ggml_type(&self) -> GGmlType {
+    // 二值向量按位打包, 没有对应的 ggml 类型, 需要用 BinaryVector 保存
+    fn to_ggml_type(&self) -> GyResult<GGmlType> {
         match self {
-            VectorType::F32 => GGmlType::F32,
-            _ => todo!(),
+            VectorType::F32 => Ok(GGmlType::F32),
+            VectorType::F16 => Ok(GGmlType::F16),
+            VectorType::BF16 => Ok(GGmlType::BF16),
+            VectorType::I8 => Ok(GGmlType::I8),
+            VectorType::U8 => Ok(GGmlType::U8),
+            VectorType::I32 => Ok(GGmlType::I32),
+            VectorType::Binary => Err(GyError::ErrUnsupportedVectorType(format!("{:?}", self))),
         }
     }
 
-    fn nbytes(&self) -> usize {
+    fn from_ggml_type(t: &GGmlType) -> GyResult<VectorType> {
+        match t {
+            GGmlType::F32 => Ok(VectorType::F32),
+            GGmlType::F16 => Ok(VectorType::F16),
+            GGmlType::BF16 => Ok(VectorType::BF16),
+            GGmlType::I8 => Ok(VectorType::I8),
+            GGmlType::U8 => Ok(VectorType::U8),
+            GGmlType::I32 => Ok(VectorType::I32),
+            t => Err(GyError::ErrUnsupportedVectorType(format!("{:?}", t))),
+        }
+    }
+
+    // n 个元素占用的字节数
+    fn nbytes(&self, n: usize) -> usize {
+        match self {
+            VectorType::F32 => n * std::mem::size_of::<f32>(),
+            VectorType::F16 | VectorType::BF16 => n * 2,
+            VectorType::I8 | VectorType::U8 => n,
+            VectorType::I32 => n * std::mem::size_of::<i32>(),
+            VectorType::Binary => (n + 7) / 8,
+        }
+    }
+
+    // 字节中保存的元素个数
+    fn elem_count(&self, bytes: &[u8]) -> usize {
+        match self {
+            VectorType::Binary => bytes.len() * 8,
+            t => bytes.len() / t.nbytes(1),
+        }
+    }
+
+    // 按本类型存储的小端字节读出第 i 个元素, 展开成 f32
+    #[inline]
+    pub(crate) fn elem(&self, bytes: &[u8], i: usize) -> f32 {
         match self {
-            VectorType::F32 => std::mem::size_of::<f32>(),
-            _ => todo!(),
+            VectorType::F32 => f32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap()),
+            VectorType::F16 => f16::from_le_bytes([bytes[i * 2], bytes[i * 2 + 1]]).to_f32(),
+            VectorType::BF16 => bf16::from_le_bytes([bytes[i * 2], bytes[i * 2 + 1]]).to_f32(),
+            VectorType::I8 => bytes[i] as i8 as f32,
+            VectorType::U8 => bytes[i] as f32,
+            VectorType::I32 => {
+                i32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap()) as f32
+            }
+            VectorType::Binary => ((bytes[i / 8] >> (i % 8)) & 1) as f32,
         }
     }
+
+    // 按本类型存储的小端字节展开成 f32
+    pub(crate) fn widen(&self, bytes: &[u8]) -> Vec<f32> {
+        (0..self.elem_count(bytes))
+            .map(|i| self.elem(bytes, i))
+            .collect()
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
 pub struct VectorEntry {
     name: String,
     index_type: AnnType,
     tensor_entry: TensorEntry,
+    #[serde(default)]
+    quantizer: Quantizer,
+    #[serde(default)]
+    sq_range: SqRange,
+    #[serde(default)]
+    metric: MetricType,
+    #[serde(default)]
+    hnsw: HnswConfig,
+    #[serde(default)]
+    ivf: IvfConfig,
+    #[serde(default)]
+    pq: PqConfig,
 }
 
 impl VectorEntry {
@@ -156,15 +327,87 @@ impl VectorEntry {
             name: field_name.to_string(),
             index_type: index_type,
             tensor_entry: tensor_entry,
+            quantizer: Quantizer::None,
+            sq_range: SqRange::Train,
+            metric: MetricType::L2,
+            hnsw: HnswConfig::default(),
+            ivf: IvfConfig::default(),
+            pq: PqConfig::default(),
         }
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn with_hnsw(mut self, hnsw: HnswConfig) -> VectorEntry {
+        self.hnsw = hnsw;
+        self
+    }
+
+    pub fn hnsw_config(&self) -> &HnswConfig {
+        &self.hnsw
+    }
+
+    // IvfFlat 和 IvfPQ 的聚类中心个数和默认的 nprobe
+    pub fn with_ivf(mut self, ivf: IvfConfig) -> VectorEntry {
+        self.ivf = ivf;
+        self
+    }
+
+    pub fn ivf_config(&self) -> &IvfConfig {
+        &self.ivf
+    }
+
+    // IvfPQ 的子空间个数和重排倍数
+    pub fn with_pq(mut self, pq: PqConfig) -> VectorEntry {
+        self.pq = pq;
+        self
+    }
+
+    pub fn pq_config(&self) -> &PqConfig {
+        &self.pq
+    }
+
+    pub fn with_metric(mut self, metric: MetricType) -> VectorEntry {
+        self.metric = metric;
+        self
+    }
+
+    pub fn metric(&self) -> MetricType {
+        self.metric
+    }
+
+    // HNSW 和 Flat 可以用 SQ8 量化存储向量, 内存占用约为 f32 的 1/4
+    pub fn with_quantizer(mut self, quantizer: Quantizer) -> VectorEntry {
+        self.quantizer = quantizer;
+        self
+    }
+
+    pub(crate) fn quantizer(&self) -> Quantizer {
+        self.quantizer
+    }
+
+    // SQ8 每一维的取值范围, 默认用最先写入的向量训练, 超出范围的值截断
+    pub fn with_sq_range(mut self, range: SqRange) -> VectorEntry {
+        self.sq_range = range;
+        self
+    }
+
+    pub(crate) fn sq_range(&self) -> SqRange {
+        self.sq_range
+    }
+
     pub(crate) fn tensor_entry(&self) -> &TensorEntry {
         &self.tensor_entry
     }
+
+    pub(crate) fn index_type(&self) -> AnnType {
+        self.index_type
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct TensorEntry {
     n_dims: usize,
     dims: [usize; 4],
@@ -214,7 +457,42 @@ impl TensorEntry {
     }
 
     pub fn nbytes(&self) -> usize {
-        self.elem_count() * self.vector_type().nbytes()
+        match self.vector_type() {
+            VectorType::Binary => (self.elem_count() + 7) / 8,
+            t => t.nbytes(self.elem_count()),
+        }
+    }
+
+    // 多向量域, 每个文档保存 dim 维向量的若干行, 行数不固定
+    pub fn multi(dim: usize, vector_type: VectorType) -> TensorEntry {
+        TensorEntry::new(2, [dim, 0], vector_type)
+    }
+
+    pub fn is_multi(&self) -> bool {
+        self.n_dims == 2 && self.dims[1] == 0
+    }
+
+    // 行数确定后的 entry, 用于读取一个文档的多向量
+    pub(crate) fn with_rows(&self, rows: usize) -> TensorEntry {
+        let mut entry = self.clone();
+        entry.dims[1] = rows;
+        entry
+    }
+
+    pub(crate) fn row_nbytes(&self) -> usize {
+        TensorEntry::new(1, [self.dims[0]], self.vector_type).nbytes()
+    }
+
+    // 索引中保存的单个向量, 由 VectorElems::from_elems 拆分得到, 紧凑类型展开为 f32
+    pub(crate) fn row_entry(&self) -> TensorEntry {
+        if !self.is_multi() {
+            return self.clone();
+        }
+        let vector_type = match self.vector_type {
+            VectorType::Binary => VectorType::Binary,
+            _ => VectorType::F32,
+        };
+        TensorEntry::new(1, [self.dims[0]], vector_type)
     }
 }
 
@@ -223,6 +501,9 @@ pub struct FieldEntry {
     name: String,
     field_id: FieldID,
     field_type: FieldType,
+    // 文本域的分词配置, 没有时整个值作为一个 term
+    #[serde(default)]
+    analyzer: Option<AnalyzerConfig>,
 }
 
 impl FieldEntry {
@@ -231,6 +512,7 @@ impl FieldEntry {
             name: field_name.to_string(),
             field_id: FieldID::default(),
             field_type: FieldType::Str,
+            analyzer: None,
         }
     }
 
@@ -239,6 +521,7 @@ impl FieldEntry {
             name: field_name.to_string(),
             field_id: FieldID::default(),
             field_type: FieldType::I64,
+            analyzer: None,
         }
     }
 
@@ -247,6 +530,7 @@ impl FieldEntry {
             name: field_name.to_string(),
             field_id: FieldID::default(),
             field_type: FieldType::I32,
+            analyzer: None,
         }
     }
 
@@ -255,6 +539,35 @@ impl FieldEntry {
             name: field_name.to_string(),
             field_id: FieldID::default(),
             field_type: FieldType::U64,
+            analyzer: None,
+        }
+    }
+
+    pub fn f64(field_name: &str) -> FieldEntry {
+        FieldEntry {
+            name: field_name.to_string(),
+            field_id: FieldID::default(),
+            field_type: FieldType::F64,
+            analyzer: None,
+        }
+    }
+
+    pub fn date(field_name: &str) -> FieldEntry {
+        FieldEntry {
+            name: field_name.to_string(),
+            field_id: FieldID::default(),
+            field_type: FieldType::DATE,
+            analyzer: None,
+        }
+    }
+
+    // 稀疏向量域, 每个非零维度作为一个 term 建倒排表
+    pub fn sparse(field_name: &str) -> FieldEntry {
+        FieldEntry {
+            name: field_name.to_string(),
+            field_id: FieldID::default(),
+            field_type: FieldType::Sparse,
+            analyzer: None,
         }
     }
 
@@ -269,6 +582,16 @@ impl FieldEntry {
     pub fn get_field_id(&self) -> &FieldID {
         &self.field_id
     }
+
+    // 只对 Str 域生效
+    pub fn with_analyzer(mut self, analyzer: AnalyzerConfig) -> FieldEntry {
+        self.analyzer = Some(analyzer);
+        self
+    }
+
+    pub fn analyzer(&self) -> Option<&AnalyzerConfig> {
+        self.analyzer.as_ref()
+    }
 }
 
 pub enum SimilarityType {
@@ -301,6 +624,26 @@ pub enum FieldType {
     F32,
     DATE,
     Bytes,
+    Sparse,
+}
+
+impl FieldType {
+    // 值的类型与域的类型是否一致
+    pub fn accepts(&self, v: &Value) -> bool {
+        match (self, v) {
+            (FieldType::Str, Value::Str(_) | Value::String(_)) => true,
+            (FieldType::I64, Value::I64(_)) => true,
+            (FieldType::I32, Value::I32(_)) => true,
+            (FieldType::U64, Value::U64(_)) => true,
+            (FieldType::U32, Value::U32(_)) => true,
+            (FieldType::F64, Value::F64(_)) => true,
+            (FieldType::F32, Value::F32(_)) => true,
+            (FieldType::DATE, Value::Date(_)) => true,
+            (FieldType::Bytes, Value::Bytes(_)) => true,
+            (FieldType::Sparse, Value::Sparse(_)) => true,
+            _ => false,
+        }
+    }
 }
 
 impl VectorSerialize for Tensor {
@@ -313,16 +656,12 @@ impl VectorSerialize for Tensor {
 
     fn vector_deserialize<R: Read + GyRead>(reader: &mut R, entry: &TensorEntry) -> GyResult<Self> {
         //  println!("nbytes:{}", entry.nbytes());
+        let dtype = entry.vector_type().to_ggml_type()?;
         let v = reader.read_bytes(entry.nbytes())?; //Vec::<u8>::binary_deserialize(reader)?;
                                                     //  println!("v:{:?}", v);
                                                     //  let v = Vec::<u8>::binary_deserialize(reader)?;
         let t = unsafe {
-            Tensor::from_bytes(
-                v,
-                entry.n_dims(),
-                Shape::from_slice(entry.dims()),
-                entry.vector_type().to_ggml_type(),
-            )
+            Tensor::from_bytes(v, entry.n_dims(), Shape::from_slice(entry.dims()), dtype)
         };
 
         //  println!("t:{:?}", unsafe { t.as_slice::<f32>() });
@@ -330,9 +669,41 @@ impl VectorSerialize for Tensor {
     }
 }
 
+// f16/bf16/i8/u8 等紧凑类型按原始大小存储, 计算距离时逐个元素展开成 f32
+// 不支持的 ggml 类型无法比较, 距离为 f32::MAX
 impl Metric for Tensor {
-    fn distance(&self, b: &Self) -> f32 {
-        self.euclidean(b)
+    fn distance(&self, b: &Self, metric: MetricType) -> f32 {
+        let ta = VectorType::from_ggml_type(&self.dtype());
+        let tb = VectorType::from_ggml_type(&b.dtype());
+        match (ta, tb) {
+            (Ok(VectorType::F32), Ok(VectorType::F32)) => match metric {
+                MetricType::L2 => self.euclidean(b),
+                _ => metric.distance(unsafe { self.as_slice::<f32>() }, unsafe {
+                    b.as_slice::<f32>()
+                }),
+            },
+            (Ok(ta), Ok(tb)) => {
+                let (x, y) = (self.as_bytes(), b.as_bytes());
+                let n = ta.elem_count(x).min(tb.elem_count(y));
+                metric.distance_pairs((0..n).map(|i| (ta.elem(x, i), tb.elem(y, i))))
+            }
+            _ => f32::MAX,
+        }
+    }
+}
+
+impl VectorElems for Tensor {
+    // 不支持的 ggml 类型没有可用的分量
+    fn to_elems(&self) -> Vec<f32> {
+        match VectorType::from_ggml_type(&self.dtype()) {
+            Ok(VectorType::F32) => self.to_vec::<f32>(),
+            Ok(t) => t.widen(self.as_bytes()),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn from_elems(v: Vec<f32>) -> Self {
+        Tensor::arr(v)
     }
 }
 
@@ -350,46 +721,100 @@ impl VectorOps for Tensor {
     fn from_vec<T: TensorType>(v: Vec<T>) -> Self {
         Tensor::arr(v)
     }
+
+    // 二值向量没有对应的 ggml 类型
+    fn supports(t: &VectorType) -> bool {
+        t.to_ggml_type().is_ok()
+    }
+
+    fn vector_type(&self) -> Option<VectorType> {
+        VectorType::from_ggml_type(&self.dtype()).ok()
+    }
 }
 
 pub type Vector = VectorBase<Tensor>;
 
 pub struct VectorBase<V: VectorSerialize + ValueSized + VectorOps> {
     pub(crate) v: V,
+    // Schema::vector_fields 对应的向量, 与声明顺序一致
+    pub(crate) vectors: Vec<V>,
     pub(crate) payload: Document,
 }
 
 impl<V: VectorSerialize + ValueSized + VectorOps> ValueSized for VectorBase<V> {
     fn bytes_size(&self) -> usize {
-        self.v.bytes_size() + self.payload.bytes_size()
+        self.size()
     }
 }
 
 impl<V: VectorSerialize + ValueSized + VectorOps> VectorSerialize for VectorBase<V> {
     fn vector_deserialize<R: Read + GyRead>(reader: &mut R, entry: &TensorEntry) -> GyResult<Self> {
+        Self::vector_deserialize_fields(reader, std::slice::from_ref(entry))
+    }
+
+    fn vector_deserialize_fields<R: Read + GyRead>(
+        reader: &mut R,
+        entries: &[TensorEntry],
+    ) -> GyResult<Self> {
         let size = usize::binary_deserialize(reader)?;
         if size == 0 {
             return Err(GyError::WalEOF);
         }
-        let v = V::vector_deserialize(reader, entry)?;
+        let v = Self::read_field(reader, &entries[0])?;
+        let mut vectors = Vec::with_capacity(entries.len() - 1);
+        for entry in entries[1..].iter() {
+            vectors.push(Self::read_field(reader, entry)?);
+        }
         let payload = Document::binary_deserialize(reader)?;
         Ok(Self {
             v: v,
+            vectors: vectors,
             payload: payload,
         })
     }
+
+    // 多向量域在向量前写入行数
+    fn vector_serialize_fields<W: Write + GyWrite>(
+        &self,
+        writer: &mut W,
+        entries: &[TensorEntry],
+    ) -> GyResult<()> {
+        self.fields_size(entries).binary_serialize(writer)?;
+        for (i, entry) in entries.iter().enumerate() {
+            let v = self.vector_at(i).unwrap();
+            if entry.is_multi() {
+                (v.bytes_size() / entry.row_nbytes()).binary_serialize(writer)?;
+            }
+            v.vector_serialize(writer)?;
+        }
+        self.payload.binary_serialize(writer)?;
+        Ok(())
+    }
+
     fn vector_serialize<W: Write + GyWrite>(&self, writer: &mut W) -> GyResult<()> {
         self.size().binary_serialize(writer)?;
         self.v.vector_serialize(writer)?;
+        for v in self.vectors.iter() {
+            v.vector_serialize(writer)?;
+        }
         self.payload.binary_serialize(writer)?;
         Ok(())
     }
 }
 
 impl<V: VectorSerialize + ValueSized + VectorOps> VectorBase<V> {
+    fn read_field<R: Read + GyRead>(reader: &mut R, entry: &TensorEntry) -> GyResult<V> {
+        if entry.is_multi() {
+            let rows = usize::binary_deserialize(reader)?;
+            return V::vector_deserialize(reader, &entry.with_rows(rows));
+        }
+        V::vector_deserialize(reader, entry)
+    }
+
     pub fn new(v: V, payload: Document) -> VectorBase<V> {
         VectorBase {
             v: v,
+            vectors: Vec::new(),
             payload: payload,
         }
     }
@@ -402,9 +827,27 @@ impl<V: VectorSerialize + ValueSized + VectorOps> VectorBase<V> {
         &self.v
     }
 
+    // 按向量域的下标取向量, 0 为 Schema::vector_field
+    pub fn vector_at(&self, i: usize) -> Option<&V> {
+        match i {
+            0 => Some(&self.v),
+            _ => self.vectors.get(i - 1),
+        }
+    }
+
+    pub fn vector_count(&self) -> usize {
+        self.vectors.len() + 1
+    }
+
+    // 为下一个向量域添加向量
+    pub fn add_vector(&mut self, v: V) {
+        self.vectors.push(v);
+    }
+
     pub fn from_array<T: TensorType, const N: usize>(xs: [T; N], payload: Document) -> Self {
         VectorBase {
             v: V::from_arr(xs),
+            vectors: Vec::new(),
             payload: payload,
         }
     }
@@ -412,21 +855,37 @@ impl<V: VectorSerialize + ValueSized + VectorOps> VectorBase<V> {
     pub fn with(v: V) -> VectorBase<V> {
         Self {
             v: v,
+            vectors: Vec::new(),
             payload: Document::new(),
         }
     }
 
     pub fn size(&self) -> usize {
-        self.v.bytes_size() + self.payload.bytes_size()
+        self.v.bytes_size()
+            + self.vectors.iter().map(|v| v.bytes_size()).sum::<usize>()
+            + self.payload.bytes_size()
+    }
+
+    // 按 entries 写入时的大小, 每个多向量域额外有 4 字节的行数
+    pub(crate) fn fields_size(&self, entries: &[TensorEntry]) -> usize {
+        self.size() + entries.iter().filter(|e| e.is_multi()).count() * std::mem::size_of::<u32>()
     }
 
     pub fn into(self) -> V {
         self.v
     }
 
+    pub fn into_vector(mut self, i: usize) -> V {
+        match i {
+            0 => self.v,
+            _ => self.vectors.swap_remove(i - 1),
+        }
+    }
+
     pub fn with_fields(v: V, field_values: Vec<FieldValue>) -> VectorBase<V> {
         Self {
             v: v,
+            vectors: Vec::new(),
             payload: Document::from(field_values),
         }
     }
@@ -480,10 +939,22 @@ impl Document {
         self.add_field_value(FieldValue::new(field, Value::I64(value)));
     }
 
+    pub fn add_f64(&mut self, field: FieldID, value: f64) {
+        self.add_field_value(FieldValue::new(field, Value::F64(value)));
+    }
+
+    pub fn add_date(&mut self, field: FieldID, value: DateTime) {
+        self.add_field_value(FieldValue::new(field, Value::Date(value)));
+    }
+
     pub fn add_text(&mut self, field: FieldID, value: &str) {
         self.add_field_value(FieldValue::new(field, Value::String(value.to_string())));
     }
 
+    pub fn add_sparse(&mut self, field: FieldID, value: SparseVector) {
+        self.add_field_value(FieldValue::new(field, Value::Sparse(value)));
+    }
+
     pub fn from(field_values: Vec<FieldValue>) -> Document {
         Self {
             field_values: field_values,
@@ -580,6 +1051,7 @@ const F32_ENCODE: u8 = 5;
 const F64_ENCODE: u8 = 6;
 const DATE_ENCODE: u8 = 7;
 const BYTES_ENCODE: u8 = 8;
+const SPARSE_ENCODE: u8 = 9;
 
 #[derive(PartialEq, Debug)]
 //域 值类型
@@ -594,6 +1066,7 @@ pub enum Value {
     F32(f32),
     Date(DateTime),
     Bytes(Vec<u8>),
+    Sparse(SparseVector),
 }
 
 impl BinarySerialize for Value {
@@ -639,6 +1112,10 @@ impl BinarySerialize for Value {
                 BYTES_ENCODE.binary_serialize(writer)?;
                 b.binary_serialize(writer)?;
             }
+            Value::Sparse(v) => {
+                SPARSE_ENCODE.binary_serialize(writer)?;
+                v.binary_serialize(writer)?;
+            }
         }
         Ok(())
     }
@@ -656,6 +1133,7 @@ impl BinarySerialize for Value {
                 Utc.timestamp_nanos(i64::binary_deserialize(reader)?),
             )),
             BYTES_ENCODE => Ok(Value::Bytes(Vec::<u8>::binary_deserialize(reader)?)),
+            SPARSE_ENCODE => Ok(Value::Sparse(SparseVector::binary_deserialize(reader)?)),
             _ => Err(GyError::ErrInvalidValueType),
         }
     }
@@ -683,6 +1161,7 @@ impl Value {
                 let str_length = varintrs::vint_size!(b.len()) as usize;
                 1 + str_length + b.len()
             }
+            Value::Sparse(v) => 1 + v.size(),
             _ => 0,
         }
     }
@@ -691,43 +1170,19 @@ impl Value {
         match &self {
             Value::Str(s) => Ok((*s).as_bytes().to_vec()),
             Value::String(s) => Ok(s.as_bytes().to_vec()),
-            Value::I64(i) => {
-                let mut v = vec![0u8; 8];
-                i.binary_serialize(&mut v)?;
-                Ok(v)
-            }
-
-            Value::U64(i) => {
-                let mut v = vec![0u8; 8];
-                i.binary_serialize(&mut v)?;
-                Ok(v)
-            }
-            Value::I32(i) => {
-                let mut v = vec![0u8; 4];
-                i.binary_serialize(&mut v)?;
-                Ok(v)
-            }
-            Value::U32(u) => {
-                let mut v = vec![0u8; 4];
-                u.binary_serialize(&mut v)?;
-                Ok(v)
-            }
-            Value::F64(f) => {
-                let mut v = vec![0u8; 8];
-                f.binary_serialize(&mut v)?;
-                Ok(v)
-            }
-            Value::F32(f) => {
-                let mut v = vec![0u8; 4];
-                f.binary_serialize(&mut v)?;
-                Ok(v)
-            }
-            Value::Date(f) => {
-                let mut v = vec![0u8; 4];
-                f.timestamp_nanos().binary_serialize(&mut v)?;
-                Ok(v)
-            }
+            // 数值转成保序的大端字节, 字节序与数值大小一致, 范围查询可以按 term 顺序扫描
+            Value::I64(i) => Ok(common::i64_to_u64(*i).to_be_bytes().to_vec()),
+            Value::U64(u) => Ok(u.to_be_bytes().to_vec()),
+            Value::I32(i) => Ok(common::i32_to_u32(*i).to_be_bytes().to_vec()),
+            Value::U32(u) => Ok(u.to_be_bytes().to_vec()),
+            Value::F64(f) => Ok(common::f64_to_u64(*f).to_be_bytes().to_vec()),
+            Value::F32(f) => Ok(common::f32_to_u32(*f).to_be_bytes().to_vec()),
+            Value::Date(d) => Ok(common::i64_to_u64(d.timestamp_nanos())
+                .to_be_bytes()
+                .to_vec()),
             Value::Bytes(v) => Ok(v.clone()),
+            // 稀疏向量按维度拆成多个 term, 见 FieldCache::add_sparse
+            Value::Sparse(_) => Err(GyError::ErrInvalidValueType),
             _ => Ok(Vec::new()),
         }
     }
@@ -959,14 +1414,15 @@ mod tests {
 
     impl GyRead for Cursor<&Vec<u8>> {
         fn cursor(&self) -> &[u8] {
-            todo!()
+            &self.get_ref()[self.position() as usize..]
         }
         fn offset(&self) -> usize {
             self.position() as usize
         }
         fn read_bytes(&mut self, n: usize) -> GyResult<&[u8]> {
-            self.set_position((self.offset() + n) as u64);
-            let v = &self.get_ref()[self.offset()..self.offset() + n];
+            let start = self.offset();
+            self.set_position((start + n) as u64);
+            let v = &self.get_ref()[start..start + n];
             Ok(v)
         }
     }
@@ -1005,6 +1461,85 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_compact_vector() {
+        let f = [0.5f32, -1.0, 2.0, 0.0];
+        let q = Tensor::arr_array(f);
+        let cases = [
+            (
+                VectorType::F16,
+                Vector::from_array(f.map(f16::from_f32), Document::new()),
+                f,
+            ),
+            (
+                VectorType::BF16,
+                Vector::from_array(f.map(bf16::from_f32), Document::new()),
+                f,
+            ),
+            (
+                VectorType::I8,
+                Vector::from_array(f.map(|x| x as i8), Document::new()),
+                [0.0, -1.0, 2.0, 0.0],
+            ),
+        ];
+        for (t, v, expect) in cases.iter() {
+            let entry = TensorEntry::new(1, [4], *t);
+            assert_eq!(entry.nbytes(), t.nbytes(4));
+            assert_eq!(v.vector().bytes_size(), entry.nbytes());
+            let mut bytes: Vec<u8> = Vec::with_capacity(1024);
+            v.vector_serialize(&mut Cursor::new(&mut bytes)).unwrap();
+            let d = Vector::vector_deserialize(&mut Cursor::new(&bytes), &entry).unwrap();
+            assert_eq!(d.vector().to_elems(), expect.to_vec());
+            // 与 f32 查询向量之间的距离按展开后的值计算
+            let l1: f32 = f
+                .iter()
+                .zip(expect.iter())
+                .map(|(x, y)| (x - y).abs())
+                .sum();
+            assert_eq!(d.vector().distance(&q, MetricType::L1), l1);
+        }
+        assert_eq!(VectorType::U8.widen(&[0, 200]), vec![0.0, 200.0]);
+        assert_eq!(VectorType::I8.widen(&[0xff]), vec![-1.0]);
+        assert_eq!(
+            VectorType::Binary.widen(&[0b101]),
+            vec![1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0]
+        );
+        // 二值向量不能保存为 Tensor, 读取时返回错误而不是 panic
+        let entry = TensorEntry::new(1, [16], VectorType::Binary);
+        assert_eq!(entry.nbytes(), 2);
+        let bytes = vec![0u8; 2];
+        assert!(matches!(
+            Tensor::vector_deserialize(&mut Cursor::new(&bytes), &entry),
+            Err(GyError::ErrUnsupportedVectorType(_))
+        ));
+    }
+
+    #[test]
+    fn test_vector_fields_size() {
+        let entries = [
+            TensorEntry::new(1, [2], VectorType::F32),
+            TensorEntry::multi(2, VectorType::F32),
+        ];
+        let mut v = Vector::from_array([1.0f32, 0.0], Document::new());
+        v.add_vector(Tensor::from_vec(
+            vec![0.5f32, 0.5, 1.0, 0.0, 0.0, 1.0],
+            2,
+            Shape::from_array([2, 3]),
+        ));
+        let mut bytes: Vec<u8> = Vec::with_capacity(1024);
+        v.vector_serialize_fields(&mut Cursor::new(&mut bytes), &entries)
+            .unwrap();
+        // 头部记录的大小包含多向量域的行数
+        let size = usize::binary_deserialize(&mut bytes.as_slice()).unwrap();
+        assert_eq!(size, bytes.len() - std::mem::size_of::<u32>());
+        assert_eq!(size, v.fields_size(&entries));
+        let d = Vector::vector_deserialize_fields(&mut Cursor::new(&bytes), &entries).unwrap();
+        assert_eq!(
+            d.vector_at(1).unwrap().to_elems(),
+            vec![0.5, 0.5, 1.0, 0.0, 0.0, 1.0]
+        );
+    }
+
     #[test]
     fn test_value() {
         let mut bytes: Vec<u8> = Vec::with_capacity(1024);
@@ -1,28 +1,37 @@
-use super::PostingReader;
-
-use super::query::{Query, Term};
+use super::query::{FilterReader, Query};
 use super::schema::{DocID, Document};
+use super::similarity::{self, ScoreDoc, Similarity, BM25};
 use super::GyResult;
 use super::IndexReader;
 
-pub struct Searcher {
-    reader: IndexReader,
+// 在内存索引或磁盘段上执行查询, 默认按 BM25 打分
+pub struct Searcher<'a, R: FilterReader> {
+    reader: &'a R,
+    similarity: Box<dyn Similarity>,
 }
 
-impl Searcher {
-    fn new(reader: IndexReader) -> Searcher {
-        Searcher { reader: reader }
+impl<'a, R: FilterReader> Searcher<'a, R> {
+    pub(crate) fn new(reader: &'a R) -> Searcher<'a, R> {
+        Searcher {
+            reader: reader,
+            similarity: Box::new(BM25::default()),
+        }
     }
 
-    fn and(&mut self, reader: IndexReader) -> Searcher {
-        Searcher { reader: reader }
+    pub fn with_similarity<S: Similarity + 'static>(mut self, similarity: S) -> Searcher<'a, R> {
+        self.similarity = Box::new(similarity);
+        self
     }
 
-    fn doc(&self, doc_id: DocID) -> GyResult<Document> {
-        self.reader.doc(doc_id)
+    // 返回得分最高的 k 个文档
+    pub fn search(&self, query: &dyn Query, k: usize) -> GyResult<Vec<ScoreDoc>> {
+        let docs = query.scores(self.reader, self.similarity.as_ref())?;
+        Ok(similarity::top_k(docs, k))
     }
+}
 
-    pub fn search(&self, query: &dyn Query) {
-        query.query(&self.reader);
+impl<'a> Searcher<'a, IndexReader> {
+    pub fn doc(&self, doc_id: DocID) -> GyResult<Document> {
+        self.reader.doc(doc_id)
     }
 }
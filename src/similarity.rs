@@ -0,0 +1,148 @@
+use super::schema::DocID;
+
+// 域的集合统计, 只计入含有该域的文档
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FieldStats {
+    doc_count: u64,
+    total_len: u64,
+}
+
+impl FieldStats {
+    pub(crate) fn new(doc_count: u64, total_len: u64) -> FieldStats {
+        FieldStats {
+            doc_count: doc_count,
+            total_len: total_len,
+        }
+    }
+
+    pub(crate) fn merge(&self, other: &FieldStats) -> FieldStats {
+        FieldStats::new(
+            self.doc_count + other.doc_count,
+            self.total_len + other.total_len,
+        )
+    }
+
+    pub fn doc_count(&self) -> u64 {
+        self.doc_count
+    }
+
+    // 该域所有文档的 token 总数
+    pub fn total_len(&self) -> u64 {
+        self.total_len
+    }
+
+    pub fn avg_len(&self) -> f32 {
+        if self.doc_count == 0 {
+            return 0.0;
+        }
+        self.total_len as f32 / self.doc_count as f32
+    }
+}
+
+// 相关性打分, 一个 term 的得分为 score(weight(..), ..)
+pub trait Similarity {
+    // 同一个 term 在所有文档上共用的权重, 如 idf
+    fn weight(&self, stats: &FieldStats, doc_freq: u64) -> f32;
+    // freq 为 term 在文档中出现的次数, doc_len 为文档在该域的 token 数
+    fn score(&self, weight: f32, freq: u32, doc_len: u32, stats: &FieldStats) -> f32;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BM25 {
+    // 词频饱和参数
+    k1: f32,
+    // 长度归一化程度, 0 表示不做归一化
+    b: f32,
+}
+
+impl BM25 {
+    pub fn new(k1: f32, b: f32) -> BM25 {
+        BM25 { k1: k1, b: b }
+    }
+}
+
+impl Default for BM25 {
+    fn default() -> Self {
+        BM25::new(1.2, 0.75)
+    }
+}
+
+impl Similarity for BM25 {
+    fn weight(&self, stats: &FieldStats, doc_freq: u64) -> f32 {
+        let n = stats.doc_count().max(doc_freq) as f32;
+        let df = doc_freq as f32;
+        (1.0 + (n - df + 0.5) / (df + 0.5)).ln()
+    }
+
+    fn score(&self, weight: f32, freq: u32, doc_len: u32, stats: &FieldStats) -> f32 {
+        let avg_len = stats.avg_len();
+        let norm = if avg_len > 0.0 {
+            1.0 - self.b + self.b * doc_len as f32 / avg_len
+        } else {
+            1.0
+        };
+        let tf = freq as f32;
+        weight * tf * (self.k1 + 1.0) / (tf + self.k1 * norm)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreDoc {
+    doc_id: DocID,
+    score: f32,
+}
+
+impl ScoreDoc {
+    pub(crate) fn new(doc_id: DocID, score: f32) -> ScoreDoc {
+        ScoreDoc {
+            doc_id: doc_id,
+            score: score,
+        }
+    }
+
+    pub fn doc_id(&self) -> DocID {
+        self.doc_id
+    }
+
+    // 越大越相关
+    pub fn score(&self) -> f32 {
+        self.score
+    }
+}
+
+// 按得分从高到低取前 k 个, 得分相同时文档 id 小的在前
+pub(crate) fn top_k(mut docs: Vec<ScoreDoc>, k: usize) -> Vec<ScoreDoc> {
+    docs.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap()
+            .then(a.doc_id.cmp(&b.doc_id))
+    });
+    docs.truncate(k);
+    docs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bm25() {
+        // 三个文档的长度为 3, 5, 4
+        let stats = FieldStats::new(3, 12);
+        assert_eq!(stats.doc_count(), 3);
+        assert_eq!(stats.avg_len(), 4.0);
+        let bm25 = BM25::default();
+        // 越少见的 term 权重越高
+        assert!(bm25.weight(&stats, 1) > bm25.weight(&stats, 3));
+        let w = bm25.weight(&stats, 1);
+        // 词频越高得分越高, 但不超过 weight * (k1 + 1)
+        assert!(bm25.score(w, 2, 4, &stats) > bm25.score(w, 1, 4, &stats));
+        assert!(bm25.score(w, 1000, 4, &stats) < w * 2.2);
+        // 同样的词频, 文档越短得分越高
+        assert!(bm25.score(w, 1, 3, &stats) > bm25.score(w, 1, 5, &stats));
+        // b 为 0 时不做长度归一化
+        let bm25 = BM25::new(1.2, 0.0);
+        assert_eq!(bm25.score(w, 1, 3, &stats), bm25.score(w, 1, 5, &stats));
+    }
+}
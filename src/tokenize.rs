@@ -1,11 +1,535 @@
+use super::query::Term;
+use super::schema::FieldID;
+use super::util::error::{GyError, GyResult};
+use jieba_rs::{Jieba, TokenizeMode};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::sync::Arc;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+// Lucene 的英文停用词表
+pub const ENGLISH_STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in", "into", "is", "it",
+    "no", "not", "of", "on", "or", "such", "that", "the", "their", "then", "there", "these",
+    "they", "this", "to", "was", "will", "with",
+];
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     pub text: String,
+    // 在文本中是第几个 token, 被过滤掉的 token 仍占一个位置
+    pub position: usize,
+}
+
+impl Token {
+    fn new(text: String, position: usize) -> Token {
+        Token {
+            text: text,
+            position: position,
+        }
+    }
+}
+
+pub trait Tokenizer: Send + Sync {
+    fn tokenize(&self, text: &str) -> Vec<Token>;
+}
+
+pub trait TokenFilter: Send + Sync {
+    fn filter(&self, tokens: Vec<Token>) -> Vec<Token>;
+}
+
+// 按空白切分
+pub struct WhitespaceTokenizer;
+
+impl Tokenizer for WhitespaceTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<Token> {
+        text.split_whitespace()
+            .enumerate()
+            .map(|(i, w)| Token::new(w.to_string(), i))
+            .collect()
+    }
+}
+
+// 按 Unicode 词边界 (UAX #29) 切分, 去掉标点和空白
+pub struct UnicodeTokenizer;
+
+impl Tokenizer for UnicodeTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<Token> {
+        text.unicode_words()
+            .enumerate()
+            .map(|(i, w)| Token::new(w.to_string(), i))
+            .collect()
+    }
+}
+
+// 每个空白分隔的词切成长度在 [min, max] 之间的字符 n-gram
+pub struct NgramTokenizer {
+    min: usize,
+    max: usize,
+}
+
+impl NgramTokenizer {
+    pub fn new(min: usize, max: usize) -> NgramTokenizer {
+        NgramTokenizer {
+            min: min.max(1),
+            max: max.max(min.max(1)),
+        }
+    }
+}
+
+impl Tokenizer for NgramTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        for w in text.split_whitespace() {
+            let chars: Vec<char> = w.chars().collect();
+            for start in 0..chars.len() {
+                for n in self.min..=self.max {
+                    if start + n > chars.len() {
+                        break;
+                    }
+                    let gram: String = chars[start..start + n].iter().collect();
+                    tokens.push(Token::new(gram, tokens.len()));
+                }
+            }
+        }
+        tokens
+    }
+}
+
+// 默认词典只加载一次, 没有用户词典的域共用
+static JIEBA: Lazy<Arc<Jieba>> = Lazy::new(|| Arc::new(Jieba::new()));
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum JiebaMode {
+    // 精确切分, 每个字只属于一个词, 适合切分查询文本
+    Search,
+    // 在精确切分的基础上再输出长词中的短词, 如 "中国科学院" 还会切出 "中国" "科学院", 适合建索引
+    Index,
+}
+
+// 中文分词, 去掉标点和空白
+pub struct JiebaTokenizer {
+    jieba: Arc<Jieba>,
+    mode: JiebaMode,
+}
+
+impl JiebaTokenizer {
+    pub fn new(mode: JiebaMode) -> GyResult<JiebaTokenizer> {
+        Ok(JiebaTokenizer {
+            jieba: JIEBA.clone(),
+            mode: mode,
+        })
+    }
+
+    // 用户词典与 jieba 词典格式相同, 每行 "词 [词频] [词性]", 词频省略时取能切出该词的词频
+    // 用户词加入默认词典的副本, 不影响其他域
+    pub fn with_user_dict(mut self, path: &PathBuf) -> GyResult<JiebaTokenizer> {
+        let mut jieba = (*self.jieba).clone();
+        let reader = BufReader::new(std::fs::File::open(path)?);
+        for line in reader.lines() {
+            let line = line?;
+            let mut parts = line.split_whitespace();
+            let word = match parts.next() {
+                Some(word) => word,
+                None => continue,
+            };
+            let freq = match parts.next() {
+                Some(freq) => Some(freq.parse::<usize>().map_err(|_| GyError::ErrLoadJieba)?),
+                None => None,
+            };
+            jieba.add_word(word, freq, parts.next());
+        }
+        self.jieba = Arc::new(jieba);
+        Ok(self)
+    }
+}
+
+impl Tokenizer for JiebaTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<Token> {
+        // 精确切分的每个词占一个位置, 标点不输出但仍占位置, 空白不占位置
+        let words = self.jieba.tokenize(text, TokenizeMode::Default, true);
+        let mut positions = Vec::with_capacity(words.len());
+        let mut pos = 0;
+        for w in words.iter() {
+            positions.push((w.end, pos));
+            if !w.word.trim().is_empty() {
+                pos += 1;
+            }
+        }
+        let segments = match self.mode {
+            JiebaMode::Search => words,
+            JiebaMode::Index => self.jieba.tokenize(text, TokenizeMode::Search, true),
+        };
+        // 长词中切出的短词取所在长词的位置, 短词按偏移有序且排在长词之前
+        let mut i = 0;
+        let mut tokens = Vec::with_capacity(segments.len());
+        for s in segments {
+            while positions[i].0 < s.end {
+                i += 1;
+            }
+            if s.word.chars().any(|c| c.is_alphanumeric()) {
+                tokens.push(Token::new(s.word.to_string(), positions[i].1));
+            }
+        }
+        tokens
+    }
+}
+
+pub struct LowercaseFilter;
+
+impl TokenFilter for LowercaseFilter {
+    fn filter(&self, tokens: Vec<Token>) -> Vec<Token> {
+        tokens
+            .into_iter()
+            .map(|t| Token::new(t.text.to_lowercase(), t.position))
+            .collect()
+    }
+}
+
+pub struct StopWordFilter {
+    words: HashSet<String>,
+}
+
+impl StopWordFilter {
+    pub fn new<S: AsRef<str>>(words: &[S]) -> StopWordFilter {
+        StopWordFilter {
+            words: words.iter().map(|w| w.as_ref().to_string()).collect(),
+        }
+    }
+}
+
+impl TokenFilter for StopWordFilter {
+    fn filter(&self, tokens: Vec<Token>) -> Vec<Token> {
+        tokens
+            .into_iter()
+            .filter(|t| !self.words.contains(&t.text))
+            .collect()
+    }
 }
 
-trait Tokenizer {}
+// 带重音等变音符号的拉丁字母转成 ASCII, 如 "café" -> "cafe"
+pub struct AsciiFoldingFilter;
 
-pub trait TokenStream {
-    fn next() {}
+impl AsciiFoldingFilter {
+    fn fold(text: &str) -> String {
+        let mut s = String::with_capacity(text.len());
+        for c in text.nfd().filter(|c| !is_combining_mark(*c)) {
+            match c {
+                'ß' => s.push_str("ss"),
+                'æ' => s.push_str("ae"),
+                'Æ' => s.push_str("AE"),
+                'œ' => s.push_str("oe"),
+                'Œ' => s.push_str("OE"),
+                'ø' => s.push('o'),
+                'Ø' => s.push('O'),
+                'đ' => s.push('d'),
+                'Đ' => s.push('D'),
+                'ł' => s.push('l'),
+                'Ł' => s.push('L'),
+                _ => s.push(c),
+            }
+        }
+        s
+    }
 }
 
-pub struct SimpleToken {}
+impl TokenFilter for AsciiFoldingFilter {
+    fn filter(&self, tokens: Vec<Token>) -> Vec<Token> {
+        tokens
+            .into_iter()
+            .map(|t| Token::new(Self::fold(&t.text), t.position))
+            .collect()
+    }
+}
+
+// 只保留字符数在 [min, max] 之间的 token
+pub struct LengthFilter {
+    min: usize,
+    max: usize,
+}
+
+impl LengthFilter {
+    pub fn new(min: usize, max: usize) -> LengthFilter {
+        LengthFilter { min: min, max: max }
+    }
+}
+
+impl TokenFilter for LengthFilter {
+    fn filter(&self, tokens: Vec<Token>) -> Vec<Token> {
+        tokens
+            .into_iter()
+            .filter(|t| {
+                let n = t.text.chars().count();
+                n >= self.min && n <= self.max
+            })
+            .collect()
+    }
+}
+
+// 分词器加上依次执行的过滤器
+pub struct Analyzer {
+    tokenizer: Box<dyn Tokenizer>,
+    filters: Vec<Box<dyn TokenFilter>>,
+}
+
+impl Analyzer {
+    pub fn new<T: Tokenizer + 'static>(tokenizer: T) -> Analyzer {
+        Analyzer {
+            tokenizer: Box::new(tokenizer),
+            filters: Vec::new(),
+        }
+    }
+
+    pub fn filter<F: TokenFilter + 'static>(mut self, filter: F) -> Analyzer {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    pub fn analyze(&self, text: &str) -> Vec<Token> {
+        let mut tokens = self.tokenizer.tokenize(text);
+        for f in self.filters.iter() {
+            tokens = f.filter(tokens);
+        }
+        tokens
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum TokenizerType {
+    Whitespace,
+    Unicode,
+    Ngram {
+        min: usize,
+        max: usize,
+    },
+    Jieba {
+        mode: JiebaMode,
+        user_dict: Option<PathBuf>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum TokenFilterType {
+    Lowercase,
+    StopWords(Vec<String>),
+    AsciiFolding,
+    Length { min: usize, max: usize },
+}
+
+// 文本域的分词配置, 保存在 schema 中, 建索引和查询时用同样的配置切分文本
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AnalyzerConfig {
+    tokenizer: TokenizerType,
+    filters: Vec<TokenFilterType>,
+}
+
+impl AnalyzerConfig {
+    pub fn new(tokenizer: TokenizerType) -> AnalyzerConfig {
+        AnalyzerConfig {
+            tokenizer: tokenizer,
+            filters: Vec::new(),
+        }
+    }
+
+    // Unicode 词边界切分, 转小写, 去掉重音, 去掉英文停用词
+    pub fn standard() -> AnalyzerConfig {
+        AnalyzerConfig::new(TokenizerType::Unicode)
+            .filter(TokenFilterType::Lowercase)
+            .filter(TokenFilterType::AsciiFolding)
+            .filter(TokenFilterType::StopWords(
+                ENGLISH_STOP_WORDS.iter().map(|w| w.to_string()).collect(),
+            ))
+    }
+
+    // 中文分词, 英文部分转小写
+    pub fn jieba(mode: JiebaMode, user_dict: Option<PathBuf>) -> AnalyzerConfig {
+        AnalyzerConfig::new(TokenizerType::Jieba {
+            mode: mode,
+            user_dict: user_dict,
+        })
+        .filter(TokenFilterType::Lowercase)
+    }
+
+    pub fn filter(mut self, filter: TokenFilterType) -> AnalyzerConfig {
+        self.filters.push(filter);
+        self
+    }
+
+    pub(crate) fn build(&self) -> GyResult<Analyzer> {
+        let mut analyzer = match &self.tokenizer {
+            TokenizerType::Whitespace => Analyzer::new(WhitespaceTokenizer),
+            TokenizerType::Unicode => Analyzer::new(UnicodeTokenizer),
+            TokenizerType::Ngram { min, max } => Analyzer::new(NgramTokenizer::new(*min, *max)),
+            TokenizerType::Jieba { mode, user_dict } => {
+                let tokenizer = JiebaTokenizer::new(*mode)?;
+                match user_dict {
+                    Some(path) => Analyzer::new(tokenizer.with_user_dict(path)?),
+                    None => Analyzer::new(tokenizer),
+                }
+            }
+        };
+        for f in self.filters.iter() {
+            analyzer = match f {
+                TokenFilterType::Lowercase => analyzer.filter(LowercaseFilter),
+                TokenFilterType::StopWords(words) => analyzer.filter(StopWordFilter::new(words)),
+                TokenFilterType::AsciiFolding => analyzer.filter(AsciiFoldingFilter),
+                TokenFilterType::Length { min, max } => {
+                    analyzer.filter(LengthFilter::new(*min, *max))
+                }
+            };
+        }
+        Ok(analyzer)
+    }
+}
+
+// 查询文本按域的分词器切分成 term, 没有配置分词器时整个文本为一个 term
+pub(crate) fn text_terms(field: FieldID, analyzer: Option<&Analyzer>, text: &str) -> Vec<Term> {
+    text_term_positions(field, analyzer, text)
+        .into_iter()
+        .map(|(t, _)| t)
+        .collect()
+}
+
+// 同 text_terms, 同时给出 term 在查询文本中的位置, 停用词等被过滤的 token 仍占位置
+pub(crate) fn text_term_positions(
+    field: FieldID,
+    analyzer: Option<&Analyzer>,
+    text: &str,
+) -> Vec<(Term, u32)> {
+    match analyzer {
+        Some(a) => a
+            .analyze(text)
+            .iter()
+            .map(|t| {
+                (
+                    Term::from_field_text(field.clone(), &t.text),
+                    t.position as u32,
+                )
+            })
+            .collect(),
+        None => vec![(Term::from_field_text(field, text), 0)],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(tokens: &[Token]) -> Vec<&str> {
+        tokens.iter().map(|t| t.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_tokenizer() {
+        let tokens = UnicodeTokenizer.tokenize("Red car, blue-ish sky!");
+        assert_eq!(texts(&tokens), vec!["Red", "car", "blue", "ish", "sky"]);
+        let tokens = WhitespaceTokenizer.tokenize("Red car, blue-ish");
+        assert_eq!(texts(&tokens), vec!["Red", "car,", "blue-ish"]);
+        let tokens = NgramTokenizer::new(2, 3).tokenize("abcd");
+        assert_eq!(texts(&tokens), vec!["ab", "abc", "bc", "bcd", "cd"]);
+    }
+
+    #[test]
+    fn test_analyzer() {
+        let analyzer = AnalyzerConfig::standard().build().unwrap();
+        let tokens = analyzer.analyze("The Café is on the Rue");
+        assert_eq!(texts(&tokens), vec!["cafe", "rue"]);
+        // 停用词占的位置保留
+        assert_eq!(tokens[0].position, 1);
+        assert_eq!(tokens[1].position, 5);
+        let analyzer = AnalyzerConfig::new(TokenizerType::Whitespace)
+            .filter(TokenFilterType::Length { min: 2, max: 4 })
+            .build()
+            .unwrap();
+        let tokens = analyzer.analyze("a bb cccc ddddd");
+        assert_eq!(texts(&tokens), vec!["bb", "cccc"]);
+    }
+
+    #[test]
+    fn test_jieba_tokenizer() {
+        let text = "小明硕士毕业于中国科学院计算所";
+        let tokens = JiebaTokenizer::new(JiebaMode::Search)
+            .unwrap()
+            .tokenize(text);
+        assert!(texts(&tokens).contains(&"中国科学院"));
+        assert!(!texts(&tokens).contains(&"科学院"));
+        let tokens = JiebaTokenizer::new(JiebaMode::Index)
+            .unwrap()
+            .tokenize(text);
+        assert!(texts(&tokens).contains(&"中国"));
+        assert!(texts(&tokens).contains(&"科学院"));
+    }
+
+    #[test]
+    fn test_jieba_positions() {
+        let position =
+            |tokens: &[Token], text: &str| tokens.iter().find(|t| t.text == text).unwrap().position;
+        let text = "小明硕士毕业于中国科学院计算所";
+        let search = JiebaTokenizer::new(JiebaMode::Search)
+            .unwrap()
+            .tokenize(text);
+        let index = JiebaTokenizer::new(JiebaMode::Index)
+            .unwrap()
+            .tokenize(text);
+        // 两种模式下同一个词的位置相同, 短词取所在长词的位置
+        let pos = position(&search, "中国科学院");
+        assert_eq!(position(&index, "中国科学院"), pos);
+        assert_eq!(position(&index, "中国"), pos);
+        assert_eq!(position(&index, "科学院"), pos);
+        assert_eq!(position(&index, "计算所"), pos + 1);
+        // 标点仍占一个位置, 空白不占
+        let tokens = JiebaTokenizer::new(JiebaMode::Search)
+            .unwrap()
+            .tokenize("苹果，手机 电脑");
+        assert_eq!(texts(&tokens), vec!["苹果", "手机", "电脑"]);
+        let positions: Vec<usize> = tokens.iter().map(|t| t.position).collect();
+        assert_eq!(positions, vec![0, 2, 3]);
+    }
+
+    #[test]
+    fn test_jieba_user_dict() {
+        let text = "我们在开发向量数据库";
+        let tokens = JiebaTokenizer::new(JiebaMode::Search)
+            .unwrap()
+            .tokenize(text);
+        assert!(!texts(&tokens).contains(&"向量数据库"));
+
+        let path = PathBuf::from("./data_jieba_user_dict.txt");
+        std::fs::write(&path, "向量数据库\n\n检索增强 10 n\n").unwrap();
+        // 精确切分时用户词作为一个整体
+        let tokens = JiebaTokenizer::new(JiebaMode::Search)
+            .unwrap()
+            .with_user_dict(&path)
+            .unwrap()
+            .tokenize(text);
+        assert!(texts(&tokens).contains(&"向量数据库"));
+        assert!(!texts(&tokens).contains(&"数据库"));
+        // 索引模式同时输出用户词和其中的短词
+        let tokens = JiebaTokenizer::new(JiebaMode::Index)
+            .unwrap()
+            .with_user_dict(&path)
+            .unwrap()
+            .tokenize(text);
+        assert!(texts(&tokens).contains(&"向量数据库"));
+        assert!(texts(&tokens).contains(&"数据库"));
+        // 用户词只加入这个分词器, 默认词典不变
+        let tokens = JiebaTokenizer::new(JiebaMode::Search)
+            .unwrap()
+            .tokenize(text);
+        assert!(!texts(&tokens).contains(&"向量数据库"));
+
+        std::fs::write(&path, "向量数据库 abc\n").unwrap();
+        assert!(matches!(
+            JiebaTokenizer::new(JiebaMode::Search)
+                .unwrap()
+                .with_user_dict(&path),
+            Err(GyError::ErrLoadJieba)
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1 +1,94 @@
-struct BitMap {}
+// 文档 id 位图, 过滤查询时作为允许列表
+#[derive(Clone)]
+pub struct BitMap {
+    bits: Vec<u64>,
+    len: usize,
+}
+
+impl BitMap {
+    pub fn new(len: usize) -> BitMap {
+        BitMap {
+            bits: vec![0u64; (len + 63) / 64],
+            len: len,
+        }
+    }
+
+    pub fn full(len: usize) -> BitMap {
+        let mut b = BitMap {
+            bits: vec![u64::MAX; (len + 63) / 64],
+            len: len,
+        };
+        // 清掉最后一个字中超出 len 的位
+        if len % 64 != 0 {
+            *b.bits.last_mut().unwrap() = (1u64 << (len % 64)) - 1;
+        }
+        b
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    // 超出范围的 id 直接忽略
+    pub fn insert(&mut self, i: usize) {
+        if i < self.len {
+            self.bits[i / 64] |= 1u64 << (i % 64);
+        }
+    }
+
+    pub fn contains(&self, i: usize) -> bool {
+        i < self.len && self.bits[i / 64] & (1u64 << (i % 64)) != 0
+    }
+
+    pub fn count(&self) -> usize {
+        self.bits.iter().map(|b| b.count_ones() as usize).sum()
+    }
+
+    pub fn intersect(&mut self, other: &BitMap) {
+        for (i, b) in self.bits.iter_mut().enumerate() {
+            *b &= other.bits.get(i).cloned().unwrap_or(0);
+        }
+    }
+
+    pub fn union(&mut self, other: &BitMap) {
+        for (b, o) in self.bits.iter_mut().zip(other.bits.iter()) {
+            *b |= *o;
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.bits.iter().enumerate().flat_map(|(i, b)| {
+            let b = *b;
+            (0..64)
+                .filter(move |j| b & (1u64 << j) != 0)
+                .map(move |j| i * 64 + j)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bitmap() {
+        let mut a = BitMap::new(100);
+        a.insert(3);
+        a.insert(64);
+        a.insert(99);
+        a.insert(100);
+        assert!(a.contains(64));
+        assert!(!a.contains(100));
+        assert_eq!(a.iter().collect::<Vec<usize>>(), vec![3, 64, 99]);
+        let full = BitMap::full(100);
+        assert_eq!(full.count(), 100);
+        let mut b = BitMap::new(100);
+        b.insert(64);
+        b.insert(7);
+        let mut c = a.clone();
+        c.intersect(&b);
+        assert_eq!(c.iter().collect::<Vec<usize>>(), vec![64]);
+        a.union(&b);
+        assert_eq!(a.count(), 4);
+    }
+}
@@ -10,3 +10,29 @@ pub fn i64_to_u64(val: i64) -> u64 {
 pub fn u64_to_i64(val: u64) -> i64 {
     (val ^ HIGHEST_BIT) as i64
 }
+
+#[inline(always)]
+pub fn i32_to_u32(val: i32) -> u32 {
+    (val as u32) ^ (1 << 31)
+}
+
+// 负数所有位取反, 正数只翻转符号位, 映射后的无符号整数与浮点数同序
+#[inline(always)]
+pub fn f64_to_u64(val: f64) -> u64 {
+    let bits = val.to_bits();
+    if bits & HIGHEST_BIT != 0 {
+        !bits
+    } else {
+        bits ^ HIGHEST_BIT
+    }
+}
+
+#[inline(always)]
+pub fn f32_to_u32(val: f32) -> u32 {
+    let bits = val.to_bits();
+    if bits & (1 << 31) != 0 {
+        !bits
+    } else {
+        bits ^ (1 << 31)
+    }
+}
@@ -1,5 +1,4 @@
 use crate::PathBuf;
-use furze::error::FstError;
 use std::io;
 use std::io::Error as IOError;
 use std::sync::{PoisonError, TryLockError};
@@ -44,15 +43,53 @@ pub enum GyError {
     #[error("invalid value type")]
     ErrInvalidValueType,
     #[error("invalid fst: {0}")]
-    ErrInvalidFst(FstError),
+    ErrInvalidFst(fst::Error),
     #[error("bad magic number")]
     ErrBadMagicNumber,
     #[error("serde json err: {0}")]
     ErrSerdeJson(serde_json::Error),
     #[error("not found from bloom {0}")]
     ErrNotFoundTermFromBloom(String),
+    #[error("term not found: {0}")]
+    ErrNotFoundTerm(String),
     #[error("collection wal invalid")]
     ErrCollectionWalInvalid,
+    #[error("invalid ann type: {0}")]
+    ErrInvalidAnnType(usize),
+    #[error("ann type mismatch")]
+    ErrAnnTypeMismatch,
+    #[error("vector field not found: {0}")]
+    ErrVectorFieldNotFound(String),
+    #[error("vector field count mismatch: expect {0}, got {1}")]
+    ErrVectorFieldMismatch(usize, usize),
+    #[error("not a sparse vector field: {0}")]
+    ErrNotSparseField(u32),
+    #[error("vector field kind mismatch, multi-vector fields only support query_multi: {0}")]
+    ErrVectorFieldKind(String),
+    #[error("multi-vector size {0} is not a multiple of row size {1}")]
+    ErrInvalidMultiVector(usize, usize),
+    #[error("load jieba dictionary failed")]
+    ErrLoadJieba,
+    #[error("invalid pattern: {0}")]
+    ErrInvalidPattern(String),
+    #[error("too many terms expanded, limit {0}")]
+    ErrTooManyTerms(usize),
+    #[error("invalid fuzzy distance: {0}, expect 1 or 2")]
+    ErrInvalidFuzzyDistance(u32),
+    #[error("binary vector of {0} bits needs more than {1} bytes")]
+    ErrInvalidBinaryVector(usize, usize),
+    #[error("unsupported tensor vector type: {0}")]
+    ErrUnsupportedVectorType(String),
+    #[error("duplicate vector field: {0}")]
+    ErrDuplicateVectorField(String),
+    #[error("vector index id mismatch: expect {0}, got {1}")]
+    ErrVectorIdMismatch(usize, usize),
+    #[error("vector field layout mismatch between segments")]
+    ErrVectorLayoutMismatch,
+    #[error("vector size mismatch: expect {0} bytes, got {1}")]
+    ErrVectorSizeMismatch(usize, usize),
+    #[error("vector type mismatch: expect {0}, got {1}")]
+    ErrVectorTypeMismatch(String, String),
 }
 
 impl From<&str> for GyError {
@@ -73,8 +110,8 @@ impl From<String> for GyError {
     }
 }
 
-impl From<FstError> for GyError {
-    fn from(e: FstError) -> Self {
+impl From<fst::Error> for GyError {
+    fn from(e: fst::Error) -> Self {
         GyError::ErrInvalidFst(e)
     }
 }
@@ -1,57 +1,110 @@
 use super::error::GyResult;
-use furze::fst::FstItem;
-use furze::{Builder, FstIterator, FST};
+use fst::map::Stream;
+use fst::{IntoStreamer, Map, MapBuilder, Streamer};
+use std::ops::Bound;
 
-pub(crate) struct FstBuilder(Builder<Vec<u8>>);
+pub(crate) struct FstBuilder {
+    builder: Option<MapBuilder<Vec<u8>>>,
+    bytes: Vec<u8>,
+}
 
 impl FstBuilder {
     pub(crate) fn new() -> FstBuilder {
-        FstBuilder(Builder::new(Vec::with_capacity(4 * 1024 * 1024)))
+        FstBuilder {
+            builder: Some(MapBuilder::memory()),
+            bytes: Vec::new(),
+        }
     }
 
+    // key 必须按字典序递增
     pub(crate) fn add(&mut self, key: &[u8], val: u64) -> GyResult<()> {
-        self.0.add(key, val)?;
+        self.builder
+            .as_mut()
+            .ok_or("fst builder already finished")?
+            .insert(key, val)?;
         Ok(())
     }
 
     pub(crate) fn finish(&mut self) -> GyResult<()> {
-        self.0.finish()?;
+        if let Some(builder) = self.builder.take() {
+            self.bytes = builder.into_inner()?;
+        }
         Ok(())
     }
 
     pub(crate) fn get_ref(&self) -> &[u8] {
-        self.0.get()
+        &self.bytes
     }
 
     pub(crate) fn reset(&mut self) -> GyResult<()> {
-        self.0.reset()?;
+        self.builder = Some(MapBuilder::memory());
+        self.bytes.clear();
         Ok(())
     }
 }
 
-pub(crate) struct FstReader<'a>(FST<&'a [u8]>);
+pub(crate) struct FstReader<'a>(Map<&'a [u8]>);
 
 impl<'a> FstReader<'a> {
-    pub(crate) fn load(b: &'a [u8]) -> FstReader {
-        Self(FST::load(b))
+    pub(crate) fn load(b: &'a [u8]) -> GyResult<FstReader<'a>> {
+        Ok(Self(Map::new(b)?))
+    }
+
+    // term 不存在时返回 None
+    pub(crate) fn get(&self, key: &[u8]) -> Option<u64> {
+        self.0.get(key)
     }
 
-    pub(crate) fn get(&self, key: &[u8]) -> GyResult<u64> {
-        let u = self.0.get(key)?;
-        Ok(u)
+    pub(crate) fn iter(&self) -> FstReaderIter<'_> {
+        self.range(Bound::Unbounded, Bound::Unbounded)
     }
 
-    pub(crate) fn iter(&self) -> FstReaderIter {
-        FstReaderIter(self.0.iter())
+    // 按字典序遍历 [lower, upper] 内的 term, 直接定位到下界, 过了上界即停止
+    pub(crate) fn range(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> FstReaderIter<'_> {
+        let upper = upper.map(|k| k.to_vec());
+        FstReaderIter {
+            stream: stream(&self.0, lower, upper.as_ref().map(|k| k.as_slice())),
+            map: &self.0,
+            upper: upper,
+        }
     }
 }
 
-pub(crate) struct FstReaderIter<'a>(FstIterator<'a, &'a [u8]>);
+fn stream<'m>(map: &'m Map<&[u8]>, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Stream<'m> {
+    let builder = match lower {
+        Bound::Included(k) => map.range().ge(k),
+        Bound::Excluded(k) => map.range().gt(k),
+        Bound::Unbounded => map.range(),
+    };
+    match upper {
+        Bound::Included(k) => builder.le(k),
+        Bound::Excluded(k) => builder.lt(k),
+        Bound::Unbounded => builder,
+    }
+    .into_stream()
+}
+
+pub(crate) struct FstReaderIter<'a> {
+    stream: Stream<'a>,
+    map: &'a Map<&'a [u8]>,
+    upper: Bound<Vec<u8>>,
+}
+
+impl<'a> FstReaderIter<'a> {
+    // 跳到不小于 key 的第一个 term, 上界不变
+    pub(crate) fn seek(&mut self, key: &[u8]) {
+        self.stream = stream(
+            self.map,
+            Bound::Included(key),
+            self.upper.as_ref().map(|k| k.as_slice()),
+        );
+    }
+}
 
 impl<'a> Iterator for FstReaderIter<'a> {
-    type Item = FstItem;
+    type Item = (Vec<u8>, u64);
     fn next(&mut self) -> Option<Self::Item> {
-        self.0.next()
+        self.stream.next().map(|(k, v)| (k.to_vec(), v))
     }
 }
 
@@ -61,21 +114,35 @@ mod tests {
     use std::vec;
 
     use super::*;
+    fn keys(iter: FstReaderIter) -> Vec<Vec<u8>> {
+        iter.map(|(k, _)| k).collect()
+    }
+
     #[test]
     fn test_fst() {
         let mut fst = FstBuilder::new();
-        fst.add(b"aa", 1).unwrap();
-        fst.add(b"bb", 2).unwrap();
-        fst.finish();
-        println!("{:?}", fst.get_ref());
-        let fst_r = FstReader::load(fst.get_ref());
-        let u = fst_r.get(b"aa").unwrap();
-        println!("u:{}", u);
-
-        let mut iter = fst_r.iter();
-        while let Some(v) = iter.next() {
-            println!("v:{:?}", v.0.as_ref());
+        for (i, k) in ["aa", "ab", "b", "bb", "c"].iter().enumerate() {
+            fst.add(k.as_bytes(), i as u64).unwrap();
         }
+        fst.finish().unwrap();
+        let fst_r = FstReader::load(fst.get_ref()).unwrap();
+        assert_eq!(fst_r.get(b"bb"), Some(3));
+        assert_eq!(fst_r.get(b"ba"), None);
+        assert_eq!(fst_r.iter().count(), 5);
+        assert_eq!(
+            keys(fst_r.range(Bound::Excluded(&b"aa"[..]), Bound::Included(&b"bb"[..]))),
+            vec![b"ab".to_vec(), b"b".to_vec(), b"bb".to_vec()]
+        );
+        // 跳过之后上界仍然有效
+        let mut iter = fst_r.range(Bound::Unbounded, Bound::Excluded(&b"c"[..]));
+        assert_eq!(iter.next().unwrap(), (b"aa".to_vec(), 0));
+        iter.seek(b"ba");
+        assert_eq!(keys(iter), vec![b"bb".to_vec()]);
+        // 重置后可以写下一个域
+        fst.reset().unwrap();
+        fst.add(b"x", 7).unwrap();
+        fst.finish().unwrap();
+        assert_eq!(FstReader::load(fst.get_ref()).unwrap().get(b"x"), Some(7));
     }
 
     // #[test]
@@ -33,15 +33,15 @@ unsafe impl Sync for Wal {}
 
 pub struct WalIter<'a, V: VectorSerialize> {
     wal_reader: WalReader<'a>,
-    tensor_entry: TensorEntry,
+    entries: Vec<TensorEntry>,
     _mark: PhantomData<V>,
 }
 
 impl<'a, V: VectorSerialize> WalIter<'a, V> {
-    pub fn new(wal_reader: WalReader<'a>, tensor_entry: TensorEntry) -> WalIter<'a, V> {
+    pub fn new(wal_reader: WalReader<'a>, entries: Vec<TensorEntry>) -> WalIter<'a, V> {
         Self {
             wal_reader: wal_reader,
-            tensor_entry: tensor_entry,
+            entries: entries,
             _mark: PhantomData::default(),
         }
     }
@@ -62,7 +62,7 @@ impl<'a, V: VectorSerialize> Iterator for WalIter<'a, V> {
         // if (offset + 4 >= self.fsize()) {
         //     return None;
         // }
-        match V::vector_deserialize(&mut self.wal_reader, &self.tensor_entry) {
+        match V::vector_deserialize_fields(&mut self.wal_reader, &self.entries) {
             Ok(v) => Some((offset, v)),
             Err(_) => None,
         }
@@ -225,8 +225,11 @@ impl Wal {
         self.i = pos;
     }
 
-    pub(crate) fn iter<'a, V: VectorSerialize>(&'a self, entry: TensorEntry) -> WalIter<'a, V> {
-        WalIter::<V>::new(WalReader::new(self, 0, self.offset()), entry)
+    pub(crate) fn iter<'a, V: VectorSerialize>(
+        &'a self,
+        entries: Vec<TensorEntry>,
+    ) -> WalIter<'a, V> {
+        WalIter::<V>::new(WalReader::new(self, 0, self.offset()), entries)
     }
 
     pub(crate) fn get_fname(&self) -> &Path {